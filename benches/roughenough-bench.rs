@@ -52,6 +52,33 @@ fn create_nested_message(c: &mut Criterion) {
     }));
 }
 
+fn response_sized_message() -> RtMessage {
+    let mut msg = RtMessage::new(5);
+    msg.add_field(Tag::SIG, &[0u8; 64]).unwrap();
+    msg.add_field(Tag::PATH, &[0u8; 192]).unwrap();
+    msg.add_field(Tag::SREP, &[0u8; 100]).unwrap();
+    msg.add_field(Tag::CERT, &[0u8; 152]).unwrap();
+    msg.add_field(Tag::INDX, &[0u8; 4]).unwrap();
+    msg
+}
+
+fn encode_message(c: &mut Criterion) {
+    let msg = response_sized_message();
+
+    c.bench_function("encode message", move |b| b.iter(|| {
+        black_box(msg.encode().unwrap())
+    }));
+}
+
+fn encode_message_into_buffer(c: &mut Criterion) {
+    let msg = response_sized_message();
+    let mut buf = vec![0u8; msg.encoded_size()];
+
+    c.bench_function("encode message into buffer", move |b| b.iter(|| {
+        black_box(msg.encode_into(&mut buf).unwrap())
+    }));
+}
+
 static SIZES: &[u8] = &[1, 3, 9, 17, 200];
 static DATA: &[u8] = &[1u8; 64];
 
@@ -89,9 +116,14 @@ criterion_group!(message_creation,
   create_nested_message
 );
 
+criterion_group!(message_encoding,
+  encode_message,
+  encode_message_into_buffer
+);
+
 criterion_group!(merkle_tree,
     create_new_merkle_tree,
     reuse_merkle_trees
 );
 
-criterion_main!(message_creation, merkle_tree);
+criterion_main!(message_creation, message_encoding, merkle_tree);
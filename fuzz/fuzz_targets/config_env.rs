@@ -0,0 +1,40 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Arbitrary `NAME=value` lines, standing in for the process environment, must produce an
+//! `EnvironmentConfig` or an error, never a panic
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use std::collections::HashMap;
+
+use roughenough::config::{EnvironmentConfig, ServerConfig};
+
+fuzz_target!(|data: &[u8]| {
+    let contents = String::from_utf8_lossy(data);
+
+    let vars: HashMap<&str, &str> = contents
+        .lines()
+        .filter_map(|line| {
+            let idx = line.find('=')?;
+            Some((&line[..idx], &line[idx + 1..]))
+        })
+        .collect();
+
+    let lookup = |name: &str| vars.get(name).map(|v| v.to_string());
+    if let Ok(config) = EnvironmentConfig::from_lookup(lookup) {
+        let _ = config.validate();
+    }
+});
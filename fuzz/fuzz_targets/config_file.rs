@@ -0,0 +1,28 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Arbitrary YAML documents must produce a `FileConfig` or an error, never a panic
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use roughenough::config::{FileConfig, ServerConfig};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(contents) = std::str::from_utf8(data) {
+        if let Ok(config) = FileConfig::from_yaml_str(contents) {
+            let _ = config.validate();
+        }
+    }
+});
@@ -0,0 +1,61 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Seed values and KMS envelopes decoded from arbitrary bytes must be accepted or rejected
+//! with an error, never a panic
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use roughenough::config::{FileConfig, MemoryConfig, ServerConfig};
+use roughenough::key::{KmsProtection, LongTermKey};
+use roughenough::kms::{EnvelopeEncryption, KmsError, KmsProvider};
+
+// Stands in for a KMS: the "wrapped" DEK is the DEK itself
+struct PassthroughKms;
+
+impl KmsProvider for PassthroughKms {
+    fn encrypt_dek(&self, plaintext_dek: &Vec<u8>) -> Result<Vec<u8>, KmsError> {
+        Ok(plaintext_dek.clone())
+    }
+
+    fn decrypt_dek(&self, encrypted_dek: &Vec<u8>) -> Result<Vec<u8>, KmsError> {
+        Ok(encrypted_dek.clone())
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    // as an envelope-encrypted seed
+    let _ = EnvelopeEncryption::key_version(data);
+    let _ = EnvelopeEncryption::decrypt_seed(&PassthroughKms, data);
+
+    // as a plaintext seed, which is only used once the config validates
+    let config = MemoryConfig::builder()
+        .port(2002)
+        .seed(data)
+        .kms_protection(KmsProtection::Plaintext)
+        .build();
+    if config.validate().is_ok() {
+        LongTermKey::new(&config.seed());
+    }
+
+    // as the hex seed of a config file
+    let yaml = format!(
+        "seed: \"{}\"\n",
+        String::from_utf8_lossy(data).escape_default()
+    );
+    if let Ok(config) = FileConfig::from_yaml_str(&yaml) {
+        let _ = config.validate();
+    }
+});
@@ -0,0 +1,313 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! A compact, size-capped binary log of the requests the server answered.
+//!
+//! Each answered request is one fixed-size little-endian record:
+//!
+//! Offset | Size | Field
+//! ------ | ---- | -----
+//! 0      | 8    | midpoint (microseconds since the epoch) sent in the response
+//! 8      | 8    | batch id, counting from 0 at server startup
+//! 16     | 8    | first 8 bytes of the request's nonce
+//! 24     | 16   | client IP address (IPv4 addresses are IPv4-mapped IPv6)
+//! 40     | 2    | client UDP port
+//! 42     | 2    | reserved, zero
+//!
+//! Files start with the 4 byte magic `RAL1`. When the current file would grow past
+//! `audit_log_max_size` it is renamed to `<path>.1` (shifting older files up to
+//! `<path>.AUDIT_LOG_GENERATIONS`, the oldest being removed) and a new file is started.
+//!
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::Error;
+
+/// Identifies an audit log file
+const MAGIC: &[u8] = b"RAL1";
+
+/// Size (in bytes) of one audit record
+pub const RECORD_LENGTH: usize = 44;
+
+/// Number of rotated files kept in addition to the current one
+pub const AUDIT_LOG_GENERATIONS: u32 = 4;
+
+/// One answered request
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AuditRecord {
+    /// Midpoint (microseconds since the epoch) sent in the response
+    pub midpoint: u64,
+    /// Batch the request was answered in
+    pub batch: u64,
+    /// Leading bytes of the request's nonce
+    pub nonce_prefix: [u8; 8],
+    /// The requesting client
+    pub addr: SocketAddr,
+}
+
+impl AuditRecord {
+    /// A record for the request with `nonce` from `addr`, answered with `midpoint` in `batch`
+    pub fn new(midpoint: u64, batch: u64, nonce: &[u8], addr: SocketAddr) -> AuditRecord {
+        let mut nonce_prefix = [0u8; 8];
+        let len = nonce.len().min(nonce_prefix.len());
+        nonce_prefix[..len].copy_from_slice(&nonce[..len]);
+
+        AuditRecord {
+            midpoint,
+            batch,
+            nonce_prefix,
+            addr,
+        }
+    }
+
+    /// Encode this record into exactly `RECORD_LENGTH` bytes
+    pub fn encode(&self) -> [u8; RECORD_LENGTH] {
+        let mut buf = [0u8; RECORD_LENGTH];
+
+        let ip = match self.addr.ip() {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+
+        LittleEndian::write_u64(&mut buf[0..8], self.midpoint);
+        LittleEndian::write_u64(&mut buf[8..16], self.batch);
+        buf[16..24].copy_from_slice(&self.nonce_prefix);
+        buf[24..40].copy_from_slice(&ip.octets());
+        LittleEndian::write_u16(&mut buf[40..42], self.addr.port());
+
+        buf
+    }
+
+    /// Decode a record previously produced by `encode()`
+    pub fn decode(buf: &[u8]) -> Result<AuditRecord, Error> {
+        if buf.len() != RECORD_LENGTH {
+            return Err(Error::InvalidAuditLog(format!(
+                "record is {} bytes, expected {}",
+                buf.len(),
+                RECORD_LENGTH
+            )));
+        }
+
+        let mut nonce_prefix = [0u8; 8];
+        nonce_prefix.copy_from_slice(&buf[16..24]);
+
+        let mut octets = [0u8; 16];
+        octets.copy_from_slice(&buf[24..40]);
+        let ip = Ipv6Addr::from(octets);
+        let ip = match ip.to_ipv4() {
+            Some(v4) if ip.segments()[5] == 0xffff => IpAddr::V4(v4),
+            _ => IpAddr::V6(ip),
+        };
+
+        Ok(AuditRecord {
+            midpoint: LittleEndian::read_u64(&buf[0..8]),
+            batch: LittleEndian::read_u64(&buf[8..16]),
+            nonce_prefix,
+            addr: SocketAddr::new(ip, LittleEndian::read_u16(&buf[40..42])),
+        })
+    }
+}
+
+///
+/// Appends [`AuditRecord`](struct.AuditRecord.html)s to a rotating set of files.
+///
+/// Records are buffered; call `flush()` once per batch of responses.
+///
+pub struct AuditLog {
+    path: PathBuf,
+    max_size: u64,
+    writer: BufWriter<File>,
+    size: u64,
+}
+
+impl AuditLog {
+    /// Open (appending to) the audit log at `path`, rotating files at `max_size` bytes
+    pub fn open<P: AsRef<Path>>(path: P, max_size: u64) -> Result<AuditLog, Error> {
+        let path = path.as_ref().to_path_buf();
+        let (writer, size) = AuditLog::open_current(&path)?;
+
+        Ok(AuditLog {
+            path,
+            max_size,
+            writer,
+            size,
+        })
+    }
+
+    fn open_current(path: &Path) -> Result<(BufWriter<File>, u64), Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut size = file.metadata()?.len();
+        let mut writer = BufWriter::new(file);
+
+        if size == 0 {
+            writer.write_all(MAGIC)?;
+            size = MAGIC.len() as u64;
+        }
+
+        Ok((writer, size))
+    }
+
+    /// Buffer `record`, first rotating the log if it would exceed its maximum size
+    pub fn append(&mut self, record: &AuditRecord) -> Result<(), Error> {
+        if self.size + RECORD_LENGTH as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        self.writer.write_all(&record.encode())?;
+        self.size += RECORD_LENGTH as u64;
+
+        Ok(())
+    }
+
+    /// Write any buffered records to disk
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+
+        for generation in (1..AUDIT_LOG_GENERATIONS).rev() {
+            let from = generation_path(&self.path, generation);
+            if from.exists() {
+                fs::rename(&from, generation_path(&self.path, generation + 1))?;
+            }
+        }
+        fs::rename(&self.path, generation_path(&self.path, 1))?;
+
+        let (writer, size) = AuditLog::open_current(&self.path)?;
+        self.writer = writer;
+        self.size = size;
+
+        Ok(())
+    }
+}
+
+/// Path of the `generation`th rotated file of the log at `path`; 0 is the current file
+pub fn generation_path(path: &Path, generation: u32) -> PathBuf {
+    if generation == 0 {
+        return path.to_path_buf();
+    }
+
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", generation));
+    PathBuf::from(name)
+}
+
+/// Read every record in the single audit log file at `path`
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Vec<AuditRecord>, Error> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+
+    if !bytes.starts_with(MAGIC) {
+        return Err(Error::InvalidAuditLog("not an audit log".to_string()));
+    }
+
+    // a partial trailing record (e.g. after a crash mid-write) is ignored
+    bytes[MAGIC.len()..]
+        .chunks(RECORD_LENGTH)
+        .filter(|chunk| chunk.len() == RECORD_LENGTH)
+        .map(AuditRecord::decode)
+        .collect()
+}
+
+/// Read the records of the log at `path`, including its rotated files, oldest first
+pub fn read_all<P: AsRef<Path>>(path: P) -> Result<Vec<AuditRecord>, Error> {
+    let mut records = Vec::new();
+
+    for generation in (0..=AUDIT_LOG_GENERATIONS).rev() {
+        let file = generation_path(path.as_ref(), generation);
+        if file.exists() {
+            records.extend(read_file(&file)?);
+        }
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::audit::{generation_path, read_all, AuditLog, AuditRecord, RECORD_LENGTH};
+    use std::env;
+    use std::fs;
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+
+    fn temp_log(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("roughenough-audit-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("audit.log")
+    }
+
+    fn record(n: u64, addr: &str) -> AuditRecord {
+        AuditRecord::new(n * 1_000_000, n, &[n as u8; 64], addr.parse().unwrap())
+    }
+
+    #[test]
+    fn records_roundtrip() {
+        for addr in &["192.0.2.1:2002", "[2001:db8::1]:40000"] {
+            let rec = record(7, addr);
+            let decoded = AuditRecord::decode(&rec.encode()).unwrap();
+
+            assert_eq!(decoded, rec);
+            assert_eq!(decoded.addr, addr.parse::<SocketAddr>().unwrap());
+        }
+    }
+
+    #[test]
+    fn wrong_length_is_rejected() {
+        assert!(AuditRecord::decode(&[0u8; RECORD_LENGTH - 1]).is_err());
+    }
+
+    #[test]
+    fn append_then_read() {
+        let path = temp_log("append");
+
+        let mut log = AuditLog::open(&path, 1024 * 1024).unwrap();
+        for n in 0..10 {
+            log.append(&record(n, "192.0.2.1:2002")).unwrap();
+        }
+        log.flush().unwrap();
+
+        let records = read_all(&path).unwrap();
+        assert_eq!(records.len(), 10);
+        assert_eq!(records[3], record(3, "192.0.2.1:2002"));
+    }
+
+    #[test]
+    fn rotation_caps_size_and_keeps_order() {
+        let path = temp_log("rotate");
+        let max_size = (4 + RECORD_LENGTH * 3) as u64;
+
+        let mut log = AuditLog::open(&path, max_size).unwrap();
+        for n in 0..9 {
+            log.append(&record(n, "192.0.2.1:2002")).unwrap();
+        }
+        log.flush().unwrap();
+
+        assert!(generation_path(&path, 2).exists());
+        assert!(fs::metadata(&path).unwrap().len() <= max_size);
+
+        let batches: Vec<u64> = read_all(&path).unwrap().iter().map(|r| r.batch).collect();
+        assert_eq!(batches, (0..9).collect::<Vec<u64>>());
+    }
+}
@@ -0,0 +1,418 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Assembly of requests into a batch answered under a single signed `SREP`.
+//!
+//! The server reads up to `batch_size` requests, adds each nonce to a
+//! [`Batch`](struct.Batch.html), and then signs the batch's Merkle root once. Nothing here
+//! touches a socket, so batching behavior can be exercised directly.
+//!
+
+use std::fmt;
+use std::net::SocketAddr;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::merkle::MerkleTree;
+use crate::{Error, Tag, MIN_REQUEST_LENGTH, NONCE_LENGTH};
+
+/// IETF drafts frame messages with this magic value and a 4-byte length
+const IETF_FRAME_MAGIC: &[u8] = b"ROUGHTIM";
+
+/// The protocol dialect, and for IETF requests the version, a request was sent in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProtocolVersion {
+    /// The original Google Roughtime protocol
+    Classic,
+
+    /// An IETF Roughtime request; the highest version in its `VER` tag, if it has one
+    Ietf(Option<u32>),
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolVersion::Classic => write!(f, "classic"),
+            ProtocolVersion::Ietf(None) => write!(f, "ietf (no VER)"),
+            // drafts are numbered 0x80000000 + draft number
+            ProtocolVersion::Ietf(Some(v)) if v & 0x8000_0000 != 0 => {
+                write!(f, "ietf draft-{:02}", v & 0x7fff_ffff)
+            }
+            ProtocolVersion::Ietf(Some(v)) => write!(f, "ietf 0x{:08x}", v),
+        }
+    }
+}
+
+///
+/// Extract the client's nonce from the request `packet`. Only the canonical request layout
+/// is accepted: a `NONC` of `NONCE_LENGTH` bytes followed by `PAD`, at least
+/// `MIN_REQUEST_LENGTH` bytes in total.
+///
+pub fn nonce_from_request(packet: &[u8]) -> Result<&[u8], Error> {
+    if packet.len() < MIN_REQUEST_LENGTH as usize {
+        return Err(Error::RequestTooShort);
+    }
+
+    let tag_count = &packet[..4];
+    let pad_offset = &packet[4..8];
+    let expected_nonc = &packet[8..12];
+    let expected_pad = &packet[12..16];
+
+    let tag_count_is_2 = tag_count == [0x02, 0x00, 0x00, 0x00];
+    let tag1_is_nonc = expected_nonc == Tag::NONC.wire_value();
+    let tag2_is_pad = expected_pad == Tag::PAD.wire_value();
+    // the NONC value must be exactly NONCE_LENGTH bytes
+    let nonce_is_64 = pad_offset == [NONCE_LENGTH as u8, 0x00, 0x00, 0x00];
+
+    if tag_count_is_2 && tag1_is_nonc && tag2_is_pad && nonce_is_64 {
+        Ok(&packet[0x10..0x50])
+    } else {
+        Err(Error::InvalidRequest)
+    }
+}
+
+///
+/// The protocol version of the request `packet`, or `None` if it is neither a classic nor
+/// an IETF request. A request is IETF if it is framed or has a `VER` tag; otherwise it is
+/// classic if it has a `NONC`.
+///
+/// This runs for every request received, so only the message header and tag table are
+/// read; the message is not decoded.
+///
+pub fn request_version(packet: &[u8]) -> Option<ProtocolVersion> {
+    let framed = packet.starts_with(IETF_FRAME_MAGIC);
+
+    let msg = if framed {
+        let len = LittleEndian::read_u32(packet.get(8..12)?) as usize;
+        RawMessage::parse(packet.get(12..12usize.checked_add(len)?)?)?
+    } else {
+        RawMessage::parse(packet)?
+    };
+
+    match msg.get(Tag::VER) {
+        Some(versions) if versions.len() >= 4 && versions.len() % 4 == 0 => {
+            let highest = versions.chunks(4).map(LittleEndian::read_u32).max();
+            Some(ProtocolVersion::Ietf(highest))
+        }
+        _ if framed => Some(ProtocolVersion::Ietf(None)),
+        _ if msg.get(Tag::NONC).is_some() => Some(ProtocolVersion::Classic),
+        _ => None,
+    }
+}
+
+// An encoded message's header, read in place: the offsets of every value but the first,
+// the tags, and the values
+struct RawMessage<'a> {
+    offsets: &'a [u8],
+    tags: &'a [u8],
+    values: &'a [u8],
+}
+
+impl<'a> RawMessage<'a> {
+    fn parse(msg: &'a [u8]) -> Option<RawMessage<'a>> {
+        let num_tags = LittleEndian::read_u32(msg.get(0..4)?) as usize;
+        // the tag count, num_tags - 1 offsets and num_tags tags
+        let header_len = num_tags.checked_mul(8)?;
+
+        if num_tags == 0 || msg.len() < header_len {
+            return None;
+        }
+
+        Some(RawMessage {
+            offsets: &msg[4..4 * num_tags],
+            tags: &msg[4 * num_tags..header_len],
+            values: &msg[header_len..],
+        })
+    }
+
+    // The value of `tag`, or `None` if it is absent or its offsets are out of bounds
+    fn get(&self, tag: Tag) -> Option<&'a [u8]> {
+        let idx = self.tags.chunks(4).position(|t| t == tag.wire_value())?;
+        let offset = |i: usize| LittleEndian::read_u32(&self.offsets[4 * i..4 * i + 4]) as usize;
+
+        let start = if idx == 0 { 0 } else { offset(idx - 1) };
+        let end = if 4 * idx == self.offsets.len() {
+            self.values.len()
+        } else {
+            offset(idx)
+        };
+
+        self.values.get(start..end)
+    }
+}
+
+/// A request in a batch
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchEntry {
+    pub nonce: Vec<u8>,
+    /// Where the response is sent
+    pub source: SocketAddr,
+    /// Index of the nonce's leaf in the batch's Merkle tree
+    pub leaf: usize,
+    /// The protocol version the request was sent in
+    pub version: ProtocolVersion,
+}
+
+///
+/// The requests to be answered together, and the Merkle tree of their nonces.
+///
+/// Deprioritized requests are held separately until the server knows whether it is
+/// keeping up: [`admit_deferred`](#method.admit_deferred) adds them to the batch,
+/// [`shed_deferred`](#method.shed_deferred) discards them.
+///
+pub struct Batch {
+    merkle: MerkleTree,
+    entries: Vec<BatchEntry>,
+    // distinct nonces, in leaf order; hashed together by `merkle_root()`
+    leaves: Vec<Vec<u8>>,
+    // (nonce, requester, version) of deprioritized requests
+    deferred: Vec<(Vec<u8>, SocketAddr, ProtocolVersion)>,
+}
+
+impl Batch {
+    /// An empty batch with room for `capacity` requests
+    pub fn with_capacity(capacity: usize) -> Batch {
+        Batch {
+            merkle: MerkleTree::new(),
+            entries: Vec::with_capacity(capacity),
+            leaves: Vec::with_capacity(capacity),
+            deferred: Vec::new(),
+        }
+    }
+
+    ///
+    /// Add the request for `nonce` from `source`, sent in protocol `version`. Retransmitted
+    /// (or replayed) nonces share one leaf, so each requester still gets a response that
+    /// verifies.
+    ///
+    pub fn push(&mut self, nonce: &[u8], source: SocketAddr, version: ProtocolVersion) {
+        let existing = self.leaves.iter().position(|leaf| leaf.as_slice() == nonce);
+
+        let leaf = match existing {
+            Some(leaf) => {
+                debug!(
+                    "Duplicate nonce '{}..' from {} shares leaf {}",
+                    hex::encode(&nonce[0..4]),
+                    source,
+                    leaf
+                );
+                leaf
+            }
+            None => {
+                self.leaves.push(Vec::from(nonce));
+                self.leaves.len() - 1
+            }
+        };
+
+        self.entries.push(BatchEntry {
+            nonce: Vec::from(nonce),
+            source,
+            leaf,
+            version,
+        });
+    }
+
+    /// Hold the deprioritized request for `nonce` from `source` until the batch is complete
+    pub fn defer(&mut self, nonce: &[u8], source: SocketAddr, version: ProtocolVersion) {
+        self.deferred.push((Vec::from(nonce), source, version));
+    }
+
+    /// Add every deferred request to the batch
+    pub fn admit_deferred(&mut self) {
+        let deferred = std::mem::replace(&mut self.deferred, Vec::new());
+
+        for (nonce, source, version) in &deferred {
+            self.push(nonce, *source, *version);
+        }
+    }
+
+    /// Discard every deferred request, returning how many there were
+    pub fn shed_deferred(&mut self) -> usize {
+        let shed = self.deferred.len();
+        self.deferred.clear();
+
+        shed
+    }
+
+    /// The requests in the batch, in the order they were added
+    pub fn entries(&self) -> &[BatchEntry] {
+        &self.entries
+    }
+
+    /// Number of requests in the batch, including duplicates
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of distinct nonces (Merkle tree leaves) in the batch
+    pub fn num_leaves(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// The root of the Merkle tree of the batch's nonces. The batch must not be empty, and
+    /// this is called once per batch, after the last request is added.
+    pub fn merkle_root(&mut self) -> Vec<u8> {
+        self.merkle.push_leaves(&self.leaves);
+        self.merkle.compute_root()
+    }
+
+    /// The Merkle inclusion path of `leaf`; only valid after `merkle_root()`
+    pub fn paths(&self, leaf: usize) -> Vec<u8> {
+        self.merkle.get_paths(leaf)
+    }
+
+    /// Empty the batch so it can be reused
+    pub fn clear(&mut self) {
+        self.merkle.reset();
+        self.entries.clear();
+        self.leaves.clear();
+        self.deferred.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use crate::batch::{nonce_from_request, request_version, Batch, ProtocolVersion};
+    use crate::merkle::root_from_paths;
+    use crate::verify::make_request;
+    use crate::{Error, RtMessage, Tag};
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    // An IETF-framed request offering `versions`
+    fn ietf_request(versions: &[u32]) -> Vec<u8> {
+        let ver: Vec<u8> = versions.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+
+        let mut msg = RtMessage::new(2);
+        if !versions.is_empty() {
+            msg.add_field(Tag::VER, &ver).unwrap();
+        }
+        msg.add_field(Tag::NONC, &[0x42; 32]).unwrap();
+        let body = msg.encode().unwrap();
+
+        let mut packet = b"ROUGHTIM".to_vec();
+        packet.extend(&(body.len() as u32).to_le_bytes());
+        packet.extend(body);
+        packet
+    }
+
+    #[test]
+    fn requests_are_classified_by_protocol_version() {
+        let classic = make_request(&[0x42; 64]);
+        assert_eq!(request_version(&classic), Some(ProtocolVersion::Classic));
+
+        let draft = ietf_request(&[0x8000_0008, 0x8000_000c]);
+        assert_eq!(
+            request_version(&draft),
+            Some(ProtocolVersion::Ietf(Some(0x8000_000c)))
+        );
+        assert_eq!(
+            ProtocolVersion::Ietf(Some(0x8000_000c)).to_string(),
+            "ietf draft-12"
+        );
+
+        let unversioned = ietf_request(&[]);
+        assert_eq!(request_version(&unversioned), Some(ProtocolVersion::Ietf(None)));
+
+        assert_eq!(request_version(&draft[..20]), None);
+        assert_eq!(request_version(&[0xff; 1024]), None);
+
+        // a frame length running past the end of the packet
+        let mut overlong = draft.clone();
+        overlong[8..12].copy_from_slice(&u32::max_value().to_le_bytes());
+        assert_eq!(request_version(&overlong), None);
+
+        // a NONC offset past the end of the message
+        let mut truncated = make_request(&[0x42; 64]);
+        truncated[4..8].copy_from_slice(&2048u32.to_le_bytes());
+        assert_eq!(request_version(&truncated), None);
+
+        // VER decides, even without the IETF framing
+        let mut msg = RtMessage::new(2);
+        msg.add_field(Tag::VER, &0x8000_000cu32.to_le_bytes()).unwrap();
+        msg.add_field(Tag::NONC, &[0x42; 32]).unwrap();
+        assert_eq!(
+            request_version(&msg.encode().unwrap()),
+            Some(ProtocolVersion::Ietf(Some(0x8000_000c)))
+        );
+    }
+
+    #[test]
+    fn nonce_is_extracted_from_canonical_requests() {
+        let request = make_request(&[0x07; 64]);
+        assert_eq!(nonce_from_request(&request).unwrap(), &[0x07; 64][..]);
+
+        match nonce_from_request(&request[..1020]) {
+            Err(Error::RequestTooShort) => (),
+            e => panic!("unexpected result {:?}", e),
+        }
+
+        let mut reordered = request.clone();
+        reordered[8..16].copy_from_slice(b"PAD\xffNONC");
+        match nonce_from_request(&reordered) {
+            Err(Error::InvalidRequest) => (),
+            e => panic!("unexpected result {:?}", e),
+        }
+    }
+
+    #[test]
+    fn duplicate_nonces_share_a_leaf() {
+        let mut batch = Batch::with_capacity(4);
+        batch.push(&[0x01; 64], addr(1), ProtocolVersion::Classic);
+        batch.push(&[0x02; 64], addr(2), ProtocolVersion::Classic);
+        batch.push(&[0x01; 64], addr(3), ProtocolVersion::Classic);
+
+        assert_eq!(batch.len(), 3);
+        assert_eq!(batch.num_leaves(), 2);
+
+        let leaves: Vec<usize> = batch.entries().iter().map(|e| e.leaf).collect();
+        assert_eq!(leaves, vec![0, 1, 0]);
+
+        let root = batch.merkle_root();
+        for entry in batch.entries() {
+            let paths = batch.paths(entry.leaf);
+            assert_eq!(root_from_paths(entry.leaf, &entry.nonce, &paths), root);
+        }
+    }
+
+    #[test]
+    fn deferred_requests_are_admitted_or_shed() {
+        let mut batch = Batch::with_capacity(4);
+        batch.push(&[0x01; 64], addr(1), ProtocolVersion::Classic);
+        batch.defer(&[0x02; 64], addr(2), ProtocolVersion::Classic);
+        assert_eq!(batch.len(), 1);
+
+        batch.admit_deferred();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch.entries()[1].source, addr(2));
+
+        batch.defer(&[0x03; 64], addr(3), ProtocolVersion::Classic);
+        assert_eq!(batch.shed_deferred(), 1);
+        batch.admit_deferred();
+        assert_eq!(batch.len(), 2);
+
+        batch.clear();
+        assert!(batch.is_empty());
+        assert_eq!(batch.num_leaves(), 0);
+    }
+}
@@ -0,0 +1,226 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! CLI supporting key ceremonies where the long-term key signs the online key's delegation
+//! outside of the server (e.g. on an air-gapped machine or an HSM).
+//!
+//!   1. `request` generates an online key and writes the bytes to be signed by the
+//!      long-term key (the certificate context followed by the DELE message)
+//!   2. The signing request is signed with Ed25519 by the long-term key, externally
+//!   3. `assemble` combines the signing request and the signature into a CERT and
+//!      validates it against the long-term public key
+//!   4. `verify` re-validates an existing CERT
+//!
+
+#[macro_use]
+extern crate log;
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::process;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use roughenough::key::{assemble_cert, signing_request, verify_cert, OnlineKey};
+use roughenough::{roughenough_version, RtMessage, CERTIFICATE_CONTEXT, SIGNATURE_LENGTH};
+
+fn read_file(path: &str) -> Vec<u8> {
+    let mut contents = Vec::new();
+
+    match File::open(path).and_then(|mut f| f.read_to_end(&mut contents)) {
+        Ok(_) => contents,
+        Err(e) => {
+            error!("Failed to read '{}': {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+fn write_file(path: &str, contents: &[u8], secret: bool) {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        if secret {
+            options.mode(0o600);
+        }
+    }
+
+    if let Err(e) = options.open(path).and_then(|mut f| f.write_all(contents)) {
+        error!("Failed to write '{}': {}", path, e);
+        process::exit(1);
+    }
+}
+
+// Accept either raw bytes or hex-encoded text (e.g. the output of another tool)
+fn raw_or_hex(contents: Vec<u8>, expected_len: usize) -> Vec<u8> {
+    if contents.len() == expected_len {
+        return contents;
+    }
+
+    let text = String::from_utf8_lossy(&contents);
+    match hex::decode(text.trim()) {
+        Ok(decoded) => decoded,
+        Err(_) => contents,
+    }
+}
+
+fn public_key_arg(matches: &ArgMatches) -> Vec<u8> {
+    let pubkey = matches.value_of("PUBLIC_KEY").unwrap();
+
+    match hex::decode(pubkey) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("Invalid public key '{}': {}", pubkey, e);
+            process::exit(1);
+        }
+    }
+}
+
+fn request(matches: &ArgMatches) {
+    let request_file = matches.value_of("REQUEST").unwrap();
+    let online_key_file = matches.value_of("ONLINE_KEY").unwrap();
+
+    let mut seed = [0u8; 32];
+    SystemRandom::new().fill(&mut seed).unwrap();
+
+    let online_key = OnlineKey::from_seed(&seed);
+    let dele_bytes = online_key.make_dele().encode().unwrap();
+
+    write_file(online_key_file, hex::encode(&seed).as_bytes(), true);
+    write_file(request_file, &signing_request(&dele_bytes), false);
+
+    info!("Online public key : {}", hex::encode(online_key.public_key()));
+    info!("Online key seed   : written to '{}' (keep it secret)", online_key_file);
+    info!("Signing request   : written to '{}'", request_file);
+    info!("Sign the request file with the long-term key, then run 'assemble'");
+}
+
+fn assemble(matches: &ArgMatches) {
+    let request = read_file(matches.value_of("REQUEST").unwrap());
+    let signature = raw_or_hex(
+        read_file(matches.value_of("SIGNATURE").unwrap()),
+        SIGNATURE_LENGTH as usize,
+    );
+    let public_key = public_key_arg(matches);
+    let cert_file = matches.value_of("CERT").unwrap();
+
+    let context = CERTIFICATE_CONTEXT.as_bytes();
+    if !request.starts_with(context) {
+        error!("Signing request does not start with the certificate context");
+        process::exit(1);
+    }
+
+    let dele_bytes = &request[context.len()..];
+    let cert = assemble_cert(dele_bytes, &signature);
+
+    if let Err(e) = verify_cert(&cert, &public_key) {
+        error!("Assembled CERT is invalid: {:?}", e);
+        process::exit(1);
+    }
+
+    write_file(cert_file, &cert.encode().unwrap(), false);
+    info!("CERT verified and written to '{}'", cert_file);
+}
+
+fn verify(matches: &ArgMatches) {
+    let cert_bytes = read_file(matches.value_of("CERT").unwrap());
+    let public_key = public_key_arg(matches);
+
+    let result = RtMessage::from_bytes(&cert_bytes).and_then(|cert| verify_cert(&cert, &public_key));
+
+    match result {
+        Ok(_) => info!("CERT is valid for long-term key {}", hex::encode(&public_key)),
+        Err(e) => {
+            error!("CERT is invalid: {:?}", e);
+            process::exit(1);
+        }
+    }
+}
+
+pub fn main() {
+    use log::Level;
+
+    simple_logger::init_with_level(Level::Info).unwrap();
+
+    let request_arg = Arg::with_name("REQUEST")
+        .short("r")
+        .long("request")
+        .takes_value(true)
+        .required(true)
+        .help("Signing request file (certificate context + DELE)");
+
+    let public_key_arg = Arg::with_name("PUBLIC_KEY")
+        .short("p")
+        .long("public-key")
+        .takes_value(true)
+        .required(true)
+        .help("Hex encoded long-term public key");
+
+    let cert_arg = Arg::with_name("CERT")
+        .short("c")
+        .long("cert")
+        .takes_value(true)
+        .required(true)
+        .help("CERT message file");
+
+    let matches = App::new("roughenough-ceremony")
+        .version(roughenough_version().as_ref())
+        .long_about("Sign a Roughenough delegation (CERT) outside of the server")
+        .subcommand(
+            SubCommand::with_name("request")
+                .about("Generate an online key and the DELE signing request")
+                .arg(request_arg.clone())
+                .arg(
+                    Arg::with_name("ONLINE_KEY")
+                        .short("o")
+                        .long("online-key")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Output file for the (secret) hex seed of the online key"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("assemble")
+                .about("Combine a signing request and its signature into a validated CERT")
+                .arg(request_arg)
+                .arg(
+                    Arg::with_name("SIGNATURE")
+                        .short("s")
+                        .long("signature")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Ed25519 signature of the request (raw or hex)"),
+                ).arg(public_key_arg.clone())
+                .arg(cert_arg.clone()),
+        ).subcommand(
+            SubCommand::with_name("verify")
+                .about("Validate a CERT against the long-term public key")
+                .arg(public_key_arg)
+                .arg(cert_arg),
+        ).get_matches();
+
+    match matches.subcommand() {
+        ("request", Some(m)) => request(m),
+        ("assemble", Some(m)) => assemble(m),
+        ("verify", Some(m)) => verify(m),
+        _ => {
+            error!("{}", matches.usage());
+            process::exit(1);
+        }
+    }
+}
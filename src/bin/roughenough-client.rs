@@ -16,210 +16,746 @@
 #[macro_use]
 extern crate clap;
 
-use ring::rand;
-use ring::rand::SecureRandom;
-
-use byteorder::{LittleEndian, ReadBytesExt};
-
 use chrono::offset::Utc;
 use chrono::TimeZone;
 
-use std::collections::HashMap;
-use std::fs::File;
+use std::fmt;
+use std::fs::{self, File};
 use std::io::Write;
 use std::iter::Iterator;
-use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::process;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use clap::{App, Arg};
-use roughenough::merkle::root_from_paths;
-use roughenough::sign::Verifier;
-use roughenough::{
-    roughenough_version, RtMessage, Tag, CERTIFICATE_CONTEXT, SIGNED_RESPONSE_CONTEXT,
+use roughenough::chain::Chain;
+use roughenough::ecosystem::{parse_server_list, ServerListing};
+use roughenough::ntp::{self, NTP_PORT};
+use roughenough::sign::BatchVerifier;
+use roughenough::verify::{
+    create_nonce, error_response, make_request, make_request_with_size, Response,
 };
+use roughenough::{roughenough_version, Error, RtMessage, MIN_REQUEST_LENGTH};
 
-fn create_nonce() -> [u8; 64] {
-    let rng = rand::SystemRandom::new();
-    let mut nonce = [0u8; 64];
-    rng.fill(&mut nonce).unwrap();
+// Exit codes, distinguishing a server that refused the request from one that never answered
+const EXIT_INVALID: i32 = 1;
+const EXIT_NETWORK: i32 = 2;
+const EXIT_REJECTED: i32 = 3;
+// With --ntp, the NTP server's time falls outside the Roughtime interval
+const EXIT_NTP_MISMATCH: i32 = 4;
 
-    nonce
+/// Why a query did not produce a usable response
+#[derive(Debug)]
+enum QueryFailure {
+    /// The request could not be sent or no response arrived
+    Network(String),
+    /// The server answered with an error response giving this cause
+    Rejected(String),
+    /// The response was malformed or failed validation
+    Invalid(String),
 }
 
-fn make_request(nonce: &[u8]) -> Vec<u8> {
-    let mut msg = RtMessage::new(1);
-    msg.add_field(Tag::NONC, nonce).unwrap();
-    msg.pad_to_kilobyte();
+impl QueryFailure {
+    fn exit_code(&self) -> i32 {
+        match self {
+            QueryFailure::Network(_) => EXIT_NETWORK,
+            QueryFailure::Rejected(_) => EXIT_REJECTED,
+            QueryFailure::Invalid(_) => EXIT_INVALID,
+        }
+    }
+}
 
-    msg.encode().unwrap()
+impl fmt::Display for QueryFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryFailure::Network(msg) | QueryFailure::Invalid(msg) => write!(f, "{}", msg),
+            QueryFailure::Rejected(cause) => write!(f, "server rejected the request: {}", cause),
+        }
+    }
 }
 
-fn receive_response(sock: &mut UdpSocket) -> RtMessage {
-    let mut buf = [0; 744];
-    let resp_len = sock.recv_from(&mut buf).unwrap().0;
+/// Print why the query failed and exit with the matching exit code
+fn fail(failure: QueryFailure) -> ! {
+    eprintln!("{}", failure);
+    process::exit(failure.exit_code())
+}
 
-    RtMessage::from_bytes(&buf[0..resp_len]).unwrap()
+/// Decode a response, recognizing error responses from servers that refused the request
+fn decode_response(bytes: &[u8]) -> Result<RtMessage, QueryFailure> {
+    if let Some(cause) = error_response(bytes) {
+        return Err(QueryFailure::Rejected(cause));
+    }
+
+    RtMessage::from_bytes(bytes)
+        .map_err(|e| QueryFailure::Invalid(format!("malformed response: {:?}", e)))
 }
 
-fn stress_test_forever(addr: &SocketAddr) -> ! {
-    if !addr.ip().is_loopback() {
-        panic!("Cannot use non-loopback address {} for stress testing", addr.ip());
+fn receive_response(sock: &mut UdpSocket) -> Result<RtMessage, QueryFailure> {
+    let mut buf = [0; 8192];
+    sock.set_read_timeout(Some(QUERY_TIMEOUT)).unwrap();
+
+    let resp_len = sock
+        .recv_from(&mut buf)
+        .map_err(|e| QueryFailure::Network(format!("no response: {}", e)))?
+        .0;
+
+    decode_response(&buf[0..resp_len])
+}
+
+/// Relays forward the request body to the server named in this header and return
+/// the server's response as the response body
+#[cfg(feature = "relay")]
+const RELAY_SERVER_HEADER: &str = "Roughtime-Server";
+#[cfg(feature = "relay")]
+const RELAY_CONTENT_TYPE: &str = "application/octet-stream";
+#[cfg(feature = "relay")]
+const MAX_RESPONSE_LENGTH: u64 = 65_536;
+
+#[cfg(feature = "relay")]
+fn relay_request(
+    relay: &str,
+    host: &str,
+    port: u16,
+    request: &[u8],
+) -> Result<RtMessage, QueryFailure> {
+    use std::io::Read;
+
+    let resp = ureq::post(relay)
+        .set(RELAY_SERVER_HEADER, &format!("{}:{}", host, port))
+        .set("Content-Type", RELAY_CONTENT_TYPE)
+        .send_bytes(request)
+        .map_err(|e| QueryFailure::Network(format!("request to relay {} failed: {}", relay, e)))?;
+
+    let mut buf = Vec::new();
+    resp.into_reader()
+        .take(MAX_RESPONSE_LENGTH)
+        .read_to_end(&mut buf)
+        .map_err(|e| QueryFailure::Network(format!("cannot read relay response: {}", e)))?;
+
+    decode_response(&buf)
+}
+
+#[cfg(not(feature = "relay"))]
+fn relay_request(
+    _relay: &str,
+    _host: &str,
+    _port: u16,
+    _request: &[u8],
+) -> Result<RtMessage, QueryFailure> {
+    unreachable!("--relay is rejected at startup without the 'relay' feature");
+}
+
+/// Which address families a hostname may resolve to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Family {
+    Any,
+    V4,
+    V6,
+}
+
+/// Resolve `host` to every address of the requested `family`, in resolver order
+fn resolve(host: &str, port: u16, family: Family) -> Result<Vec<SocketAddr>, String> {
+    let addrs = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("cannot resolve {}: {}", host, e))?;
+
+    let addrs: Vec<SocketAddr> = addrs
+        .filter(|addr| match family {
+            Family::Any => true,
+            Family::V4 => addr.is_ipv4(),
+            Family::V6 => addr.is_ipv6(),
+        })
+        .collect();
+
+    if addrs.is_empty() {
+        let which = match family {
+            Family::Any => "",
+            Family::V4 => "IPv4 ",
+            Family::V6 => "IPv6 ",
+        };
+        return Err(format!("{} has no {}addresses", host, which));
     }
 
-    println!("Stress testing!");
+    Ok(addrs)
+}
+
+/// Resolve an NTP server given as `host`, `host:port` or `[ipv6]:port`, defaulting to port 123
+fn resolve_ntp_server(server: &str, family: Family) -> Result<SocketAddr, String> {
+    let parse_port = |port: &str| {
+        port.parse::<u16>()
+            .map_err(|_| format!("invalid NTP port in '{}'", server))
+    };
+
+    let (host, port) = if server.starts_with('[') {
+        match server.find("]:") {
+            Some(end) => (&server[1..end], parse_port(&server[end + 2..])?),
+            None => (server.trim_matches(|c| c == '[' || c == ']'), NTP_PORT),
+        }
+    } else if server.matches(':').count() == 1 {
+        let idx = server.find(':').unwrap();
+        (&server[..idx], parse_port(&server[idx + 1..])?)
+    } else {
+        // a hostname, IPv4 address or bare IPv6 address
+        (server, NTP_PORT)
+    };
+
+    resolve(host, port, family).map(|addrs| addrs[0])
+}
+
+///
+/// Parse the `--bind` source address: an IP address with an optional port, e.g. `192.0.2.1`,
+/// `192.0.2.1:5000`, `2001:db8::1` or `[2001:db8::1]:5000`. The port defaults to 0 (any).
+///
+fn parse_bind(bind: &str) -> Result<SocketAddr, String> {
+    if let Ok(addr) = bind.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
 
+    let ip = bind.trim_start_matches('[').trim_end_matches(']');
+    let invalid = format!("--bind '{}' is not an IP address with an optional port", bind);
+    ip.parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, 0))
+        .map_err(|_| invalid)
+}
+
+///
+/// A UDP socket for talking to `addr`, bound to `source` if given or else to any address
+/// of the same family as `addr`
+///
+fn open_socket(addr: &SocketAddr, source: Option<SocketAddr>) -> UdpSocket {
+    let bind_addr = match source {
+        Some(source) => source,
+        None if addr.is_ipv6() => "[::]:0".parse().unwrap(),
+        None => "0.0.0.0:0".parse().unwrap(),
+    };
+
+    UdpSocket::bind(bind_addr).unwrap_or_else(|e| {
+        eprintln!("Couldn't open UDP socket on {}: {}", bind_addr, e);
+        process::exit(1);
+    })
+}
+
+/// The midpoint of `response` in UTC microseconds (a TAI midpoint is corrected by its
+/// `DTAI` offset), or `None` if the offset is larger than the midpoint itself
+fn utc_midpoint(response: &Response) -> Option<u64> {
+    let dtai = u64::from(response.dtai().unwrap_or(0)) * 1_000_000;
+    response.midpoint().unwrap().checked_sub(dtai)
+}
+
+/// One query's response, with the round-trip time and the server's clock offset
+struct Measurement {
+    response: Response,
+    rtt: Duration,
+    /// Seconds the server's (UTC) midpoint is ahead of the local clock
+    offset: f64,
+}
+
+///
+/// Send a single request to `addr` and wait up to `timeout` for the response, which must
+/// verify against `pub_key` if one is given. The request is sent from `source` if given.
+///
+fn query_once(
+    addr: &SocketAddr,
+    source: Option<SocketAddr>,
+    pub_key: Option<&[u8]>,
+    request_size: usize,
+    timeout: Duration,
+) -> Result<Measurement, QueryFailure> {
     let nonce = create_nonce();
-    let socket = UdpSocket::bind("0.0.0.0:0").expect("Couldn't open UDP socket");
-    let request = make_request(&nonce);
-    loop {
-        socket.send_to(&request, addr).unwrap();
+    let request = make_request_with_size(&nonce, request_size);
+
+    let socket = open_socket(addr, source);
+    socket.set_read_timeout(Some(timeout)).unwrap();
+
+    let started = Instant::now();
+    let local_sent = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+    socket
+        .send_to(&request, addr)
+        .map_err(|e| QueryFailure::Network(format!("cannot send request to {}: {}", addr, e)))?;
+
+    let mut buf = [0u8; 8192];
+    let len = socket
+        .recv_from(&mut buf)
+        .map_err(|e| QueryFailure::Network(format!("no response from {}: {}", addr, e)))?
+        .0;
+    let rtt = started.elapsed();
+
+    let response = Response::new(decode_response(&buf[..len])?, &nonce)
+        .map_err(|e| QueryFailure::Invalid(format!("malformed response: {:?}", e)))?;
+    match pub_key {
+        Some(pub_key) => response.verify(pub_key),
+        None => response.verify_merkle(),
     }
+    .map_err(|e| QueryFailure::Invalid(format!("response failed validation: {:?}", e)))?;
+
+    let midpoint = utc_midpoint(&response).ok_or_else(|| {
+        QueryFailure::Invalid("response failed validation: DTAI exceeds MIDP".to_string())
+    })?;
+
+    // compare against the local clock half-way through the round trip
+    let local = local_sent + rtt / 2;
+    let local_micros = local.as_secs() * 1_000_000 + u64::from(local.subsec_micros());
+    let offset = (midpoint as f64 - local_micros as f64) / 1e6;
+
+    Ok(Measurement {
+        response,
+        rtt,
+        offset,
+    })
 }
 
-struct ResponseHandler {
-    pub_key: Option<Vec<u8>>,
-    msg: HashMap<Tag, Vec<u8>>,
-    srep: HashMap<Tag, Vec<u8>>,
-    cert: HashMap<Tag, Vec<u8>>,
-    dele: HashMap<Tag, Vec<u8>>,
-    nonce: [u8; 64],
+///
+/// Query the Roughtime server at `addr` and the NTP server at `ntp_addr` once each and
+/// report how far apart their clocks are. Exits with `EXIT_NTP_MISMATCH` if the NTP time
+/// falls outside the Roughtime interval, widened by both round trips' uncertainty.
+///
+fn ntp_cross_check(
+    addr: &SocketAddr,
+    source: Option<SocketAddr>,
+    pub_key: Option<&[u8]>,
+    ntp_addr: &SocketAddr,
+) -> ! {
+    let rt = query_once(
+        addr,
+        source,
+        pub_key,
+        MIN_REQUEST_LENGTH as usize,
+        QUERY_TIMEOUT,
+    )
+    .unwrap_or_else(|e| fail(e));
+    let ntp = ntp::query(ntp_addr, QUERY_TIMEOUT).unwrap_or_else(|e| {
+        fail(QueryFailure::Network(format!("NTP query to {} failed: {:?}", ntp_addr, e)))
+    });
+
+    let radius = f64::from(rt.response.radius().unwrap()) / 1e6;
+    let discrepancy = ntp.offset - rt.offset;
+    let tolerance = radius + as_secs_f64(rt.rtt) / 2.0 + ntp.delay / 2.0;
+
+    let from = source.map_or(String::new(), |s| format!(" (from {})", s));
+    println!(
+        "Roughtime {}{}: offset={:.6}s radius={:.6}s rtt={:.6}s verified={}",
+        addr,
+        from,
+        rt.offset,
+        radius,
+        as_secs_f64(rt.rtt),
+        if pub_key.is_some() { "Yes" } else { "No" }
+    );
+    println!(
+        "NTP       {}: offset={:.6}s delay={:.6}s stratum={}",
+        ntp_addr, ntp.offset, ntp.delay, ntp.stratum
+    );
+
+    if discrepancy.abs() > tolerance {
+        println!(
+            "NTP is {:+.6}s from Roughtime, OUTSIDE the Roughtime interval (+/- {:.6}s)",
+            discrepancy, tolerance
+        );
+        process::exit(EXIT_NTP_MISMATCH);
+    }
+
+    println!(
+        "NTP is {:+.6}s from Roughtime, within the Roughtime interval (+/- {:.6}s)",
+        discrepancy, tolerance
+    );
+    process::exit(0);
 }
 
-struct ParsedResponse {
-    verified: bool,
-    midpoint: u64,
-    radius: u32,
+fn as_secs_f64(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_micros()) / 1e6
 }
 
-impl ResponseHandler {
-    pub fn new(pub_key: Option<Vec<u8>>, response: RtMessage, nonce: [u8; 64]) -> ResponseHandler {
-        let msg = response.into_hash_map();
-        let srep = RtMessage::from_bytes(&msg[&Tag::SREP])
-            .unwrap()
-            .into_hash_map();
-        let cert = RtMessage::from_bytes(&msg[&Tag::CERT])
-            .unwrap()
-            .into_hash_map();
-        let dele = RtMessage::from_bytes(&cert[&Tag::DELE])
-            .unwrap()
-            .into_hash_map();
-
-        ResponseHandler {
-            pub_key,
-            msg,
-            srep,
-            cert,
-            dele,
-            nonce,
+///
+/// Query each of `addrs` once and print one line per address. Returns false if any
+/// address failed to answer with a valid response.
+///
+fn query_all(
+    addrs: &[SocketAddr],
+    source: Option<SocketAddr>,
+    pub_key: Option<&[u8]>,
+    request_size: usize,
+    timeout: Duration,
+) -> bool {
+    let mut all_ok = true;
+
+    for addr in addrs {
+        match query_once(addr, source, pub_key, request_size, timeout) {
+            Ok(m) => println!(
+                "{:<45} OK      offset={:.6}s radius={}us rtt={:.6}s verified={}",
+                addr.to_string(),
+                m.offset,
+                m.response.radius().unwrap(),
+                as_secs_f64(m.rtt),
+                if pub_key.is_some() { "Yes" } else { "No" }
+            ),
+            Err(e) => {
+                all_ok = false;
+                println!("{:<45} FAILED  {}", addr.to_string(), e);
+            }
         }
     }
 
-    pub fn extract_time(&self) -> ParsedResponse {
-        let midpoint = self.srep[&Tag::MIDP]
-            .as_slice()
-            .read_u64::<LittleEndian>()
-            .unwrap();
-        let radius = self.srep[&Tag::RADI]
-            .as_slice()
-            .read_u32::<LittleEndian>()
-            .unwrap();
-
-        let verified = if self.pub_key.is_some() {
-            self.validate_dele();
-            self.validate_srep();
-            self.validate_merkle();
-            self.validate_midpoint(midpoint);
-            true
-        } else {
-            false
-        };
+    all_ok
+}
 
-        ParsedResponse {
-            verified,
-            midpoint,
-            radius,
+///
+/// Query every server in `servers` once and print a table of the results, followed by
+/// the reason for each failure. Returns false if any server failed.
+///
+fn query_server_list(
+    servers: &[ServerListing],
+    family: Family,
+    source: Option<SocketAddr>,
+    request_size: usize,
+    timeout: Duration,
+) -> bool {
+    let mut failures = Vec::new();
+
+    println!(
+        "{:<30} {:<45} {:<7} {:>12} {:>12} {:>10}",
+        "NAME", "ADDRESS", "STATUS", "OFFSET", "RADIUS", "RTT"
+    );
+
+    for server in servers {
+        let resolved = resolve(&server.host, server.port, family).map_err(QueryFailure::Network);
+        let result = resolved.and_then(|addrs| {
+            query_once(&addrs[0], source, Some(&server.public_key), request_size, timeout)
+                .map(|m| (addrs[0], m))
+        });
+
+        match result {
+            Ok((addr, m)) => println!(
+                "{:<30} {:<45} {:<7} {:>11.6}s {:>10}us {:>9.6}s",
+                server.name,
+                addr.to_string(),
+                "OK",
+                m.offset,
+                m.response.radius().unwrap(),
+                as_secs_f64(m.rtt)
+            ),
+            Err(e) => {
+                let address = format!("{}:{}", server.host, server.port);
+                println!(
+                    "{:<30} {:<45} {:<7} {:>12} {:>12} {:>10}",
+                    server.name, address, "FAILED", "-", "-", "-"
+                );
+                failures.push((&server.name, e));
+            }
         }
     }
 
-    fn validate_dele(&self) {
-        let mut full_cert = Vec::from(CERTIFICATE_CONTEXT.as_bytes());
-        full_cert.extend(&self.cert[&Tag::DELE]);
+    if !failures.is_empty() {
+        println!();
+        for (name, e) in &failures {
+            println!("{}: {}", name, e);
+        }
+    }
 
-        assert!(
-            self.validate_sig(
-                self.pub_key.as_ref().unwrap(),
-                &self.cert[&Tag::SIG],
-                &full_cert
-            ),
-            "Invalid signature on DELE tag, response may not be authentic"
-        );
+    println!(
+        "{} of {} servers answered with valid responses",
+        servers.len() - failures.len(),
+        servers.len()
+    );
+
+    failures.is_empty()
+}
+
+// A verification failure as a sentence, without the error variant's name
+fn explain(error: &Error) -> String {
+    match error {
+        Error::InvalidResponse(msg) | Error::InvalidSignature(msg) => msg.clone(),
+        Error::TagNotFound(tag) => format!("required tag {:?} is missing", tag),
+        other => format!("{:?}", other),
     }
+}
 
-    fn validate_srep(&self) {
-        let mut full_srep = Vec::from(SIGNED_RESPONSE_CONTEXT.as_bytes());
-        full_srep.extend(&self.msg[&Tag::SREP]);
+///
+/// Describe the experimental post-quantum signature of the SREP, if the response has one,
+/// with the response size and, when `verify` is set, the time taken to check it.
+///
+#[cfg(feature = "pq")]
+fn check_pq_signature(response: &Response, verify: bool) -> Option<String> {
+    if !response.has_pq_signature() {
+        return None;
+    }
 
-        assert!(
-            self.validate_sig(&self.dele[&Tag::PUBK], &self.msg[&Tag::SIG], &full_srep),
-            "Invalid signature on SREP tag, response may not be authentic"
-        );
+    let size = response.message().encoded_size();
+    if !verify {
+        return Some(format!(
+            "Post-quantum SREP signature: not verified (response is {} bytes)",
+            size
+        ));
     }
 
-    fn validate_merkle(&self) {
-        let srep = RtMessage::from_bytes(&self.msg[&Tag::SREP])
-            .unwrap()
-            .into_hash_map();
-        let index = self.msg[&Tag::INDX]
-            .as_slice()
-            .read_u32::<LittleEndian>()
-            .unwrap();
-        let paths = &self.msg[&Tag::PATH];
+    let started = Instant::now();
+    if let Err(e) = response.verify_pq() {
+        fail(QueryFailure::Invalid(format!(
+            "Post-quantum signature failed validation: {}",
+            explain(&e)
+        )));
+    }
 
-        let hash = root_from_paths(index as usize, &self.nonce, paths);
+    Some(format!(
+        "Post-quantum SREP signature: verified in {}us (response is {} bytes)",
+        started.elapsed().as_micros(),
+        size
+    ))
+}
 
-        assert_eq!(
-            hash, srep[&Tag::ROOT],
-            "Nonce is not present in the response's merkle tree"
-        );
+#[cfg(not(feature = "pq"))]
+fn check_pq_signature(response: &Response, _verify: bool) -> Option<String> {
+    if !response.has_pq_signature() {
+        return None;
     }
 
-    fn validate_midpoint(&self, midpoint: u64) {
-        let mint = self.dele[&Tag::MINT]
-            .as_slice()
-            .read_u64::<LittleEndian>()
-            .unwrap();
-        let maxt = self.dele[&Tag::MAXT]
-            .as_slice()
-            .read_u64::<LittleEndian>()
-            .unwrap();
-
-        assert!(
-            midpoint >= mint,
-            "Response midpoint {} lies *before* delegation span ({}, {})",
-            midpoint, mint, maxt
-        );
-        assert!(
-            midpoint <= maxt,
-            "Response midpoint {} lies *after* delegation span ({}, {})",
-            midpoint, mint, maxt
-        );
+    Some(format!(
+        "Post-quantum SREP signature: not verified, support was not compiled in \
+         (response is {} bytes)",
+        response.message().encoded_size()
+    ))
+}
+
+/// How received times are printed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Text,
+    /// One JSON object per response, each on its own line
+    Json,
+    /// A header row, then one row per response
+    Csv,
+}
+
+/// Columns of the CSV output, in the order of its header row
+const CSV_COLUMNS: [&str; 8] = [
+    "midpoint",
+    "radius",
+    "verified",
+    "merkle_index",
+    "dtai",
+    "instance_id",
+    "server",
+    "local_address",
+];
+
+/// A received time, as printed in the JSON and CSV output formats
+struct Record {
+    midpoint: String,
+    radius: u32,
+    verified: bool,
+    index: u32,
+    dtai: Option<u32>,
+    instance_id: Option<String>,
+    server: String,
+    /// The local address the request was sent from; `None` when sent through a relay
+    local_addr: Option<SocketAddr>,
+}
+
+impl Record {
+    fn to_json(&self) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+
+        format!(
+            "{{\"midpoint\":{},\"radius\":{},\"verified\":{},\"merkle_index\":{},\"dtai\":{},\
+             \"instance_id\":{},\"server\":{},\"local_address\":{}}}",
+            json_string(&self.midpoint),
+            self.radius,
+            self.verified,
+            self.index,
+            optional(self.dtai.map(|dtai| dtai.to_string())),
+            optional(self.instance_id.as_ref().map(|id| json_string(id))),
+            json_string(&self.server),
+            optional(self.local_addr.map(|addr| json_string(&addr.to_string()))),
+        )
+    }
+
+    fn to_csv(&self) -> String {
+        let fields = [
+            csv_field(&self.midpoint),
+            self.radius.to_string(),
+            self.verified.to_string(),
+            self.index.to_string(),
+            self.dtai.map_or(String::new(), |dtai| dtai.to_string()),
+            self.instance_id.as_ref().map_or(String::new(), |id| csv_field(id)),
+            csv_field(&self.server),
+            self.local_addr.map_or(String::new(), |addr| addr.to_string()),
+        ];
+
+        fields.join(",")
+    }
+}
+
+/// `value` as a quoted JSON string
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+/// `value` as a CSV field, quoted if it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
     }
+}
+
+///
+/// Run each verification step on every response and print its result. Returns a
+/// description of the first failure, if any.
+///
+fn verify_strict(
+    responses: &[Response],
+    pub_key: &[u8],
+    max_radius: Option<u32>,
+) -> Option<String> {
+    let mut first_failure = None;
 
-    fn validate_sig(&self, public_key: &[u8], sig: &[u8], data: &[u8]) -> bool {
-        let mut verifier = Verifier::new(public_key);
-        verifier.update(data);
-        verifier.verify(sig)
+    for (idx, response) in responses.iter().enumerate() {
+        println!("Verifying response #{}:", idx);
+
+        for step in response.verify_strict(pub_key, max_radius) {
+            match step.result {
+                Ok(_) => println!("  {:<22} PASS", step.name),
+                Err(ref e) => {
+                    println!("  {:<22} FAIL", step.name);
+                    if first_failure.is_none() {
+                        first_failure =
+                            Some(format!("response #{}, {}: {}", idx, step.name, explain(e)));
+                    }
+                }
+            }
+        }
     }
+
+    first_failure
+}
+
+fn stress_test_forever(addr: &SocketAddr, source: Option<SocketAddr>) -> ! {
+    if !addr.ip().is_loopback() {
+        panic!("Cannot use non-loopback address {} for stress testing", addr.ip());
+    }
+
+    println!("Stress testing!");
+
+    let nonce = create_nonce();
+    let socket = open_socket(addr, source);
+    let request = make_request(&nonce);
+    loop {
+        socket.send_to(&request, addr).unwrap();
+    }
+}
+
+// Exit codes of Nagios (and Icinga, Sensu, ...) check plugins
+const NAGIOS_OK: i32 = 0;
+const NAGIOS_WARNING: i32 = 1;
+const NAGIOS_CRITICAL: i32 = 2;
+const NAGIOS_UNKNOWN: i32 = 3;
+
+/// How long a query waits for each response
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Print a Nagios plugin status line (with optional performance data) and exit with the
+/// matching exit code
+fn nagios_exit(code: i32, message: &str, perfdata: Option<String>) -> ! {
+    let status = match code {
+        NAGIOS_OK => "OK",
+        NAGIOS_WARNING => "WARNING",
+        NAGIOS_CRITICAL => "CRITICAL",
+        _ => "UNKNOWN",
+    };
+
+    match perfdata {
+        Some(perfdata) => println!("ROUGHTIME {} - {} | {}", status, message, perfdata),
+        None => println!("ROUGHTIME {} - {}", status, message),
+    }
+
+    process::exit(code)
+}
+
+///
+/// Query the server once and report its clock offset from the local clock as a Nagios
+/// check. The response must verify against `pub_key`. Offsets larger than `warning` or
+/// `critical` seconds (in either direction) are reported as WARNING or CRITICAL, as is a
+/// radius larger than `max_radius` microseconds.
+///
+fn nagios_check(
+    addr: &SocketAddr,
+    source: Option<SocketAddr>,
+    pub_key: &[u8],
+    warning: f64,
+    critical: f64,
+    max_radius: Option<u32>,
+) -> ! {
+    let Measurement {
+        response,
+        rtt,
+        offset,
+    } = match query_once(
+        addr,
+        source,
+        Some(pub_key),
+        MIN_REQUEST_LENGTH as usize,
+        QUERY_TIMEOUT,
+    ) {
+        Ok(measurement) => measurement,
+        Err(e) => nagios_exit(NAGIOS_CRITICAL, &e.to_string(), None),
+    };
+
+    let radius = f64::from(response.radius().unwrap()) / 1e6;
+    let rtt = as_secs_f64(rtt);
+
+    let perfdata = format!(
+        "offset={:.6}s;{w}:{};{c}:{};; rtt={:.6}s;;;0; radius={:.6}s;;;0;",
+        offset,
+        warning,
+        critical,
+        rtt,
+        radius,
+        w = -warning,
+        c = -critical
+    );
+    let message = format!("offset {:.6}s, radius {:.6}s, rtt {:.6}s", offset, radius, rtt);
+
+    if let Some(max_radius) = max_radius {
+        if response.radius().unwrap() > max_radius {
+            let message = format!("{}; radius exceeds maximum of {}us", message, max_radius);
+            nagios_exit(NAGIOS_CRITICAL, &message, Some(perfdata));
+        }
+    }
+
+    let code = if offset.abs() > critical {
+        NAGIOS_CRITICAL
+    } else if offset.abs() > warning {
+        NAGIOS_WARNING
+    } else {
+        NAGIOS_OK
+    };
+
+    nagios_exit(code, &message, Some(perfdata))
 }
 
 fn main() {
     let matches = App::new("roughenough client")
     .version(roughenough_version().as_ref())
     .arg(Arg::with_name("host")
-      .required(true)
+      .required_unless("servers")
       .help("The Roughtime server to connect to")
       .takes_value(true))
     .arg(Arg::with_name("port")
-      .required(true)
+      .required_unless("servers")
       .help("The Roughtime server port to connect to")
       .takes_value(true))
     .arg(Arg::with_name("public-key")
@@ -252,10 +788,94 @@ fn main() {
       .takes_value(true)
       .help("Writes all requsts to the specified file, in addition to sending them to the server. Useful for generating fuzer inputs")
     )
+    .arg(Arg::with_name("relay")
+      .short("r")
+      .long("relay")
+      .takes_value(true)
+      .help("Send requests as HTTPS POSTs to this relay URL instead of directly over UDP. Responses are still verified end-to-end. Requires the 'relay' feature")
+    )
+    .arg(Arg::with_name("max-radius")
+      .long("max-radius")
+      .takes_value(true)
+      .help("Reject (exit non-zero) responses whose radius, the server's uncertainty, exceeds this many microseconds")
+    )
+    .arg(Arg::with_name("nagios")
+      .long("nagios")
+      .requires("public-key")
+      .help("Run as a Nagios/Icinga check plugin: query once, print an OK/WARNING/CRITICAL status line with perfdata (offset, rtt, radius) and exit with the matching code")
+    )
+    .arg(Arg::with_name("warning")
+      .long("warning")
+      .takes_value(true)
+      .help("With --nagios, the clock offset (in seconds) above which the check is WARNING")
+      .default_value("1")
+    )
+    .arg(Arg::with_name("critical")
+      .long("critical")
+      .takes_value(true)
+      .help("With --nagios, the clock offset (in seconds) above which the check is CRITICAL")
+      .default_value("5")
+    )
+    .arg(Arg::with_name("request-size")
+      .long("request-size")
+      .takes_value(true)
+      .help("Total size in bytes of each request, adjusted by padding. Must be a multiple of 4. Requests under 1024 bytes are ignored by conforming servers")
+      .default_value("1024")
+    )
+    .arg(Arg::with_name("chain")
+      .long("chain")
+      .takes_value(true)
+      .requires("public-key")
+      .help("Chain the requests, each nonce derived from the previous reply, and write the chain to this file in the Go reference client's JSON format")
+    )
+    .arg(Arg::with_name("strict")
+      .long("strict")
+      .requires("public-key")
+      .help("Report every verification step (CERT signature, DELE window, SREP signature, Merkle path, nonce, radius) individually and explain the first failure")
+    )
+    .arg(Arg::with_name("ipv4")
+      .short("4")
+      .long("ipv4")
+      .conflicts_with("ipv6")
+      .help("Only use IPv4 addresses of the server")
+    )
+    .arg(Arg::with_name("ipv6")
+      .short("6")
+      .long("ipv6")
+      .help("Only use IPv6 addresses of the server")
+    )
+    .arg(Arg::with_name("all-addresses")
+      .long("all-addresses")
+      .conflicts_with_all(&["relay", "nagios", "stress", "chain"])
+      .help("Query every address the server's hostname resolves to (limited by -4/-6) once and report each result. Exits non-zero if any address fails")
+    )
+    .arg(Arg::with_name("ntp")
+      .long("ntp")
+      .takes_value(true)
+      .conflicts_with_all(&["relay", "nagios", "stress", "chain", "all-addresses"])
+      .help("Also query this NTP server (host or host:port) once and report how far its time is from the Roughtime server's. Exits 4 if the NTP time is outside the Roughtime interval")
+    )
+    .arg(Arg::with_name("bind")
+      .long("bind")
+      .takes_value(true)
+      .conflicts_with("relay")
+      .help("Send requests from this local address, with an optional port (addr[:port] or [ipv6]:port), e.g. to measure from one interface of a multi-homed probe. Only server addresses of the same family are used")
+    )
+    .arg(Arg::with_name("servers")
+      .long("servers")
+      .takes_value(true)
+      .conflicts_with_all(&["host", "port", "public-key", "relay", "nagios", "stress", "chain", "all-addresses", "ntp"])
+      .help("Query each server in this file once and print a table of the results. The file is either ecosystem.json style JSON or 'name address port pubkey' lines. Exits non-zero if any server fails")
+    )
+    .arg(Arg::with_name("format")
+      .long("format")
+      .takes_value(true)
+      .possible_values(&["text", "json", "csv"])
+      .conflicts_with_all(&["nagios", "stress", "strict", "all-addresses", "ntp", "servers"])
+      .help("Print received times as 'text' (the default), 'json' (an object per line) or 'csv' (with a header row). JSON and CSV include the local address each request was sent from")
+    )
     .get_matches();
 
-    let host = matches.value_of("host").unwrap();
-    let port = value_t_or_exit!(matches.value_of("port"), u16);
     let num_requests = value_t_or_exit!(matches.value_of("num-requests"), u16) as usize;
     let time_format = matches.value_of("time-format").unwrap();
     let stress = matches.is_present("stress");
@@ -263,59 +883,387 @@ fn main() {
         .value_of("public-key")
         .map(|pkey| hex::decode(pkey).expect("Error parsing public key!"));
     let out = matches.value_of("output");
+    let format = match matches.value_of("format") {
+        Some("json") => OutputFormat::Json,
+        Some("csv") => OutputFormat::Csv,
+        _ => OutputFormat::Text,
+    };
+    let chain_out = matches.value_of("chain");
+    let request_size = value_t_or_exit!(matches.value_of("request-size"), usize);
+
+    // NONC tag, offset and value plus the PAD tag
+    let min_size = 4 + 4 + 8 + 64;
+    if request_size % 4 != 0 || request_size < min_size {
+        eprintln!(
+            "--request-size must be a multiple of 4 and at least {} bytes",
+            min_size
+        );
+        process::exit(1);
+    }
+    if request_size < MIN_REQUEST_LENGTH as usize {
+        eprintln!(
+            "Warning: requests of {} bytes are below the {} byte amplification-safe minimum \
+             and will be ignored by conforming servers",
+            request_size, MIN_REQUEST_LENGTH
+        );
+    }
+
+    let relay = matches.value_of("relay");
+    if let Some(relay) = relay {
+        if !cfg!(feature = "relay") {
+            eprintln!("HTTPS relay support was not compiled in; rebuild with '--features relay'");
+            process::exit(EXIT_INVALID);
+        }
+        if !relay.starts_with("https://") {
+            eprintln!("Relay URL must use https: {}", relay);
+            process::exit(EXIT_INVALID);
+        }
+    }
+
+    let source = matches.value_of("bind").map(|bind| {
+        parse_bind(bind).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        })
+    });
+    let family = match source {
+        Some(source) if matches.is_present("ipv4") && source.is_ipv6() => {
+            eprintln!("--bind {} is an IPv6 address but -4 was given", source);
+            process::exit(1);
+        }
+        Some(source) if matches.is_present("ipv6") && source.is_ipv4() => {
+            eprintln!("--bind {} is an IPv4 address but -6 was given", source);
+            process::exit(1);
+        }
+        // only a server address of the source's family can be reached from it
+        Some(source) if source.is_ipv4() => Family::V4,
+        Some(_) => Family::V6,
+        None if matches.is_present("ipv4") => Family::V4,
+        None if matches.is_present("ipv6") => Family::V6,
+        None => Family::Any,
+    };
+    if source.map_or(false, |s| s.port() != 0) && num_requests > 1 && chain_out.is_none() {
+        eprintln!("--bind with a fixed port can only send one request at a time; use port 0");
+        process::exit(1);
+    }
+    // appended to the "Requesting time from" lines
+    let from = source.map_or(String::new(), |s| format!(" from {}", s));
+    let max_radius = if matches.is_present("max-radius") {
+        Some(value_t_or_exit!(matches.value_of("max-radius"), u32))
+    } else {
+        None
+    };
+
+    if let Some(path) = matches.value_of("servers") {
+        let servers = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path, e))
+            .and_then(|contents| parse_server_list(&contents).map_err(|e| format!("{:?}", e)))
+            .unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                process::exit(1);
+            });
+
+        if let Some(source) = source {
+            println!("Querying from {}", source);
+        }
+        if !query_server_list(&servers, family, source, request_size, QUERY_TIMEOUT) {
+            process::exit(1);
+        }
+        return;
+    }
 
-    println!("Requesting time from: {:?}:{:?}", host, port);
+    let host = matches.value_of("host").unwrap();
+    let port = value_t_or_exit!(matches.value_of("port"), u16);
 
-    let addr = (host, port).to_socket_addrs().unwrap().next().unwrap();
+    if matches.is_present("nagios") {
+        let warning = value_t_or_exit!(matches.value_of("warning"), f64);
+        let critical = value_t_or_exit!(matches.value_of("critical"), f64);
+
+        if relay.is_some() {
+            nagios_exit(NAGIOS_UNKNOWN, "--nagios cannot be combined with --relay", None);
+        }
+
+        let addr = match resolve(host, port, family) {
+            Ok(addrs) => addrs[0],
+            Err(e) => nagios_exit(NAGIOS_UNKNOWN, &e, None),
+        };
+
+        nagios_check(&addr, source, pub_key.as_ref().unwrap(), warning, critical, max_radius)
+    }
+
+    // the relay resolves the server itself
+    let addrs = match relay {
+        Some(_) => Vec::new(),
+        None => resolve(host, port, family).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        }),
+    };
+
+    if let Some(ntp_server) = matches.value_of("ntp") {
+        let ntp_addr = resolve_ntp_server(ntp_server, family).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        });
+
+        ntp_cross_check(&addrs[0], source, pub_key.as_ref().map(Vec::as_slice), &ntp_addr)
+    }
+
+    if matches.is_present("all-addresses") {
+        println!(
+            "Requesting time from all {} addresses of {}:{}{}",
+            addrs.len(),
+            host,
+            port,
+            from
+        );
+        let pub_key = pub_key.as_ref().map(Vec::as_slice);
+        if !query_all(&addrs, source, pub_key, request_size, QUERY_TIMEOUT) {
+            process::exit(1);
+        }
+        return;
+    }
+
+    match relay {
+        _ if format != OutputFormat::Text => (),
+        Some(relay) => println!("Requesting time from: {:?}:{:?} via {}", host, port, relay),
+        None => println!("Requesting time from: {:?}:{:?}{}", host, port, from),
+    }
 
     if stress {
-        stress_test_forever(&addr)
+        stress_test_forever(&addrs[0], source)
     }
 
     let mut requests = Vec::with_capacity(num_requests);
+    // the address each request was sent from, unless it went through a relay
+    let mut local_addrs: Vec<Option<SocketAddr>> = Vec::with_capacity(num_requests);
     let mut file = out.map(|o| File::create(o).expect("Failed to create file!"));
+    let mut chain = Chain::new();
+
+    // a chained request can't be made until the previous reply is in, so they're sent serially
+    let chained_responses: Option<Vec<RtMessage>> = chain_out.map(|_| {
+        let pub_key = pub_key.as_ref().unwrap();
+        let mut responses = Vec::with_capacity(num_requests);
 
-    for _ in 0..num_requests {
-        let nonce = create_nonce();
-        let socket = UdpSocket::bind("0.0.0.0:0").expect("Couldn't open UDP socket");
-        let request = make_request(&nonce);
+        for _ in 0..num_requests {
+            let blind = create_nonce();
+            let nonce = chain.next_nonce(&blind);
+            let request = make_request_with_size(&nonce, request_size);
 
-        if let Some(f) = file.as_mut() {
-            f.write_all(&request).expect("Failed to write to file!")
+            if let Some(f) = file.as_mut() {
+                f.write_all(&request).expect("Failed to write to file!")
+            }
+
+            let response = match relay {
+                Some(relay) => relay_request(relay, host, port, &request),
+                None => {
+                    let mut socket = open_socket(&addrs[0], source);
+                    socket.send_to(&request, addrs[0]).unwrap();
+                    local_addrs.push(socket.local_addr().ok());
+                    receive_response(&mut socket)
+                }
+            }
+            .unwrap_or_else(|e| fail(e));
+
+            chain.push(pub_key, &blind, &response.encode().unwrap());
+            requests.push((nonce, request));
+            responses.push(response);
         }
 
-        requests.push((nonce, request, socket));
+        responses
+    });
+
+    if chained_responses.is_none() {
+        for _ in 0..num_requests {
+            let nonce = create_nonce().to_vec();
+            let request = make_request_with_size(&nonce, request_size);
+
+            if let Some(f) = file.as_mut() {
+                f.write_all(&request).expect("Failed to write to file!")
+            }
+
+            requests.push((nonce, request));
+        }
     }
 
-    for &mut (_, ref request, ref mut socket) in &mut requests {
-        socket.send_to(request, addr).unwrap();
+    let raw_responses: Result<Vec<RtMessage>, QueryFailure> = match (chained_responses, relay) {
+        (Some(responses), _) => Ok(responses),
+        (None, Some(relay)) => requests
+            .iter()
+            .map(|&(_, ref request)| relay_request(relay, host, port, request))
+            .collect(),
+        (None, None) => {
+            let addr = addrs[0];
+            let mut sockets = Vec::with_capacity(num_requests);
+
+            for &(_, ref request) in &requests {
+                let socket = open_socket(&addr, source);
+                socket.send_to(request, addr).unwrap();
+                local_addrs.push(socket.local_addr().ok());
+                sockets.push(socket);
+            }
+
+            sockets.iter_mut().map(receive_response).collect()
+        }
+    };
+
+    let raw_responses = raw_responses.unwrap_or_else(|e| fail(e));
+    let mut responses = Vec::with_capacity(num_requests);
+
+    for (&(ref nonce, _), resp) in requests.iter().zip(raw_responses) {
+        match Response::new(resp, nonce) {
+            Ok(response) => responses.push(response),
+            Err(e) => fail(QueryFailure::Invalid(format!("malformed response: {:?}", e))),
+        }
     }
 
-    for (nonce, _, mut socket) in requests {
-        let resp = receive_response(&mut socket);
+    if matches.is_present("strict") {
+        if let Some(failure) = verify_strict(&responses, pub_key.as_ref().unwrap(), max_radius) {
+            eprintln!("Verification failed at {}", failure);
+            process::exit(1);
+        }
+    } else if let Some(ref pub_key) = pub_key {
+        let mut batch = BatchVerifier::new();
+        for response in &responses {
+            if let Err(e) = response.add_signatures(pub_key, &mut batch) {
+                fail(QueryFailure::Invalid(format!("malformed response: {:?}", e)));
+            }
+        }
+
+        // two signatures per response: DELE then SREP
+        if let Err(idx) = batch.verify() {
+            let tag = if idx % 2 == 0 { "DELE" } else { "SREP" };
+            fail(QueryFailure::Invalid(format!(
+                "Invalid signature on {} tag of response #{}, response may not be authentic",
+                tag,
+                idx / 2
+            )));
+        }
+    }
+
+    if format == OutputFormat::Csv {
+        println!("{}", CSV_COLUMNS.join(","));
+    }
 
-        let ParsedResponse {
-            verified,
-            midpoint,
-            radius,
-        } = ResponseHandler::new(pub_key.clone(), resp.clone(), nonce).extract_time();
+    for (idx, response) in responses.into_iter().enumerate() {
+        let verified = if pub_key.is_some() {
+            if let Err(e) = response.verify_merkle().and_then(|_| response.verify_midpoint()) {
+                fail(QueryFailure::Invalid(format!("Response failed validation: {:?}", e)));
+            }
+            true
+        } else {
+            false
+        };
 
-        let map = resp.into_hash_map();
-        let index = map[&Tag::INDX]
-            .as_slice()
-            .read_u32::<LittleEndian>()
-            .unwrap();
+        let dtai = response.dtai();
+        // a TAI midpoint is displayed as UTC
+        let midpoint = utc_midpoint(&response).unwrap_or_else(|| {
+            fail(QueryFailure::Invalid("Response failed validation: DTAI exceeds MIDP".to_string()))
+        });
+        let radius = response.radius().unwrap();
+        let index = response.index().unwrap();
 
         let seconds = midpoint / 10_u64.pow(6);
         let nsecs = (midpoint - (seconds * 10_u64.pow(6))) * 10_u64.pow(3);
         let spec = Utc.timestamp(seconds as i64, nsecs as u32);
         let out = spec.format(time_format).to_string();
         let verify_str = if verified { "Yes" } else { "No" };
+        let pq_note = check_pq_signature(&response, verified);
 
-        println!(
-            "Received time from server: midpoint={:?}, radius={:?}, verified={} (merkle_index={})",
-            out, radius, verify_str, index
-        );
+        if format == OutputFormat::Text {
+            println!(
+                "Received time from server: midpoint={:?}, radius={:?}, verified={} (merkle_index={})",
+                out, radius, verify_str, index
+            );
+            if let Some(dtai) = dtai {
+                println!("Server clock is TAI: TAI-UTC offset={}s", dtai);
+            }
+            if let Some(instance_id) = response.instance_id() {
+                println!("Answered by server instance: {}", instance_id);
+            }
+            if let Some(note) = pq_note {
+                println!("{}", note);
+            }
+        } else {
+            // keep stdout to records only
+            if let Some(note) = pq_note {
+                eprintln!("{}", note);
+            }
+
+            let record = Record {
+                midpoint: out,
+                radius,
+                verified,
+                index,
+                dtai,
+                instance_id: response.instance_id(),
+                server: format!("{}:{}", host, port),
+                local_addr: local_addrs.get(idx).and_then(|&addr| addr),
+            };
+            match format {
+                OutputFormat::Json => println!("{}", record.to_json()),
+                _ => println!("{}", record.to_csv()),
+            }
+        }
+
+        if let Some(max_radius) = max_radius {
+            if radius > max_radius {
+                eprintln!(
+                    "Rejecting response: radius {}us exceeds the maximum acceptable uncertainty of {}us",
+                    radius, max_radius
+                );
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(path) = chain_out {
+        let mut f = File::create(path).expect("Failed to create chain file!");
+        f.write_all(chain.to_json().as_bytes())
+            .expect("Failed to write chain file!");
+        let wrote = format!("Wrote chain of {} queries to {}", chain.links().len(), path);
+        match format {
+            OutputFormat::Text => println!("{}", wrote),
+            _ => eprintln!("{}", wrote),
+        }
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::{Record, CSV_COLUMNS};
+
+    fn record(instance_id: Option<&str>) -> Record {
+        Record {
+            midpoint: "Oct 15 2026 13:58:01".to_string(),
+            radius: 1_000_000,
+            verified: true,
+            index: 3,
+            dtai: None,
+            instance_id: instance_id.map(str::to_string),
+            server: "localhost:2002".to_string(),
+            local_addr: Some("192.0.2.77:40917".parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn json_records_are_escaped() {
+        let json = record(Some("fra \"2\"\n")).to_json();
+        assert!(json.contains(r#""instance_id":"fra \"2\"\u000a""#));
+        assert!(json.contains(r#""dtai":null"#));
+        assert!(json.contains(r#""local_address":"192.0.2.77:40917""#));
+
+        let json = record(None).to_json();
+        assert!(json.contains(r#""instance_id":null"#));
+    }
+
+    #[test]
+    fn csv_records_match_the_header() {
+        let csv = record(Some("a,\"b\"")).to_csv();
+        assert_eq!(
+            csv,
+            r#"Oct 15 2026 13:58:01,1000000,true,3,,"a,""b""",localhost:2002,192.0.2.77:40917"#
+        );
+        assert_eq!(record(None).to_csv().split(',').count(), CSV_COLUMNS.len());
+    }
+}
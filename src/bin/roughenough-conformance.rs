@@ -0,0 +1,337 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Protocol conformance checks against any Roughtime server.
+//!
+//! Each check sends one or more crafted requests and compares what comes back (or
+//! doesn't) with what the protocol requires, then prints a pass/fail matrix:
+//!
+//! ```text
+//! $ roughenough-conformance roughtime.int08h.com 2002 -p 016e6e...9bec1
+//! CHECK                          EXPECTED     RESULT
+//! valid request                  response     PASS
+//! oversized PAD                  response     PASS
+//! ...
+//! ```
+//!
+
+// for value_t_or_exit!()
+#[macro_use]
+extern crate clap;
+
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::process;
+use std::time::Duration;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use clap::{App, Arg};
+
+use roughenough::verify::{create_nonce, make_request, Response};
+use roughenough::{roughenough_version, MIN_REQUEST_LENGTH};
+
+/// What a check requires of the server
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Expect {
+    /// A response that parses (and verifies, if a public key was given)
+    Response,
+    /// No response at all
+    Silence,
+}
+
+struct Outcome {
+    name: &'static str,
+    expect: Expect,
+    passed: bool,
+    detail: String,
+}
+
+struct Checker {
+    addr: SocketAddr,
+    pub_key: Option<Vec<u8>>,
+    timeout: Duration,
+}
+
+impl Checker {
+    // Send `request` and return the response, if any arrives before the timeout
+    fn exchange(&self, request: &[u8]) -> Option<Vec<u8>> {
+        let bind_addr = if self.addr.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+        let socket = UdpSocket::bind(bind_addr).expect("Couldn't open UDP socket");
+        socket.set_read_timeout(Some(self.timeout)).unwrap();
+        socket.send_to(request, self.addr).expect("send_to failed");
+
+        let mut buf = [0u8; 4096];
+        socket.recv_from(&mut buf).ok().map(|(len, _)| buf[..len].to_vec())
+    }
+
+    // Parse (and verify, if possible) `bytes` as the response to a request with `nonce`
+    fn validate(&self, bytes: &[u8], nonce: &[u8]) -> Result<Response, String> {
+        let response = Response::from_bytes(bytes, nonce).map_err(|e| format!("{:?}", e))?;
+
+        match self.pub_key {
+            Some(ref pub_key) => response.verify(pub_key).map_err(|e| format!("{:?}", e))?,
+            None => response.verify_merkle().map_err(|e| format!("{:?}", e))?,
+        }
+
+        Ok(response)
+    }
+
+    // Send `request` for `nonce` and judge the result against `expect`
+    fn check(&self, name: &'static str, expect: Expect, request: &[u8], nonce: &[u8]) -> Outcome {
+        let (passed, detail) = match (expect, self.exchange(request)) {
+            (Expect::Response, Some(bytes)) => match self.validate(&bytes, nonce) {
+                Ok(_) => (true, String::new()),
+                Err(e) => (false, format!("invalid response: {}", e)),
+            },
+            (Expect::Response, None) => (false, "no response".to_string()),
+            (Expect::Silence, None) => (true, String::new()),
+            (Expect::Silence, Some(bytes)) => {
+                (false, format!("server answered with {} bytes", bytes.len()))
+            }
+        };
+
+        Outcome {
+            name,
+            expect,
+            passed,
+            detail,
+        }
+    }
+
+    // Send the same request twice; both responses must be valid and time must not go backwards
+    fn check_nonce_reuse(&self) -> Outcome {
+        let nonce = create_nonce();
+        let request = make_request(&nonce);
+
+        let mut midpoints = Vec::new();
+        let mut failure = None;
+
+        for attempt in 1..=2 {
+            match self.exchange(&request) {
+                Some(bytes) => match self.validate(&bytes, &nonce) {
+                    Ok(response) => midpoints.push(response.midpoint().unwrap_or(0)),
+                    Err(e) => failure = Some(format!("response {} invalid: {}", attempt, e)),
+                },
+                None => failure = Some(format!("no response to request {}", attempt)),
+            }
+        }
+
+        if failure.is_none() && midpoints.len() == 2 && midpoints[1] < midpoints[0] {
+            failure = Some(format!(
+                "midpoint went backwards: {} then {}",
+                midpoints[0], midpoints[1]
+            ));
+        }
+
+        Outcome {
+            name: "reused nonce",
+            expect: Expect::Response,
+            passed: failure.is_none(),
+            detail: failure.unwrap_or_default(),
+        }
+    }
+}
+
+// Encode `fields` exactly as given: no sorting, no validation of tags or lengths
+fn encode_raw(fields: &[(&[u8], &[u8])]) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    out.write_u32::<LittleEndian>(fields.len() as u32).unwrap();
+
+    let mut offset = 0;
+    for &(_, value) in fields.iter().take(fields.len().saturating_sub(1)) {
+        offset += value.len() as u32;
+        out.write_u32::<LittleEndian>(offset).unwrap();
+    }
+    for &(tag, _) in fields {
+        out.extend_from_slice(tag);
+    }
+    for &(_, value) in fields {
+        out.extend_from_slice(value);
+    }
+
+    out
+}
+
+// PAD value bringing a message of NONC (`nonce_len` bytes) + PAD to `total` bytes
+fn padding(nonce_len: usize, total: usize) -> Vec<u8> {
+    // 4 (num tags) + 4 (one offset) + 8 (two tags) of header
+    vec![0u8; total - 16 - nonce_len]
+}
+
+fn run_checks(checker: &Checker) -> Vec<Outcome> {
+    let min_len = MIN_REQUEST_LENGTH as usize;
+    let mut outcomes = Vec::new();
+
+    let nonce = create_nonce();
+    outcomes.push(checker.check(
+        "valid request",
+        Expect::Response,
+        &make_request(&nonce),
+        &nonce,
+    ));
+
+    let nonce = create_nonce();
+    let oversized = encode_raw(&[(b"NONC", &nonce), (b"PAD\xff", &padding(64, 1400))]);
+    outcomes.push(checker.check("oversized PAD", Expect::Response, &oversized, &nonce));
+
+    let nonce = create_nonce();
+    let undersized = encode_raw(&[(b"NONC", &nonce), (b"PAD\xff", &padding(64, min_len - 4))]);
+    outcomes.push(checker.check(
+        "request under 1024 bytes",
+        Expect::Silence,
+        &undersized,
+        &nonce,
+    ));
+
+    let nonce = create_nonce();
+    let request = make_request(&nonce);
+    outcomes.push(checker.check(
+        "truncated request",
+        Expect::Silence,
+        &request[..request.len() / 2],
+        &nonce,
+    ));
+
+    let nonce = create_nonce();
+    let reordered = encode_raw(&[(b"PAD\xff", &padding(64, min_len)), (b"NONC", &nonce)]);
+    outcomes.push(checker.check("reordered tags", Expect::Silence, &reordered, &nonce));
+
+    let nonce = create_nonce();
+    let mut bad_offset = make_request(&nonce);
+    // the single offset (bytes 4..8) points past the end of the message
+    (&mut bad_offset[4..8])
+        .write_u32::<LittleEndian>(MIN_REQUEST_LENGTH * 2)
+        .unwrap();
+    outcomes.push(checker.check("offset past end", Expect::Silence, &bad_offset, &nonce));
+
+    let nonce = create_nonce();
+    let short_nonce = encode_raw(&[(b"NONC", &nonce[..32]), (b"PAD\xff", &padding(32, min_len))]);
+    outcomes.push(checker.check("32 byte nonce", Expect::Silence, &short_nonce, &nonce));
+
+    let nonce = create_nonce();
+    let no_nonce = encode_raw(&[(b"PAD\xff", &vec![0u8; min_len - 8])]);
+    outcomes.push(checker.check("missing NONC", Expect::Silence, &no_nonce, &nonce));
+
+    outcomes.push(checker.check_nonce_reuse());
+
+    outcomes
+}
+
+fn main() {
+    let matches = App::new("roughenough conformance")
+        .version(roughenough_version().as_ref())
+        .about("Runs protocol conformance checks against a Roughtime server")
+        .arg(Arg::with_name("host")
+            .required(true)
+            .help("The Roughtime server to check")
+            .takes_value(true))
+        .arg(Arg::with_name("port")
+            .required(true)
+            .help("The Roughtime server's port")
+            .takes_value(true))
+        .arg(Arg::with_name("public-key")
+            .short("p")
+            .long("public-key")
+            .takes_value(true)
+            .help("The server public key. If unset, signatures are not checked"))
+        .arg(Arg::with_name("timeout")
+            .short("t")
+            .long("timeout")
+            .takes_value(true)
+            .help("Milliseconds to wait for each response; checks expecting silence wait this long")
+            .default_value("1000"))
+        .get_matches();
+
+    let host = matches.value_of("host").unwrap();
+    let port = value_t_or_exit!(matches.value_of("port"), u16);
+    let timeout = value_t_or_exit!(matches.value_of("timeout"), u64);
+    let pub_key = matches
+        .value_of("public-key")
+        .map(|pkey| hex::decode(pkey).expect("Error parsing public key!"));
+
+    let addr = match (host, port).to_socket_addrs().map(|mut addrs| addrs.next()) {
+        Ok(Some(addr)) => addr,
+        _ => {
+            eprintln!("Cannot resolve {}:{}", host, port);
+            process::exit(2);
+        }
+    };
+
+    let checker = Checker {
+        addr,
+        pub_key,
+        timeout: Duration::from_millis(timeout),
+    };
+
+    let outcomes = run_checks(&checker);
+
+    println!("{:<30} {:<12} RESULT", "CHECK", "EXPECTED");
+    for outcome in &outcomes {
+        let expected = match outcome.expect {
+            Expect::Response => "response",
+            Expect::Silence => "no response",
+        };
+        let result = if outcome.passed { "PASS" } else { "FAIL" };
+
+        if outcome.detail.is_empty() {
+            println!("{:<30} {:<12} {}", outcome.name, expected, result);
+        } else {
+            println!(
+                "{:<30} {:<12} {} ({})",
+                outcome.name, expected, result, outcome.detail
+            );
+        }
+    }
+
+    let failed = outcomes.iter().filter(|o| !o.passed).count();
+    println!("{} of {} checks passed", outcomes.len() - failed, outcomes.len());
+
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode_raw, padding, run_checks, Checker};
+    use roughenough::config::MemoryConfig;
+    use roughenough::test_support::TestServer;
+    use roughenough::RtMessage;
+    use std::time::Duration;
+
+    #[test]
+    fn raw_encoding_matches_rtmessage() {
+        let nonce = [7u8; 64];
+        let raw = encode_raw(&[(b"NONC", &nonce), (b"PAD\xff", &padding(64, 1024))]);
+
+        assert_eq!(raw.len(), 1024);
+        let msg = RtMessage::from_bytes(&raw).unwrap();
+        assert_eq!(msg.encode().unwrap(), raw);
+    }
+
+    #[test]
+    fn this_server_conforms() {
+        let server = TestServer::start(MemoryConfig::new(0));
+        let checker = Checker {
+            addr: server.addr(),
+            pub_key: Some(server.public_key().to_vec()),
+            timeout: Duration::from_millis(250),
+        };
+
+        for outcome in run_checks(&checker) {
+            assert!(outcome.passed, "{}: {}", outcome.name, outcome.detail);
+        }
+    }
+}
@@ -0,0 +1,429 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Differential testing against the Roughtime Go reference implementation.
+//!
+//! Identical inputs are given to this crate and to `tools/go-reference` (a thin wrapper
+//! around the reference's `protocol` package, run as a subprocess) and the results are
+//! compared:
+//!
+//! * encoding of random messages
+//! * decoding of those encodings and of corrupted copies of them
+//! * verification of responses from this server, and corrupted copies of them
+//! * verification of responses created by the reference
+//!
+//! Both sides rejecting an input counts as agreement; error messages are not compared.
+//!
+//! ```text
+//! $ (cd tools/go-reference && go build)
+//! $ roughenough-difftest tools/go-reference/go-reference 500
+//! ```
+//!
+
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::net::UdpSocket;
+use std::process::{self, Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::time::Duration;
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+use roughenough::config::MemoryConfig;
+use roughenough::key::LongTermKey;
+use roughenough::test_support::TestServer;
+use roughenough::verify::{create_nonce, make_request, Response};
+use roughenough::{roughenough_version, RtMessage, Tag};
+
+const DEFAULT_ITERATIONS: u32 = 100;
+
+/// Every tag this crate knows, in ascending wire order
+const TAGS: &[Tag] = &[
+    Tag::SIG,
+    Tag::VER,
+    Tag::SRV,
+    Tag::DUT1,
+    Tag::NONC,
+    Tag::DELE,
+    Tag::PATH,
+    Tag::DTAI,
+    Tag::RADI,
+    Tag::PUBK,
+    Tag::LEAP,
+    Tag::MIDP,
+    Tag::SREP,
+    Tag::VERS,
+    Tag::MINT,
+    Tag::ROOT,
+    Tag::CERT,
+    Tag::MAXT,
+    Tag::INDX,
+    Tag::ZZZZ,
+    Tag::PAD,
+];
+
+type Outcome = Result<String, String>;
+
+/// The Go reference, driven one command per line over stdin/stdout
+struct Reference {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Reference {
+    fn start(path: &str) -> Reference {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap_or_else(|e| panic!("failed to run Go reference '{}': {}", path, e));
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+
+        Reference {
+            child,
+            stdin,
+            stdout,
+        }
+    }
+
+    fn call(&mut self, command: &str) -> Outcome {
+        writeln!(self.stdin, "{}", command).expect("writing to Go reference");
+
+        let mut line = String::new();
+        self.stdout
+            .read_line(&mut line)
+            .expect("reading from Go reference");
+
+        let line = line.trim_end();
+        if line.starts_with("ok") {
+            Ok(line[2..].trim_start().to_string())
+        } else if line.starts_with("err ") {
+            Err(line[4..].to_string())
+        } else {
+            panic!("unexpected output from Go reference: {:?}", line);
+        }
+    }
+}
+
+impl Drop for Reference {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Agreement tally for one kind of check
+struct Report {
+    name: &'static str,
+    passed: u32,
+    failures: Vec<String>,
+}
+
+impl Report {
+    fn new(name: &'static str) -> Report {
+        Report {
+            name,
+            passed: 0,
+            failures: Vec::new(),
+        }
+    }
+
+    fn compare(&mut self, input: &str, ours: Outcome, reference: Outcome) {
+        match (&ours, &reference) {
+            (Ok(a), Ok(b)) if a == b => self.passed += 1,
+            (Err(_), Err(_)) => self.passed += 1,
+            _ => self.failures.push(format!(
+                "input:     {}\n    ours:      {:?}\n    reference: {:?}",
+                input, ours, reference
+            )),
+        }
+    }
+
+    fn print(&self) {
+        println!(
+            "{:<24} {:>6} agree, {:>4} differ",
+            self.name,
+            self.passed,
+            self.failures.len()
+        );
+        for failure in &self.failures {
+            println!("  - {}", failure);
+        }
+    }
+}
+
+fn random_bytes(rng: &SystemRandom, len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    rng.fill(&mut bytes).unwrap();
+    bytes
+}
+
+fn random_below(rng: &SystemRandom, bound: usize) -> usize {
+    random_bytes(rng, 4)
+        .iter()
+        .fold(0usize, |acc, b| (acc << 8) | *b as usize)
+        % bound
+}
+
+// A message of randomly chosen distinct tags with random values. Values are usually a
+// multiple of 4 bytes long; occasionally not, to compare how invalid lengths are handled.
+fn random_fields(rng: &SystemRandom) -> Vec<(Tag, Vec<u8>)> {
+    let mut fields = Vec::new();
+
+    for tag in TAGS {
+        if random_below(rng, 4) == 0 {
+            let len = if random_below(rng, 20) == 0 {
+                random_below(rng, 16)
+            } else {
+                4 * random_below(rng, 17)
+            };
+            fields.push((*tag, random_bytes(rng, len)));
+        }
+    }
+
+    fields
+}
+
+fn our_encode(fields: &[(Tag, Vec<u8>)]) -> Outcome {
+    let mut msg = RtMessage::new(fields.len() as u32);
+    for &(tag, ref value) in fields {
+        msg.insert_field(tag, value).map_err(|e| format!("{:?}", e))?;
+    }
+
+    msg.encode().map(hex::encode).map_err(|e| format!("{:?}", e))
+}
+
+fn encode_command(fields: &[(Tag, Vec<u8>)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|&(tag, ref value)| format!("{}={}", tag.wire_u32(), hex::encode(value)))
+        .collect();
+
+    format!("encode {}", fields.join(","))
+}
+
+fn our_decode(bytes: &[u8]) -> Outcome {
+    let msg = RtMessage::from_bytes(bytes).map_err(|e| format!("{:?}", e))?;
+    let fields: Vec<String> = msg
+        .iter()
+        .map(|(tag, value)| format!("{}={}", tag.wire_u32(), hex::encode(value)))
+        .collect();
+
+    Ok(fields.join(","))
+}
+
+fn our_verify(reply: &[u8], pub_key: &[u8], nonce: &[u8]) -> Outcome {
+    let response = Response::from_bytes(reply, nonce).map_err(|e| format!("{:?}", e))?;
+    response.verify(pub_key).map_err(|e| format!("{:?}", e))?;
+
+    Ok(format!(
+        "{} {}",
+        response.midpoint().unwrap(),
+        response.radius().unwrap()
+    ))
+}
+
+// Copies of `bytes` with a corrupted header: truncated, wrong tag count, skewed offsets
+fn corruptions(rng: &SystemRandom, bytes: &[u8]) -> Vec<Vec<u8>> {
+    let mut result = Vec::new();
+
+    if bytes.len() >= 4 {
+        result.push(bytes[..bytes.len() - 4].to_vec());
+        result.push(bytes[..random_below(rng, bytes.len() / 4) * 4].to_vec());
+
+        let mut num_tags = bytes.to_vec();
+        num_tags[0] = num_tags[0].wrapping_add(1);
+        result.push(num_tags);
+    }
+
+    // the offsets follow the 4 byte tag count
+    let num_fields = if bytes.len() >= 4 { bytes[0] as usize } else { 0 };
+    if num_fields > 1 && bytes.len() >= 4 * num_fields {
+        let mut offset = bytes.to_vec();
+        let idx = 4 + 4 * random_below(rng, num_fields - 1);
+        offset[idx] = offset[idx].wrapping_add(4);
+        result.push(offset);
+    }
+
+    result
+}
+
+fn flip_random_bit(rng: &SystemRandom, bytes: &[u8]) -> Vec<u8> {
+    let mut flipped = bytes.to_vec();
+    let idx = random_below(rng, flipped.len());
+    flipped[idx] ^= 1 << random_below(rng, 8);
+    flipped
+}
+
+fn check_encoding(reference: &mut Reference, rng: &SystemRandom, iterations: u32) -> (Report, Report) {
+    let mut encode = Report::new("encode");
+    let mut decode = Report::new("decode");
+
+    for _ in 0..iterations {
+        let fields = random_fields(rng);
+        let command = encode_command(&fields);
+
+        let ours = our_encode(&fields);
+        let theirs = reference.call(&command);
+        encode.compare(&command, ours.clone(), theirs);
+
+        if let Ok(encoded) = ours {
+            let bytes = hex::decode(&encoded).unwrap();
+
+            let mut inputs = vec![bytes.clone()];
+            inputs.extend(corruptions(rng, &bytes));
+
+            for input in inputs {
+                let input = hex::encode(input);
+                decode.compare(
+                    &input,
+                    our_decode(&hex::decode(&input).unwrap()),
+                    reference.call(&format!("decode {}", input)),
+                );
+            }
+        }
+    }
+
+    (encode, decode)
+}
+
+fn query(server: &TestServer, nonce: &[u8]) -> Vec<u8> {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    socket.send_to(&make_request(nonce), server.addr()).unwrap();
+
+    let mut buf = [0u8; 4096];
+    let (len, _) = socket.recv_from(&mut buf).expect("no response from test server");
+    buf[..len].to_vec()
+}
+
+fn check_our_responses(reference: &mut Reference, rng: &SystemRandom, iterations: u32) -> Report {
+    let mut report = Report::new("verify ours");
+    let server = TestServer::start(MemoryConfig::new(0));
+    let pub_key = hex::encode(server.public_key());
+
+    for i in 0..iterations {
+        let nonce = create_nonce();
+        let mut reply = query(&server, &nonce);
+
+        // every other response is corrupted
+        if i % 2 == 1 {
+            reply = flip_random_bit(rng, &reply);
+        }
+
+        let command = format!(
+            "verify {} {} {}",
+            hex::encode(&reply),
+            pub_key,
+            hex::encode(&nonce[..])
+        );
+        report.compare(
+            &command,
+            our_verify(&reply, server.public_key(), &nonce),
+            reference.call(&command),
+        );
+    }
+
+    report
+}
+
+fn check_reference_responses(
+    reference: &mut Reference,
+    rng: &SystemRandom,
+    iterations: u32,
+) -> Report {
+    let mut report = Report::new("verify reference");
+
+    let seed = random_bytes(rng, 32);
+    let pub_key = LongTermKey::new(&seed).public_key().to_vec();
+
+    for _ in 0..iterations {
+        let nonces: Vec<Vec<u8>> = (0..1 + random_below(rng, 8))
+            .map(|_| create_nonce().to_vec())
+            .collect();
+        let midpoint = 1_500_000_000_000_000 + random_below(rng, 1 << 30) as u64;
+        let radius = 1 + random_below(rng, 5_000_000) as u32;
+
+        let nonce_args: Vec<String> = nonces.iter().map(hex::encode).collect();
+        let command = format!(
+            "replies {} {} {} {}",
+            hex::encode(&seed),
+            midpoint,
+            radius,
+            nonce_args.join(" ")
+        );
+
+        let replies = match reference.call(&command) {
+            Ok(replies) => replies,
+            Err(e) => {
+                report.failures.push(format!("reference failed to reply: {}", e));
+                continue;
+            }
+        };
+
+        for (reply, nonce) in replies.split_whitespace().zip(&nonces) {
+            let reply = hex::decode(reply).unwrap();
+            let expected = Ok(format!("{} {}", midpoint, radius));
+
+            report.compare(
+                &format!("reply to {}", hex::encode(nonce)),
+                our_verify(&reply, &pub_key, nonce),
+                expected,
+            );
+        }
+    }
+
+    report
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("roughenough-difftest {}", roughenough_version());
+        eprintln!("Usage: roughenough-difftest <go-reference binary> [iterations]");
+        process::exit(2);
+    }
+
+    let iterations = args
+        .get(2)
+        .map(|n| n.parse().expect("iterations must be a number"))
+        .unwrap_or(DEFAULT_ITERATIONS);
+
+    let rng = SystemRandom::new();
+    let mut reference = Reference::start(&args[1]);
+
+    let (encode, decode) = check_encoding(&mut reference, &rng, iterations);
+    let reports = vec![
+        encode,
+        decode,
+        check_our_responses(&mut reference, &rng, iterations),
+        check_reference_responses(&mut reference, &rng, iterations),
+    ];
+
+    for report in &reports {
+        report.print();
+    }
+
+    let failures: usize = reports.iter().map(|r| r.failures.len()).sum();
+    if failures > 0 {
+        eprintln!("{} differences from the Go reference", failures);
+        process::exit(1);
+    }
+}
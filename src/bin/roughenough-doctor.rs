@@ -0,0 +1,509 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Diagnoses a Roughenough deployment end to end and explains how to fix what it finds.
+//!
+//! Takes the same configuration argument as the server and checks, in order: the
+//! configuration, the seed (including KMS decryption), signing with the derived keys, the
+//! system clock, the UDP socket, and finally a real query and its verification. If a
+//! server is already listening on the configured port it is queried; otherwise a
+//! temporary server is started on an ephemeral port with the same keys and clock.
+//!
+//! ```text
+//! $ roughenough-doctor /etc/roughenough.yaml
+//! [ OK ] config     loaded from /etc/roughenough.yaml
+//! [ OK ] seed       Plaintext seed, long-term key fingerprint ab:d1:10:4e:6d:0e:c7:e2
+//! ...
+//! ```
+//!
+
+use std::fs;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::process;
+use std::time::Duration;
+
+use roughenough::clock::{self, Clock};
+use roughenough::config::{self, MemoryConfig, ServerConfig};
+use roughenough::key::{self, KmsProtection, LongTermKey, SharedDelegation};
+use roughenough::kms;
+use roughenough::responder::ResponseBuilder;
+use roughenough::selfcheck;
+use roughenough::test_support::TestServer;
+use roughenough::verify::{create_nonce, make_request, Response};
+
+/// How long to wait for the answer to the test query
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+struct Finding {
+    check: &'static str,
+    status: Status,
+    detail: String,
+    hint: Option<String>,
+}
+
+#[derive(Default)]
+struct Report {
+    findings: Vec<Finding>,
+}
+
+impl Report {
+    fn add(&mut self, check: &'static str, status: Status, detail: String, hint: Option<&str>) {
+        self.findings.push(Finding {
+            check,
+            status,
+            detail,
+            hint: hint.map(str::to_string),
+        });
+    }
+
+    fn ok(&mut self, check: &'static str, detail: String) {
+        self.add(check, Status::Ok, detail, None);
+    }
+
+    fn warn(&mut self, check: &'static str, detail: String, hint: &str) {
+        self.add(check, Status::Warn, detail, Some(hint));
+    }
+
+    fn fail(&mut self, check: &'static str, detail: String, hint: &str) {
+        self.add(check, Status::Fail, detail, Some(hint));
+    }
+
+    fn failed(&self) -> bool {
+        self.findings.iter().any(|f| f.status == Status::Fail)
+    }
+
+    fn print(&self) {
+        for finding in &self.findings {
+            let status = match finding.status {
+                Status::Ok => "[ OK ]",
+                Status::Warn => "[WARN]",
+                Status::Fail => "[FAIL]",
+            };
+
+            println!("{} {:<10} {}", status, finding.check, finding.detail);
+            if let Some(ref hint) = finding.hint {
+                println!("{:<17} hint: {}", "", hint);
+            }
+        }
+    }
+}
+
+// The address to query a socket bound to `addr` at: loopback when bound to all interfaces
+fn reachable_addr(addr: SocketAddr) -> SocketAddr {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+
+    SocketAddr::new(ip, addr.port())
+}
+
+// Query `addr` once and verify the response against `public_key`, returning the offset
+// of its midpoint from the local clock and its radius, both in seconds
+fn query(addr: SocketAddr, public_key: &[u8]) -> Result<(f64, f64), String> {
+    let bind_addr = if addr.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    };
+    let socket = UdpSocket::bind(bind_addr).map_err(|e| format!("cannot open socket: {}", e))?;
+    socket.set_read_timeout(Some(QUERY_TIMEOUT)).unwrap();
+
+    let nonce = create_nonce();
+    socket
+        .send_to(&make_request(&nonce), addr)
+        .map_err(|e| format!("cannot send to {}: {}", addr, e))?;
+
+    let mut buf = [0u8; 4096];
+    let (len, _) = socket
+        .recv_from(&mut buf)
+        .map_err(|e| format!("no response from {}: {}", addr, e))?;
+
+    let response = Response::from_bytes(&buf[..len], &nonce)
+        .map_err(|e| format!("malformed response from {}: {:?}", addr, e))?;
+    response
+        .verify(public_key)
+        .map_err(|e| format!("response from {} does not verify: {:?}", addr, e))?;
+
+    let dtai = u64::from(response.dtai().unwrap_or(0)) * 1_000_000;
+    let utc = response
+        .midpoint()
+        .unwrap()
+        .checked_sub(dtai)
+        .ok_or_else(|| format!("response from {} has a DTAI exceeding its MIDP", addr))?;
+    let midpoint = utc as f64 / 1e6;
+    let radius = f64::from(response.radius().unwrap()) / 1e6;
+    let now = time::get_time();
+    let local = now.sec as f64 + f64::from(now.nsec) / 1e9;
+
+    Ok((midpoint - local, radius))
+}
+
+fn check_seed(report: &mut Report, config: &Box<ServerConfig>) -> Option<Vec<u8>> {
+    let seed = match kms::load_seed(config) {
+        Ok(seed) => seed,
+        Err(e) => {
+            let hint = match config.kms_protection() {
+                KmsProtection::Plaintext => "the seed must be 64 hex characters (32 bytes)",
+                _ => {
+                    "check the KMS key id, that the seed blob was produced with it by \
+                     roughenough-kms, and that this host's credentials may decrypt with it"
+                }
+            };
+            report.fail("seed", format!("cannot load seed: {:?}", e), hint);
+            return None;
+        }
+    };
+
+    if let Err(e) = selfcheck::check_seed(&seed) {
+        report.fail(
+            "seed",
+            e,
+            "the KMS key decrypted the blob to the wrong length; re-encrypt the seed",
+        );
+        return None;
+    }
+
+    let public_key = LongTermKey::new(&seed).public_key().to_vec();
+    report.ok(
+        "seed",
+        format!(
+            "{} seed, long-term key fingerprint {}",
+            config.kms_protection(),
+            key::fingerprint(&public_key)
+        ),
+    );
+
+    Some(seed)
+}
+
+fn check_signing(report: &mut Report, config: &Box<ServerConfig>, seed: &[u8]) {
+    let mut long_term_key = LongTermKey::new(seed);
+    let public_key = long_term_key.public_key().to_vec();
+
+    let mut responder = match config.shared_delegation() {
+        Some(path) => {
+            let delegation = fs::read(path)
+                .map_err(roughenough::Error::from)
+                .and_then(|sealed| SharedDelegation::open(&sealed, seed))
+                .and_then(|d| d.verify(&public_key).map(|_| d));
+
+            match delegation {
+                Ok(d) => ResponseBuilder::new(d.online_key(), d.cert_bytes().to_vec()),
+                Err(e) => {
+                    report.fail(
+                        "signing",
+                        format!("cannot load shared delegation '{}': {:?}", path, e),
+                        "the delegation must be sealed with this seed; regenerate it with \
+                         'roughenough-server share-delegation'",
+                    );
+                    return;
+                }
+            }
+        }
+        None => ResponseBuilder::from_long_term_key(&mut long_term_key),
+    };
+
+    let midpoint = clock::to_micros(time::get_time(), config.secondsoffset());
+    match selfcheck::check_signing(&mut responder, &public_key, midpoint) {
+        Ok(_) => report.ok("signing", "test response signed and verified".to_string()),
+        Err(e) => report.fail(
+            "signing",
+            e,
+            "an expired shared delegation must be regenerated; otherwise report a bug",
+        ),
+    }
+}
+
+fn check_clock(report: &mut Report, config: &Box<ServerConfig>) {
+    let now_secs = time::get_time().sec + config.secondsoffset() as i64;
+    if let Err(e) = selfcheck::check_clock(now_secs) {
+        report.fail(
+            "clock",
+            e,
+            "set the system clock (and check secondsoffset) before starting the server",
+        );
+        return;
+    }
+
+    if let Err(e) = Clock::new(config.clock_source()) {
+        report.fail(
+            "clock",
+            format!("{} clock is unusable: {:?}", config.clock_source(), e),
+            "configure the kernel TAI offset (chrony 'leapsectz right/UTC') or use \
+             clock_source: realtime",
+        );
+        return;
+    }
+
+    match clock::is_synchronized() {
+        Some(true) => report.ok(
+            "clock",
+            format!(
+                "{} clock is plausible and synchronized",
+                config.clock_source()
+            ),
+        ),
+        Some(false) => report.warn(
+            "clock",
+            "the kernel reports the system clock as unsynchronized".to_string(),
+            "run chrony, ntpd or another time daemon so served time stays accurate",
+        ),
+        None => report.ok(
+            "clock",
+            format!(
+                "{} clock is plausible (synchronization status unavailable)",
+                config.clock_source()
+            ),
+        ),
+    }
+}
+
+// Whether a server is already bound to the configured address, or `None` on failure
+fn check_socket(report: &mut Report, addr: SocketAddr) -> Option<bool> {
+    match UdpSocket::bind(addr) {
+        Ok(_) => {
+            report.ok("socket", format!("{} is free to bind", addr));
+            Some(false)
+        }
+        Err(ref e) if e.kind() == ErrorKind::AddrInUse => {
+            report.ok(
+                "socket",
+                format!("{} is in use, presumably by the server", addr),
+            );
+            Some(true)
+        }
+        Err(ref e) if e.kind() == ErrorKind::AddrNotAvailable => {
+            report.fail(
+                "socket",
+                format!("cannot bind {}: {}", addr, e),
+                "the interface address is not assigned to this host; use one of its \
+                 addresses or 0.0.0.0",
+            );
+            None
+        }
+        Err(ref e) if e.kind() == ErrorKind::PermissionDenied => {
+            report.fail(
+                "socket",
+                format!("cannot bind {}: {}", addr, e),
+                "ports below 1024 need root (see allow_root and user) or CAP_NET_BIND_SERVICE",
+            );
+            None
+        }
+        Err(e) => {
+            report.fail(
+                "socket",
+                format!("cannot bind {}: {}", addr, e),
+                "check the interface and port settings",
+            );
+            None
+        }
+    }
+}
+
+fn check_query(report: &mut Report, config: &Box<ServerConfig>, seed: &[u8], in_use: bool) {
+    let public_key = LongTermKey::new(seed).public_key().to_vec();
+
+    // keep the temporary server alive until the query is answered
+    let (addr, target, _server) = if in_use {
+        let addr = reachable_addr(config.udp_socket_addr().unwrap());
+        (addr, "running server", None)
+    } else {
+        let test_config = MemoryConfig::builder()
+            .interface(config.interface())
+            .port(0)
+            .seed(seed)
+            .batch_size(config.batch_size())
+            .secondsoffset(config.secondsoffset())
+            .clock_source(config.clock_source())
+            .midpoint_resolution(config.midpoint_resolution())
+            .build();
+        let server = TestServer::start(test_config);
+
+        (
+            reachable_addr(server.addr()),
+            "temporary server",
+            Some(server),
+        )
+    };
+
+    match query(addr, &public_key) {
+        Ok((offset, radius)) if offset.abs() > radius => report.warn(
+            "query",
+            format!(
+                "{} at {} is {:.3}s from the local clock, more than its radius of {:.3}s",
+                target, addr, offset, radius
+            ),
+            "check secondsoffset and that the server's clock source matches this host's",
+        ),
+        Ok((offset, radius)) => report.ok(
+            "query",
+            format!(
+                "{} at {} answered and verified (offset {:.6}s, radius {:.6}s)",
+                target, addr, offset, radius
+            ),
+        ),
+        Err(e) if in_use => report.fail(
+            "query",
+            e,
+            "another process may hold the port, or the running server uses a different seed \
+             or configuration; restart it",
+        ),
+        Err(e) => report.fail(
+            "query",
+            e,
+            "a firewall may be dropping local UDP traffic; otherwise report a bug",
+        ),
+    }
+}
+
+// Run every check against the configuration named by `arg`, stopping at the first
+// failure that later checks depend on
+fn diagnose(arg: &str) -> Report {
+    let mut report = Report::default();
+
+    let config = match config::from_arg(arg) {
+        Ok(config) => config,
+        Err(e) => {
+            report.fail(
+                "config",
+                format!("cannot load '{}': {:?}", arg, e),
+                "pass a YAML file path, file://, ssm:// or ENV with the ROUGHENOUGH_* \
+                 variables set",
+            );
+            return report;
+        }
+    };
+
+    if let Err(errors) = config.validate() {
+        for e in errors {
+            report.fail(
+                "config",
+                format!("{:?}", e),
+                "see 'Server Configuration' in the README",
+            );
+        }
+        return report;
+    }
+    report.ok("config", format!("loaded from {}", arg));
+
+    let seed = match check_seed(&mut report, &config) {
+        Some(seed) => seed,
+        None => return report,
+    };
+
+    check_signing(&mut report, &config, &seed);
+    check_clock(&mut report, &config);
+
+    let addr = match config.udp_socket_addr() {
+        Ok(addr) => addr,
+        Err(e) => {
+            report.fail(
+                "socket",
+                format!("{:?}", e),
+                "the interface must be an IP address or a resolvable hostname",
+            );
+            return report;
+        }
+    };
+
+    if report.failed() {
+        return report;
+    }
+
+    if let Some(in_use) = check_socket(&mut report, addr) {
+        check_query(&mut report, &config, &seed, in_use);
+    }
+
+    report
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
+    if args.len() != 2 {
+        eprintln!("Usage: roughenough-doctor <ENV | /path/to/config.yaml>");
+        process::exit(2);
+    }
+
+    let report = diagnose(&args[1]);
+    report.print();
+
+    if report.failed() {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::net::UdpSocket;
+
+    use super::{diagnose, Status};
+
+    fn write_config(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}-{}.yaml", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn healthy_configuration_passes() {
+        let port = {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket.local_addr().unwrap().port()
+        };
+        let path = write_config(
+            "doctor-ok",
+            &format!(
+                "interface: 127.0.0.1\nport: {}\nseed: \
+                 f61075c988feb9cb700a4a6a3291bfbc9cab11b9c9eca8c802468eb38a43d7d3\n",
+                port
+            ),
+        );
+
+        let report = diagnose(&path);
+        fs::remove_file(&path).unwrap();
+
+        for finding in &report.findings {
+            assert_ne!(finding.status, Status::Fail, "{}", finding.detail);
+        }
+        assert_eq!(report.findings.last().unwrap().check, "query");
+    }
+
+    #[test]
+    fn invalid_configuration_is_explained() {
+        let path = write_config(
+            "doctor-bad",
+            "interface: 127.0.0.1\nport: 2002\nseed: 0011\n",
+        );
+
+        let report = diagnose(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(report.failed());
+        assert!(report.findings.iter().all(|f| f.check == "config"));
+        assert!(report.findings[0].hint.is_some());
+    }
+}
@@ -0,0 +1,636 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Pretty-print Roughtime messages for debugging interoperability problems.
+//!
+//! The input is a hex string, a file holding a message (raw or hex), `-` for stdin, or a
+//! pcap capture, in which case every UDP payload is decoded. Decoding is deliberately
+//! lenient: unknown tags, misaligned offsets and unsorted tags are reported but do not stop
+//! the rest of the message from being shown.
+//!
+//! ```text
+//! $ roughenough-inspect response.bin
+//! $ roughenough-inspect 0200000040000000...
+//! $ roughenough-inspect --port 2002 capture.pcap
+//! ```
+//!
+
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::process;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use chrono::offset::Utc;
+use chrono::TimeZone;
+use clap::{App, Arg};
+
+use roughenough::{roughenough_version, Tag};
+
+/// IETF drafts frame messages with this magic value and a 4-byte length
+const FRAME_MAGIC: &[u8] = b"ROUGHTIM";
+
+/// Nesting beyond this depth is not decoded
+const MAX_DEPTH: usize = 8;
+
+/// Hex characters per output line
+const HEX_LINE_WIDTH: usize = 64;
+
+// Timestamps at least this large are taken to be microseconds (1973 onward), smaller
+// ones seconds (as in the IETF drafts)
+const MIN_MICROS_TIMESTAMP: u64 = 100_000_000_000_000;
+
+/// A message decoded without judging its validity
+struct Decoded<'a> {
+    fields: Vec<(&'a [u8], &'a [u8])>,
+    problems: Vec<String>,
+}
+
+///
+/// Split `bytes` into its (tag, value) fields. Fails only if the header itself cannot be
+/// read; other problems are collected in `problems`.
+///
+fn decode(bytes: &[u8]) -> Result<Decoded, String> {
+    let mut problems = Vec::new();
+
+    if bytes.len() < 4 {
+        return Err(format!("{} bytes is too short for a message", bytes.len()));
+    }
+    if bytes.len() % 4 != 0 {
+        problems.push(format!("length {} is not a multiple of 4", bytes.len()));
+    }
+
+    let num_tags = LittleEndian::read_u32(&bytes[0..4]) as usize;
+    if num_tags == 0 {
+        return Ok(Decoded {
+            fields: Vec::new(),
+            problems,
+        });
+    }
+
+    let header_len = 8 * num_tags;
+    if num_tags > 1024 || header_len > bytes.len() {
+        return Err(format!(
+            "header of {} tags does not fit in {} bytes",
+            num_tags,
+            bytes.len()
+        ));
+    }
+
+    let body_len = bytes.len() - header_len;
+    let mut bounds = vec![0];
+    for i in 0..num_tags - 1 {
+        let offset = LittleEndian::read_u32(&bytes[4 + 4 * i..]) as usize;
+        if offset % 4 != 0 {
+            problems.push(format!("offset {} is not a multiple of 4", offset));
+        }
+        bounds.push(offset);
+    }
+    bounds.push(body_len);
+
+    let tags_start = 4 + 4 * (num_tags - 1);
+    let mut fields: Vec<(&[u8], &[u8])> = Vec::with_capacity(num_tags);
+
+    for i in 0..num_tags {
+        let tag = &bytes[tags_start + 4 * i..tags_start + 4 * i + 4];
+
+        if i > 0 && LittleEndian::read_u32(tag) <= LittleEndian::read_u32(fields[i - 1].0) {
+            problems.push(format!("tag {} is not in increasing order", tag_name(tag)));
+        }
+        if Tag::from_wire(tag).is_err() {
+            problems.push(format!("tag {} is unknown", tag_name(tag)));
+        }
+
+        let (start, end) = (bounds[i], bounds[i + 1]);
+        let value: &[u8] = if start <= end && end <= body_len {
+            &bytes[header_len + start..header_len + end]
+        } else {
+            problems.push(format!(
+                "value of {} has invalid bounds {}..{} (body is {} bytes)",
+                tag_name(tag),
+                start,
+                end,
+                body_len
+            ));
+            &[]
+        };
+
+        fields.push((tag, value));
+    }
+
+    Ok(Decoded { fields, problems })
+}
+
+/// Printable name of the wire tag `tag`, e.g. `SIG` or `AB\x01C`
+fn tag_name(tag: &[u8]) -> String {
+    if let Ok(known) = Tag::from_wire(tag) {
+        return format!("{:?}", known);
+    }
+
+    tag.iter()
+        .map(|&b| {
+            if b.is_ascii_alphanumeric() {
+                (b as char).to_string()
+            } else {
+                format!("\\x{:02x}", b)
+            }
+        })
+        .collect()
+}
+
+fn format_timestamp(value: u64) -> String {
+    let (secs, micros, unit) = if value >= MIN_MICROS_TIMESTAMP {
+        (value / 1_000_000, value % 1_000_000, "microseconds")
+    } else {
+        (value, 0, "seconds")
+    };
+
+    match Utc
+        .timestamp_opt(secs as i64, (micros * 1000) as u32)
+        .single()
+    {
+        Some(time) => format!(
+            "{} ({} as {})",
+            value,
+            time.format("%Y-%m-%dT%H:%M:%S%.6fZ"),
+            unit
+        ),
+        None => format!("{} (not a valid time)", value),
+    }
+}
+
+fn write_hex(out: &mut String, indent: &str, bytes: &[u8]) {
+    let hex = hex::encode(bytes);
+    for start in (0..hex.len()).step_by(HEX_LINE_WIDTH) {
+        let end = (start + HEX_LINE_WIDTH).min(hex.len());
+        writeln!(out, "{}{}", indent, &hex[start..end]).unwrap();
+    }
+}
+
+// Describe the value of the field `tag`, one level below `depth`
+fn write_value(out: &mut String, depth: usize, tag: &[u8], value: &[u8]) {
+    let indent = "  ".repeat(depth + 1);
+
+    match (Tag::from_wire(tag), value.len()) {
+        (Ok(Tag::SREP), _) | (Ok(Tag::DELE), _) | (Ok(Tag::CERT), _) if depth < MAX_DEPTH => {
+            write_message(out, depth + 1, value)
+        }
+        (Ok(Tag::MIDP), 8) | (Ok(Tag::MINT), 8) | (Ok(Tag::MAXT), 8) => {
+            let timestamp = format_timestamp(LittleEndian::read_u64(value));
+            writeln!(out, "{}{}", indent, timestamp).unwrap();
+        }
+        (Ok(Tag::RADI), 4) | (Ok(Tag::INDX), 4) => {
+            writeln!(out, "{}{}", indent, LittleEndian::read_u32(value)).unwrap();
+        }
+        (Ok(Tag::DTAI), 4) | (Ok(Tag::DUT1), 4) => {
+            writeln!(out, "{}{}", indent, LittleEndian::read_i32(value)).unwrap();
+        }
+        (Ok(Tag::VER), len) | (Ok(Tag::VERS), len) if len % 4 == 0 => {
+            let versions: Vec<String> = value
+                .chunks(4)
+                .map(|v| format!("0x{:08x}", LittleEndian::read_u32(v)))
+                .collect();
+            writeln!(out, "{}{}", indent, versions.join(" ")).unwrap();
+        }
+        (Ok(Tag::PATH), len) => {
+            let hash_len = if len % 64 == 0 { 64 } else { 32 };
+            for (i, hash) in value.chunks(hash_len).enumerate() {
+                writeln!(out, "{}[{}]", indent, i).unwrap();
+                write_hex(out, &indent, hash);
+            }
+        }
+        (Ok(Tag::PAD), len) | (Ok(Tag::ZZZZ), len) => {
+            let zeros = if value.iter().all(|&b| b == 0) {
+                "all zero"
+            } else {
+                "not all zero"
+            };
+            writeln!(out, "{}{} bytes of padding, {}", indent, len, zeros).unwrap();
+        }
+        _ => write_hex(out, &indent, value),
+    }
+}
+
+/// Describe the message `bytes`, indented to `depth`
+fn write_message(out: &mut String, depth: usize, bytes: &[u8]) {
+    let indent = "  ".repeat(depth);
+
+    let decoded = match decode(bytes) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            writeln!(out, "{}undecodable: {}", indent, e).unwrap();
+            write_hex(out, &indent, bytes);
+            return;
+        }
+    };
+
+    writeln!(
+        out,
+        "{}message: {} tags, {} bytes",
+        indent,
+        decoded.fields.len(),
+        bytes.len()
+    )
+    .unwrap();
+    for problem in &decoded.problems {
+        writeln!(out, "{}! {}", indent, problem).unwrap();
+    }
+
+    for (tag, value) in decoded.fields {
+        writeln!(out, "{}{} ({} bytes)", indent, tag_name(tag), value.len()).unwrap();
+        write_value(out, depth, tag, value);
+    }
+}
+
+/// Describe a complete packet, which may carry IETF framing
+fn describe_packet(bytes: &[u8]) -> String {
+    let mut out = String::new();
+
+    if bytes.len() >= 12 && bytes.starts_with(FRAME_MAGIC) {
+        let len = LittleEndian::read_u32(&bytes[8..12]) as usize;
+        let body = &bytes[12..];
+        writeln!(out, "framed: {} byte message", len).unwrap();
+        if len != body.len() {
+            writeln!(
+                out,
+                "! frame length {} but {} bytes follow",
+                len,
+                body.len()
+            )
+            .unwrap();
+        }
+        write_message(&mut out, 0, &body[..len.min(body.len())]);
+    } else {
+        write_message(&mut out, 0, bytes);
+    }
+
+    out
+}
+
+/// A UDP datagram found in a capture
+struct Datagram<'a> {
+    src: (IpAddr, u16),
+    dst: (IpAddr, u16),
+    payload: &'a [u8],
+}
+
+// Link-layer header types (http://www.tcpdump.org/linktypes.html)
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+///
+/// The UDP datagrams of the pcap file `bytes`, with the time each was captured (seconds
+/// since the epoch). Packets other than UDP over IPv4 or IPv6 are skipped.
+///
+fn parse_pcap(bytes: &[u8]) -> Result<Vec<(f64, Datagram)>, String> {
+    if bytes.len() < 24 {
+        return Err("pcap file is truncated".to_string());
+    }
+
+    let (big_endian, nanos) = match &bytes[0..4] {
+        [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+        [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+        [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+        [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+        [0x0a, 0x0d, 0x0d, 0x0a] => {
+            return Err("pcapng is not supported; convert with 'editcap -F pcap'".to_string())
+        }
+        _ => return Err("not a pcap file".to_string()),
+    };
+    let read_u32 = |b: &[u8]| {
+        if big_endian {
+            BigEndian::read_u32(b)
+        } else {
+            LittleEndian::read_u32(b)
+        }
+    };
+
+    let linktype = read_u32(&bytes[20..24]);
+    let mut datagrams = Vec::new();
+    let mut pos = 24;
+
+    while pos + 16 <= bytes.len() {
+        let secs = f64::from(read_u32(&bytes[pos..]));
+        let frac = f64::from(read_u32(&bytes[pos + 4..]));
+        let incl_len = read_u32(&bytes[pos + 8..]) as usize;
+        let start = pos + 16;
+
+        if start + incl_len > bytes.len() {
+            return Err(format!("packet at offset {} is truncated", pos));
+        }
+
+        let time = secs + frac / if nanos { 1e9 } else { 1e6 };
+        if let Some(datagram) = link_payload(linktype, &bytes[start..start + incl_len])
+            .and_then(|(ethertype, ip)| udp_datagram(ethertype, ip))
+        {
+            datagrams.push((time, datagram));
+        }
+
+        pos = start + incl_len;
+    }
+
+    Ok(datagrams)
+}
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+
+// The (ethertype, IP packet) carried by a link-layer frame
+fn link_payload(linktype: u32, frame: &[u8]) -> Option<(u16, &[u8])> {
+    let ip_version = |ip: &[u8]| match ip.first().map(|b| b >> 4) {
+        Some(4) => Some(ETHERTYPE_IPV4),
+        Some(6) => Some(ETHERTYPE_IPV6),
+        _ => None,
+    };
+
+    match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = BigEndian::read_u16(frame.get(offset..offset + 2)?);
+            while ethertype == ETHERTYPE_VLAN {
+                offset += 4;
+                ethertype = BigEndian::read_u16(frame.get(offset..offset + 2)?);
+            }
+            Some((ethertype, frame.get(offset + 2..)?))
+        }
+        LINKTYPE_LINUX_SLL => Some((BigEndian::read_u16(frame.get(14..16)?), frame.get(16..)?)),
+        LINKTYPE_LINUX_SLL2 => Some((BigEndian::read_u16(frame.get(0..2)?), frame.get(20..)?)),
+        LINKTYPE_NULL => {
+            let ip = frame.get(4..)?;
+            Some((ip_version(ip)?, ip))
+        }
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some((ip_version(frame)?, frame)),
+        _ => None,
+    }
+}
+
+const IPPROTO_UDP: u8 = 17;
+
+// The UDP datagram in the IP packet `ip`, if it is one
+fn udp_datagram(ethertype: u16, ip: &[u8]) -> Option<Datagram> {
+    let (src, dst, udp) = match ethertype {
+        ETHERTYPE_IPV4 => {
+            let header_len = usize::from(ip.first()? & 0x0f) * 4;
+            let fragment_offset = BigEndian::read_u16(ip.get(6..8)?) & 0x1fff;
+            if *ip.get(9)? != IPPROTO_UDP || fragment_offset != 0 {
+                return None;
+            }
+            let src = Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]);
+            let dst = Ipv4Addr::new(ip[16], ip[17], ip[18], ip[19]);
+            (IpAddr::V4(src), IpAddr::V4(dst), ip.get(header_len..)?)
+        }
+        ETHERTYPE_IPV6 => {
+            if *ip.get(6)? != IPPROTO_UDP || ip.len() < 40 {
+                return None;
+            }
+            let mut src = [0u8; 16];
+            let mut dst = [0u8; 16];
+            src.copy_from_slice(&ip[8..24]);
+            dst.copy_from_slice(&ip[24..40]);
+            (
+                IpAddr::V6(Ipv6Addr::from(src)),
+                IpAddr::V6(Ipv6Addr::from(dst)),
+                &ip[40..],
+            )
+        }
+        _ => return None,
+    };
+
+    let src_port = BigEndian::read_u16(udp.get(0..2)?);
+    let dst_port = BigEndian::read_u16(udp.get(2..4)?);
+    let udp_len = usize::from(BigEndian::read_u16(udp.get(4..6)?));
+
+    // the UDP length excludes any Ethernet padding captured after the datagram
+    let payload = udp.get(8..udp_len.max(8).min(udp.len()))?;
+
+    Some(Datagram {
+        src: (src, src_port),
+        dst: (dst, dst_port),
+        payload,
+    })
+}
+
+fn format_endpoint(endpoint: (IpAddr, u16)) -> String {
+    match endpoint.0 {
+        IpAddr::V4(ip) => format!("{}:{}", ip, endpoint.1),
+        IpAddr::V6(ip) => format!("[{}]:{}", ip, endpoint.1),
+    }
+}
+
+fn is_pcap(bytes: &[u8]) -> bool {
+    bytes.len() >= 4
+        && match &bytes[0..4] {
+            [0xd4, 0xc3, 0xb2, 0xa1]
+            | [0xa1, 0xb2, 0xc3, 0xd4]
+            | [0x4d, 0x3c, 0xb2, 0xa1]
+            | [0xa1, 0xb2, 0x3c, 0x4d]
+            | [0x0a, 0x0d, 0x0d, 0x0a] => true,
+            _ => false,
+        }
+}
+
+// `bytes` decoded from hex if they are hex text, otherwise unchanged
+fn unhex(bytes: Vec<u8>) -> Vec<u8> {
+    let text: Vec<u8> = bytes
+        .iter()
+        .cloned()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+
+    if !text.is_empty() && text.iter().all(u8::is_ascii_hexdigit) {
+        if let Ok(decoded) = hex::decode(&text) {
+            return decoded;
+        }
+    }
+
+    bytes
+}
+
+fn read_input(input: &str) -> Result<Vec<u8>, String> {
+    if input == "-" {
+        let mut bytes = Vec::new();
+        io::stdin()
+            .read_to_end(&mut bytes)
+            .map_err(|e| format!("failed to read stdin: {}", e))?;
+        Ok(bytes)
+    } else if Path::new(input).exists() {
+        fs::read(input).map_err(|e| format!("failed to read '{}': {}", input, e))
+    } else {
+        hex::decode(input.trim())
+            .map_err(|_| format!("'{}' is neither a file nor a hex string", input))
+    }
+}
+
+fn inspect_capture(bytes: &[u8], port: Option<u16>) -> Result<(), String> {
+    let datagrams = parse_pcap(bytes)?;
+    let mut shown = 0;
+
+    for (i, (time, datagram)) in datagrams.iter().enumerate() {
+        if let Some(port) = port {
+            if datagram.src.1 != port && datagram.dst.1 != port {
+                continue;
+            }
+        }
+
+        println!(
+            "#{} at {:.6}: {} -> {}, {} bytes",
+            i + 1,
+            time,
+            format_endpoint(datagram.src),
+            format_endpoint(datagram.dst),
+            datagram.payload.len()
+        );
+        print!("{}", describe_packet(datagram.payload));
+        println!();
+        shown += 1;
+    }
+
+    if shown == 0 {
+        return Err("no matching UDP packets in capture".to_string());
+    }
+    Ok(())
+}
+
+fn main() {
+    let matches = App::new("roughenough-inspect")
+        .version(roughenough_version().as_ref())
+        .long_about("Decode and pretty-print Roughtime messages")
+        .arg(Arg::with_name("INPUT").required(true).help(
+            "A hex string, a file holding a message (raw or hex) or a pcap capture, or - for stdin",
+        ))
+        .arg(
+            Arg::with_name("port")
+                .short("p")
+                .long("port")
+                .takes_value(true)
+                .help("Only decode captured packets to or from this UDP port"),
+        )
+        .get_matches();
+
+    let port = matches.value_of("port").map(|p| {
+        p.parse::<u16>().unwrap_or_else(|_| {
+            eprintln!("invalid port '{}'", p);
+            process::exit(2);
+        })
+    });
+
+    let result = read_input(matches.value_of("INPUT").unwrap()).and_then(|bytes| {
+        if is_pcap(&bytes) {
+            inspect_capture(&bytes, port)
+        } else {
+            print!("{}", describe_packet(&unhex(bytes)));
+            Ok(())
+        }
+    });
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{describe_packet, parse_pcap, unhex};
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use roughenough::{RtMessage, Tag};
+
+    fn sample_response() -> Vec<u8> {
+        let mut srep = RtMessage::new(3);
+        srep.add_u32(Tag::RADI, 1_000_000).unwrap();
+        srep.add_u64(Tag::MIDP, 1_563_817_089_112_000).unwrap();
+        srep.add_field(Tag::ROOT, &[0xab; 64]).unwrap();
+
+        let mut msg = RtMessage::new(3);
+        msg.add_field(Tag::SIG, &[0x11; 64]).unwrap();
+        msg.add_nested(Tag::SREP, &srep).unwrap();
+        msg.add_field(Tag::INDX, &[0, 0, 0, 0]).unwrap();
+        msg.encode().unwrap()
+    }
+
+    #[test]
+    fn nested_messages_and_timestamps_are_decoded() {
+        let out = describe_packet(&sample_response());
+
+        assert!(out.starts_with("message: 3 tags"));
+        assert!(out.contains("\n  message: 3 tags"));
+        assert!(out.contains("2019-07-22T17:38:09.112000Z as microseconds"));
+        assert!(out.contains("    1000000\n"));
+        assert!(!out.contains("!"));
+    }
+
+    #[test]
+    fn unknown_and_unsorted_tags_are_reported() {
+        let mut bytes = vec![2, 0, 0, 0, 4, 0, 0, 0];
+        bytes.extend_from_slice(b"XTRZNONC");
+        bytes.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let out = describe_packet(&bytes);
+        assert!(out.contains("! tag XTRZ is unknown"));
+        assert!(out.contains("! tag NONC is not in increasing order"));
+        assert!(out.contains("XTRZ (4 bytes)\n  01020304"));
+    }
+
+    #[test]
+    fn framed_and_hex_input() {
+        let msg = sample_response();
+        let mut framed = b"ROUGHTIM".to_vec();
+        framed.write_u32::<LittleEndian>(msg.len() as u32).unwrap();
+        framed.extend_from_slice(&msg);
+
+        let text = format!("{}\n", hex::encode(&framed));
+        let out = describe_packet(&unhex(text.into_bytes()));
+        assert!(out.starts_with(&format!("framed: {} byte message", msg.len())));
+    }
+
+    #[test]
+    fn udp_payloads_are_extracted_from_pcap() {
+        let payload = sample_response();
+
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        let total_len = 20 + 8 + payload.len();
+        frame.extend_from_slice(&[0x45, 0, (total_len >> 8) as u8, total_len as u8]);
+        frame.extend_from_slice(&[0, 0, 0, 0, 64, 17, 0, 0]);
+        frame.extend_from_slice(&[192, 0, 2, 1, 192, 0, 2, 2]);
+        let udp_len = 8 + payload.len();
+        frame.extend_from_slice(&[0x07, 0xd2, 0xc3, 0x50]);
+        frame.extend_from_slice(&[(udp_len >> 8) as u8, udp_len as u8, 0, 0]);
+        frame.extend_from_slice(&payload);
+
+        let mut pcap = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        pcap.extend_from_slice(&[0; 8]);
+        for &field in &[65535, 1, 1_563_817_089, 0, frame.len(), frame.len()] {
+            pcap.write_u32::<LittleEndian>(field as u32).unwrap();
+        }
+        pcap.extend_from_slice(&frame);
+
+        let datagrams = parse_pcap(&pcap).unwrap();
+        assert_eq!(datagrams.len(), 1);
+
+        let datagram = &datagrams[0].1;
+        assert_eq!(datagram.src, ("192.0.2.1".parse().unwrap(), 2002));
+        assert_eq!(datagram.dst, ("192.0.2.2".parse().unwrap(), 50000));
+        assert_eq!(datagram.payload, payload.as_slice());
+    }
+}
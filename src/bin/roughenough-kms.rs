@@ -19,44 +19,246 @@
 #[macro_use]
 extern crate log;
 
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+
 use clap::{App, Arg};
+use ring::rand::{SecureRandom, SystemRandom};
+use roughenough::key::{
+    combine_shares, fingerprint, seed_from_mnemonic, seed_to_mnemonic, split_seed, LongTermKey,
+    SeedShare,
+};
+#[cfg(feature = "ceremony")]
+use roughenough::kms::qr_code;
+use roughenough::kms::{
+    config_snippet, import_sheets, patch_config, read_sheets, ImportedSeed, SealedSeed, Sheet,
+};
 use roughenough::roughenough_version;
 
 #[cfg(feature = "awskms")]
-fn aws_kms(kms_key: &str, plaintext_seed: &[u8]) {
+fn aws_kms(kms_key: &str, plaintext_seed: &[u8]) -> Option<Vec<u8>> {
     use roughenough::kms::{AwsKms, EnvelopeEncryption};
 
     let client = AwsKms::from_arn(kms_key).unwrap();
 
     match EnvelopeEncryption::encrypt_seed(&client, &plaintext_seed) {
-        Ok(encrypted_blob) => {
-            println!("kms_protection: \"{}\"", kms_key);
-            println!("seed: {}", hex::encode(&encrypted_blob));
-        }
+        Ok(encrypted_blob) => Some(encrypted_blob),
         Err(e) => {
             error!("Error: {:?}", e);
+            None
         }
     }
 }
 
 #[cfg(feature = "gcpkms")]
-fn gcp_kms(kms_key: &str, plaintext_seed: &[u8]) {
+fn gcp_kms(kms_key: &str, plaintext_seed: &[u8]) -> Option<Vec<u8>> {
     use roughenough::kms::{EnvelopeEncryption, GcpKms};
 
     let client = GcpKms::from_resource_id(kms_key).unwrap();
 
     match EnvelopeEncryption::encrypt_seed(&client, &plaintext_seed) {
         Ok(encrypted_blob) => {
-            println!("kms_protection: \"{}\"", kms_key);
-            println!("seed: {}", hex::encode(&encrypted_blob));
+            if let Ok(Some(version)) = EnvelopeEncryption::key_version(&encrypted_blob) {
+                info!("Seed wrapped by key version '{}'", version);
+            }
+            Some(encrypted_blob)
         }
         Err(e) => {
             error!("Error: {:?}", e);
+            None
+        }
+    }
+}
+
+// A seed given as hex or, if it has several words, as a BIP39 mnemonic
+fn parse_seed(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim();
+
+    if text.split_whitespace().count() > 1 {
+        seed_from_mnemonic(text).map_err(|e| format!("error parsing seed mnemonic: {:?}", e))
+    } else {
+        hex::decode(text).map_err(|e| format!("error parsing seed value: {}", e))
+    }
+}
+
+// The plaintext seed: from stdin if `arg` is "-", the hex or mnemonic value of `arg`, or
+// freshly generated
+fn plaintext_seed(arg: Option<&str>) -> Result<Vec<u8>, String> {
+    match arg {
+        Some("-") => {
+            let mut input = String::new();
+            io::stdin()
+                .read_to_string(&mut input)
+                .map_err(|e| format!("cannot read seed from stdin: {}", e))?;
+            parse_seed(&input)
         }
+        Some(seed) => parse_seed(seed),
+        None => {
+            let mut seed = vec![0u8; 32];
+            SystemRandom::new()
+                .fill(&mut seed)
+                .map_err(|_| "cannot generate a random seed".to_string())?;
+            Ok(seed)
+        }
+    }
+}
+
+// Recover the seed from Shamir shares read from stdin, one per line
+fn combined_seed() -> Result<Vec<u8>, String> {
+    let mut input = String::new();
+    io::stdin()
+        .read_to_string(&mut input)
+        .map_err(|e| format!("cannot read shares from stdin: {}", e))?;
+
+    let shares = input
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.parse::<SeedShare>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("error parsing share: {:?}", e))?;
+
+    info!("Combining {} shares", shares.len());
+    combine_shares(&shares).map_err(|e| format!("cannot combine shares: {:?}", e))
+}
+
+// Split `seed` into `shares` Shamir shares with `threshold`, exiting if that is not possible
+fn split(seed: &[u8], threshold: &str, shares: &str) -> Vec<SeedShare> {
+    let split = match (threshold.parse::<u8>(), shares.parse::<u8>()) {
+        (Ok(t), Ok(n)) => split_seed(seed, t, n).map_err(|e| format!("{:?}", e)),
+        _ => Err(format!("'{}' of '{}' is not a number of shares", threshold, shares)),
+    };
+
+    split.unwrap_or_else(|e| {
+        error!("Cannot split seed: {}", e);
+        process::exit(1);
+    })
+}
+
+// Print Shamir shares one per line
+fn print_shares(shares: &[SeedShare]) {
+    println!(
+        "Shamir shares of the seed, any {} of {} recover it (give one to each custodian):",
+        shares[0].threshold,
+        shares.len()
+    );
+    for share in shares {
+        println!("{}", share);
     }
 }
 
-#[allow(unused_variables)]
+// Print the sheets of an offline key ceremony, each on its own page, then the public key
+#[cfg(feature = "ceremony")]
+fn print_ceremony(public_key: &[u8], sheets: &[Sheet]) {
+    for (i, sheet) in sheets.iter().enumerate() {
+        let label = format!("sheet {} of {}", i + 1, sheets.len());
+        match sheet.printable(&label) {
+            Ok(text) => print!("{}\x0c", text),
+            Err(e) => {
+                error!("Cannot print ceremony sheet: {:?}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    println!("=== Roughenough key ceremony: public key (not secret) ===");
+    println!("Fingerprint : {}", fingerprint(public_key));
+    let hex_key = hex::encode(public_key);
+    println!("\n{}\n\n{}", hex_key, qr_code(&hex_key).unwrap());
+}
+
+#[cfg(not(feature = "ceremony"))]
+fn print_ceremony(_public_key: &[u8], _sheets: &[Sheet]) {
+    panic!("Key ceremony support was not compiled in; rebuild with '--features ceremony'");
+}
+
+// One ceremony sheet per share
+fn share_sheets(public_key: &[u8], shares: Vec<SeedShare>) -> Vec<Sheet> {
+    shares
+        .into_iter()
+        .map(|share| Sheet {
+            public_key: public_key.to_vec(),
+            seed: SealedSeed::Share(share),
+        })
+        .collect()
+}
+
+// Read ceremony sheets from stdin. A KMS-encrypted seed is written out here and `None`
+// returned; a seed recovered from shares is returned to be protected like any other seed.
+fn imported_seed(
+    kms_key: Option<&str>,
+    config: Option<&str>,
+) -> Result<Option<Vec<u8>>, String> {
+    let mut input = String::new();
+    io::stdin()
+        .read_to_string(&mut input)
+        .map_err(|e| format!("cannot read ceremony sheets from stdin: {}", e))?;
+
+    let (public_key, seed) = read_sheets(&input)
+        .and_then(|sheets| import_sheets(&sheets))
+        .map_err(|e| format!("cannot import ceremony sheets: {:?}", e))?;
+
+    info!("Imported ceremony for public key {}", hex::encode(&public_key));
+    info!("Public key fingerprint  : {}", fingerprint(&public_key));
+
+    match seed {
+        ImportedSeed::Plaintext(seed) => Ok(Some(seed)),
+        ImportedSeed::Encrypted(sheet_key, blob) => {
+            if kms_key.map_or(false, |k| k != sheet_key) {
+                return Err(format!(
+                    "seed was encrypted with '{}', not the given key",
+                    sheet_key
+                ));
+            }
+            match config {
+                Some(path) => update_config_file(path, &sheet_key, &blob),
+                None => print!("{}", config_snippet(&sheet_key, &blob)),
+            }
+            Ok(None)
+        }
+    }
+}
+
+// Print `seed` as a numbered BIP39 mnemonic, four words per line, for writing down
+fn print_mnemonic(seed: &[u8]) {
+    let phrase = seed_to_mnemonic(seed).expect("32 byte seed");
+    let words: Vec<&str> = phrase.split(' ').collect();
+
+    println!("BIP39 mnemonic of the seed (secret, store it offline):");
+    for (row, chunk) in words.chunks(4).enumerate() {
+        let line: Vec<String> = chunk
+            .iter()
+            .enumerate()
+            .map(|(i, word)| format!("{:>2}. {:<10}", row * 4 + i + 1, word))
+            .collect();
+        println!("{}", line.join(" ").trim_end());
+    }
+}
+
+// Replace kms_protection and seed in the config file at `path`, keeping the original as `path`.bak
+fn update_config_file(path: &str, kms_key: &str, encrypted_blob: &[u8]) {
+    let backup = format!("{}.bak", path);
+
+    let result = fs::read_to_string(path)
+        .map_err(|e| format!("cannot read {}: {}", path, e))
+        .and_then(|contents| {
+            patch_config(&contents, kms_key, encrypted_blob).map_err(|e| format!("{:?}", e))
+        })
+        .and_then(|patched| {
+            fs::copy(path, &backup).map_err(|e| format!("cannot back up {}: {}", path, e))?;
+            fs::write(path, patched).map_err(|e| format!("cannot write {}: {}", path, e))
+        });
+
+    match result {
+        Ok(_) => info!("Updated {} (previous version saved as {})", path, backup),
+        Err(e) => {
+            error!("Failed to update config file: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+#[allow(unused_variables, unused_mut)]
 pub fn main() {
     use log::Level;
 
@@ -70,22 +272,80 @@ pub fn main() {
                 .short("k")
                 .long("kms-key")
                 .takes_value(true)
-                .required(true)
+                .required_unless_one(&["MNEMONIC", "SHARES", "COMBINE", "IMPORT"])
                 .help("Identity of the KMS key to be used"),
         ).arg(
             Arg::with_name("SEED")
                 .short("s")
                 .long("seed")
                 .takes_value(true)
-                .required(true)
-                .help("32 byte hex seed for the server's long-term identity"),
+                .help("32 byte hex seed, or its 24 word BIP39 mnemonic, for the server's long-term identity, or '-' to read it from stdin. If omitted, a new random seed is generated"),
+        ).arg(
+            Arg::with_name("CONFIG")
+                .short("c")
+                .long("config")
+                .takes_value(true)
+                .help("Set kms_protection and seed in this YAML config file (the original is kept as <file>.bak) instead of printing them"),
+        ).arg(
+            Arg::with_name("MNEMONIC")
+                .short("m")
+                .long("mnemonic")
+                .help("Also print the seed as a BIP39 mnemonic for an offline paper backup. Without --kms-key, only the mnemonic is printed"),
+        ).arg(
+            Arg::with_name("SHARES")
+                .long("shares")
+                .takes_value(true)
+                .requires("THRESHOLD")
+                .help("Also split the seed into this many Shamir shares (at most 255), one per custodian. Without --kms-key, only the shares are printed"),
+        ).arg(
+            Arg::with_name("THRESHOLD")
+                .long("threshold")
+                .takes_value(true)
+                .requires("SHARES")
+                .help("Number of Shamir shares needed to recover the seed, at least 2"),
+        ).arg(
+            Arg::with_name("COMBINE")
+                .long("combine")
+                .conflicts_with("SEED")
+                .help("Recover the seed from a quorum of Shamir shares read from stdin, one per line"),
+        ).arg(
+            Arg::with_name("CEREMONY")
+                .long("ceremony")
+                .conflicts_with_all(&["SEED", "COMBINE", "CONFIG"])
+                .help("Offline key ceremony: generate a new seed and print the public key and the KMS-encrypted (--kms-key) and/or share-split (--shares) seed as printable sheets with QR codes (requires the ceremony feature)"),
+        ).arg(
+            Arg::with_name("IMPORT")
+                .long("import")
+                .conflicts_with_all(&["SEED", "COMBINE", "CEREMONY"])
+                .help("Import key ceremony sheets (typed or scanned) from stdin. An encrypted seed goes straight into the config; shares are combined, checked against the public key, and encrypted with --kms-key"),
         ).get_matches();
 
-    let kms_key = matches.value_of("KEY_ID").unwrap();
-    let plaintext_seed = matches
-        .value_of("SEED")
-        .map(|seed| hex::decode(seed).expect("Error parsing seed value"))
-        .unwrap();
+    let is_ceremony = matches.is_present("CEREMONY");
+    if is_ceremony && !cfg!(feature = "ceremony") {
+        error!("Key ceremony support was not compiled in; rebuild with '--features ceremony'");
+        process::exit(1);
+    }
+    if is_ceremony && !matches.is_present("KEY_ID") && !matches.is_present("SHARES") {
+        error!("A ceremony needs --kms-key and/or --shares to protect the seed");
+        process::exit(1);
+    }
+
+    let seed = if matches.is_present("IMPORT") {
+        match imported_seed(matches.value_of("KEY_ID"), matches.value_of("CONFIG")) {
+            Ok(Some(seed)) => Ok(seed),
+            Ok(None) => return,
+            Err(e) => Err(e),
+        }
+    } else if matches.is_present("COMBINE") {
+        combined_seed()
+    } else {
+        plaintext_seed(matches.value_of("SEED"))
+    };
+
+    let plaintext_seed = seed.unwrap_or_else(|e| {
+        error!("{}", e);
+        process::exit(1);
+    });
 
     if plaintext_seed.len() != 32 {
         error!(
@@ -95,14 +355,86 @@ pub fn main() {
         return;
     }
 
+    if matches.is_present("MNEMONIC") {
+        print_mnemonic(&plaintext_seed);
+    }
+
+    let public_key = LongTermKey::new(&plaintext_seed).public_key().to_vec();
+    let mut sheets = Vec::new();
+
+    if let Some(shares) = matches.value_of("SHARES") {
+        let shares = split(&plaintext_seed, matches.value_of("THRESHOLD").unwrap(), shares);
+        if is_ceremony {
+            sheets = share_sheets(&public_key, shares);
+        } else {
+            print_shares(&shares);
+        }
+    }
+
+    let kms_key = match matches.value_of("KEY_ID") {
+        Some(kms_key) => kms_key,
+        None if is_ceremony => {
+            print_ceremony(&public_key, &sheets);
+            return;
+        }
+        None => {
+            info!("Long-term public key    : {}", hex::encode(&public_key));
+            info!("Public key fingerprint  : {}", fingerprint(&public_key));
+            return;
+        }
+    };
+
+    let mut encrypted_blob: Option<Vec<u8>> = None;
+
     if cfg!(feature = "awskms") {
         #[cfg(feature = "awskms")]
-        aws_kms(kms_key, &plaintext_seed);
+        {
+            encrypted_blob = aws_kms(kms_key, &plaintext_seed);
+        }
     } else if cfg!(feature = "gcpkms") {
         #[cfg(feature = "gcpkms")]
-        gcp_kms(kms_key, &plaintext_seed);
+        {
+            encrypted_blob = gcp_kms(kms_key, &plaintext_seed);
+        }
     } else {
         warn!("KMS support was not compiled, nothing to do.");
         warn!("For information on KMS support see the Roughenough documentation.");
+        return;
+    }
+
+    let encrypted_blob = match encrypted_blob {
+        Some(blob) => blob,
+        None => process::exit(1),
+    };
+
+    if is_ceremony {
+        sheets.insert(
+            0,
+            Sheet {
+                public_key: public_key.clone(),
+                seed: SealedSeed::Encrypted {
+                    kms_key: kms_key.to_string(),
+                    blob: encrypted_blob,
+                },
+            },
+        );
+        print_ceremony(&public_key, &sheets);
+        return;
+    }
+
+    let given_seed = ["SEED", "COMBINE", "IMPORT"]
+        .iter()
+        .any(|arg| matches.is_present(arg));
+
+    if !given_seed {
+        // the plaintext seed is never shown, only its public key
+        info!("Generated a new long-term identity");
+        info!("Long-term public key    : {}", hex::encode(&public_key));
+        info!("Public key fingerprint  : {}", fingerprint(&public_key));
+    }
+
+    match matches.value_of("CONFIG") {
+        Some(path) => update_config_file(path, kms_key, &encrypted_blob),
+        None => print!("{}", config_snippet(kms_key, &encrypted_blob)),
     }
 }
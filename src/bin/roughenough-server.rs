@@ -24,91 +24,281 @@
 extern crate log;
 
 use std::env;
+use std::fs;
 use std::process;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
 
+use roughenough::audit;
 use roughenough::config;
 use roughenough::config::ServerConfig;
+use roughenough::ecosystem;
+use roughenough::key::{self, LongTermKey, SharedDelegation};
+use roughenough::kms;
+use roughenough::privileges;
 use roughenough::roughenough_version;
+use roughenough::selftest;
 use roughenough::server::Server;
 
-macro_rules! check_ctrlc {
-    ($keep_running:expr) => {
-        if !$keep_running.load(Ordering::Acquire) {
-            warn!("Ctrl-C caught, exiting...");
-            return;
-        }
-    };
-}
+fn serve(config: Box<ServerConfig>) {
+    if let Err(e) = privileges::check_root(config.as_ref()) {
+        error!("{:?}", e);
+        process::exit(1);
+    }
 
-fn polling_loop(config: Box<ServerConfig>) {
     let mut server = Server::new(config);
+    let public_key = hex::decode(server.get_public_key()).unwrap();
 
-    info!("Long-term public key    : {}", server.get_public_key());
-    info!("Online public key       : {}", server.get_online_key());
-    info!(
-        "Max response batch size : {}",
-        server.get_config().batch_size()
-    );
-    info!(
-        "Status updates every    : {} seconds",
-        server.get_config().status_interval().as_secs()
-    );
-    info!(
-        "Server listening on     : {}:{}",
-        server.get_config().interface(),
-        server.get_config().port()
-    );
-    info!(
-        "Second offset is : {}",
-        server.get_config().secondsoffset()
-    );
-    if let Some(hc_port) = server.get_config().health_check_port() {
-        info!(
-            "TCP health check        : {}:{}",
-            server.get_config().interface(),
-            hc_port
-        );
+    // written before dropping privileges, like the audit log it may live in a protected directory
+    if let Some(path) = server.get_config().public_key_file() {
+        if let Err(e) = fs::write(path, key::describe_public_key(&public_key)) {
+            error!("Failed to write public key file '{}': {}", path, e);
+            process::exit(1);
+        }
     }
 
+    // sockets are bound, root is no longer needed
+    if let Err(e) = privileges::drop_privileges(server.get_config().as_ref()) {
+        error!("Failed to drop privileges: {:?}", e);
+        process::exit(1);
+    }
+
+    server.log_startup();
+
     let kr = server.get_keep_running();
-    let kr_new = kr.clone();
 
     ctrlc::set_handler(move || kr.store(false, Ordering::Release))
         .expect("failed setting Ctrl-C handler");
 
-    loop {
-        check_ctrlc!(kr_new);
-        if server.process_events() {
-            return;
+    server.run();
+}
+
+// Run `workers` copies of this server, restarting any that exit, until Ctrl-C
+#[cfg(unix)]
+fn supervise(config: Box<ServerConfig>, args: &[String]) {
+    use roughenough::supervisor::Supervisor;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    if let Err(e) = privileges::check_root(config.as_ref()) {
+        error!("{:?}", e);
+        process::exit(1);
+    }
+
+    let mut supervisor = match Supervisor::new(config.workers(), args) {
+        Ok(supervisor) => supervisor,
+        Err(e) => {
+            error!("Failed to start supervisor: {:?}", e);
+            process::exit(1);
+        }
+    };
+
+    info!("Supervising {} workers", config.workers());
+
+    let keep_running = Arc::new(AtomicBool::new(true));
+    let kr = keep_running.clone();
+
+    ctrlc::set_handler(move || kr.store(false, Ordering::Release))
+        .expect("failed setting Ctrl-C handler");
+
+    supervisor.run(&keep_running, config.status_interval());
+    warn!("Ctrl-C caught, workers stopped, exiting...");
+}
+
+#[cfg(not(unix))]
+fn supervise(_config: Box<ServerConfig>, _args: &[String]) {
+    error!("multi-process mode (workers) is only supported on Unix");
+    process::exit(1);
+}
+
+#[cfg(unix)]
+fn is_worker() -> bool {
+    roughenough::supervisor::is_worker()
+}
+
+#[cfg(not(unix))]
+fn is_worker() -> bool {
+    false
+}
+
+// Print this server's ecosystem.json entry without starting the server
+fn print_ecosystem_entry(config: Box<ServerConfig>, name: &str, address: &str) {
+    let seed = match kms::load_seed(&config) {
+        Ok(seed) => seed,
+        Err(e) => {
+            error!("Failed to load seed: {:#?}", e);
+            process::exit(1);
+        }
+    };
+
+    let long_term_key = LongTermKey::new(&seed);
+    println!(
+        "{}",
+        ecosystem::server_entry(name, address, long_term_key.public_key())
+    );
+}
+
+// Generate an online key and CERT for the whole fleet and write them sealed to `out_file`
+fn write_shared_delegation(config: Box<ServerConfig>, out_file: &str) {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    let seed = match kms::load_seed(&config) {
+        Ok(seed) => seed,
+        Err(e) => {
+            error!("Failed to load seed: {:#?}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut long_term_key = LongTermKey::new(&seed);
+    let delegation = SharedDelegation::generate(&mut long_term_key);
+    let sealed = delegation.seal(&seed).expect("sealing shared delegation");
+
+    let result = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(out_file)
+        .and_then(|mut f| f.write_all(&sealed));
+
+    match result {
+        Ok(_) => {
+            info!("Online public key : {}", delegation.online_key());
+            info!("Sealed delegation : written to '{}'", out_file);
+        }
+        Err(e) => {
+            error!("Failed to write '{}': {}", out_file, e);
+            process::exit(1);
         }
     }
 }
 
-pub fn main() {
-    use log::Level;
+// Print the audit log records (oldest first) matching an optional client IP or nonce prefix
+fn print_audit_log(path: &str, filter: Option<&str>) {
+    use chrono::{TimeZone, Utc};
+    use std::net::IpAddr;
 
-    simple_logger::init_with_level(Level::Info).unwrap();
+    let ip_filter = filter.and_then(|f| f.parse::<IpAddr>().ok());
+    let nonce_filter = match (filter, ip_filter) {
+        (Some(f), None) => match hex::decode(f) {
+            Ok(prefix) => Some(prefix),
+            Err(_) => {
+                error!("'{}' is neither an IP address nor a hex nonce prefix", f);
+                process::exit(1);
+            }
+        },
+        _ => None,
+    };
 
-    info!("Roughenough server v{} starting", roughenough_version());
+    let records = match audit::read_all(path) {
+        Ok(records) => records,
+        Err(e) => {
+            error!("Failed to read audit log '{}': {:?}", path, e);
+            process::exit(1);
+        }
+    };
 
-    let mut args = env::args();
-    if args.len() != 2 {
-        error!("Usage: server <ENV | /path/to/config.yaml>");
-        process::exit(1);
+    for record in records {
+        if ip_filter.map_or(false, |ip| ip != record.addr.ip()) {
+            continue;
+        }
+        if let Some(ref prefix) = nonce_filter {
+            if !record.nonce_prefix.starts_with(prefix) {
+                continue;
+            }
+        }
+
+        let secs = (record.midpoint / 1_000_000) as i64;
+        let nsecs = (record.midpoint % 1_000_000) as u32 * 1_000;
+
+        println!(
+            "{} batch={} client={} nonce={}",
+            Utc.timestamp(secs, nsecs).to_rfc3339(),
+            record.batch,
+            record.addr,
+            hex::encode(&record.nonce_prefix)
+        );
     }
+}
+
+// Measure attainable responses/sec on one core and then on every core, without the network
+fn selftest_bench(batch_size: u8) {
+    let cores = num_cpus::get();
+    let duration = Duration::from_secs(5);
 
-    let arg1 = args.nth(1).unwrap();
-    let config = match config::make_config(&arg1) {
+    info!("Benchmarking batches of {} requests for {:?} per run", batch_size, duration);
+    let single = selftest::run(batch_size, 1, duration);
+    info!("{}", single);
+
+    if cores > 1 {
+        let all = selftest::run(batch_size, cores, duration);
+        info!("{}", all);
+        info!(
+            "Capacity estimate: {:.0} responses/sec on {} cores",
+            all.responses_per_sec(),
+            cores
+        );
+    } else {
+        info!("Capacity estimate: {:.0} responses/sec", single.responses_per_sec());
+    }
+}
+
+fn load_config(arg: &str) -> Box<ServerConfig> {
+    match config::from_arg(arg) {
         Err(e) => {
             error!("{:?}", e);
             process::exit(1)
         }
         Ok(ref cfg) if !config::is_valid_config(&cfg) => process::exit(1),
         Ok(cfg) => cfg,
-    };
+    }
+}
+
+pub fn main() {
+    use log::Level;
+
+    simple_logger::init_with_level(Level::Info).unwrap();
+
+    let args: Vec<String> = env::args().collect();
 
-    polling_loop(config);
+    match args.len() {
+        2 | 3 if args[1] == "--selftest-bench" => {
+            let batch_size = args.get(2).map_or(64, |arg| load_config(arg).batch_size());
+            selftest_bench(batch_size);
+            return;
+        }
+        2 => {
+            info!("Roughenough server v{} starting", roughenough_version());
+            let config = load_config(&args[1]);
+
+            if config.workers() > 0 && !is_worker() {
+                supervise(config, &args[1..]);
+            } else {
+                serve(config);
+            }
+        }
+        // stdout is the entry only, so no startup logging
+        5 if args[1] == "ecosystem" => {
+            print_ecosystem_entry(load_config(&args[2]), &args[3], &args[4]);
+            return;
+        }
+        4 if args[1] == "share-delegation" => {
+            write_shared_delegation(load_config(&args[2]), &args[3]);
+            return;
+        }
+        3 | 4 if args[1] == "audit" => {
+            print_audit_log(&args[2], args.get(3).map(String::as_str));
+            return;
+        }
+        _ => {
+            error!("Usage: server <ENV | /path/to/config.yaml>");
+            error!("       server ecosystem <ENV | /path/to/config.yaml> <name> <host:port>");
+            error!("       server share-delegation <ENV | /path/to/config.yaml> <output file>");
+            error!("       server audit <audit log> [client IP | hex nonce prefix]");
+            error!("       server --selftest-bench [ENV | /path/to/config.yaml]");
+            process::exit(1);
+        }
+    }
 
     info!("Done.");
     process::exit(0);
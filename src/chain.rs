@@ -0,0 +1,189 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Query chains in the JSON format written by the Go reference client.
+//!
+//! Each query after the first uses a nonce derived from the previous reply, so a chain
+//! proves its replies were received in order. A third party holding the chain can detect
+//! any server whose reply contradicts that order.
+//!
+//! ```json
+//! {
+//!   "links": [
+//!     {
+//!       "publicKeyType": "ed25519",
+//!       "serverPublicKey": "AW5uAoTSTDfG5NfY1bTh08GUnOqlRb+HVhbJ3ODJvsE=",
+//!       "nonceOrBlind": "...",
+//!       "reply": "..."
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! The first link's `nonceOrBlind` is the nonce itself; later links hold a random blind
+//! and their nonce is `SHA-512(previous reply || blind)`.
+//!
+
+use ring::digest;
+
+use crate::NONCE_LENGTH;
+
+/// One query in a chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainLink {
+    /// Long-term public key of the server that was queried
+    pub public_key: Vec<u8>,
+    /// The nonce (first link) or the blind (later links)
+    pub nonce_or_blind: Vec<u8>,
+    /// The server's complete encoded reply
+    pub reply: Vec<u8>,
+}
+
+///
+/// A sequence of queries, each nonce committing to the reply before it.
+///
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Chain {
+    links: Vec<ChainLink>,
+}
+
+impl Chain {
+    /// An empty chain
+    pub fn new() -> Chain {
+        Chain { links: Vec::new() }
+    }
+
+    /// The links added so far, oldest first
+    pub fn links(&self) -> &[ChainLink] {
+        &self.links
+    }
+
+    ///
+    /// The nonce to send in the next query, given a freshly generated random `blind`.
+    /// This is `blind` itself for the first query.
+    ///
+    pub fn next_nonce(&self, blind: &[u8]) -> Vec<u8> {
+        match self.links.last() {
+            Some(link) => chained_nonce(&link.reply, blind),
+            None => blind.to_vec(),
+        }
+    }
+
+    /// Record a query to the server with `public_key` made using `blind`, and its `reply`
+    pub fn push(&mut self, public_key: &[u8], blind: &[u8], reply: &[u8]) {
+        self.links.push(ChainLink {
+            public_key: public_key.to_vec(),
+            nonce_or_blind: blind.to_vec(),
+            reply: reply.to_vec(),
+        });
+    }
+
+    /// The nonce each link's query was sent with, recomputed from the chain
+    pub fn nonces(&self) -> Vec<Vec<u8>> {
+        let mut prev: Option<&[u8]> = None;
+        let mut nonces = Vec::with_capacity(self.links.len());
+
+        for link in &self.links {
+            nonces.push(match prev {
+                Some(reply) => chained_nonce(reply, &link.nonce_or_blind),
+                None => link.nonce_or_blind.clone(),
+            });
+            prev = Some(&link.reply);
+        }
+
+        nonces
+    }
+
+    /// Serialize the chain as Go reference client JSON
+    pub fn to_json(&self) -> String {
+        let links: Vec<String> = self
+            .links
+            .iter()
+            .map(|link| {
+                format!(
+                    r#"    {{
+      "publicKeyType": "ed25519",
+      "serverPublicKey": "{}",
+      "nonceOrBlind": "{}",
+      "reply": "{}"
+    }}"#,
+                    base64::encode(&link.public_key),
+                    base64::encode(&link.nonce_or_blind),
+                    base64::encode(&link.reply)
+                )
+            })
+            .collect();
+
+        format!("{{\n  \"links\": [\n{}\n  ]\n}}\n", links.join(",\n"))
+    }
+}
+
+/// Nonce for a query following the one answered by `prev_reply`, using the random `blind`
+pub fn chained_nonce(prev_reply: &[u8], blind: &[u8]) -> Vec<u8> {
+    let mut ctx = digest::Context::new(&digest::SHA512);
+    ctx.update(prev_reply);
+    ctx.update(blind);
+
+    ctx.finish().as_ref()[..NONCE_LENGTH as usize].to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chain::{chained_nonce, Chain};
+    use ring::digest;
+
+    #[test]
+    fn first_nonce_is_the_blind() {
+        let chain = Chain::new();
+        assert_eq!(chain.next_nonce(&[7u8; 64]), vec![7u8; 64]);
+    }
+
+    #[test]
+    fn later_nonces_hash_previous_reply_and_blind() {
+        let mut chain = Chain::new();
+        chain.push(&[1u8; 32], &[2u8; 64], b"first reply");
+
+        let expected = digest::digest(&digest::SHA512, b"first reply\x03\x03\x03\x03");
+        assert_eq!(chain.next_nonce(&[3u8; 4]), expected.as_ref().to_vec());
+        assert_eq!(chained_nonce(b"first reply", &[3u8; 4]).len(), 64);
+    }
+
+    #[test]
+    fn nonces_are_recomputed_from_links() {
+        let mut chain = Chain::new();
+
+        let first = chain.next_nonce(&[2u8; 64]);
+        chain.push(&[1u8; 32], &[2u8; 64], b"reply one");
+        let second = chain.next_nonce(&[4u8; 64]);
+        chain.push(&[1u8; 32], &[4u8; 64], b"reply two");
+
+        assert_eq!(chain.nonces(), vec![first, second]);
+    }
+
+    #[test]
+    fn json_has_go_field_names() {
+        let mut chain = Chain::new();
+        chain.push(&[0u8; 3], &[0xffu8; 3], b"abc");
+        chain.push(&[0u8; 3], &[0xffu8; 3], b"abc");
+
+        let json = chain.to_json();
+        assert!(json.starts_with("{\n  \"links\": ["));
+        assert!(json.contains(r#""publicKeyType": "ed25519""#));
+        assert!(json.contains(r#""serverPublicKey": "AAAA""#));
+        assert!(json.contains(r#""nonceOrBlind": "////""#));
+        assert!(json.contains(r#""reply": "YWJj""#));
+        assert_eq!(json.matches("publicKeyType").count(), 2);
+    }
+}
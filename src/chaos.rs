@@ -0,0 +1,219 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Deliberate misbehavior for testing Roughtime clients and verifiers.
+//!
+//! Faults are configured with `chaos_faults` and `chaos_probability` and are only
+//! injected when the server is compiled with the `chaos` feature.
+//!
+
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(feature = "chaos")]
+use byteorder::{ByteOrder, LittleEndian};
+#[cfg(feature = "chaos")]
+use ring::rand::{SecureRandom, SystemRandom};
+
+#[cfg(feature = "chaos")]
+use crate::config::ServerConfig;
+use crate::{RtMessage, Tag, HASH_LENGTH};
+
+/// Amount (in seconds) a `SkewedMidpoint` fault moves the midpoint into the future
+pub const SKEW_SECONDS: u64 = 60 * 60;
+
+/// A kind of misbehavior the server can inject into a response
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Fault {
+    /// A validly signed SREP whose midpoint is `SKEW_SECONDS` in the future
+    SkewedMidpoint,
+
+    /// The SREP signature has a flipped bit
+    InvalidSignature,
+
+    /// The PATH is cut off in the middle of a hash
+    TruncatedPath,
+
+    /// A validly signed CERT whose delegation expired at the start of the epoch
+    ExpiredDelegation,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::SkewedMidpoint => write!(f, "skewed-midpoint"),
+            Fault::InvalidSignature => write!(f, "invalid-signature"),
+            Fault::TruncatedPath => write!(f, "truncated-path"),
+            Fault::ExpiredDelegation => write!(f, "expired-delegation"),
+        }
+    }
+}
+
+impl FromStr for Fault {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Fault, String> {
+        match s {
+            "skewed-midpoint" => Ok(Fault::SkewedMidpoint),
+            "invalid-signature" => Ok(Fault::InvalidSignature),
+            "truncated-path" => Ok(Fault::TruncatedPath),
+            "expired-delegation" => Ok(Fault::ExpiredDelegation),
+            s => Err(format!("unknown Fault '{}'", s)),
+        }
+    }
+}
+
+///
+/// Decides, per response, whether and which configured fault to inject.
+///
+#[cfg(feature = "chaos")]
+pub struct FaultInjector {
+    faults: Vec<Fault>,
+    probability: f64,
+    rng: SystemRandom,
+}
+
+#[cfg(feature = "chaos")]
+impl FaultInjector {
+    /// Returns an injector for the faults in `config`, or `None` if no faults are configured
+    pub fn from_config(config: &ServerConfig) -> Option<FaultInjector> {
+        if config.chaos_faults().is_empty() {
+            return None;
+        }
+
+        Some(FaultInjector {
+            faults: config.chaos_faults().to_vec(),
+            probability: config.chaos_probability(),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    /// Returns true if `fault` is one of the configured faults
+    pub fn injects(&self, fault: Fault) -> bool {
+        self.faults.contains(&fault)
+    }
+
+    /// With the configured probability return one of the configured faults (chosen uniformly)
+    pub fn choose(&self) -> Option<Fault> {
+        let mut bytes = [0u8; 8];
+        self.rng.fill(&mut bytes).unwrap();
+
+        let roll = f64::from(LittleEndian::read_u32(&bytes[0..4])) / (f64::from(u32::max_value()) + 1.0);
+
+        if roll >= self.probability {
+            return None;
+        }
+
+        let pick = LittleEndian::read_u32(&bytes[4..8]) as usize;
+        Some(self.faults[pick % self.faults.len()])
+    }
+}
+
+/// Return a copy of `msg` with the value of `tag` replaced by `value`
+pub fn replace_field(msg: &RtMessage, tag: Tag, value: &[u8]) -> RtMessage {
+    let mut result = RtMessage::new(msg.num_fields());
+
+    for (t, v) in msg {
+        let v = if t == tag { value } else { v };
+        result.add_field(t, v).unwrap();
+    }
+
+    result
+}
+
+/// Return a copy of the signed response `srep` (SIG and SREP) with a corrupted signature
+pub fn corrupt_signature(srep: &RtMessage) -> RtMessage {
+    let mut sig = srep.get_field(Tag::SIG).unwrap().to_vec();
+    sig[0] ^= 0x01;
+
+    replace_field(srep, Tag::SIG, &sig)
+}
+
+/// Cut `path` off in the middle of its last hash. An empty path becomes half a hash.
+pub fn truncate_path(path: &[u8]) -> Vec<u8> {
+    let half_hash = HASH_LENGTH as usize / 2;
+
+    if path.is_empty() {
+        vec![0u8; half_hash]
+    } else {
+        path[..path.len() - half_hash].to_vec()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chaos::{corrupt_signature, truncate_path, Fault};
+    #[cfg(feature = "chaos")]
+    use crate::{chaos::FaultInjector, config::MemoryConfig};
+    use crate::{RtMessage, Tag};
+
+    #[test]
+    fn fault_from_string() {
+        for fault in &[
+            Fault::SkewedMidpoint,
+            Fault::InvalidSignature,
+            Fault::TruncatedPath,
+            Fault::ExpiredDelegation,
+        ] {
+            assert_eq!(fault.to_string().parse(), Ok(*fault));
+        }
+        assert!("frobble".parse::<Fault>().is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "chaos")]
+    fn no_faults_no_injector() {
+        assert!(FaultInjector::from_config(&MemoryConfig::new(0)).is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "chaos")]
+    fn probability_bounds_are_respected() {
+        let always = MemoryConfig::builder()
+            .chaos_faults(&[Fault::TruncatedPath])
+            .chaos_probability(1.0)
+            .build();
+        let never = MemoryConfig::builder()
+            .chaos_faults(&[Fault::TruncatedPath])
+            .chaos_probability(0.0)
+            .build();
+
+        let always = FaultInjector::from_config(&always).unwrap();
+        let never = FaultInjector::from_config(&never).unwrap();
+
+        for _ in 0..100 {
+            assert_eq!(always.choose(), Some(Fault::TruncatedPath));
+            assert_eq!(never.choose(), None);
+        }
+    }
+
+    #[test]
+    fn truncated_paths_are_not_whole_hashes() {
+        assert_eq!(truncate_path(&[]).len(), 32);
+        assert_eq!(truncate_path(&[0; 128]).len(), 96);
+    }
+
+    #[test]
+    fn signature_is_corrupted() {
+        let mut srep = RtMessage::new(2);
+        srep.add_field(Tag::SIG, &[0; 64]).unwrap();
+        srep.add_field(Tag::SREP, &[1; 8]).unwrap();
+
+        let corrupted = corrupt_signature(&srep);
+
+        assert_ne!(corrupted.get_field(Tag::SIG), srep.get_field(Tag::SIG));
+        assert_eq!(corrupted.get_field(Tag::SREP), srep.get_field(Tag::SREP));
+    }
+}
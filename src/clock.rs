@@ -0,0 +1,257 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Sources of the time placed in a response's midpoint.
+//!
+
+use std::fmt;
+use std::str::FromStr;
+
+use time::Timespec;
+
+use crate::Error;
+
+/// Clock the server reads its midpoint from
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ClockSource {
+    /// The UTC system clock (`CLOCK_REALTIME`)
+    Realtime,
+
+    /// International Atomic Time (`CLOCK_TAI`), which has no leap seconds. Responses also
+    /// carry a `DTAI` tag with the current TAI-UTC offset. Linux only.
+    Tai,
+}
+
+impl fmt::Display for ClockSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClockSource::Realtime => write!(f, "realtime"),
+            ClockSource::Tai => write!(f, "tai"),
+        }
+    }
+}
+
+impl FromStr for ClockSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ClockSource, String> {
+        match s {
+            "realtime" => Ok(ClockSource::Realtime),
+            "tai" => Ok(ClockSource::Tai),
+            s => Err(format!("unknown ClockSource '{}'", s)),
+        }
+    }
+}
+
+///
+/// Reads the current time from a [`ClockSource`](enum.ClockSource.html).
+///
+pub struct Clock {
+    source: ClockSource,
+}
+
+impl Clock {
+    ///
+    /// A clock reading from `source`. Fails if `source` is `Tai` and the kernel does not
+    /// know the TAI-UTC offset; `CLOCK_TAI` would then silently equal `CLOCK_REALTIME`.
+    ///
+    pub fn new(source: ClockSource) -> Result<Clock, Error> {
+        if source == ClockSource::Tai {
+            let offset = tai_offset()?;
+            if offset == 0 {
+                return Err(Error::InvalidConfiguration(
+                    "clock_source is 'tai' but the kernel's TAI offset is not set \
+                     (configure it with e.g. chrony's 'leapsectz' or ntpd's 'leapfile')"
+                        .to_string(),
+                ));
+            }
+        }
+
+        Ok(Clock { source })
+    }
+
+    /// The source this clock reads from
+    pub fn source(&self) -> ClockSource {
+        self.source
+    }
+
+    ///
+    /// The current time and, for `Tai` clocks, the TAI-UTC offset in seconds to report
+    /// in the `DTAI` tag.
+    ///
+    pub fn now(&self) -> (Timespec, Option<u32>) {
+        match self.source {
+            ClockSource::Realtime => (time::get_time(), None),
+            ClockSource::Tai => {
+                // the offset was non-zero at startup; keep serving if a later read fails
+                let dtai = tai_offset().ok().map(|offset| offset as u32);
+                (tai_now(), dtai)
+            }
+        }
+    }
+}
+
+/// `now` shifted by `secondsoffset` seconds, in microseconds since the epoch
+pub fn to_micros(now: Timespec, secondsoffset: u64) -> u64 {
+    let secs = (now.sec as u64 + secondsoffset) * 1_000_000;
+    let nsecs = (now.nsec as u64) / 1_000;
+
+    secs + nsecs
+}
+
+///
+/// Round `midpoint` (microseconds) to the nearest multiple of `resolution` microseconds,
+/// widening `radius` by the most the rounding can move the midpoint so the response
+/// remains truthful. Returns the coarsened `(midpoint, radius)`.
+///
+pub fn coarsen(midpoint: u64, radius: u32, resolution: u32) -> (u64, u32) {
+    if resolution <= 1 {
+        return (midpoint, radius);
+    }
+
+    let resolution = u64::from(resolution);
+    let rounded = (midpoint + resolution / 2) / resolution * resolution;
+    let widened = radius.saturating_add(((resolution + 1) / 2) as u32);
+
+    (rounded, widened)
+}
+
+/// The kernel's current TAI-UTC offset in seconds (0 if it has not been configured)
+#[cfg(target_os = "linux")]
+pub fn tai_offset() -> Result<i32, Error> {
+    // `modes` of zero makes adjtimex a read-only query
+    let mut timex: libc::timex = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::adjtimex(&mut timex) } < 0 {
+        return Err(Error::from(std::io::Error::last_os_error()));
+    }
+
+    Ok(timex.tai as i32)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn tai_offset() -> Result<i32, Error> {
+    Err(Error::InvalidConfiguration(
+        "CLOCK_TAI is only supported on Linux".to_string(),
+    ))
+}
+
+///
+/// Whether the kernel considers the system clock synchronized (by NTP, chrony or PTP), or
+/// `None` where that can't be determined.
+///
+#[cfg(target_os = "linux")]
+pub fn is_synchronized() -> Option<bool> {
+    let mut timex: libc::timex = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::adjtimex(&mut timex) } < 0 {
+        return None;
+    }
+
+    Some(timex.status & libc::STA_UNSYNC == 0)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn is_synchronized() -> Option<bool> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn tai_now() -> Timespec {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+
+    if unsafe { libc::clock_gettime(libc::CLOCK_TAI, &mut ts) } != 0 {
+        panic!(
+            "clock_gettime(CLOCK_TAI) failed: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+
+    Timespec::new(ts.tv_sec as i64, ts.tv_nsec as i32)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn tai_now() -> Timespec {
+    unreachable!("Clock::new() rejects ClockSource::Tai on this platform")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::clock::{coarsen, Clock, ClockSource};
+    use crate::key::OnlineKey;
+    use crate::{RtMessage, Tag};
+
+    #[test]
+    fn clock_source_from_string() {
+        assert_eq!("realtime".parse(), Ok(ClockSource::Realtime));
+        assert_eq!("tai".parse(), Ok(ClockSource::Tai));
+        assert!("gps".parse::<ClockSource>().is_err());
+        assert_eq!(ClockSource::Tai.to_string(), "tai");
+    }
+
+    #[test]
+    fn realtime_has_no_dtai() {
+        let clock = Clock::new(ClockSource::Realtime).unwrap();
+        let (now, dtai) = clock.now();
+
+        assert!(now.sec > 0);
+        assert_eq!(dtai, None);
+    }
+
+    #[test]
+    fn srep_carries_dtai_only_when_given() {
+        let mut online_key = OnlineKey::new();
+        let now = time::get_time();
+
+        let with = online_key.make_srep_with_dtai(now, &[0; 64], 0, Some(37));
+        let srep = RtMessage::from_bytes(with.get_field(Tag::SREP).unwrap()).unwrap();
+        assert_eq!(srep.get_u32(Tag::DTAI).unwrap(), 37);
+
+        let without = online_key.make_srep(now, &[0; 64], 0);
+        let srep = RtMessage::from_bytes(without.get_field(Tag::SREP).unwrap()).unwrap();
+        assert!(srep.get_field(Tag::DTAI).is_none());
+    }
+
+    #[test]
+    fn coarsened_midpoint_stays_within_radius() {
+        assert_eq!(coarsen(1_234_567, 1_000_000, 1), (1_234_567, 1_000_000));
+        assert_eq!(coarsen(1_234_567, 1_000_000, 1_000), (1_235_000, 1_000_500));
+        assert_eq!(coarsen(1_234_499, 1_000_000, 1_000), (1_234_000, 1_000_500));
+
+        for &t in &[0u64, 499, 500, 999, 1_000_001, 1_563_849_123_456_789] {
+            for &res in &[1u32, 3, 1_000, 1_000_000] {
+                let (midp, radi) = coarsen(t, 0, res);
+                let error = if midp > t { midp - t } else { t - midp };
+                assert!(error <= u64::from(radi), "t={} res={}", t, res);
+                assert_eq!(midp % u64::from(res), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn tai_is_ahead_of_utc_when_available() {
+        // TAI is only usable where the host's kernel knows the offset
+        if let Ok(clock) = Clock::new(ClockSource::Tai) {
+            let (now, dtai) = clock.now();
+            let utc = time::get_time();
+
+            assert!(dtai.unwrap() > 0);
+            assert!(now.sec > utc.sec);
+        }
+    }
+}
@@ -13,10 +13,17 @@
 // limitations under the License.
 
 use std::env;
+use std::str::FromStr;
 use std::time::Duration;
 
-use crate::config::ServerConfig;
-use crate::config::{DEFAULT_BATCH_SIZE, DEFAULT_STATUS_INTERVAL, DEFAULT_SECONDSOFFSET};
+use crate::config::{HealthCheckMode, ServerConfig};
+use crate::config::{
+    DEFAULT_AUDIT_LOG_MAX_SIZE, DEFAULT_BATCH_SIZE, DEFAULT_CHAOS_PROBABILITY,
+    DEFAULT_MIDPOINT_RESOLUTION, DEFAULT_SECONDSOFFSET, DEFAULT_STATUS_INTERVAL,
+};
+use crate::chaos::Fault;
+use crate::clock::ClockSource;
+use crate::filter::Cidr;
 use crate::key::KmsProtection;
 use crate::Error;
 
@@ -34,6 +41,30 @@ use crate::Error;
 ///   status_interval   | `ROUGHENOUGH_STATUS_INTERVAL`
 ///   kms_protection    | `ROUGHENOUGH_KMS_PROTECTION`
 ///   health_check_port | `ROUGHENOUGH_HEALTH_CHECK_PORT`
+///   user              | `ROUGHENOUGH_USER`
+///   group             | `ROUGHENOUGH_GROUP`
+///   allow_root        | `ROUGHENOUGH_ALLOW_ROOT`
+///   otlp_endpoint     | `ROUGHENOUGH_OTLP_ENDPOINT`
+///   readiness_check_port | `ROUGHENOUGH_READINESS_CHECK_PORT`
+///   health_check_mode | `ROUGHENOUGH_HEALTH_CHECK_MODE`
+///   shared_delegation | `ROUGHENOUGH_SHARED_DELEGATION`
+///   chaos_faults      | `ROUGHENOUGH_CHAOS_FAULTS`
+///   chaos_probability | `ROUGHENOUGH_CHAOS_PROBABILITY`
+///   clock_source      | `ROUGHENOUGH_CLOCK_SOURCE`
+///   midpoint_resolution | `ROUGHENOUGH_MIDPOINT_RESOLUTION`
+///   audit_log         | `ROUGHENOUGH_AUDIT_LOG`
+///   audit_log_max_size | `ROUGHENOUGH_AUDIT_LOG_MAX_SIZE`
+///   workers           | `ROUGHENOUGH_WORKERS`
+///   unicast_hops      | `ROUGHENOUGH_UNICAST_HOPS`
+///   public_key_file   | `ROUGHENOUGH_PUBLIC_KEY_FILE`
+///   rate_limit        | `ROUGHENOUGH_RATE_LIMIT`
+///   rate_limit_exempt_cidrs | `ROUGHENOUGH_RATE_LIMIT_EXEMPT_CIDRS`
+///   allow_cidrs       | `ROUGHENOUGH_ALLOW_CIDRS`
+///   deny_cidrs        | `ROUGHENOUGH_DENY_CIDRS`
+///   shutdown_delay    | `ROUGHENOUGH_SHUTDOWN_DELAY`
+///   bind_device       | `ROUGHENOUGH_BIND_DEVICE`
+///   instance_id       | `ROUGHENOUGH_INSTANCE_ID`
+///   pq_signatures     | `ROUGHENOUGH_PQ_SIGNATURES`
 ///
 pub struct EnvironmentConfig {
     port: u16,
@@ -44,6 +75,30 @@ pub struct EnvironmentConfig {
     status_interval: Duration,
     kms_protection: KmsProtection,
     health_check_port: Option<u16>,
+    user: Option<String>,
+    group: Option<String>,
+    allow_root: bool,
+    otlp_endpoint: Option<String>,
+    readiness_check_port: Option<u16>,
+    health_check_mode: HealthCheckMode,
+    shared_delegation: Option<String>,
+    chaos_faults: Vec<Fault>,
+    chaos_probability: f64,
+    clock_source: ClockSource,
+    midpoint_resolution: u32,
+    audit_log: Option<String>,
+    audit_log_max_size: u64,
+    workers: u32,
+    unicast_hops: Option<u32>,
+    public_key_file: Option<String>,
+    rate_limit: Option<u32>,
+    rate_limit_exempt_cidrs: Vec<Cidr>,
+    allow_cidrs: Vec<Cidr>,
+    deny_cidrs: Vec<Cidr>,
+    shutdown_delay: Duration,
+    bind_device: Option<String>,
+    instance_id: Option<String>,
+    pq_signatures: bool,
 }
 
 const ROUGHENOUGH_PORT: &str = "ROUGHENOUGH_PORT";
@@ -54,9 +109,44 @@ const ROUGHENOUGH_SECONDSOFFSET: &str = "ROUGHENOUGH_SECONDSOFFSET";
 const ROUGHENOUGH_STATUS_INTERVAL: &str = "ROUGHENOUGH_STATUS_INTERVAL";
 const ROUGHENOUGH_KMS_PROTECTION: &str = "ROUGHENOUGH_KMS_PROTECTION";
 const ROUGHENOUGH_HEALTH_CHECK_PORT: &str = "ROUGHENOUGH_HEALTH_CHECK_PORT";
+const ROUGHENOUGH_USER: &str = "ROUGHENOUGH_USER";
+const ROUGHENOUGH_GROUP: &str = "ROUGHENOUGH_GROUP";
+const ROUGHENOUGH_ALLOW_ROOT: &str = "ROUGHENOUGH_ALLOW_ROOT";
+const ROUGHENOUGH_OTLP_ENDPOINT: &str = "ROUGHENOUGH_OTLP_ENDPOINT";
+const ROUGHENOUGH_READINESS_CHECK_PORT: &str = "ROUGHENOUGH_READINESS_CHECK_PORT";
+const ROUGHENOUGH_HEALTH_CHECK_MODE: &str = "ROUGHENOUGH_HEALTH_CHECK_MODE";
+const ROUGHENOUGH_SHARED_DELEGATION: &str = "ROUGHENOUGH_SHARED_DELEGATION";
+const ROUGHENOUGH_CHAOS_FAULTS: &str = "ROUGHENOUGH_CHAOS_FAULTS";
+const ROUGHENOUGH_CHAOS_PROBABILITY: &str = "ROUGHENOUGH_CHAOS_PROBABILITY";
+const ROUGHENOUGH_CLOCK_SOURCE: &str = "ROUGHENOUGH_CLOCK_SOURCE";
+const ROUGHENOUGH_MIDPOINT_RESOLUTION: &str = "ROUGHENOUGH_MIDPOINT_RESOLUTION";
+const ROUGHENOUGH_AUDIT_LOG: &str = "ROUGHENOUGH_AUDIT_LOG";
+const ROUGHENOUGH_AUDIT_LOG_MAX_SIZE: &str = "ROUGHENOUGH_AUDIT_LOG_MAX_SIZE";
+const ROUGHENOUGH_WORKERS: &str = "ROUGHENOUGH_WORKERS";
+const ROUGHENOUGH_UNICAST_HOPS: &str = "ROUGHENOUGH_UNICAST_HOPS";
+const ROUGHENOUGH_PUBLIC_KEY_FILE: &str = "ROUGHENOUGH_PUBLIC_KEY_FILE";
+const ROUGHENOUGH_RATE_LIMIT: &str = "ROUGHENOUGH_RATE_LIMIT";
+const ROUGHENOUGH_RATE_LIMIT_EXEMPT_CIDRS: &str = "ROUGHENOUGH_RATE_LIMIT_EXEMPT_CIDRS";
+const ROUGHENOUGH_ALLOW_CIDRS: &str = "ROUGHENOUGH_ALLOW_CIDRS";
+const ROUGHENOUGH_DENY_CIDRS: &str = "ROUGHENOUGH_DENY_CIDRS";
+const ROUGHENOUGH_SHUTDOWN_DELAY: &str = "ROUGHENOUGH_SHUTDOWN_DELAY";
+const ROUGHENOUGH_BIND_DEVICE: &str = "ROUGHENOUGH_BIND_DEVICE";
+const ROUGHENOUGH_INSTANCE_ID: &str = "ROUGHENOUGH_INSTANCE_ID";
+const ROUGHENOUGH_PQ_SIGNATURES: &str = "ROUGHENOUGH_PQ_SIGNATURES";
 
 impl EnvironmentConfig {
     pub fn new() -> Result<Self, Error> {
+        EnvironmentConfig::from_lookup(|name| env::var(name).ok())
+    }
+
+    ///
+    /// Read a configuration from variables looked up with `lookup` instead of the process
+    /// environment. Malformed values are returned as errors.
+    ///
+    pub fn from_lookup<F>(lookup: F) -> Result<Self, Error>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
         let mut cfg = EnvironmentConfig {
             port: 0,
             interface: "".to_string(),
@@ -66,62 +156,196 @@ impl EnvironmentConfig {
             status_interval: DEFAULT_STATUS_INTERVAL,
             kms_protection: KmsProtection::Plaintext,
             health_check_port: None,
+            user: None,
+            group: None,
+            allow_root: false,
+            otlp_endpoint: None,
+            readiness_check_port: None,
+            health_check_mode: HealthCheckMode::Fixed,
+            shared_delegation: None,
+            chaos_faults: Vec::new(),
+            chaos_probability: DEFAULT_CHAOS_PROBABILITY,
+            clock_source: ClockSource::Realtime,
+            midpoint_resolution: DEFAULT_MIDPOINT_RESOLUTION,
+            audit_log: None,
+            audit_log_max_size: DEFAULT_AUDIT_LOG_MAX_SIZE,
+            workers: 0,
+            unicast_hops: None,
+            public_key_file: None,
+            rate_limit: None,
+            rate_limit_exempt_cidrs: Vec::new(),
+            allow_cidrs: Vec::new(),
+            deny_cidrs: Vec::new(),
+            shutdown_delay: Duration::from_secs(0),
+            bind_device: None,
+            instance_id: None,
+            pq_signatures: false,
         };
 
-        if let Ok(port) = env::var(ROUGHENOUGH_PORT) {
-            cfg.port = port
-                .parse()
-                .unwrap_or_else(|_| panic!("invalid port: {}", port));
-        };
+        if let Some(port) = lookup(ROUGHENOUGH_PORT) {
+            cfg.port = parse_var(ROUGHENOUGH_PORT, &port)?;
+        }
 
-        if let Ok(interface) = env::var(ROUGHENOUGH_INTERFACE) {
-            cfg.interface = interface.to_string();
-        };
+        if let Some(interface) = lookup(ROUGHENOUGH_INTERFACE) {
+            cfg.interface = interface;
+        }
 
-        if let Ok(seed) = env::var(ROUGHENOUGH_SEED) {
-            cfg.seed =
-                hex::decode(&seed).expect("invalid seed value; 'seed' should be a hex value");
-        };
+        if let Some(seed) = lookup(ROUGHENOUGH_SEED) {
+            cfg.seed = hex::decode(&seed).map_err(|_| {
+                Error::InvalidConfiguration(format!(
+                    "invalid {} value; 'seed' should be a hex value",
+                    ROUGHENOUGH_SEED
+                ))
+            })?;
+        }
 
-        if let Ok(batch_size) = env::var(ROUGHENOUGH_BATCH_SIZE) {
-            cfg.batch_size = batch_size
-                .parse()
-                .unwrap_or_else(|_| panic!("invalid batch_size: {}", batch_size));
-        };
+        if let Some(batch_size) = lookup(ROUGHENOUGH_BATCH_SIZE) {
+            cfg.batch_size = parse_var(ROUGHENOUGH_BATCH_SIZE, &batch_size)?;
+        }
 
-        if let Ok(secondsoffset) = env::var(ROUGHENOUGH_SECONDSOFFSET) {
-            cfg.secondsoffset = secondsoffset
-                .parse()
-                .unwrap_or_else(|_| panic!("invalid secondsoffset: {}", secondsoffset));
-        };
+        if let Some(secondsoffset) = lookup(ROUGHENOUGH_SECONDSOFFSET) {
+            cfg.secondsoffset = parse_var(ROUGHENOUGH_SECONDSOFFSET, &secondsoffset)?;
+        }
 
+        if let Some(status_interval) = lookup(ROUGHENOUGH_STATUS_INTERVAL) {
+            let val: u16 = parse_var(ROUGHENOUGH_STATUS_INTERVAL, &status_interval)?;
+            cfg.status_interval = Duration::from_secs(u64::from(val));
+        }
 
-        if let Ok(status_interval) = env::var(ROUGHENOUGH_STATUS_INTERVAL) {
-            let val: u16 = status_interval
-                .parse()
-                .unwrap_or_else(|_| panic!("invalid status_interval: {}", status_interval));
+        if let Some(kms_protection) = lookup(ROUGHENOUGH_KMS_PROTECTION) {
+            cfg.kms_protection = parse_var(ROUGHENOUGH_KMS_PROTECTION, &kms_protection)?;
+        }
 
-            cfg.status_interval = Duration::from_secs(u64::from(val));
-        };
+        if let Some(health_check_port) = lookup(ROUGHENOUGH_HEALTH_CHECK_PORT) {
+            cfg.health_check_port = Some(parse_var(
+                ROUGHENOUGH_HEALTH_CHECK_PORT,
+                &health_check_port,
+            )?);
+        }
 
-        if let Ok(kms_protection) = env::var(ROUGHENOUGH_KMS_PROTECTION) {
-            cfg.kms_protection = kms_protection
-                .parse()
-                .unwrap_or_else(|_| panic!("invalid kms_protection value: {}", kms_protection));
+        if let Some(user) = lookup(ROUGHENOUGH_USER) {
+            cfg.user = Some(user);
         }
 
-        if let Ok(health_check_port) = env::var(ROUGHENOUGH_HEALTH_CHECK_PORT) {
-            let val: u16 = health_check_port
-                .parse()
-                .unwrap_or_else(|_| panic!("invalid health_check_port: {}", health_check_port));
+        if let Some(group) = lookup(ROUGHENOUGH_GROUP) {
+            cfg.group = Some(group);
+        }
 
-            cfg.health_check_port = Some(val);
-        };
+        if let Some(allow_root) = lookup(ROUGHENOUGH_ALLOW_ROOT) {
+            cfg.allow_root = parse_var(ROUGHENOUGH_ALLOW_ROOT, &allow_root)?;
+        }
+
+        if let Some(otlp_endpoint) = lookup(ROUGHENOUGH_OTLP_ENDPOINT) {
+            cfg.otlp_endpoint = Some(otlp_endpoint);
+        }
+
+        if let Some(readiness_check_port) = lookup(ROUGHENOUGH_READINESS_CHECK_PORT) {
+            cfg.readiness_check_port = Some(parse_var(
+                ROUGHENOUGH_READINESS_CHECK_PORT,
+                &readiness_check_port,
+            )?);
+        }
+
+        if let Some(health_check_mode) = lookup(ROUGHENOUGH_HEALTH_CHECK_MODE) {
+            cfg.health_check_mode = parse_var(ROUGHENOUGH_HEALTH_CHECK_MODE, &health_check_mode)?;
+        }
+
+        if let Some(shared_delegation) = lookup(ROUGHENOUGH_SHARED_DELEGATION) {
+            cfg.shared_delegation = Some(shared_delegation);
+        }
+
+        if let Some(chaos_faults) = lookup(ROUGHENOUGH_CHAOS_FAULTS) {
+            cfg.chaos_faults = parse_list(ROUGHENOUGH_CHAOS_FAULTS, &chaos_faults)?;
+        }
+
+        if let Some(chaos_probability) = lookup(ROUGHENOUGH_CHAOS_PROBABILITY) {
+            cfg.chaos_probability = parse_var(ROUGHENOUGH_CHAOS_PROBABILITY, &chaos_probability)?;
+        }
+
+        if let Some(clock_source) = lookup(ROUGHENOUGH_CLOCK_SOURCE) {
+            cfg.clock_source = parse_var(ROUGHENOUGH_CLOCK_SOURCE, &clock_source)?;
+        }
+
+        if let Some(midpoint_resolution) = lookup(ROUGHENOUGH_MIDPOINT_RESOLUTION) {
+            cfg.midpoint_resolution =
+                parse_var(ROUGHENOUGH_MIDPOINT_RESOLUTION, &midpoint_resolution)?;
+        }
+
+        if let Some(audit_log) = lookup(ROUGHENOUGH_AUDIT_LOG) {
+            cfg.audit_log = Some(audit_log);
+        }
+
+        if let Some(audit_log_max_size) = lookup(ROUGHENOUGH_AUDIT_LOG_MAX_SIZE) {
+            cfg.audit_log_max_size =
+                parse_var(ROUGHENOUGH_AUDIT_LOG_MAX_SIZE, &audit_log_max_size)?;
+        }
+
+        if let Some(workers) = lookup(ROUGHENOUGH_WORKERS) {
+            cfg.workers = parse_var(ROUGHENOUGH_WORKERS, &workers)?;
+        }
+
+        if let Some(unicast_hops) = lookup(ROUGHENOUGH_UNICAST_HOPS) {
+            cfg.unicast_hops = Some(parse_var(ROUGHENOUGH_UNICAST_HOPS, &unicast_hops)?);
+        }
+
+        if let Some(public_key_file) = lookup(ROUGHENOUGH_PUBLIC_KEY_FILE) {
+            cfg.public_key_file = Some(public_key_file);
+        }
+
+        if let Some(rate_limit) = lookup(ROUGHENOUGH_RATE_LIMIT) {
+            cfg.rate_limit = Some(parse_var(ROUGHENOUGH_RATE_LIMIT, &rate_limit)?);
+        }
+
+        if let Some(exempt_cidrs) = lookup(ROUGHENOUGH_RATE_LIMIT_EXEMPT_CIDRS) {
+            cfg.rate_limit_exempt_cidrs =
+                parse_list(ROUGHENOUGH_RATE_LIMIT_EXEMPT_CIDRS, &exempt_cidrs)?;
+        }
+
+        if let Some(allow_cidrs) = lookup(ROUGHENOUGH_ALLOW_CIDRS) {
+            cfg.allow_cidrs = parse_list(ROUGHENOUGH_ALLOW_CIDRS, &allow_cidrs)?;
+        }
+
+        if let Some(deny_cidrs) = lookup(ROUGHENOUGH_DENY_CIDRS) {
+            cfg.deny_cidrs = parse_list(ROUGHENOUGH_DENY_CIDRS, &deny_cidrs)?;
+        }
+
+        if let Some(shutdown_delay) = lookup(ROUGHENOUGH_SHUTDOWN_DELAY) {
+            let val: u32 = parse_var(ROUGHENOUGH_SHUTDOWN_DELAY, &shutdown_delay)?;
+            cfg.shutdown_delay = Duration::from_secs(u64::from(val));
+        }
+
+        if let Some(bind_device) = lookup(ROUGHENOUGH_BIND_DEVICE) {
+            cfg.bind_device = Some(bind_device);
+        }
+
+        if let Some(instance_id) = lookup(ROUGHENOUGH_INSTANCE_ID) {
+            cfg.instance_id = Some(instance_id);
+        }
+
+        if let Some(pq_signatures) = lookup(ROUGHENOUGH_PQ_SIGNATURES) {
+            cfg.pq_signatures = parse_var(ROUGHENOUGH_PQ_SIGNATURES, &pq_signatures)?;
+        }
 
         Ok(cfg)
     }
 }
 
+// The value `value` of the variable `name`
+fn parse_var<T: FromStr>(name: &str, value: &str) -> Result<T, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::InvalidConfiguration(format!("invalid {}: {}", name, value)))
+}
+
+// The comma-separated list `value` of the variable `name`
+fn parse_list<T: FromStr<Err = String>>(name: &str, value: &str) -> Result<Vec<T>, Error> {
+    value
+        .split(',')
+        .map(|item| item.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|e| Error::InvalidConfiguration(format!("invalid {}: {}", name, e)))
+}
+
 impl ServerConfig for EnvironmentConfig {
     fn interface(&self) -> &str {
         self.interface.as_ref()
@@ -154,4 +378,100 @@ impl ServerConfig for EnvironmentConfig {
     fn health_check_port(&self) -> Option<u16> {
         self.health_check_port
     }
+
+    fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    fn allow_root(&self) -> bool {
+        self.allow_root
+    }
+
+    fn otlp_endpoint(&self) -> Option<&str> {
+        self.otlp_endpoint.as_deref()
+    }
+
+    fn readiness_check_port(&self) -> Option<u16> {
+        self.readiness_check_port
+    }
+
+    fn health_check_mode(&self) -> HealthCheckMode {
+        self.health_check_mode
+    }
+
+    fn shared_delegation(&self) -> Option<&str> {
+        self.shared_delegation.as_deref()
+    }
+
+    fn chaos_faults(&self) -> &[Fault] {
+        &self.chaos_faults
+    }
+
+    fn chaos_probability(&self) -> f64 {
+        self.chaos_probability
+    }
+
+    fn clock_source(&self) -> ClockSource {
+        self.clock_source
+    }
+
+    fn midpoint_resolution(&self) -> u32 {
+        self.midpoint_resolution
+    }
+
+    fn audit_log(&self) -> Option<&str> {
+        self.audit_log.as_deref()
+    }
+
+    fn audit_log_max_size(&self) -> u64 {
+        self.audit_log_max_size
+    }
+
+    fn workers(&self) -> u32 {
+        self.workers
+    }
+
+    fn unicast_hops(&self) -> Option<u32> {
+        self.unicast_hops
+    }
+
+    fn public_key_file(&self) -> Option<&str> {
+        self.public_key_file.as_deref()
+    }
+
+    fn rate_limit(&self) -> Option<u32> {
+        self.rate_limit
+    }
+
+    fn rate_limit_exempt_cidrs(&self) -> &[Cidr] {
+        &self.rate_limit_exempt_cidrs
+    }
+
+    fn allow_cidrs(&self) -> &[Cidr] {
+        &self.allow_cidrs
+    }
+
+    fn deny_cidrs(&self) -> &[Cidr] {
+        &self.deny_cidrs
+    }
+
+    fn shutdown_delay(&self) -> Duration {
+        self.shutdown_delay
+    }
+
+    fn bind_device(&self) -> Option<&str> {
+        self.bind_device.as_deref()
+    }
+
+    fn instance_id(&self) -> Option<&str> {
+        self.instance_id.as_deref()
+    }
+
+    fn pq_signatures(&self) -> bool {
+        self.pq_signatures
+    }
 }
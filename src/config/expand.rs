@@ -0,0 +1,183 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Environment variable interpolation in configuration values.
+//!
+//! `${VAR}` is replaced with the value of the environment variable `VAR`, and
+//! `${VAR:-default}` with `default` when `VAR` is unset or empty. `$$` is a literal `$`.
+//! Referring to an unset variable without a default is an error.
+//!
+
+use std::env;
+
+use yaml_rust::yaml::{Array, Hash};
+use yaml_rust::Yaml;
+
+use crate::Error;
+
+/// Expand `${VAR}` references in `value` from the process environment
+pub fn expand_env(value: &str) -> Result<String, Error> {
+    expand_with(value, |name| env::var(name).ok())
+}
+
+/// Expand `${VAR}` references in `value`, looking variables up with `lookup`
+pub fn expand_with<F>(value: &str, lookup: F) -> Result<String, Error>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        if rest.starts_with("$$") {
+            out.push('$');
+            rest = &rest[2..];
+        } else if rest.starts_with("${") {
+            let end = rest.find('}').ok_or_else(|| {
+                Error::InvalidConfiguration(format!("unterminated '${{' in '{}'", value))
+            })?;
+
+            let reference = &rest[2..end];
+            let (name, default) = match reference.find(":-") {
+                Some(idx) => (&reference[..idx], Some(&reference[idx + 2..])),
+                None => (reference, None),
+            };
+
+            if name.is_empty() {
+                return Err(Error::InvalidConfiguration(format!(
+                    "empty variable name in '{}'",
+                    value
+                )));
+            }
+
+            match (lookup(name).filter(|v| !v.is_empty()), default) {
+                (Some(v), _) => out.push_str(&v),
+                (None, Some(default)) => out.push_str(default),
+                (None, None) => {
+                    return Err(Error::InvalidConfiguration(format!(
+                        "environment variable '{}' is not set",
+                        name
+                    )))
+                }
+            }
+
+            rest = &rest[end + 1..];
+        } else {
+            out.push('$');
+            rest = &rest[1..];
+        }
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+///
+/// Expand `${VAR}` references in every string of a parsed YAML document. A string that
+/// changes is re-typed the way YAML would have typed the expanded text, so e.g.
+/// `port: ${PORT}` yields an integer.
+///
+pub fn expand_yaml(doc: &Yaml) -> Result<Yaml, Error> {
+    match doc {
+        Yaml::String(s) if s.contains('$') => {
+            let expanded = expand_env(s)?;
+            if expanded == *s {
+                Ok(doc.clone())
+            } else {
+                Ok(retype(expanded))
+            }
+        }
+        Yaml::Array(items) => {
+            let expanded: Result<Array, Error> = items.iter().map(expand_yaml).collect();
+            Ok(Yaml::Array(expanded?))
+        }
+        Yaml::Hash(map) => {
+            let mut expanded = Hash::new();
+            for (key, value) in map {
+                expanded.insert(key.clone(), expand_yaml(value)?);
+            }
+            Ok(Yaml::Hash(expanded))
+        }
+        other => Ok(other.clone()),
+    }
+}
+
+// The YAML scalar `value` would have been parsed as
+fn retype(value: String) -> Yaml {
+    if value == "true" || value == "false" {
+        Yaml::Boolean(value == "true")
+    } else if let Ok(i) = value.parse::<i64>() {
+        Yaml::Integer(i)
+    } else if value.contains('.') && value.parse::<f64>().is_ok() {
+        Yaml::Real(value)
+    } else {
+        Yaml::String(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::expand::{expand_with, retype};
+    use yaml_rust::Yaml;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOST" => Some("time.example.com".to_string()),
+            "PORT" => Some("2002".to_string()),
+            "EMPTY" => Some("".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn variables_are_replaced() {
+        assert_eq!(expand_with("${HOST}", lookup).unwrap(), "time.example.com");
+        assert_eq!(
+            expand_with("/etc/${HOST}/seed-${PORT}", lookup).unwrap(),
+            "/etc/time.example.com/seed-2002"
+        );
+        assert_eq!(expand_with("no variables", lookup).unwrap(), "no variables");
+    }
+
+    #[test]
+    fn defaults_and_escapes() {
+        assert_eq!(expand_with("${MISSING:-8686}", lookup).unwrap(), "8686");
+        assert_eq!(expand_with("${EMPTY:-fallback}", lookup).unwrap(), "fallback");
+        assert_eq!(expand_with("${PORT:-1}", lookup).unwrap(), "2002");
+        assert_eq!(expand_with("cost $$5 $x", lookup).unwrap(), "cost $5 $x");
+    }
+
+    #[test]
+    fn unset_or_malformed_references_fail() {
+        assert!(expand_with("${MISSING}", lookup).is_err());
+        assert!(expand_with("${HOST", lookup).is_err());
+        assert!(expand_with("${}", lookup).is_err());
+    }
+
+    #[test]
+    fn expanded_values_are_retyped() {
+        assert_eq!(retype("2002".to_string()), Yaml::Integer(2002));
+        assert_eq!(retype("true".to_string()), Yaml::Boolean(true));
+        assert_eq!(retype("0.25".to_string()), Yaml::Real("0.25".to_string()));
+        assert_eq!(retype("127.0.0.1".to_string()), Yaml::String("127.0.0.1".to_string()));
+
+        // a 64 digit seed stays a string
+        let seed = "1".repeat(64);
+        assert_eq!(retype(seed.clone()), Yaml::String(seed));
+    }
+}
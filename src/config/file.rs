@@ -14,11 +14,19 @@
 
 use std::fs::File;
 use std::io::Read;
+use std::str::FromStr;
 use std::time::Duration;
-use yaml_rust::YamlLoader;
+use yaml_rust::{Yaml, YamlLoader};
 
-use crate::config::ServerConfig;
-use crate::config::{DEFAULT_BATCH_SIZE, DEFAULT_STATUS_INTERVAL};
+use crate::config::expand::expand_yaml;
+use crate::config::{HealthCheckMode, ServerConfig};
+use crate::config::{
+    DEFAULT_AUDIT_LOG_MAX_SIZE, DEFAULT_BATCH_SIZE, DEFAULT_CHAOS_PROBABILITY,
+    DEFAULT_MIDPOINT_RESOLUTION, DEFAULT_STATUS_INTERVAL,
+};
+use crate::chaos::Fault;
+use crate::clock::ClockSource;
+use crate::filter::Cidr;
 use crate::key::KmsProtection;
 use crate::Error;
 
@@ -34,6 +42,9 @@ use crate::Error;
 /// seed: f61075c988feb9cb700a4a6a3291bfbc9cab11b9c9eca8c802468eb38a43d7d3
 /// ```
 ///
+/// Values may refer to environment variables as `${VAR}` or `${VAR:-default}`, see
+/// [`expand_env`](fn.expand_env.html).
+///
 pub struct FileConfig {
     port: u16,
     interface: String,
@@ -43,18 +54,52 @@ pub struct FileConfig {
     status_interval: Duration,
     kms_protection: KmsProtection,
     health_check_port: Option<u16>,
+    user: Option<String>,
+    group: Option<String>,
+    allow_root: bool,
+    otlp_endpoint: Option<String>,
+    readiness_check_port: Option<u16>,
+    health_check_mode: HealthCheckMode,
+    shared_delegation: Option<String>,
+    chaos_faults: Vec<Fault>,
+    chaos_probability: f64,
+    clock_source: ClockSource,
+    midpoint_resolution: u32,
+    audit_log: Option<String>,
+    audit_log_max_size: u64,
+    workers: u32,
+    unicast_hops: Option<u32>,
+    public_key_file: Option<String>,
+    rate_limit: Option<u32>,
+    rate_limit_exempt_cidrs: Vec<Cidr>,
+    allow_cidrs: Vec<Cidr>,
+    deny_cidrs: Vec<Cidr>,
+    shutdown_delay: Duration,
+    bind_device: Option<String>,
+    instance_id: Option<String>,
+    pq_signatures: bool,
 }
 
 impl FileConfig {
     pub fn new(config_file: &str) -> Result<Self, Error> {
-        let mut infile = File::open(config_file).expect("failed to open config file");
-
         let mut contents = String::new();
-        infile
-            .read_to_string(&mut contents)
-            .expect("could not read config file");
+        File::open(config_file)
+            .and_then(|mut infile| infile.read_to_string(&mut contents))
+            .map_err(|e| {
+                Error::InvalidConfiguration(format!(
+                    "could not read config file '{}': {}",
+                    config_file, e
+                ))
+            })?;
+
+        FileConfig::from_yaml_str(&contents)
+    }
 
-        let cfg = YamlLoader::load_from_str(&contents).expect("could not parse config file");
+    /// Read a configuration from a YAML document held in memory
+    pub fn from_yaml_str(contents: &str) -> Result<Self, Error> {
+        let cfg = YamlLoader::load_from_str(contents).map_err(|e| {
+            Error::InvalidConfiguration(format!("could not parse config: {}", e))
+        })?;
 
         if cfg.len() != 1 {
             return Err(Error::InvalidConfiguration(
@@ -71,34 +116,118 @@ impl FileConfig {
             status_interval: DEFAULT_STATUS_INTERVAL,
             kms_protection: KmsProtection::Plaintext,
             health_check_port: None,
+            user: None,
+            group: None,
+            allow_root: false,
+            otlp_endpoint: None,
+            readiness_check_port: None,
+            health_check_mode: HealthCheckMode::Fixed,
+            shared_delegation: None,
+            chaos_faults: Vec::new(),
+            chaos_probability: DEFAULT_CHAOS_PROBABILITY,
+            clock_source: ClockSource::Realtime,
+            midpoint_resolution: DEFAULT_MIDPOINT_RESOLUTION,
+            audit_log: None,
+            audit_log_max_size: DEFAULT_AUDIT_LOG_MAX_SIZE,
+            workers: 0,
+            unicast_hops: None,
+            public_key_file: None,
+            rate_limit: None,
+            rate_limit_exempt_cidrs: Vec::new(),
+            allow_cidrs: Vec::new(),
+            deny_cidrs: Vec::new(),
+            shutdown_delay: Duration::from_secs(0),
+            bind_device: None,
+            instance_id: None,
+            pq_signatures: false,
         };
 
-        for (key, value) in cfg[0].as_hash().unwrap() {
-            match key.as_str().unwrap() {
-                "port" => config.port = value.as_i64().unwrap() as u16,
-                "interface" => config.interface = value.as_str().unwrap().to_string(),
-                "batch_size" => config.batch_size = value.as_i64().unwrap() as u8,
-                "secondsoffset" => config.secondsoffset = value.as_i64().unwrap() as u64,
+        let doc = expand_yaml(&cfg[0])?;
+        let settings = doc.as_hash().ok_or_else(|| {
+            Error::InvalidConfiguration("config is not a map of settings".to_string())
+        })?;
+
+        for (key, value) in settings {
+            let key = key.as_str().ok_or_else(|| {
+                Error::InvalidConfiguration(format!("config key {:?} is not a string", key))
+            })?;
+
+            match key {
+                "port" => config.port = int_value(key, value, u64::from(u16::max_value()))? as u16,
+                "interface" => config.interface = str_value(key, value)?.to_string(),
+                "batch_size" => {
+                    config.batch_size = int_value(key, value, u64::from(u8::max_value()))? as u8
+                }
+                "secondsoffset" => config.secondsoffset = int_value(key, value, u64::max_value())?,
                 "seed" => {
-                    let val = value.as_str().unwrap().to_string();
-                    config.seed = hex::decode(val)
-                        .expect("seed value invalid; 'seed' should be 32 byte hex value");
+                    config.seed = hex::decode(str_value(key, value)?).map_err(|_| {
+                        Error::InvalidConfiguration(
+                            "seed value invalid; 'seed' should be 32 byte hex value".to_string(),
+                        )
+                    })?;
                 }
                 "status_interval" => {
-                    let val = value.as_i64().expect("status_interval value invalid");
-                    config.status_interval = Duration::from_secs(val as u64)
-                }
-                "kms_protection" => {
-                    let val =
-                        value.as_str().unwrap().parse().unwrap_or_else(|_| {
-                            panic!("invalid kms_protection value: {:?}", value)
-                        });
-                    config.kms_protection = val
+                    let val = int_value(key, value, u64::max_value())?;
+                    config.status_interval = Duration::from_secs(val)
                 }
+                "kms_protection" => config.kms_protection = parsed_value(key, value)?,
                 "health_check_port" => {
-                    let val = value.as_i64().unwrap() as u16;
+                    let val = int_value(key, value, u64::from(u16::max_value()))? as u16;
                     config.health_check_port = Some(val);
                 }
+                "user" => config.user = Some(str_value(key, value)?.to_string()),
+                "group" => config.group = Some(str_value(key, value)?.to_string()),
+                "allow_root" => config.allow_root = bool_value(key, value)?,
+                "otlp_endpoint" => config.otlp_endpoint = Some(str_value(key, value)?.to_string()),
+                "readiness_check_port" => {
+                    let val = int_value(key, value, u64::from(u16::max_value()))? as u16;
+                    config.readiness_check_port = Some(val);
+                }
+                "health_check_mode" => config.health_check_mode = parsed_value(key, value)?,
+                "shared_delegation" => {
+                    config.shared_delegation = Some(str_value(key, value)?.to_string())
+                }
+                "chaos_faults" => config.chaos_faults = list_value(key, value)?,
+                "chaos_probability" => config.chaos_probability = float_value(key, value)?,
+                "clock_source" => config.clock_source = parsed_value(key, value)?,
+                "midpoint_resolution" => {
+                    let val = int_value(key, value, u64::from(u32::max_value()))?;
+                    config.midpoint_resolution = val as u32
+                }
+                "audit_log" => config.audit_log = Some(str_value(key, value)?.to_string()),
+                "audit_log_max_size" => {
+                    config.audit_log_max_size = int_value(key, value, u64::max_value())?
+                }
+                "workers" => {
+                    config.workers = int_value(key, value, u64::from(u32::max_value()))? as u32
+                }
+                "unicast_hops" => {
+                    let val = int_value(key, value, u64::from(u32::max_value()))? as u32;
+                    config.unicast_hops = Some(val);
+                }
+                "public_key_file" => {
+                    config.public_key_file = Some(str_value(key, value)?.to_string())
+                }
+                "rate_limit" => {
+                    let val = int_value(key, value, u64::from(u32::max_value()))? as u32;
+                    config.rate_limit = Some(val);
+                }
+                "rate_limit_exempt_cidrs" => {
+                    config.rate_limit_exempt_cidrs = list_value(key, value)?
+                }
+                "allow_cidrs" => config.allow_cidrs = list_value(key, value)?,
+                "deny_cidrs" => config.deny_cidrs = list_value(key, value)?,
+                "shutdown_delay" => {
+                    let val = int_value(key, value, u64::from(u32::max_value()))?;
+                    config.shutdown_delay = Duration::from_secs(val)
+                }
+                "bind_device" => {
+                    config.bind_device = Some(str_value(key, value)?.to_string())
+                }
+                "instance_id" => {
+                    config.instance_id = Some(str_value(key, value)?.to_string())
+                }
+                "pq_signatures" => config.pq_signatures = bool_value(key, value)?,
                 unknown => {
                     return Err(Error::InvalidConfiguration(format!(
                         "unknown config key: {}",
@@ -112,6 +241,61 @@ impl FileConfig {
     }
 }
 
+fn invalid_value(key: &str, value: &Yaml, expected: &str) -> Error {
+    Error::InvalidConfiguration(format!(
+        "invalid {} value {:?}; expected {}",
+        key, value, expected
+    ))
+}
+
+fn str_value<'a>(key: &str, value: &'a Yaml) -> Result<&'a str, Error> {
+    value
+        .as_str()
+        .ok_or_else(|| invalid_value(key, value, "a string"))
+}
+
+fn bool_value(key: &str, value: &Yaml) -> Result<bool, Error> {
+    value
+        .as_bool()
+        .ok_or_else(|| invalid_value(key, value, "true or false"))
+}
+
+fn float_value(key: &str, value: &Yaml) -> Result<f64, Error> {
+    match value {
+        Yaml::Integer(i) => Ok(*i as f64),
+        _ => value
+            .as_f64()
+            .ok_or_else(|| invalid_value(key, value, "a number")),
+    }
+}
+
+// A non-negative integer no larger than `max`
+fn int_value(key: &str, value: &Yaml, max: u64) -> Result<u64, Error> {
+    match value.as_i64() {
+        Some(i) if i >= 0 && i as u64 <= max => Ok(i as u64),
+        _ => Err(invalid_value(
+            key,
+            value,
+            &format!("an integer from 0 to {}", max),
+        )),
+    }
+}
+
+fn parsed_value<T: FromStr<Err = String>>(key: &str, value: &Yaml) -> Result<T, Error> {
+    str_value(key, value)?
+        .parse()
+        .map_err(|e| Error::InvalidConfiguration(format!("invalid {} value: {}", key, e)))
+}
+
+fn list_value<T: FromStr<Err = String>>(key: &str, value: &Yaml) -> Result<Vec<T>, Error> {
+    value
+        .as_vec()
+        .ok_or_else(|| invalid_value(key, value, "a list"))?
+        .iter()
+        .map(|v| parsed_value(key, v))
+        .collect()
+}
+
 impl ServerConfig for FileConfig {
     fn interface(&self) -> &str {
         self.interface.as_ref()
@@ -143,4 +327,100 @@ impl ServerConfig for FileConfig {
     fn health_check_port(&self) -> Option<u16> {
         self.health_check_port
     }
+
+    fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    fn allow_root(&self) -> bool {
+        self.allow_root
+    }
+
+    fn otlp_endpoint(&self) -> Option<&str> {
+        self.otlp_endpoint.as_deref()
+    }
+
+    fn readiness_check_port(&self) -> Option<u16> {
+        self.readiness_check_port
+    }
+
+    fn health_check_mode(&self) -> HealthCheckMode {
+        self.health_check_mode
+    }
+
+    fn shared_delegation(&self) -> Option<&str> {
+        self.shared_delegation.as_deref()
+    }
+
+    fn chaos_faults(&self) -> &[Fault] {
+        &self.chaos_faults
+    }
+
+    fn chaos_probability(&self) -> f64 {
+        self.chaos_probability
+    }
+
+    fn clock_source(&self) -> ClockSource {
+        self.clock_source
+    }
+
+    fn midpoint_resolution(&self) -> u32 {
+        self.midpoint_resolution
+    }
+
+    fn audit_log(&self) -> Option<&str> {
+        self.audit_log.as_deref()
+    }
+
+    fn audit_log_max_size(&self) -> u64 {
+        self.audit_log_max_size
+    }
+
+    fn workers(&self) -> u32 {
+        self.workers
+    }
+
+    fn unicast_hops(&self) -> Option<u32> {
+        self.unicast_hops
+    }
+
+    fn public_key_file(&self) -> Option<&str> {
+        self.public_key_file.as_deref()
+    }
+
+    fn rate_limit(&self) -> Option<u32> {
+        self.rate_limit
+    }
+
+    fn rate_limit_exempt_cidrs(&self) -> &[Cidr] {
+        &self.rate_limit_exempt_cidrs
+    }
+
+    fn allow_cidrs(&self) -> &[Cidr] {
+        &self.allow_cidrs
+    }
+
+    fn deny_cidrs(&self) -> &[Cidr] {
+        &self.deny_cidrs
+    }
+
+    fn shutdown_delay(&self) -> Duration {
+        self.shutdown_delay
+    }
+
+    fn bind_device(&self) -> Option<&str> {
+        self.bind_device.as_deref()
+    }
+
+    fn instance_id(&self) -> Option<&str> {
+        self.instance_id.as_deref()
+    }
+
+    fn pq_signatures(&self) -> bool {
+        self.pq_signatures
+    }
 }
@@ -12,8 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::config::ServerConfig;
-use crate::config::{DEFAULT_BATCH_SIZE, DEFAULT_STATUS_INTERVAL,DEFAULT_SECONDSOFFSET};
+use crate::config::{HealthCheckMode, ServerConfig};
+use crate::config::{
+    DEFAULT_AUDIT_LOG_MAX_SIZE, DEFAULT_BATCH_SIZE, DEFAULT_CHAOS_PROBABILITY,
+    DEFAULT_MIDPOINT_RESOLUTION, DEFAULT_SECONDSOFFSET, DEFAULT_STATUS_INTERVAL,
+};
+use crate::chaos::Fault;
+use crate::clock::ClockSource;
+use crate::filter::Cidr;
 use crate::key::KmsProtection;
 use std::time::Duration;
 
@@ -22,6 +28,19 @@ use hex;
 /// A purely in-memory Roughenough config for testing purposes.
 ///
 /// This is useful for testing or fuzzing a server without the need to create additional files.
+///
+/// Use [`MemoryConfig::builder()`](struct.MemoryConfig.html#method.builder) to override
+/// individual settings:
+///
+/// ```
+/// use roughenough::config::MemoryConfig;
+///
+/// let config = MemoryConfig::builder()
+///     .port(2002)
+///     .seed(&[0x11; 32])
+///     .batch_size(16)
+///     .build();
+/// ```
 pub struct MemoryConfig {
     pub port: u16,
     pub interface: String,
@@ -31,6 +50,30 @@ pub struct MemoryConfig {
     pub status_interval: Duration,
     pub kms_protection: KmsProtection,
     pub health_check_port: Option<u16>,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub allow_root: bool,
+    pub otlp_endpoint: Option<String>,
+    pub readiness_check_port: Option<u16>,
+    pub health_check_mode: HealthCheckMode,
+    pub shared_delegation: Option<String>,
+    pub chaos_faults: Vec<Fault>,
+    pub chaos_probability: f64,
+    pub clock_source: ClockSource,
+    pub midpoint_resolution: u32,
+    pub audit_log: Option<String>,
+    pub audit_log_max_size: u64,
+    pub workers: u32,
+    pub unicast_hops: Option<u32>,
+    pub public_key_file: Option<String>,
+    pub rate_limit: Option<u32>,
+    pub rate_limit_exempt_cidrs: Vec<Cidr>,
+    pub allow_cidrs: Vec<Cidr>,
+    pub deny_cidrs: Vec<Cidr>,
+    pub shutdown_delay: Duration,
+    pub bind_device: Option<String>,
+    pub instance_id: Option<String>,
+    pub pq_signatures: bool,
 }
 
 impl MemoryConfig {
@@ -45,8 +88,211 @@ impl MemoryConfig {
             status_interval: DEFAULT_STATUS_INTERVAL,
             kms_protection: KmsProtection::Plaintext,
             health_check_port: None,
+            user: None,
+            group: None,
+            allow_root: false,
+            otlp_endpoint: None,
+            readiness_check_port: None,
+            health_check_mode: HealthCheckMode::Fixed,
+            shared_delegation: None,
+            chaos_faults: Vec::new(),
+            chaos_probability: DEFAULT_CHAOS_PROBABILITY,
+            clock_source: ClockSource::Realtime,
+            midpoint_resolution: DEFAULT_MIDPOINT_RESOLUTION,
+            audit_log: None,
+            audit_log_max_size: DEFAULT_AUDIT_LOG_MAX_SIZE,
+            workers: 0,
+            unicast_hops: None,
+            public_key_file: None,
+            rate_limit: None,
+            rate_limit_exempt_cidrs: Vec::new(),
+            allow_cidrs: Vec::new(),
+            deny_cidrs: Vec::new(),
+            shutdown_delay: Duration::from_secs(0),
+            bind_device: None,
+            instance_id: None,
+            pq_signatures: false,
         }
     }
+
+    /// Create a [`MemoryConfigBuilder`](struct.MemoryConfigBuilder.html) starting from
+    /// the same defaults as `MemoryConfig::new(0)`.
+    pub fn builder() -> MemoryConfigBuilder {
+        MemoryConfigBuilder {
+            config: MemoryConfig::new(0),
+        }
+    }
+}
+
+/// Builds a [`MemoryConfig`](struct.MemoryConfig.html) one setting at a time.
+pub struct MemoryConfigBuilder {
+    config: MemoryConfig,
+}
+
+impl MemoryConfigBuilder {
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+
+    pub fn interface(mut self, interface: &str) -> Self {
+        self.config.interface = interface.to_string();
+        self
+    }
+
+    pub fn seed(mut self, seed: &[u8]) -> Self {
+        self.config.seed = seed.to_vec();
+        self
+    }
+
+    pub fn batch_size(mut self, batch_size: u8) -> Self {
+        self.config.batch_size = batch_size;
+        self
+    }
+
+    pub fn secondsoffset(mut self, secondsoffset: u64) -> Self {
+        self.config.secondsoffset = secondsoffset;
+        self
+    }
+
+    pub fn status_interval(mut self, status_interval: Duration) -> Self {
+        self.config.status_interval = status_interval;
+        self
+    }
+
+    pub fn kms_protection(mut self, kms_protection: KmsProtection) -> Self {
+        self.config.kms_protection = kms_protection;
+        self
+    }
+
+    pub fn health_check_port(mut self, health_check_port: Option<u16>) -> Self {
+        self.config.health_check_port = health_check_port;
+        self
+    }
+
+    pub fn user(mut self, user: Option<&str>) -> Self {
+        self.config.user = user.map(str::to_string);
+        self
+    }
+
+    pub fn group(mut self, group: Option<&str>) -> Self {
+        self.config.group = group.map(str::to_string);
+        self
+    }
+
+    pub fn allow_root(mut self, allow_root: bool) -> Self {
+        self.config.allow_root = allow_root;
+        self
+    }
+
+    pub fn otlp_endpoint(mut self, otlp_endpoint: Option<&str>) -> Self {
+        self.config.otlp_endpoint = otlp_endpoint.map(str::to_string);
+        self
+    }
+
+    pub fn readiness_check_port(mut self, readiness_check_port: Option<u16>) -> Self {
+        self.config.readiness_check_port = readiness_check_port;
+        self
+    }
+
+    pub fn health_check_mode(mut self, health_check_mode: HealthCheckMode) -> Self {
+        self.config.health_check_mode = health_check_mode;
+        self
+    }
+
+    pub fn shared_delegation(mut self, shared_delegation: Option<&str>) -> Self {
+        self.config.shared_delegation = shared_delegation.map(str::to_string);
+        self
+    }
+
+    pub fn chaos_faults(mut self, chaos_faults: &[Fault]) -> Self {
+        self.config.chaos_faults = chaos_faults.to_vec();
+        self
+    }
+
+    pub fn chaos_probability(mut self, chaos_probability: f64) -> Self {
+        self.config.chaos_probability = chaos_probability;
+        self
+    }
+
+    pub fn clock_source(mut self, clock_source: ClockSource) -> Self {
+        self.config.clock_source = clock_source;
+        self
+    }
+
+    pub fn midpoint_resolution(mut self, midpoint_resolution: u32) -> Self {
+        self.config.midpoint_resolution = midpoint_resolution;
+        self
+    }
+
+    pub fn audit_log(mut self, audit_log: Option<&str>) -> Self {
+        self.config.audit_log = audit_log.map(str::to_string);
+        self
+    }
+
+    pub fn audit_log_max_size(mut self, audit_log_max_size: u64) -> Self {
+        self.config.audit_log_max_size = audit_log_max_size;
+        self
+    }
+
+    pub fn workers(mut self, workers: u32) -> Self {
+        self.config.workers = workers;
+        self
+    }
+
+    pub fn unicast_hops(mut self, unicast_hops: Option<u32>) -> Self {
+        self.config.unicast_hops = unicast_hops;
+        self
+    }
+
+    pub fn public_key_file(mut self, public_key_file: Option<&str>) -> Self {
+        self.config.public_key_file = public_key_file.map(str::to_string);
+        self
+    }
+
+    pub fn rate_limit(mut self, rate_limit: Option<u32>) -> Self {
+        self.config.rate_limit = rate_limit;
+        self
+    }
+
+    pub fn rate_limit_exempt_cidrs(mut self, rate_limit_exempt_cidrs: &[Cidr]) -> Self {
+        self.config.rate_limit_exempt_cidrs = rate_limit_exempt_cidrs.to_vec();
+        self
+    }
+
+    pub fn allow_cidrs(mut self, allow_cidrs: &[Cidr]) -> Self {
+        self.config.allow_cidrs = allow_cidrs.to_vec();
+        self
+    }
+
+    pub fn deny_cidrs(mut self, deny_cidrs: &[Cidr]) -> Self {
+        self.config.deny_cidrs = deny_cidrs.to_vec();
+        self
+    }
+
+    pub fn shutdown_delay(mut self, shutdown_delay: Duration) -> Self {
+        self.config.shutdown_delay = shutdown_delay;
+        self
+    }
+
+    pub fn bind_device(mut self, bind_device: Option<&str>) -> Self {
+        self.config.bind_device = bind_device.map(str::to_string);
+        self
+    }
+
+    pub fn instance_id(mut self, instance_id: Option<&str>) -> Self {
+        self.config.instance_id = instance_id.map(str::to_string);
+        self
+    }
+
+    pub fn pq_signatures(mut self, pq_signatures: bool) -> Self {
+        self.config.pq_signatures = pq_signatures;
+        self
+    }
+
+    pub fn build(self) -> MemoryConfig {
+        self.config
+    }
 }
 
 impl ServerConfig for MemoryConfig {
@@ -79,4 +325,152 @@ impl ServerConfig for MemoryConfig {
     fn health_check_port(&self) -> Option<u16> {
         self.health_check_port
     }
+
+    fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    fn allow_root(&self) -> bool {
+        self.allow_root
+    }
+
+    fn otlp_endpoint(&self) -> Option<&str> {
+        self.otlp_endpoint.as_deref()
+    }
+
+    fn readiness_check_port(&self) -> Option<u16> {
+        self.readiness_check_port
+    }
+
+    fn health_check_mode(&self) -> HealthCheckMode {
+        self.health_check_mode
+    }
+
+    fn shared_delegation(&self) -> Option<&str> {
+        self.shared_delegation.as_deref()
+    }
+
+    fn chaos_faults(&self) -> &[Fault] {
+        &self.chaos_faults
+    }
+
+    fn chaos_probability(&self) -> f64 {
+        self.chaos_probability
+    }
+
+    fn clock_source(&self) -> ClockSource {
+        self.clock_source
+    }
+
+    fn midpoint_resolution(&self) -> u32 {
+        self.midpoint_resolution
+    }
+
+    fn audit_log(&self) -> Option<&str> {
+        self.audit_log.as_deref()
+    }
+
+    fn audit_log_max_size(&self) -> u64 {
+        self.audit_log_max_size
+    }
+
+    fn workers(&self) -> u32 {
+        self.workers
+    }
+
+    fn unicast_hops(&self) -> Option<u32> {
+        self.unicast_hops
+    }
+
+    fn public_key_file(&self) -> Option<&str> {
+        self.public_key_file.as_deref()
+    }
+
+    fn rate_limit(&self) -> Option<u32> {
+        self.rate_limit
+    }
+
+    fn rate_limit_exempt_cidrs(&self) -> &[Cidr] {
+        &self.rate_limit_exempt_cidrs
+    }
+
+    fn allow_cidrs(&self) -> &[Cidr] {
+        &self.allow_cidrs
+    }
+
+    fn deny_cidrs(&self) -> &[Cidr] {
+        &self.deny_cidrs
+    }
+
+    fn shutdown_delay(&self) -> Duration {
+        self.shutdown_delay
+    }
+
+    fn bind_device(&self) -> Option<&str> {
+        self.bind_device.as_deref()
+    }
+
+    fn instance_id(&self) -> Option<&str> {
+        self.instance_id.as_deref()
+    }
+
+    fn pq_signatures(&self) -> bool {
+        self.pq_signatures
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::config::{MemoryConfig, ServerConfig, DEFAULT_BATCH_SIZE};
+    use crate::key::KmsProtection;
+
+    #[test]
+    fn builder_defaults_match_new() {
+        let built = MemoryConfig::builder().build();
+        let new = MemoryConfig::new(0);
+
+        assert_eq!(built.port(), new.port());
+        assert_eq!(built.interface(), new.interface());
+        assert_eq!(built.seed(), new.seed());
+        assert_eq!(built.batch_size(), DEFAULT_BATCH_SIZE);
+        assert_eq!(built.health_check_port(), None);
+    }
+
+    #[test]
+    fn builder_overrides_every_field() {
+        let cfg = MemoryConfig::builder()
+            .port(2002)
+            .interface("::1")
+            .seed(&[0x11; 32])
+            .batch_size(8)
+            .secondsoffset(30)
+            .status_interval(Duration::from_secs(5))
+            .kms_protection(KmsProtection::AwsKmsEnvelope("arn:aws:kms:x".to_string()))
+            .health_check_port(Some(8000))
+            .user(Some("roughtime"))
+            .group(Some("nogroup"))
+            .allow_root(true)
+            .build();
+
+        assert_eq!(cfg.port(), 2002);
+        assert_eq!(cfg.interface(), "::1");
+        assert_eq!(cfg.seed(), vec![0x11; 32]);
+        assert_eq!(cfg.batch_size(), 8);
+        assert_eq!(cfg.secondsoffset(), 30);
+        assert_eq!(cfg.status_interval(), Duration::from_secs(5));
+        assert_eq!(
+            *cfg.kms_protection(),
+            KmsProtection::AwsKmsEnvelope("arn:aws:kms:x".to_string())
+        );
+        assert_eq!(cfg.health_check_port(), Some(8000));
+        assert_eq!(cfg.user(), Some("roughtime"));
+        assert_eq!(cfg.group(), Some("nogroup"));
+        assert!(cfg.allow_root());
+    }
 }
@@ -22,9 +22,14 @@
 //! such as files or environment variables.
 //!
 
-use std::net::SocketAddr;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
 use std::time::Duration;
 
+mod expand;
+pub use self::expand::expand_env;
+
 mod file;
 pub use self::file::FileConfig;
 
@@ -32,10 +37,17 @@ mod environment;
 pub use self::environment::EnvironmentConfig;
 
 mod memory;
-pub use self::memory::MemoryConfig;
+pub use self::memory::{MemoryConfig, MemoryConfigBuilder};
+
+#[cfg(feature = "awskms")]
+mod ssm;
 
+use crate::chaos::Fault;
+use crate::clock::ClockSource;
+use crate::filter::Cidr;
 use crate::key::KmsProtection;
 use crate::Error;
+use crate::MIN_SEED_LENGTH;
 
 /// Maximum number of requests to process in one batch and include the the Merkle tree.
 pub const DEFAULT_BATCH_SIZE: u8 = 64;
@@ -46,6 +58,74 @@ pub const DEFAULT_STATUS_INTERVAL: Duration = Duration::from_secs(600);
 /// Seconds offset
 pub const DEFAULT_SECONDSOFFSET: u64 = 0;
 
+/// Probability that a response has a fault injected when `chaos_faults` are configured
+pub const DEFAULT_CHAOS_PROBABILITY: f64 = 0.1;
+
+/// Midpoints are reported with microsecond resolution by default
+pub const DEFAULT_MIDPOINT_RESOLUTION: u32 = 1;
+
+/// Coarsest accepted `midpoint_resolution` (one second, in microseconds)
+pub const MAX_MIDPOINT_RESOLUTION: u32 = 1_000_000;
+
+/// The audit log is rotated at 64 MiB by default
+pub const DEFAULT_AUDIT_LOG_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Largest accepted number of `workers`
+pub const MAX_WORKERS: u32 = 256;
+
+/// Smallest accepted `audit_log_max_size`, enough for a full batch of records
+pub const MIN_AUDIT_LOG_MAX_SIZE: u64 = 4096;
+
+/// Largest accepted `secondsoffset`, roughly 100 years. Anything larger is almost certainly
+/// a typo (e.g. milliseconds instead of seconds).
+pub const MAX_SECONDSOFFSET: u64 = 100 * 365 * 24 * 60 * 60;
+
+/// Longest accepted `bind_device`: Linux's `IFNAMSIZ` less the terminating NUL
+pub const MAX_DEVICE_NAME: usize = 15;
+
+/// Longest accepted `instance_id`, in bytes. It is sent in every response.
+pub const MAX_INSTANCE_ID_LENGTH: usize = 64;
+
+///
+/// How the HTTP health check responder (`health_check_port`) decides the server is healthy.
+///
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HealthCheckMode {
+    /// Always respond `200 OK`; only proves the event loop is running
+    Fixed,
+
+    /// Query the server's own UDP port with a fresh nonce and respond `200 OK` only if
+    /// the signed response verifies, otherwise `503 Service Unavailable`
+    SelfQuery,
+
+    /// Serve the gRPC health checking protocol (`grpc.health.v1.Health`) instead of HTTP,
+    /// reporting `SERVING` while the server is ready. Requires the `grpc-health` feature.
+    Grpc,
+}
+
+impl fmt::Display for HealthCheckMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HealthCheckMode::Fixed => write!(f, "fixed"),
+            HealthCheckMode::SelfQuery => write!(f, "self-query"),
+            HealthCheckMode::Grpc => write!(f, "grpc"),
+        }
+    }
+}
+
+impl FromStr for HealthCheckMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HealthCheckMode, String> {
+        match s {
+            "fixed" => Ok(HealthCheckMode::Fixed),
+            "self-query" => Ok(HealthCheckMode::SelfQuery),
+            "grpc" => Ok(HealthCheckMode::Grpc),
+            s => Err(format!("unknown HealthCheckMode '{}'", s)),
+        }
+    }
+}
+
 ///
 /// Specifies parameters needed to configure a Roughenough server.
 ///
@@ -54,7 +134,7 @@ pub const DEFAULT_SECONDSOFFSET: u64 = 0;
 ///
 /// YAML Key | Environment Variable | Necessity | Description
 /// --- | --- | --- | ---
-/// `interface` | `ROUGHENOUGH_INTERFACE` | Required | IP address or interface name for listening to client requests
+/// `interface` | `ROUGHENOUGH_INTERFACE` | Required | IP address, or hostname resolved once at startup, for listening to client requests
 /// `port` | `ROUGHENOUGH_PORT` | Required | UDP port to listen for requests
 /// `seed` | `ROUGHENOUGH_SEED` | Required | A 32-byte hexadecimal value used to generate the server's long-term key pair. **This is a secret value and must be un-guessable**, treat it with care. (If compiled with KMS support, length will vary)
 /// `batch_size` | `ROUGHENOUGH_BATCH_SIZE` | Optional | The maximum number of requests to process in one batch. All nonces in a batch are used to build a Merkle tree, the root of which is signed. Default is `64` requests per batch.
@@ -62,6 +142,30 @@ pub const DEFAULT_SECONDSOFFSET: u64 = 0;
 /// `status_interval` | `ROUGHENOUGH_STATUS_INTERVAL` | Optional | Number of _seconds_ between each logged status update. Default is `600` seconds (10 minutes).
 /// `health_check_port` | `ROUGHENOUGH_HEALTH_CHECK_PORT` | Optional | If present, enable an HTTP health check responder on the provided port. **Use with caution**.
 /// `kms_protection` | `ROUGHENOUGH_KMS_PROTECTION` | Optional | If compiled with KMS support, the ID of the KMS key used to protect the long-term identity.
+/// `user` | `ROUGHENOUGH_USER` | Optional | If present, the user to switch to after binding sockets. The server refuses to start as root unless `user` or `allow_root` is set.
+/// `group` | `ROUGHENOUGH_GROUP` | Optional | If present, the group to switch to after binding sockets. Defaults to the primary group of `user`.
+/// `allow_root` | `ROUGHENOUGH_ALLOW_ROOT` | Optional | Set to `true` to permit running as root without `user`. Default is `false`.
+/// `otlp_endpoint` | `ROUGHENOUGH_OTLP_ENDPOINT` | Optional | If compiled with OpenTelemetry support, the base URL of an OTLP/HTTP collector to export metrics and traces to.
+/// `readiness_check_port` | `ROUGHENOUGH_READINESS_CHECK_PORT` | Optional | If present, enable an HTTP readiness check responder on the provided port. Returns `503` while the server cannot serve correct time. **Use with caution**.
+/// `health_check_mode` | `ROUGHENOUGH_HEALTH_CHECK_MODE` | Optional | `fixed` (default) always answers health checks with `200`; `self-query` first sends a Roughtime request to the server's own UDP port and answers `200` only if the signed response verifies. `grpc` serves the gRPC health checking protocol instead, `SERVING` while the server is ready (requires the `grpc-health` feature).
+/// `shared_delegation` | `ROUGHENOUGH_SHARED_DELEGATION` | Optional | If present, path to a sealed delegation shared by all instances of an anycast fleet, used instead of a per-instance online key.
+/// `chaos_faults` | `ROUGHENOUGH_CHAOS_FAULTS` | Optional | If compiled with fault injection support, the faults (`skewed-midpoint`, `invalid-signature`, `truncated-path`, `expired-delegation`) to inject into responses. **Testing only**.
+/// `chaos_probability` | `ROUGHENOUGH_CHAOS_PROBABILITY` | Optional | Probability (0.0 to 1.0) that a response has one of the `chaos_faults` injected. Default is `0.1`.
+/// `clock_source` | `ROUGHENOUGH_CLOCK_SOURCE` | Optional | `realtime` (default) reads the midpoint from the UTC system clock; `tai` reads it from `CLOCK_TAI` and adds a `DTAI` tag to responses. `tai` requires Linux with the kernel's TAI offset configured.
+/// `midpoint_resolution` | `ROUGHENOUGH_MIDPOINT_RESOLUTION` | Optional | Resolution (in microseconds, 1 to 1000000) of the midpoint in responses. Coarser values (e.g. `1000` for milliseconds) avoid revealing fine-grained timing of the server's processing; the radius is widened to stay truthful. Default is `1`.
+/// `audit_log` | `ROUGHENOUGH_AUDIT_LOG` | Optional | If present, path of a compact binary log recording the time, client address, nonce prefix and batch of every answered request.
+/// `audit_log_max_size` | `ROUGHENOUGH_AUDIT_LOG_MAX_SIZE` | Optional | Size (in bytes) at which the audit log is rotated; four rotated files are kept. Default is `67108864` (64 MiB).
+/// `workers` | `ROUGHENOUGH_WORKERS` | Optional | If non-zero, run this many worker processes sharing the port (`SO_REUSEPORT`) under a supervisor that restarts crashed workers. Unix only. Default is `0`, a single process.
+/// `unicast_hops` | `ROUGHENOUGH_UNICAST_HOPS` | Optional | If present, the IP TTL (IPv4) or unicast hop limit (IPv6), 1 to 255, of response packets. Anycast operators can use a low value to bound how far a node's responses travel. Default is the system default.
+/// `public_key_file` | `ROUGHENOUGH_PUBLIC_KEY_FILE` | Optional | If present, path of a file the long-term public key is written to at startup, in hex, Base64 and fingerprint forms, for provisioning systems to distribute to clients.
+/// `rate_limit` | `ROUGHENOUGH_RATE_LIMIT` | Optional | If present, the maximum number of requests per second answered for any one source IP address (bursts of up to this many are allowed). Requests over the limit are dropped. Default is no limit.
+/// `rate_limit_exempt_cidrs` | `ROUGHENOUGH_RATE_LIMIT_EXEMPT_CIDRS` | Optional | If present, a list of trusted networks (monitoring probes, internal load tests) whose requests bypass `rate_limit`. They are still subject to `deny_cidrs` and `allow_cidrs`. Comma-separated in the environment variable. Default is none.
+/// `allow_cidrs` | `ROUGHENOUGH_ALLOW_CIDRS` | Optional | If present, a list of networks (e.g. `192.0.2.0/24`, `2001:db8::/32`); only requests from these networks are answered. Comma-separated in the environment variable. Default is to answer all sources.
+/// `deny_cidrs` | `ROUGHENOUGH_DENY_CIDRS` | Optional | If present, a list of networks whose requests are never answered. Takes precedence over `allow_cidrs`. Comma-separated in the environment variable.
+/// `shutdown_delay` | `ROUGHENOUGH_SHUTDOWN_DELAY` | Optional | Number of _seconds_ the server keeps answering requests after receiving SIGTERM, while failing readiness checks, before closing its socket. Gives load balancers and anycast route withdrawals time to drain traffic during rolling updates. Default is `0`.
+/// `bind_device` | `ROUGHENOUGH_BIND_DEVICE` | Optional | Name of the network device (for example `eth1`, or a VRF master device) to bind the UDP socket to with `SO_BINDTODEVICE`, for VRF and policy-routing setups where binding by address is not enough. Linux only; requires `CAP_NET_RAW` on kernels older than 5.7. Default is unset.
+/// `instance_id` | `ROUGHENOUGH_INSTANCE_ID` | Optional | Opaque identifier (1-64 bytes, e.g. `fra-2`) of this instance, added to every response in the non-critical private-use `IID\xff` tag so operators debugging anycast can tell which node answered a client. It is outside the signed SREP and does not affect verification. Default is unset (no tag).
+/// `pq_signatures` | `ROUGHENOUGH_PQ_SIGNATURES` | Optional | **Experimental**. If compiled with post-quantum support, set to `true` to additionally sign every SREP with an ML-DSA-44 online key (certified in the DELE) carried in private-use tags. Responses grow by about 3.7 KB. Not compatible with `shared_delegation`. Default is `false`.
 ///
 /// Implementations of this trait obtain a valid configuration from different back-end
 /// sources. See:
@@ -71,7 +175,7 @@ pub const DEFAULT_SECONDSOFFSET: u64 = 0;
 /// The health check and KMS features require
 ///
 pub trait ServerConfig {
-    /// [Required] IP address or interface name to listen for client requests
+    /// [Required] IP address, or hostname resolved at startup, to listen for client requests
     fn interface(&self) -> &str;
 
     /// [Required] UDP port to listen for requests
@@ -104,84 +208,660 @@ pub trait ServerConfig {
     /// https://cloud.google.com/load-balancing/docs/health-checks#legacy-health-checks
     fn health_check_port(&self) -> Option<u16>;
 
-    /// Convenience function to create a `SocketAddr` from the provided `interface` and `port`
+    /// [Optional] If present, the user the server switches to after binding its sockets.
+    /// Only meaningful when the server is started as root.
+    fn user(&self) -> Option<&str>;
+
+    /// [Optional] If present, the group the server switches to after binding its sockets.
+    /// Defaults to the primary group of `user` when only `user` is provided.
+    fn group(&self) -> Option<&str>;
+
+    /// [Optional] Permit the server to keep running as root when `user` is not provided.
+    /// Defaults to `false`.
+    fn allow_root(&self) -> bool;
+
+    /// [Optional] If present, the base URL of an OpenTelemetry collector (OTLP over HTTP)
+    /// that server metrics and traces are exported to. Requires the `otlp` feature.
+    fn otlp_endpoint(&self) -> Option<&str>;
+
+    /// [Optional] If present, the TCP port to respond to HTTP readiness checks. Unlike
+    /// `health_check_port` (liveness), a `503` is returned while the server is unable to
+    /// serve correct time: the system clock is implausible or the delegation has expired.
+    fn readiness_check_port(&self) -> Option<u16>;
+
+    /// [Optional] How the `health_check_port` responder decides the server is healthy.
+    /// Defaults to [HealthCheckMode::Fixed](enum.HealthCheckMode.html).
+    fn health_check_mode(&self) -> HealthCheckMode;
+
+    /// [Optional] If present, path to a sealed shared delegation (online key and CERT) used
+    /// instead of generating a per-instance online key. See
+    /// [SharedDelegation](../key/struct.SharedDelegation.html).
+    fn shared_delegation(&self) -> Option<&str>;
+
+    /// [Optional] Faults the server deliberately injects into responses, for testing clients.
+    /// Requires the `chaos` feature. **Never enable on a production server**. Defaults to none.
+    fn chaos_faults(&self) -> &[Fault];
+
+    /// [Optional] Probability (0.0 to 1.0) that any one response has a fault injected.
+    /// Defaults to [DEFAULT_CHAOS_PROBABILITY](constant.DEFAULT_CHAOS_PROBABILITY.html)
+    fn chaos_probability(&self) -> f64;
+
+    /// [Optional] Clock the midpoint is read from. Defaults to
+    /// [ClockSource::Realtime](../clock/enum.ClockSource.html).
+    fn clock_source(&self) -> ClockSource;
+
+    /// [Optional] Resolution, in microseconds, of the midpoint in responses. The midpoint is
+    /// rounded to a multiple of this value and the radius widened to cover the rounding.
+    /// Defaults to [DEFAULT_MIDPOINT_RESOLUTION](constant.DEFAULT_MIDPOINT_RESOLUTION.html).
+    fn midpoint_resolution(&self) -> u32;
+
+    /// [Optional] If present, path of a binary [audit log](../audit/index.html) recording
+    /// each answered request. Defaults to none.
+    fn audit_log(&self) -> Option<&str>;
+
+    /// [Optional] Size in bytes at which the audit log is rotated. Defaults to
+    /// [DEFAULT_AUDIT_LOG_MAX_SIZE](constant.DEFAULT_AUDIT_LOG_MAX_SIZE.html).
+    fn audit_log_max_size(&self) -> u64;
+
+    /// [Optional] Number of worker processes run by a [supervisor](../supervisor/index.html),
+    /// all sharing the UDP port via `SO_REUSEPORT`. `0` (the default) runs a single process.
+    fn workers(&self) -> u32;
+
+    /// [Optional] IP TTL (IPv4) or unicast hop limit (IPv6) of responses. Unset (the
+    /// default) uses the system default.
+    fn unicast_hops(&self) -> Option<u32>;
+
+    /// [Optional] Path of a file the long-term public key (hex, Base64 and fingerprint) is
+    /// written to at startup
+    fn public_key_file(&self) -> Option<&str>;
+
+    /// [Optional] If present, the maximum requests per second answered for any one source
+    /// IP address; requests over the limit are dropped. Defaults to no limit.
+    fn rate_limit(&self) -> Option<u32>;
+
+    /// [Optional] Requests from these networks (e.g. monitoring probes) are not subject to
+    /// `rate_limit`, though `deny_cidrs` and `allow_cidrs` still apply. Defaults to none.
+    fn rate_limit_exempt_cidrs(&self) -> &[Cidr];
+
+    /// [Optional] If not empty, only requests from these networks are answered. Defaults
+    /// to none (all sources are answered).
+    fn allow_cidrs(&self) -> &[Cidr];
+
+    /// [Optional] Requests from these networks are never answered. Defaults to none.
+    fn deny_cidrs(&self) -> &[Cidr];
+
+    /// [Optional] How long the server keeps answering requests after SIGTERM (or Ctrl-C),
+    /// failing readiness checks meanwhile, so load balancers and anycast withdrawals can
+    /// drain traffic first. Defaults to zero (stop immediately).
+    fn shutdown_delay(&self) -> Duration;
+
+    /// [Optional] Network device (e.g. `eth1` or a VRF device) the UDP socket is bound to
+    /// with `SO_BINDTODEVICE`, in addition to `interface`. Linux only; unset by default.
+    fn bind_device(&self) -> Option<&str>;
+
+    /// [Optional] Opaque identifier of this server instance, sent in every response in a
+    /// non-critical private-use tag so anycast operators can tell which node answered.
+    /// Unset (no tag) by default.
+    fn instance_id(&self) -> Option<&str>;
+
+    /// [Optional] Experimental: also sign every SREP with a post-quantum (ML-DSA-44) online
+    /// key, carried in private-use tags. Requires the `pq` feature. Defaults to `false`.
+    fn pq_signatures(&self) -> bool;
+
+    /// Convenience function to create a `SocketAddr` from the provided `interface` and `port`.
+    /// A hostname `interface` is resolved on every call, see
+    /// [resolve_addr](fn.resolve_addr.html); the server does so once, at startup.
     fn udp_socket_addr(&self) -> Result<SocketAddr, Error> {
-        let addr = format!("{}:{}", self.interface(), self.port());
-        match addr.parse() {
-            Ok(v) => Ok(v),
-            Err(_) => Err(Error::InvalidConfiguration(addr)),
+        resolve_addr(self.interface(), self.port())
+    }
+
+    /// Check the configuration for problems. Returns `Ok(())` if the configuration is
+    /// valid, otherwise a list with an `Error::InvalidConfiguration` for _every_
+    /// problem found.
+    fn validate(&self) -> Result<(), Vec<Error>> {
+        let mut errors = Vec::new();
+        let mut invalid = |msg: String| errors.push(Error::InvalidConfiguration(msg));
+
+        if self.port() == 0 {
+            invalid("port is missing or zero".to_string());
+        }
+        // a hostname `interface` is resolved by the server when it starts, not here
+        if self.interface().is_empty() {
+            invalid("interface is missing".to_string());
+        }
+        if let Some(hc_port) = self.health_check_port() {
+            if hc_port == 0 {
+                invalid("health_check_port must not be zero".to_string());
+            }
+        }
+        if self.health_check_mode() == HealthCheckMode::Grpc && !cfg!(feature = "grpc-health") {
+            invalid(
+                "health_check_mode is 'grpc' but gRPC health support was not compiled in"
+                    .to_string(),
+            );
+        }
+        if let Some(rc_port) = self.readiness_check_port() {
+            if rc_port == 0 {
+                invalid("readiness_check_port must not be zero".to_string());
+            } else if self.health_check_port() == Some(rc_port) {
+                invalid("readiness_check_port must differ from health_check_port".to_string());
+            }
+        }
+
+        let seed_len = self.seed().len();
+        match *self.kms_protection() {
+            _ if seed_len == 0 => invalid("seed value is missing".to_string()),
+            KmsProtection::Plaintext if seed_len != MIN_SEED_LENGTH as usize => invalid(format!(
+                "plaintext seed value must be {} bytes long; found {} bytes",
+                MIN_SEED_LENGTH, seed_len
+            )),
+            KmsProtection::AwsKmsEnvelope(_) | KmsProtection::GoogleKmsEnvelope(_)
+                if seed_len <= MIN_SEED_LENGTH as usize =>
+            {
+                invalid(
+                    "KMS use enabled but seed value is too short to be an encrypted blob"
+                        .to_string(),
+                )
+            }
+            _ => (),
+        }
+
+        match *self.kms_protection() {
+            KmsProtection::AwsKmsEnvelope(_) if !cfg!(feature = "awskms") => invalid(
+                "kms_protection is an AWS key but AWS KMS support was not compiled in".to_string(),
+            ),
+            KmsProtection::GoogleKmsEnvelope(_) if !cfg!(feature = "gcpkms") => invalid(
+                "kms_protection is a Google key but GCP KMS support was not compiled in"
+                    .to_string(),
+            ),
+            _ => (),
+        }
+
+        if self.otlp_endpoint().is_some() && !cfg!(feature = "otlp") {
+            invalid(
+                "otlp_endpoint is set but OpenTelemetry support was not compiled in".to_string(),
+            );
+        }
+
+        if self.pq_signatures() && !cfg!(feature = "pq") {
+            invalid(
+                "pq_signatures is set but post-quantum support was not compiled in".to_string(),
+            );
+        }
+        if self.pq_signatures() && self.shared_delegation().is_some() {
+            invalid("pq_signatures cannot be used with a shared_delegation".to_string());
+        }
+
+        if !self.chaos_faults().is_empty() && !cfg!(feature = "chaos") {
+            invalid("chaos_faults are set but fault injection was not compiled in".to_string());
+        }
+        if !(self.chaos_probability() >= 0.0 && self.chaos_probability() <= 1.0) {
+            invalid(format!(
+                "chaos_probability {} is invalid; valid range 0.0-1.0",
+                self.chaos_probability()
+            ));
+        }
+
+        if self.clock_source() == ClockSource::Tai && !cfg!(target_os = "linux") {
+            invalid("clock_source 'tai' is only supported on Linux".to_string());
+        }
+
+        if self.midpoint_resolution() < 1
+            || self.midpoint_resolution() > MAX_MIDPOINT_RESOLUTION
+        {
+            invalid(format!(
+                "midpoint_resolution {} is invalid; valid range 1-{}",
+                self.midpoint_resolution(),
+                MAX_MIDPOINT_RESOLUTION
+            ));
+        }
+
+        if self.audit_log().is_some() && self.audit_log_max_size() < MIN_AUDIT_LOG_MAX_SIZE {
+            invalid(format!(
+                "audit_log_max_size {} is too small; minimum is {}",
+                self.audit_log_max_size(),
+                MIN_AUDIT_LOG_MAX_SIZE
+            ));
+        }
+
+        if self.workers() > 0 && !cfg!(unix) {
+            invalid("workers (multi-process mode) is only supported on Unix".to_string());
+        }
+        if self.workers() > MAX_WORKERS {
+            invalid(format!(
+                "workers {} is invalid; maximum is {}",
+                self.workers(),
+                MAX_WORKERS
+            ));
+        }
+
+        if let Some(hops) = self.unicast_hops() {
+            if hops < 1 || hops > 255 {
+                invalid(format!("unicast_hops {} is invalid; valid range 1-255", hops));
+            }
+        }
+
+        if let Some(device) = self.bind_device() {
+            if !cfg!(target_os = "linux") {
+                invalid("bind_device (SO_BINDTODEVICE) is only supported on Linux".to_string());
+            } else if device.is_empty() || device.len() > MAX_DEVICE_NAME || device.contains('\0') {
+                invalid(format!(
+                    "bind_device '{}' is not a valid device name of 1-{} bytes",
+                    device, MAX_DEVICE_NAME
+                ));
+            }
+        }
+
+        if let Some(id) = self.instance_id() {
+            if id.is_empty() || id.len() > MAX_INSTANCE_ID_LENGTH || id.contains('\0') {
+                invalid(format!(
+                    "instance_id '{}' is invalid; it must be 1-{} bytes without NULs",
+                    id, MAX_INSTANCE_ID_LENGTH
+                ));
+            }
+        }
+
+        if self.rate_limit() == Some(0) {
+            invalid("rate_limit must be at least 1 request per second".to_string());
+        }
+
+        if self.batch_size() < 1 || self.batch_size() > 64 {
+            invalid(format!(
+                "batch_size {} is invalid; valid range 1-64",
+                self.batch_size()
+            ));
+        }
+
+        if self.secondsoffset() > MAX_SECONDSOFFSET {
+            invalid(format!(
+                "secondsoffset {} is implausible; maximum is {}",
+                self.secondsoffset(),
+                MAX_SECONDSOFFSET
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
         }
     }
 }
 
-/// Factory function to create a `ServerConfig` _trait object_ based on the value
-/// of the provided `arg`.
 ///
-///   * `ENV` will return an [`EnvironmentConfig`](struct.EnvironmentConfig.html)
-///   * any other value returns a [`FileConfig`](struct.FileConfig.html)
+/// Resolve `host`, an IP address or a hostname, to a socket address with `port`. IPv6
+/// addresses may be bracketed. Hostnames are looked up with the system resolver and the
+/// first address returned is used.
+///
+pub fn resolve_addr(host: &str, port: u16) -> Result<SocketAddr, Error> {
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = literal.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, port));
+    }
+
+    let mut addrs = (host, port).to_socket_addrs().map_err(|e| {
+        Error::InvalidConfiguration(format!("'{}' could not be resolved: {}", host, e))
+    })?;
+
+    addrs.next().ok_or_else(|| {
+        Error::InvalidConfiguration(format!("'{}' resolved to no addresses", host))
+    })
+}
+
+/// Factory function to create a `ServerConfig` _trait object_ based on the value
+/// of the provided `arg`. Equivalent to [`from_arg`](fn.from_arg.html).
 ///
 pub fn make_config(arg: &str) -> Result<Box<ServerConfig>, Error> {
-    if arg == "ENV" {
-        match EnvironmentConfig::new() {
-            Ok(cfg) => Ok(Box::new(cfg)),
-            Err(e) => Err(e),
-        }
-    } else {
-        match FileConfig::new(arg) {
-            Ok(cfg) => Ok(Box::new(cfg)),
-            Err(e) => Err(e),
-        }
+    from_arg(arg)
+}
+
+///
+/// Create the `ServerConfig` _trait object_ named by `arg`, one of:
+///
+///   * `ENV` or `env://` for an [`EnvironmentConfig`](struct.EnvironmentConfig.html)
+///   * `file:///path/to/config.yaml` or a plain path for a [`FileConfig`](struct.FileConfig.html)
+///   * `ssm://parameter-name` for a `FileConfig` parsed from the YAML value of an AWS Systems
+///     Manager parameter (`ssm:///roughenough/prod` names `/roughenough/prod`). Requires
+///     the `awskms` feature.
+///
+pub fn from_arg(arg: &str) -> Result<Box<ServerConfig>, Error> {
+    if arg == "ENV" || arg == "env://" {
+        return Ok(Box::new(EnvironmentConfig::new()?));
+    }
+
+    if arg.starts_with("file://") {
+        return Ok(Box::new(FileConfig::new(&arg["file://".len()..])?));
+    }
+
+    if arg.starts_with("ssm://") {
+        return ssm_config(&arg["ssm://".len()..]);
     }
+
+    match arg.find("://") {
+        Some(idx) => Err(Error::InvalidConfiguration(format!(
+            "unknown config source scheme '{}'",
+            &arg[..idx]
+        ))),
+        None => Ok(Box::new(FileConfig::new(arg)?)),
+    }
+}
+
+#[cfg(feature = "awskms")]
+fn ssm_config(name: &str) -> Result<Box<ServerConfig>, Error> {
+    let contents = ssm::fetch_parameter(name)?;
+    Ok(Box::new(FileConfig::from_yaml_str(&contents)?))
+}
+
+#[cfg(not(feature = "awskms"))]
+fn ssm_config(_name: &str) -> Result<Box<ServerConfig>, Error> {
+    Err(Error::InvalidConfiguration(
+        "ssm:// configuration requires the 'awskms' feature".to_string(),
+    ))
 }
 
 ///
 /// Validate configuration settings. Returns `true` if the config is valid, `false` otherwise.
+/// Each problem found by [`ServerConfig::validate`](trait.ServerConfig.html#method.validate)
+/// is logged.
 ///
 pub fn is_valid_config(cfg: &Box<ServerConfig>) -> bool {
-    let mut is_valid = true;
+    match cfg.validate() {
+        Ok(_) => true,
+        Err(errors) => {
+            for e in errors {
+                error!("{:?}", e);
+            }
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{
+        from_arg, resolve_addr, EnvironmentConfig, FileConfig, HealthCheckMode, MemoryConfig,
+        ServerConfig, MAX_INSTANCE_ID_LENGTH, MAX_SECONDSOFFSET,
+    };
+    use crate::key::KmsProtection;
+    use crate::Error;
+
+    use std::net::SocketAddr;
+
+    fn error_messages(cfg: &MemoryConfig) -> Vec<String> {
+        cfg.validate()
+            .expect_err("expected invalid config")
+            .into_iter()
+            .map(|e| match e {
+                Error::InvalidConfiguration(msg) => msg,
+                e => panic!("unexpected error {:?}", e),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn valid_config_passes() {
+        let cfg = MemoryConfig::new(2002);
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn all_problems_are_reported() {
+        let cfg = MemoryConfig::builder()
+            .port(0)
+            .seed(&[1, 2, 3])
+            .batch_size(0)
+            .secondsoffset(MAX_SECONDSOFFSET + 1)
+            .build();
+
+        let msgs = error_messages(&cfg);
+        assert_eq!(msgs.len(), 4);
+        assert!(msgs[0].contains("port"));
+        assert!(msgs[1].contains("plaintext seed"));
+        assert!(msgs[2].contains("batch_size"));
+        assert!(msgs[3].contains("secondsoffset"));
+    }
+
+    #[test]
+    fn bad_interface_is_reported() {
+        let cfg = MemoryConfig::builder()
+            .port(2002)
+            .interface("no-such-host.invalid")
+            .build();
+
+        match cfg.udp_socket_addr() {
+            Err(Error::InvalidConfiguration(msg)) => {
+                assert!(msg.contains("'no-such-host.invalid' could not be resolved"), "{}", msg)
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn interface_may_be_a_hostname_or_ipv6_address() {
+        let addr = resolve_addr("localhost", 2002).unwrap();
+        assert!(addr.ip().is_loopback());
+        assert_eq!(addr.port(), 2002);
+
+        let v6: SocketAddr = "[::1]:2002".parse().unwrap();
+        assert_eq!(resolve_addr("::1", 2002).unwrap(), v6);
+        assert_eq!(resolve_addr("[::1]", 2002).unwrap(), v6);
+
+        let cfg = MemoryConfig::builder().port(2002).interface("localhost").build();
+        assert!(cfg.validate().is_ok());
+    }
 
-    if cfg.port() == 0 {
-        error!("unset port: {}", cfg.port());
-        is_valid = false;
+    #[test]
+    fn kms_seed_must_be_longer_than_plaintext() {
+        let cfg = MemoryConfig::builder()
+            .port(2002)
+            .kms_protection(KmsProtection::GoogleKmsEnvelope(
+                "projects/p/locations/l/keyRings/r/cryptoKeys/k".to_string(),
+            ))
+            .build();
+
+        let msgs = error_messages(&cfg);
+        assert!(msgs[0].contains("too short to be an encrypted blob"));
     }
-    if cfg.interface().is_empty() {
-        error!("interface is missing");
-        is_valid = false;
+
+    #[test]
+    fn unicast_hops_must_fit_in_a_ttl() {
+        for &hops in &[0, 256] {
+            let cfg = MemoryConfig::builder()
+                .port(2002)
+                .unicast_hops(Some(hops))
+                .build();
+
+            let msgs = error_messages(&cfg);
+            assert_eq!(msgs.len(), 1);
+            assert!(msgs[0].contains("unicast_hops"));
+        }
+
+        let cfg = MemoryConfig::builder()
+            .port(2002)
+            .unicast_hops(Some(8))
+            .build();
+        assert!(cfg.validate().is_ok());
     }
-    if cfg.seed().is_empty() {
-        error!("seed value is missing");
-        is_valid = false;
+
+    #[test]
+    fn bind_device_names_are_checked() {
+        let too_long = "a-very-long-device-name";
+        let cfg = MemoryConfig::builder()
+            .port(2002)
+            .bind_device(Some(too_long))
+            .build();
+
+        let msgs = error_messages(&cfg);
+        assert_eq!(msgs.len(), 1);
+        assert!(msgs[0].contains("bind_device"));
+
+        let cfg = MemoryConfig::builder()
+            .port(2002)
+            .bind_device(Some("vrf-blue"))
+            .build();
+        assert_eq!(cfg.validate().is_ok(), cfg!(target_os = "linux"));
     }
-    if *cfg.kms_protection() == KmsProtection::Plaintext && cfg.seed().len() != 32 {
-        error!("plaintext seed value must be 32 characters long");
-        is_valid = false;
+
+    #[test]
+    fn pq_signatures_need_their_own_delegation() {
+        let cfg = MemoryConfig::builder()
+            .port(2002)
+            .pq_signatures(true)
+            .shared_delegation(Some("/etc/roughenough/delegation.sealed"))
+            .build();
+
+        let msgs = error_messages(&cfg);
+        assert!(msgs.iter().any(|m| m.contains("shared_delegation")), "{:?}", msgs);
+        assert_eq!(msgs.len(), if cfg!(feature = "pq") { 1 } else { 2 });
     }
-    if *cfg.kms_protection() != KmsProtection::Plaintext && cfg.seed().len() <= 32 {
-        error!("KMS use enabled but seed value is too short to be an encrypted blob");
-        is_valid = false;
+
+    #[test]
+    fn instance_ids_are_checked() {
+        let too_long = "x".repeat(MAX_INSTANCE_ID_LENGTH + 1);
+        for id in &["", too_long.as_str()] {
+            let cfg = MemoryConfig::builder()
+                .port(2002)
+                .instance_id(Some(id))
+                .build();
+
+            let msgs = error_messages(&cfg);
+            assert_eq!(msgs.len(), 1);
+            assert!(msgs[0].contains("instance_id"));
+        }
+
+        let cfg = MemoryConfig::builder()
+            .port(2002)
+            .instance_id(Some("fra-2"))
+            .build();
+        assert!(cfg.validate().is_ok());
     }
-    if cfg.batch_size() < 1 || cfg.batch_size() > 64 {
-        error!(
-            "batch_size {} is invalid; valid range 1-64",
-            cfg.batch_size()
-        );
-        is_valid = false;
+
+    #[test]
+    fn rate_limit_of_zero_is_rejected() {
+        let cfg = MemoryConfig::builder()
+            .port(2002)
+            .rate_limit(Some(0))
+            .build();
+
+        let msgs = error_messages(&cfg);
+        assert_eq!(msgs.len(), 1);
+        assert!(msgs[0].contains("rate_limit"));
     }
 
-    if is_valid {
-        match cfg.udp_socket_addr() {
-            Err(e) => {
-                error!(
-                    "failed to create UDP socket {}:{} {:?}",
-                    cfg.interface(),
-                    cfg.port(),
-                    e
-                );
-                is_valid = false;
+    #[test]
+    fn health_check_mode_from_string() {
+        assert_eq!("fixed".parse(), Ok(HealthCheckMode::Fixed));
+        assert_eq!("self-query".parse(), Ok(HealthCheckMode::SelfQuery));
+        assert_eq!("grpc".parse(), Ok(HealthCheckMode::Grpc));
+        assert!("frobble".parse::<HealthCheckMode>().is_err());
+    }
+
+    #[test]
+    fn grpc_health_requires_feature() {
+        let cfg = MemoryConfig::builder()
+            .port(2002)
+            .health_check_port(Some(8000))
+            .health_check_mode(HealthCheckMode::Grpc)
+            .build();
+
+        assert_eq!(cfg.validate().is_ok(), cfg!(feature = "grpc-health"));
+    }
+
+    #[test]
+    fn config_source_from_arg() {
+        let path = std::env::temp_dir().join(format!("roughenough-cfg-{}.yaml", std::process::id()));
+        std::fs::write(
+            &path,
+            "interface: 127.0.0.1\nport: 8686\nseed: f61075c988feb9cb700a4a6a3291bfbc9cab11b9c9eca8c802468eb38a43d7d3\n",
+        )
+        .unwrap();
+
+        let plain = from_arg(path.to_str().unwrap()).unwrap();
+        let url = from_arg(&format!("file://{}", path.display())).unwrap();
+        assert_eq!(plain.port(), 8686);
+        assert_eq!(url.port(), 8686);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn malformed_file_values_are_errors() {
+        let malformed = [
+            "- not\n- a map\n",
+            "? [1, 2]\n: complex key\n",
+            "port: -1\n",
+            "port: 70000\n",
+            "port: eighty\n",
+            "interface: [127, 0, 0, 1]\n",
+            "seed: not-hex\n",
+            "allow_root: maybe\n",
+            "chaos_probability: high\n",
+            "clock_source: sundial\n",
+            "deny_cidrs: 10.0.0.0/8\n",
+            "deny_cidrs: [10.0.0.0/99]\n",
+        ];
+
+        for yaml in malformed.iter() {
+            match FileConfig::from_yaml_str(yaml) {
+                Err(Error::InvalidConfiguration(_)) => (),
+                Err(e) => panic!("unexpected error {:?} for {:?}", e, yaml),
+                Ok(_) => panic!("expected an error for {:?}", yaml),
             }
-            _ => (),
         }
+
+        let cfg = FileConfig::from_yaml_str("port: 2002\nchaos_probability: 1\n").unwrap();
+        assert_eq!(cfg.port(), 2002);
+        assert_eq!(cfg.chaos_probability(), 1.0);
     }
 
-    is_valid
+    #[test]
+    fn malformed_environment_values_are_errors() {
+        let malformed = [
+            ("ROUGHENOUGH_PORT", "70000"),
+            ("ROUGHENOUGH_SEED", "xyz"),
+            ("ROUGHENOUGH_ALLOW_ROOT", "yes please"),
+            ("ROUGHENOUGH_CHAOS_FAULTS", "skewed-midpoint,gremlins"),
+            ("ROUGHENOUGH_RATE_LIMIT", "-5"),
+        ];
+
+        for &(name, value) in malformed.iter() {
+            let result = EnvironmentConfig::from_lookup(|n| {
+                if n == name {
+                    Some(value.to_string())
+                } else {
+                    None
+                }
+            });
+
+            match result {
+                Err(Error::InvalidConfiguration(msg)) => assert!(msg.contains(name)),
+                Err(e) => panic!("unexpected error {:?} for {}", e, name),
+                Ok(_) => panic!("expected an error for {}={}", name, value),
+            }
+        }
+
+        let cfg = EnvironmentConfig::from_lookup(|n| match n {
+            "ROUGHENOUGH_PORT" => Some("2002".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(cfg.port(), 2002);
+    }
+
+    #[test]
+    fn unknown_config_scheme_is_rejected() {
+        match from_arg("consul://roughenough") {
+            Err(Error::InvalidConfiguration(msg)) => assert!(msg.contains("'consul'")),
+            _ => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "awskms"))]
+    fn ssm_requires_awskms() {
+        match from_arg("ssm:///roughenough/prod") {
+            Err(Error::InvalidConfiguration(msg)) => assert!(msg.contains("awskms")),
+            _ => panic!("expected an error"),
+        }
+    }
 }
@@ -0,0 +1,50 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Configuration stored as a YAML document in an AWS Systems Manager parameter.
+//!
+
+use std::default::Default;
+
+use rusoto_core::Region;
+use rusoto_ssm::{GetParameterRequest, Ssm, SsmClient};
+
+use crate::Error;
+
+///
+/// Fetch the value of the SSM parameter `name` (e.g. `/roughenough/prod`) from the
+/// default region, decrypting it if it is a `SecureString`.
+///
+pub fn fetch_parameter(name: &str) -> Result<String, Error> {
+    let client = SsmClient::new(Region::default());
+
+    let mut request: GetParameterRequest = Default::default();
+    request.name = name.to_string();
+    request.with_decryption = Some(true);
+
+    match client.get_parameter(request).sync() {
+        Ok(result) => match result.parameter.and_then(|p| p.value) {
+            Some(value) => Ok(value),
+            None => Err(Error::InvalidConfiguration(format!(
+                "SSM parameter '{}' has no value",
+                name
+            ))),
+        },
+        Err(e) => Err(Error::InvalidConfiguration(format!(
+            "unable to fetch SSM parameter '{}': {}",
+            name, e
+        ))),
+    }
+}
@@ -0,0 +1,301 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Entries for `ecosystem.json`, the format used by public lists of Roughtime servers.
+//!
+//! ```json
+//! {
+//!   "name": "roughtime.int08h.com",
+//!   "version": "Google-Roughtime",
+//!   "publicKeyType": "ed25519",
+//!   "publicKey": "AW5uAoTSTDfG5NfY1bTh08GUnOqlRb+HVhbJ3ODJvsE=",
+//!   "addresses": [
+//!     {
+//!       "protocol": "udp",
+//!       "address": "roughtime.int08h.com:2002"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Lists of servers to query can be read either in this format (an array of entries, or an
+//! object holding one under `"servers"`) or as plain text, one `name address port pubkey`
+//! line per server with the public key in hex. Blank lines and `#` comments are ignored.
+//!
+
+use yaml_rust::{Yaml, YamlLoader};
+
+use crate::Error;
+
+/// Protocol version spoken by this server, as named in `ecosystem.json`
+pub const PROTOCOL_VERSION: &str = "Google-Roughtime";
+
+///
+/// Returns the `ecosystem.json` entry for a server called `name`, reachable over UDP at
+/// `address` (`host:port`), with the long-term Ed25519 `public_key`.
+///
+pub fn server_entry(name: &str, address: &str, public_key: &[u8]) -> String {
+    format!(
+        r#"{{
+  "name": {},
+  "version": {},
+  "publicKeyType": "ed25519",
+  "publicKey": {},
+  "addresses": [
+    {{
+      "protocol": "udp",
+      "address": {}
+    }}
+  ]
+}}"#,
+        json_string(name),
+        json_string(PROTOCOL_VERSION),
+        json_string(&base64::encode(public_key)),
+        json_string(address)
+    )
+}
+
+/// A server to query, as read from a server list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerListing {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    /// Long-term Ed25519 public key
+    pub public_key: Vec<u8>,
+}
+
+///
+/// Parse a server list, either `ecosystem.json` style JSON or `name address port pubkey`
+/// lines; the format is chosen by whether `contents` starts with `{` or `[`.
+///
+pub fn parse_server_list(contents: &str) -> Result<Vec<ServerListing>, Error> {
+    let trimmed = contents.trim_start();
+
+    if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        parse_ecosystem(contents)
+    } else {
+        parse_lines(contents)
+    }
+}
+
+fn invalid(msg: String) -> Error {
+    Error::InvalidConfiguration(msg)
+}
+
+// JSON is (for our purposes) YAML, so the ecosystem format is read with the YAML parser
+fn parse_ecosystem(contents: &str) -> Result<Vec<ServerListing>, Error> {
+    let docs = YamlLoader::load_from_str(contents)
+        .map_err(|e| invalid(format!("malformed server list: {}", e)))?;
+
+    let entries = match docs.get(0) {
+        Some(Yaml::Array(entries)) => entries,
+        Some(doc @ Yaml::Hash(_)) => match doc["servers"] {
+            Yaml::Array(ref entries) => entries,
+            _ => return Err(invalid("server list has no \"servers\" array".to_string())),
+        },
+        _ => return Err(invalid("server list is empty".to_string())),
+    };
+
+    entries.iter().map(parse_entry).collect()
+}
+
+fn parse_entry(entry: &Yaml) -> Result<ServerListing, Error> {
+    let name = entry["name"]
+        .as_str()
+        .ok_or_else(|| invalid("server list entry without a name".to_string()))?;
+
+    match entry["publicKeyType"].as_str() {
+        Some("ed25519") => (),
+        other => {
+            return Err(invalid(format!(
+                "server {}: unsupported publicKeyType {:?}",
+                name, other
+            )))
+        }
+    }
+
+    let public_key = entry["publicKey"]
+        .as_str()
+        .and_then(|key| base64::decode(key).ok())
+        .ok_or_else(|| invalid(format!("server {}: missing or invalid publicKey", name)))?;
+
+    let address = entry["addresses"]
+        .as_vec()
+        .and_then(|addrs| {
+            addrs
+                .iter()
+                .find(|addr| addr["protocol"].as_str() == Some("udp"))
+        })
+        .and_then(|addr| addr["address"].as_str())
+        .ok_or_else(|| invalid(format!("server {}: no udp address", name)))?;
+
+    let (host, port) = split_address(address)
+        .ok_or_else(|| invalid(format!("server {}: invalid address '{}'", name, address)))?;
+
+    Ok(ServerListing {
+        name: name.to_string(),
+        host,
+        port,
+        public_key,
+    })
+}
+
+// Split `host:port`, removing the brackets of an IPv6 literal
+fn split_address(address: &str) -> Option<(String, u16)> {
+    let idx = address.rfind(':')?;
+    let port = address[idx + 1..].parse().ok()?;
+    let host = address[..idx].trim_start_matches('[').trim_end_matches(']');
+
+    if host.is_empty() {
+        None
+    } else {
+        Some((host.to_string(), port))
+    }
+}
+
+fn parse_lines(contents: &str) -> Result<Vec<ServerListing>, Error> {
+    let mut servers = Vec::new();
+
+    for (lineno, line) in contents.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(idx) => &line[..idx],
+            None => line,
+        };
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+
+        let bad_line = |what: &str| invalid(format!("server list line {}: {}", lineno + 1, what));
+
+        if fields.len() != 4 {
+            return Err(bad_line("expected 'name address port pubkey'"));
+        }
+
+        let port = fields[2].parse().map_err(|_| bad_line("invalid port"))?;
+        let public_key = hex::decode(fields[3]).map_err(|_| bad_line("invalid public key"))?;
+
+        servers.push(ServerListing {
+            name: fields[0].to_string(),
+            host: fields[1].to_string(),
+            port,
+            public_key,
+        });
+    }
+
+    Ok(servers)
+}
+
+// Quote and escape `value` as a JSON string
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ecosystem::{json_string, parse_server_list, server_entry, ServerListing};
+
+    const INT08H_KEY: &str = "016e6e0284d24c37c6e4d7d8d5b4e1d3c1949ceaa545bf875616c9dce0c9bec1";
+
+    fn int08h() -> ServerListing {
+        ServerListing {
+            name: "roughtime.int08h.com".to_string(),
+            host: "roughtime.int08h.com".to_string(),
+            port: 2002,
+            public_key: hex::decode(INT08H_KEY).unwrap(),
+        }
+    }
+
+    #[test]
+    fn entry_contains_base64_public_key() {
+        let pubkey =
+            hex::decode("016e6e0284d24c37c6e4d7d8d5b4e1d3c1949ceaa545bf875616c9dce0c9bec1")
+                .unwrap();
+
+        let entry = server_entry("roughtime.int08h.com", "roughtime.int08h.com:2002", &pubkey);
+
+        assert!(entry.contains(r#""name": "roughtime.int08h.com""#));
+        assert!(entry.contains(r#""publicKey": "AW5uAoTSTDfG5NfY1bTh08GUnOqlRb+HVhbJ3ODJvsE=""#));
+        assert!(entry.contains(r#""address": "roughtime.int08h.com:2002""#));
+        assert!(entry.contains(r#""version": "Google-Roughtime""#));
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!(json_string("plain"), r#""plain""#);
+        assert_eq!(json_string("a\"b\\c"), r#""a\"b\\c""#);
+        assert_eq!(json_string("x\ny\u{1}"), r#""x\ny\u0001""#);
+    }
+
+    #[test]
+    fn line_format_is_parsed() {
+        let list = format!(
+            "# name address port pubkey\n\nroughtime.int08h.com roughtime.int08h.com 2002 {}  # int08h\n",
+            INT08H_KEY
+        );
+
+        assert_eq!(parse_server_list(&list).unwrap(), vec![int08h()]);
+        assert!(parse_server_list("a b 2002").is_err());
+        assert!(parse_server_list("a b port 00").is_err());
+        assert!(parse_server_list("a b 2002 nothex").is_err());
+    }
+
+    #[test]
+    fn ecosystem_format_round_trips() {
+        let entry = server_entry(
+            "roughtime.int08h.com",
+            "roughtime.int08h.com:2002",
+            &hex::decode(INT08H_KEY).unwrap(),
+        );
+
+        let servers = format!("{{ \"servers\": [ {} ] }}", entry);
+        assert_eq!(parse_server_list(&servers).unwrap(), vec![int08h()]);
+
+        let array = format!("[ {} ]", entry);
+        assert_eq!(parse_server_list(&array).unwrap(), vec![int08h()]);
+    }
+
+    #[test]
+    fn ecosystem_ipv6_literal_and_bad_entries() {
+        let entry = server_entry("v6", "[2001:db8::1]:2002", &[1u8; 32]);
+        let parsed = parse_server_list(&format!("[{}]", entry)).unwrap();
+        assert_eq!(parsed[0].host, "2001:db8::1");
+        assert_eq!(parsed[0].port, 2002);
+
+        let other_key = entry.replace("ed25519", "ecdsa");
+        assert!(parse_server_list(&format!("[{}]", other_key)).is_err());
+
+        let no_port = server_entry("x", "example.com", &[1u8; 32]);
+        assert!(parse_server_list(&format!("[{}]", no_port)).is_err());
+    }
+}
@@ -23,9 +23,15 @@ pub enum Error {
     /// The associated tag was added to an `RtMessage` in non-increasing order.
     TagNotStrictlyIncreasing(Tag),
 
+    /// The associated tag was inserted into an `RtMessage` that already contains it.
+    DuplicateTag(Tag),
+
     /// The associated byte sequence does not correspond to a valid Roughtime tag.
     InvalidTag(Box<[u8]>),
 
+    /// The associated tag is not present in the message
+    TagNotFound(Tag),
+
     /// Invalid number of tags specified
     InvalidNumTags(u32),
 
@@ -35,6 +41,9 @@ pub enum Error {
     /// Encoding failed. The associated `std::io::Error` should provide more information.
     EncodingFailure(std::io::Error),
 
+    /// Output buffer (second value) is smaller than the encoded message (first value)
+    BufferTooSmall(usize, usize),
+
     /// Request was less than 1024 bytes
     RequestTooShort,
 
@@ -50,8 +59,35 @@ pub enum Error {
     /// Otherwise invalid request
     InvalidRequest,
 
+    /// Length of a PATH is not a multiple of the hash size
+    InvalidPathLength(u32),
+
+    /// PATH has more hashes than the maximum tree depth
+    PathTooDeep(u32),
+
+    /// INDX (first value) does not identify a leaf of a tree with the depth of PATH (second value)
+    InvalidIndex(u32, u32),
+
+    /// A response failed validation for the reason provided
+    InvalidResponse(String),
+
+    /// A signature did not verify for the reason provided
+    InvalidSignature(String),
+
     /// Runtime configuration is invalid for the reason provided
     InvalidConfiguration(String),
+
+    /// An audit log file is malformed for the reason provided
+    InvalidAuditLog(String),
+
+    /// A BIP39 mnemonic could not be encoded or decoded for the reason provided
+    InvalidMnemonic(String),
+
+    /// A Shamir share of the seed was malformed, or shares could not be combined
+    InvalidShare(String),
+
+    /// A key ceremony sheet was malformed, or sheets did not belong together
+    InvalidCeremonySheet(String),
 }
 
 impl From<std::io::Error> for Error {
@@ -0,0 +1,574 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Filters deciding, before any parsing or signing, whether a request is answered.
+//!
+//! Every request passes through a [`FilterChain`](struct.FilterChain.html). The server
+//! builds one from its configuration (`deny_cidrs`, `allow_cidrs`, then `rate_limit`
+//! with its `rate_limit_exempt_cidrs`);
+//! embedders can add their own [`RequestFilter`](trait.RequestFilter.html)s with
+//! [`Server::add_filter`](../server/struct.Server.html#method.add_filter).
+//!
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::config::ServerConfig;
+
+/// What to do with a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Answer the request
+    Accept,
+    /// Silently discard the request
+    Drop,
+    /// Answer the request only if the server is keeping up with its load; requests from
+    /// deprioritized sources are the first to be shed when the server falls behind
+    Deprioritize,
+}
+
+///
+/// Inspects each incoming request. Filters run on the server's event loop for every
+/// packet, so must be cheap.
+///
+pub trait RequestFilter {
+    /// Short name used when logging the filter's decisions
+    fn name(&self) -> &str;
+
+    /// Decide what to do with `packet`, received from `source` at `now`
+    fn check(&mut self, source: &SocketAddr, packet: &[u8], now: Instant) -> Verdict;
+}
+
+///
+/// Filters applied in order. The first `Drop` wins; otherwise any `Deprioritize` does.
+///
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<RequestFilter>>,
+}
+
+impl FilterChain {
+    /// An empty chain, accepting everything
+    pub fn new() -> FilterChain {
+        FilterChain::default()
+    }
+
+    /// The chain configured by `config`: deny list, then allow list, then rate limit
+    pub fn from_config(config: &ServerConfig) -> FilterChain {
+        let mut chain = FilterChain::new();
+
+        if !config.deny_cidrs().is_empty() {
+            chain.push(Box::new(CidrFilter::deny(config.deny_cidrs())));
+        }
+        if !config.allow_cidrs().is_empty() {
+            chain.push(Box::new(CidrFilter::allow(config.allow_cidrs())));
+        }
+        if let Some(rate) = config.rate_limit() {
+            let limiter = RateLimiter::new(rate, rate).exempt(config.rate_limit_exempt_cidrs());
+            chain.push(Box::new(limiter));
+        }
+
+        chain
+    }
+
+    /// Append `filter` to the chain
+    pub fn push(&mut self, filter: Box<RequestFilter>) {
+        self.filters.push(filter);
+    }
+
+    /// Number of filters in the chain
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    /// True if the chain has no filters
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Run `packet` from `source` through the filters
+    pub fn check(&mut self, source: &SocketAddr, packet: &[u8], now: Instant) -> Verdict {
+        let mut verdict = Verdict::Accept;
+
+        for filter in &mut self.filters {
+            match filter.check(source, packet, now) {
+                Verdict::Drop => {
+                    debug!("Filter '{}' dropped request from {}", filter.name(), source);
+                    return Verdict::Drop;
+                }
+                Verdict::Deprioritize => verdict = Verdict::Deprioritize,
+                Verdict::Accept => (),
+            }
+        }
+
+        verdict
+    }
+}
+
+/// An IPv4 or IPv6 network, e.g. `192.0.2.0/24` or `2001:db8::/32`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// True if `addr` lies within this network. IPv4-mapped IPv6 addresses match IPv4 networks.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        let addr = match addr {
+            IpAddr::V6(v6) if self.network.is_ipv4() => v6.to_ipv4().map_or(*addr, IpAddr::V4),
+            IpAddr::V6(_) => *addr,
+            IpAddr::V4(_) => *addr,
+        };
+
+        match (self.network, addr) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_matches(&net.octets(), &ip.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(&net.octets(), &ip.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+// Do the first `prefix_len` bits of `a` and `b` agree?
+fn prefix_matches(a: &[u8], b: &[u8], prefix_len: u8) -> bool {
+    let full_bytes = (prefix_len / 8) as usize;
+    let rem_bits = prefix_len % 8;
+
+    if a[..full_bytes] != b[..full_bytes] {
+        return false;
+    }
+    if rem_bits == 0 {
+        return true;
+    }
+
+    let mask = 0xffu8 << (8 - rem_bits);
+    a[full_bytes] & mask == b[full_bytes] & mask
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    /// Parse `address/prefix_len`; a bare address is a single-host network
+    fn from_str(s: &str) -> Result<Cidr, String> {
+        let (addr, prefix_len) = match s.find('/') {
+            Some(idx) => (&s[..idx], Some(&s[idx + 1..])),
+            None => (s, None),
+        };
+
+        let network: IpAddr = addr
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid network address in '{}'", s))?;
+
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|&len| len <= max_len)
+                .ok_or_else(|| format!("invalid prefix length in '{}'", s))?,
+            None => max_len,
+        };
+
+        Ok(Cidr {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// Whether a [`CidrFilter`](struct.CidrFilter.html) lists the only sources to answer, or
+/// sources never to answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ListKind {
+    Allow,
+    Deny,
+}
+
+///
+/// Drops requests by source address: from outside the listed networks (an allow list),
+/// or from inside them (a deny list).
+///
+pub struct CidrFilter {
+    kind: ListKind,
+    networks: Vec<Cidr>,
+}
+
+impl CidrFilter {
+    /// Answer only requests from `networks`
+    pub fn allow(networks: &[Cidr]) -> CidrFilter {
+        CidrFilter {
+            kind: ListKind::Allow,
+            networks: networks.to_vec(),
+        }
+    }
+
+    /// Never answer requests from `networks`
+    pub fn deny(networks: &[Cidr]) -> CidrFilter {
+        CidrFilter {
+            kind: ListKind::Deny,
+            networks: networks.to_vec(),
+        }
+    }
+}
+
+impl RequestFilter for CidrFilter {
+    fn name(&self) -> &str {
+        match self.kind {
+            ListKind::Allow => "allow_cidrs",
+            ListKind::Deny => "deny_cidrs",
+        }
+    }
+
+    fn check(&mut self, source: &SocketAddr, _packet: &[u8], _now: Instant) -> Verdict {
+        let listed = self.networks.iter().any(|net| net.contains(&source.ip()));
+
+        match (self.kind, listed) {
+            (ListKind::Allow, true) | (ListKind::Deny, false) => Verdict::Accept,
+            _ => Verdict::Drop,
+        }
+    }
+}
+
+/// Most sources a rate limiter tracks at once
+const MAX_TRACKED_SOURCES: usize = 65_536;
+
+/// Least time between two scans for sources to forget, as each scan visits every bucket
+const EVICTION_INTERVAL: Duration = Duration::from_secs(1);
+
+// Token bucket of one source address
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+///
+/// Limits each source IP address to `rate` requests per second, with bursts of up to
+/// `burst` requests. Requests over the limit are dropped. Sources in the
+/// [`exempt`](#method.exempt) networks are not limited.
+///
+/// At most `MAX_TRACKED_SOURCES` sources are tracked. When full, sources whose buckets
+/// have refilled are forgotten, then if that is not enough (e.g. under a flood of spoofed
+/// sources) the least recently seen. Such scans run at most once per `EVICTION_INTERVAL`;
+/// in between, requests from new sources are not tracked and are deprioritized.
+///
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    exempt: Vec<Cidr>,
+    buckets: HashMap<IpAddr, Bucket>,
+    last_eviction: Option<Instant>,
+}
+
+impl RateLimiter {
+    /// Allow `rate` requests per second per source, in bursts of up to `burst`
+    pub fn new(rate: u32, burst: u32) -> RateLimiter {
+        RateLimiter {
+            rate: f64::from(rate),
+            burst: f64::from(burst.max(1)),
+            exempt: Vec::new(),
+            buckets: HashMap::new(),
+            last_eviction: None,
+        }
+    }
+
+    /// Never limit requests from `networks`, e.g. trusted monitoring probes
+    pub fn exempt(mut self, networks: &[Cidr]) -> RateLimiter {
+        self.exempt = networks.to_vec();
+        self
+    }
+
+    /// Number of source addresses currently tracked
+    pub fn tracked_sources(&self) -> usize {
+        self.buckets.len()
+    }
+
+    // Make room for new sources, unless the last scan was too recent. Returns whether
+    // there is room.
+    fn make_room(&mut self, now: Instant) -> bool {
+        match self.last_eviction {
+            Some(last) if now.duration_since(last) < EVICTION_INTERVAL => return false,
+            _ => self.last_eviction = Some(now),
+        }
+
+        self.evict_idle(now);
+
+        // still mostly full: forget the least recently seen down to three quarters
+        let keep = MAX_TRACKED_SOURCES / 4 * 3;
+        if self.buckets.len() > keep {
+            let mut seen: Vec<(Instant, IpAddr)> = self
+                .buckets
+                .iter()
+                .map(|(ip, bucket)| (bucket.updated, *ip))
+                .collect();
+            seen.sort_unstable();
+
+            for &(_, ip) in &seen[..seen.len() - keep] {
+                self.buckets.remove(&ip);
+            }
+        }
+
+        true
+    }
+
+    // Forget sources whose buckets have refilled; they are indistinguishable from new ones
+    fn evict_idle(&mut self, now: Instant) {
+        let (rate, burst) = (self.rate, self.burst);
+
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.duration_since(bucket.updated);
+            let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_micros()) / 1e6;
+            bucket.tokens + secs * rate < burst
+        });
+    }
+}
+
+impl RequestFilter for RateLimiter {
+    fn name(&self) -> &str {
+        "rate_limit"
+    }
+
+    fn check(&mut self, source: &SocketAddr, _packet: &[u8], now: Instant) -> Verdict {
+        if self.exempt.iter().any(|net| net.contains(&source.ip())) {
+            return Verdict::Accept;
+        }
+
+        if self.buckets.len() >= MAX_TRACKED_SOURCES
+            && !self.buckets.contains_key(&source.ip())
+            && !self.make_room(now)
+        {
+            return Verdict::Deprioritize;
+        }
+
+        let burst = self.burst;
+        let bucket = self.buckets.entry(source.ip()).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+
+        let elapsed = now.duration_since(bucket.updated);
+        let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_micros()) / 1e6;
+        bucket.tokens = (bucket.tokens + secs * self.rate).min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Verdict::Accept
+        } else {
+            Verdict::Drop
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::MemoryConfig;
+    use crate::filter::{
+        Cidr, CidrFilter, FilterChain, RateLimiter, RequestFilter, Verdict, MAX_TRACKED_SOURCES,
+    };
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::time::{Duration, Instant};
+
+    fn addr(s: &str) -> SocketAddr {
+        SocketAddr::new(s.parse().unwrap(), 2002)
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn cidr_parsing_and_matching() {
+        let net: Cidr = "192.0.2.0/24".parse().unwrap();
+        assert!(net.contains(&ip("192.0.2.77")));
+        assert!(!net.contains(&ip("192.0.3.1")));
+        assert!(net.contains(&ip("::ffff:192.0.2.1")));
+        assert_eq!(net.to_string(), "192.0.2.0/24");
+
+        let net: Cidr = "10.0.0.0/9".parse().unwrap();
+        assert!(net.contains(&ip("10.127.255.255")));
+        assert!(!net.contains(&ip("10.128.0.0")));
+
+        let net: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(net.contains(&ip("2001:db8:1::1")));
+        assert!(!net.contains(&ip("2001:db9::1")));
+        assert!(!net.contains(&ip("192.0.2.1")));
+
+        let host: Cidr = "198.51.100.7".parse().unwrap();
+        assert!(host.contains(&ip("198.51.100.7")));
+        assert!(!host.contains(&ip("198.51.100.8")));
+
+        assert!("0.0.0.0/0"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains(&ip("203.0.113.9")));
+        assert!("192.0.2.0/33".parse::<Cidr>().is_err());
+        assert!("not-an-ip/8".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn allow_and_deny_lists() {
+        let nets = vec!["192.0.2.0/24".parse().unwrap()];
+        let now = Instant::now();
+
+        let mut allow = CidrFilter::allow(&nets);
+        assert_eq!(allow.check(&addr("192.0.2.1"), &[], now), Verdict::Accept);
+        assert_eq!(allow.check(&addr("198.51.100.1"), &[], now), Verdict::Drop);
+
+        let mut deny = CidrFilter::deny(&nets);
+        assert_eq!(deny.check(&addr("192.0.2.1"), &[], now), Verdict::Drop);
+        assert_eq!(deny.check(&addr("198.51.100.1"), &[], now), Verdict::Accept);
+    }
+
+    #[test]
+    fn rate_limiter_refills() {
+        let mut limiter = RateLimiter::new(10, 2);
+        let source = addr("192.0.2.1");
+        let start = Instant::now();
+
+        assert_eq!(limiter.check(&source, &[], start), Verdict::Accept);
+        assert_eq!(limiter.check(&source, &[], start), Verdict::Accept);
+        assert_eq!(limiter.check(&source, &[], start), Verdict::Drop);
+
+        // other sources have their own buckets
+        assert_eq!(
+            limiter.check(&addr("192.0.2.2"), &[], start),
+            Verdict::Accept
+        );
+
+        // one token every 100ms
+        let later = start + Duration::from_millis(100);
+        assert_eq!(limiter.check(&source, &[], later), Verdict::Accept);
+        assert_eq!(limiter.check(&source, &[], later), Verdict::Drop);
+    }
+
+    #[test]
+    fn rate_limiter_tracks_a_bounded_number_of_sources() {
+        let mut limiter = RateLimiter::new(10, 2);
+        let start = Instant::now();
+        let spoofed = |i: usize| SocketAddr::new(IpAddr::V4(Ipv4Addr::from(i as u32)), 2002);
+
+        // a flood of distinct sources, none of them idle
+        for i in 0..MAX_TRACKED_SOURCES + 20_000 {
+            let verdict = limiter.check(&spoofed(i), &[], start);
+            assert!(limiter.tracked_sources() <= MAX_TRACKED_SOURCES);
+            assert_ne!(verdict, Verdict::Drop);
+        }
+
+        // full again, and no scan allowed yet: new sources are not tracked
+        assert_eq!(limiter.tracked_sources(), MAX_TRACKED_SOURCES);
+        let fresh = addr("198.51.100.1");
+        assert_eq!(limiter.check(&fresh, &[], start), Verdict::Deprioritize);
+        assert_eq!(limiter.tracked_sources(), MAX_TRACKED_SOURCES);
+
+        let later = start + Duration::from_secs(1);
+        assert_eq!(limiter.check(&fresh, &[], later), Verdict::Accept);
+        assert!(limiter.tracked_sources() <= MAX_TRACKED_SOURCES);
+    }
+
+    #[test]
+    fn chain_from_config() {
+        let nets = vec!["192.0.2.0/24".parse().unwrap()];
+        let cfg = MemoryConfig::builder()
+            .deny_cidrs(&nets)
+            .rate_limit(Some(1))
+            .build();
+
+        let mut chain = FilterChain::from_config(&cfg);
+        assert_eq!(chain.len(), 2);
+
+        let now = Instant::now();
+        assert_eq!(chain.check(&addr("192.0.2.1"), &[], now), Verdict::Drop);
+        assert_eq!(
+            chain.check(&addr("198.51.100.1"), &[], now),
+            Verdict::Accept
+        );
+        assert_eq!(chain.check(&addr("198.51.100.1"), &[], now), Verdict::Drop);
+
+        assert!(FilterChain::from_config(&MemoryConfig::new(2002)).is_empty());
+    }
+
+    #[test]
+    fn exempt_sources_bypass_only_the_rate_limit() {
+        let probes: Vec<Cidr> = vec!["192.0.2.0/24".parse().unwrap()];
+        let denied: Vec<Cidr> = vec!["192.0.2.66/32".parse().unwrap()];
+        let cfg = MemoryConfig::builder()
+            .deny_cidrs(&denied)
+            .rate_limit(Some(1))
+            .rate_limit_exempt_cidrs(&probes)
+            .build();
+
+        let mut chain = FilterChain::from_config(&cfg);
+        let now = Instant::now();
+
+        for _ in 0..10 {
+            assert_eq!(chain.check(&addr("192.0.2.1"), &[], now), Verdict::Accept);
+        }
+        assert_eq!(chain.check(&addr("192.0.2.66"), &[], now), Verdict::Drop);
+
+        assert_eq!(
+            chain.check(&addr("198.51.100.1"), &[], now),
+            Verdict::Accept
+        );
+        assert_eq!(chain.check(&addr("198.51.100.1"), &[], now), Verdict::Drop);
+
+        let mut limiter = RateLimiter::new(1, 1).exempt(&probes);
+        assert_eq!(limiter.check(&addr("192.0.2.1"), &[], now), Verdict::Accept);
+        assert_eq!(limiter.check(&addr("192.0.2.1"), &[], now), Verdict::Accept);
+        assert_eq!(limiter.tracked_sources(), 0);
+    }
+
+    struct Fixed(Verdict);
+
+    impl RequestFilter for Fixed {
+        fn name(&self) -> &str {
+            "fixed"
+        }
+
+        fn check(&mut self, _source: &SocketAddr, _packet: &[u8], _now: Instant) -> Verdict {
+            self.0
+        }
+    }
+
+    #[test]
+    fn chain_combines_verdicts() {
+        let source = addr("192.0.2.1");
+        let now = Instant::now();
+
+        let mut chain = FilterChain::new();
+        assert_eq!(chain.check(&source, &[], now), Verdict::Accept);
+
+        chain.push(Box::new(Fixed(Verdict::Accept)));
+        chain.push(Box::new(Fixed(Verdict::Deprioritize)));
+        assert_eq!(chain.check(&source, &[], now), Verdict::Deprioritize);
+
+        chain.push(Box::new(Fixed(Verdict::Drop)));
+        assert_eq!(chain.check(&source, &[], now), Verdict::Drop);
+    }
+}
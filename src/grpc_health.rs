@@ -0,0 +1,143 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! The standard gRPC health checking protocol (`grpc.health.v1.Health`) on the health
+//! check port.
+//!
+//! Enabled with the `grpc-health` feature and `health_check_mode: grpc`. The overall
+//! server status (service name `""`) is `SERVING` while the server is ready to serve
+//! correct time, by the same criteria as the readiness check, and `NOT_SERVING` otherwise.
+//!
+
+use std::net::TcpListener;
+
+use tokio::runtime::{Builder, Runtime};
+use tonic::transport::server::TcpIncoming;
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+
+use crate::Error;
+
+/// Service name whose status describes the whole server
+const SERVER_STATUS: &str = "";
+
+///
+/// A `grpc.health.v1.Health` service answering on a background thread.
+///
+/// The service keeps running until the `GrpcHealth` is dropped.
+///
+pub struct GrpcHealth {
+    runtime: Runtime,
+    reporter: HealthReporter,
+    serving: bool,
+}
+
+impl GrpcHealth {
+    /// Serve the health service on `listener`, initially reporting `NOT_SERVING`
+    pub fn start(listener: TcpListener) -> Result<GrpcHealth, Error> {
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("grpc-health")
+            .enable_all()
+            .build()?;
+
+        listener.set_nonblocking(true)?;
+        let incoming = {
+            let _guard = runtime.enter();
+            let listener = tokio::net::TcpListener::from_std(listener)?;
+            TcpIncoming::from_listener(listener, true, None)
+                .map_err(|e| Error::InvalidConfiguration(format!("gRPC health listener: {}", e)))?
+        };
+
+        let (mut reporter, service) = tonic_health::server::health_reporter();
+        runtime.block_on(reporter.set_service_status(SERVER_STATUS, ServingStatus::NotServing));
+
+        runtime.spawn(async move {
+            let result = tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming(incoming)
+                .await;
+
+            if let Err(e) = result {
+                error!("gRPC health service failed: {}", e);
+            }
+        });
+
+        Ok(GrpcHealth {
+            runtime,
+            reporter,
+            serving: false,
+        })
+    }
+
+    /// Report the server as `SERVING` if `serving`, otherwise `NOT_SERVING`
+    pub fn set_serving(&mut self, serving: bool) {
+        if serving == self.serving {
+            return;
+        }
+
+        let status = if serving {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        };
+
+        info!("gRPC health status now {:?}", status);
+        self.runtime
+            .block_on(self.reporter.set_service_status(SERVER_STATUS, status));
+        self.serving = serving;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+
+    use tokio::runtime::Runtime;
+    use tonic_health::pb::health_check_response::ServingStatus;
+    use tonic_health::pb::health_client::HealthClient;
+    use tonic_health::pb::HealthCheckRequest;
+
+    use crate::grpc_health::GrpcHealth;
+
+    fn check(runtime: &Runtime, port: u16) -> i32 {
+        runtime.block_on(async {
+            let mut client = HealthClient::connect(format!("http://127.0.0.1:{}", port))
+                .await
+                .unwrap();
+            let request = HealthCheckRequest {
+                service: String::new(),
+            };
+
+            client.check(request).await.unwrap().into_inner().status
+        })
+    }
+
+    #[test]
+    fn status_follows_readiness() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut health = GrpcHealth::start(listener).unwrap();
+        let runtime = Runtime::new().unwrap();
+
+        assert_eq!(check(&runtime, port), ServingStatus::NotServing as i32);
+
+        health.set_serving(true);
+        assert_eq!(check(&runtime, port), ServingStatus::Serving as i32);
+
+        health.set_serving(false);
+        assert_eq!(check(&runtime, port), ServingStatus::NotServing as i32);
+    }
+}
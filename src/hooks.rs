@@ -0,0 +1,134 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Callbacks for programs embedding the [`Server`](../server/struct.Server.html).
+//!
+//! Hooks run synchronously on the server's event loop, so they should be quick: hand
+//! anything slow (network calls, disk writes) off to another thread.
+//!
+//! ```no_run
+//! use roughenough::config::MemoryConfig;
+//! use roughenough::server::Server;
+//!
+//! let mut server = Server::new(Box::new(MemoryConfig::new(2002)));
+//!
+//! let mut answered = 0u64;
+//! server.on_response_sent(move |event| {
+//!     answered += 1;
+//!     println!("answered {} ({} so far)", event.destination, answered);
+//! });
+//! ```
+//!
+
+use std::net::SocketAddr;
+
+/// A request received by the server and admitted by its request filters
+#[derive(Debug)]
+pub struct RequestEvent<'a> {
+    /// Address the request came from
+    pub source: SocketAddr,
+    /// The complete request packet
+    pub packet: &'a [u8],
+    /// The request's nonce, or `None` if the request is invalid and will not be answered
+    pub nonce: Option<&'a [u8]>,
+}
+
+/// A batch of requests whose signed response (SREP) has been created
+#[derive(Debug)]
+pub struct BatchEvent<'a> {
+    /// Sequence number of the batch since the server started
+    pub batch: u64,
+    /// Number of requests answered by the batch
+    pub size: usize,
+    /// Midpoint of the signed response, in microseconds
+    pub midpoint: u64,
+    /// Radius of the signed response, in microseconds
+    pub radius: u32,
+    /// Root of the batch's Merkle tree
+    pub merkle_root: &'a [u8],
+}
+
+/// A response sent to a client
+#[derive(Debug)]
+pub struct ResponseEvent<'a> {
+    /// Address the response was sent to
+    pub destination: SocketAddr,
+    /// Nonce of the request being answered
+    pub nonce: &'a [u8],
+    /// Sequence number of the batch the response belongs to
+    pub batch: u64,
+    /// Size of the response, in bytes
+    pub bytes_sent: usize,
+}
+
+///
+/// The callbacks registered with a server. Each is optional; a server without hooks
+/// pays only for checking that none are set.
+///
+#[derive(Default)]
+pub struct Hooks {
+    on_request: Option<Box<FnMut(&RequestEvent)>>,
+    on_batch_signed: Option<Box<FnMut(&BatchEvent)>>,
+    on_response_sent: Option<Box<FnMut(&ResponseEvent)>>,
+}
+
+impl Hooks {
+    /// No callbacks
+    pub fn new() -> Hooks {
+        Hooks::default()
+    }
+
+    /// Call `f` for every request the request filters admit, valid or not
+    pub fn set_on_request<F>(&mut self, f: F)
+    where
+        F: FnMut(&RequestEvent) + 'static,
+    {
+        self.on_request = Some(Box::new(f));
+    }
+
+    /// Call `f` each time a batch's SREP is signed, before its responses are sent
+    pub fn set_on_batch_signed<F>(&mut self, f: F)
+    where
+        F: FnMut(&BatchEvent) + 'static,
+    {
+        self.on_batch_signed = Some(Box::new(f));
+    }
+
+    /// Call `f` after each response is sent
+    pub fn set_on_response_sent<F>(&mut self, f: F)
+    where
+        F: FnMut(&ResponseEvent) + 'static,
+    {
+        self.on_response_sent = Some(Box::new(f));
+    }
+
+    pub(crate) fn request(&mut self, event: &RequestEvent) {
+        if let Some(ref mut f) = self.on_request {
+            f(event);
+        }
+    }
+
+    pub(crate) fn batch_signed(&mut self, event: &BatchEvent) {
+        if let Some(ref mut f) = self.on_batch_signed {
+            f(event);
+        }
+    }
+
+    pub(crate) fn response_sent(&mut self, event: &ResponseEvent) {
+        if let Some(ref mut f) = self.on_response_sent {
+            f(event);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Assembly and validation of the CERT message delegating to the online key.
+//!
+//! Split into separate steps so the DELE can be signed outside of the server, for example
+//! during an air-gapped key ceremony.
+//!
+
+use crate::error::Error;
+use crate::message::RtMessage;
+use crate::sign::Verifier;
+use crate::tag::Tag;
+use crate::{CERTIFICATE_CONTEXT, PUBKEY_LENGTH, SIGNATURE_LENGTH};
+
+/// The exact bytes the long-term key signs to delegate to the online key: the
+/// certificate context string followed by the encoded DELE message.
+pub fn signing_request(dele_bytes: &[u8]) -> Vec<u8> {
+    let mut request = Vec::with_capacity(CERTIFICATE_CONTEXT.len() + dele_bytes.len());
+    request.extend_from_slice(CERTIFICATE_CONTEXT.as_bytes());
+    request.extend_from_slice(dele_bytes);
+
+    request
+}
+
+/// Create a CERT message from the encoded DELE and the long-term key's signature of
+/// its [`signing_request`](fn.signing_request.html).
+pub fn assemble_cert(dele_bytes: &[u8], signature: &[u8]) -> RtMessage {
+    let mut cert_msg = RtMessage::new(2);
+    cert_msg.add_field(Tag::SIG, signature).unwrap();
+    cert_msg.add_field(Tag::DELE, dele_bytes).unwrap();
+
+    cert_msg
+}
+
+/// Check that `cert` is well formed and its DELE is signed by `long_term_public_key`.
+pub fn verify_cert(cert: &RtMessage, long_term_public_key: &[u8]) -> Result<(), Error> {
+    if long_term_public_key.len() != PUBKEY_LENGTH as usize {
+        return Err(Error::InvalidSignature(format!(
+            "public key must be {} bytes, found {}",
+            PUBKEY_LENGTH,
+            long_term_public_key.len()
+        )));
+    }
+
+    let signature = cert.get_field(Tag::SIG).ok_or(Error::TagNotFound(Tag::SIG))?;
+    let dele_bytes = cert
+        .get_field(Tag::DELE)
+        .ok_or(Error::TagNotFound(Tag::DELE))?;
+
+    if signature.len() != SIGNATURE_LENGTH as usize {
+        return Err(Error::InvalidValueLength(Tag::SIG, signature.len() as u32));
+    }
+
+    // DELE must decode and carry the delegated key and validity window
+    let dele = RtMessage::from_bytes(dele_bytes)?;
+    for tag in &[Tag::PUBK, Tag::MINT, Tag::MAXT] {
+        dele.get_field(*tag).ok_or(Error::TagNotFound(*tag))?;
+    }
+
+    let mut verifier = Verifier::new(long_term_public_key);
+    verifier.update(&signing_request(dele_bytes));
+
+    if verifier.verify(signature) {
+        Ok(())
+    } else {
+        Err(Error::InvalidSignature(
+            "DELE signature does not match long-term public key".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::key::{assemble_cert, verify_cert, LongTermKey, OnlineKey};
+    use crate::sign::{RingSigner, Signer};
+    use crate::Error;
+
+    #[test]
+    fn externally_signed_cert_matches_make_cert() {
+        let seed = [0x5a; 32];
+        let online_key = OnlineKey::new();
+        let dele_bytes = online_key.make_dele().encode().unwrap();
+
+        // "external" signer holding the long-term seed
+        let mut signer = RingSigner::from_seed(&seed);
+        signer.update(&super::signing_request(&dele_bytes));
+        let cert = assemble_cert(&dele_bytes, &signer.sign());
+
+        let mut long_term_key = LongTermKey::new(&seed);
+        let expected = long_term_key.make_cert(&online_key);
+
+        assert_eq!(cert.encode().unwrap(), expected.encode().unwrap());
+        assert!(verify_cert(&cert, long_term_key.public_key()).is_ok());
+    }
+
+    #[test]
+    fn cert_signed_by_wrong_key_is_rejected() {
+        let online_key = OnlineKey::new();
+        let cert = LongTermKey::new(&[0x01; 32]).make_cert(&online_key);
+        let other_key = LongTermKey::new(&[0x02; 32]);
+
+        match verify_cert(&cert, other_key.public_key()) {
+            Err(Error::InvalidSignature(_)) => (),
+            e => panic!("unexpected result {:?}", e),
+        }
+    }
+}
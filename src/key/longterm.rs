@@ -19,41 +19,47 @@
 use std::fmt;
 use std::fmt::Formatter;
 
-use crate::key::OnlineKey;
+use crate::key::{assemble_cert, signing_request, OnlineKey};
 use crate::message::RtMessage;
-use crate::sign::Signer;
-use crate::tag::Tag;
-use crate::CERTIFICATE_CONTEXT;
+use crate::sign::{RingSigner, Signer};
 
 ///
 /// Represents the server's long-term identity.
 ///
 pub struct LongTermKey {
-    signer: Signer,
+    signer: Box<Signer>,
 }
 
 impl LongTermKey {
+    /// Create a long-term key held in memory, derived from `seed`
     pub fn new(seed: &[u8]) -> Self {
         LongTermKey {
-            signer: Signer::from_seed(seed),
+            signer: Box::new(RingSigner::from_seed(seed)),
         }
     }
 
+    /// Create a long-term key whose signing operations are performed by `signer`,
+    /// for example an HSM or KMS asymmetric key.
+    pub fn from_signer(signer: Box<Signer>) -> Self {
+        LongTermKey { signer }
+    }
+
     /// Create a CERT message with a DELE containing the provided online key
     /// and a SIG of the DELE value signed by the long-term key
     pub fn make_cert(&mut self, online_key: &OnlineKey) -> RtMessage {
-        let dele_bytes = online_key.make_dele().encode().unwrap();
+        self.sign_dele(&online_key.make_dele())
+    }
 
-        self.signer.update(CERTIFICATE_CONTEXT.as_bytes());
-        self.signer.update(&dele_bytes);
+    /// Create a CERT message containing the provided DELE and a SIG of it signed
+    /// by the long-term key
+    pub fn sign_dele(&mut self, dele: &RtMessage) -> RtMessage {
+        let dele_bytes = dele.encode().unwrap();
 
-        let dele_signature = self.signer.sign();
+        self.signer.update(&signing_request(&dele_bytes));
 
-        let mut cert_msg = RtMessage::new(2);
-        cert_msg.add_field(Tag::SIG, &dele_signature).unwrap();
-        cert_msg.add_field(Tag::DELE, &dele_bytes).unwrap();
+        let dele_signature = self.signer.sign();
 
-        cert_msg
+        assemble_cert(&dele_bytes, &dele_signature)
     }
 
     /// Return the public key for the provided seed
@@ -64,6 +70,6 @@ impl LongTermKey {
 
 impl fmt::Display for LongTermKey {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "{}", self.signer)
+        write!(f, "{}", hex::encode(self.signer.public_key_bytes()))
     }
 }
@@ -0,0 +1,197 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! [BIP39](https://github.com/bitcoin/bips/blob/master/bip-0039.mediawiki) mnemonics for
+//! writing a seed down on paper. A 32-byte seed becomes 24 English words, the last of
+//! which carries a checksum.
+//!
+
+use ring::digest;
+
+use crate::error::Error;
+
+/// The BIP39 English wordlist, one word per line in index order
+const WORDLIST: &str = include_str!("bip39-english.txt");
+
+/// Bits of seed (or checksum) encoded by each word
+const BITS_PER_WORD: usize = 11;
+
+fn wordlist() -> Vec<&'static str> {
+    WORDLIST.lines().collect()
+}
+
+// Seeds from 128 to 256 bits in steps of 32 bits, as BIP39 allows
+fn check_seed_length(len: usize) -> Result<(), Error> {
+    if len < 16 || len > 32 || len % 4 != 0 {
+        return Err(Error::InvalidMnemonic(format!(
+            "a {} byte seed cannot be encoded; BIP39 supports 16 to 32 bytes in steps of 4",
+            len
+        )));
+    }
+
+    Ok(())
+}
+
+// `seed` followed by its checksum bits, one bit per byte
+fn seed_bits(seed: &[u8]) -> Vec<u8> {
+    let checksum_bits = seed.len() / 4;
+    let checksum = digest::digest(&digest::SHA256, seed);
+
+    let bits = |bytes: &[u8]| -> Vec<u8> {
+        bytes
+            .iter()
+            .flat_map(|b| (0..8).rev().map(move |i| (b >> i) & 1))
+            .collect()
+    };
+
+    let mut all = bits(seed);
+    all.extend(bits(checksum.as_ref()).into_iter().take(checksum_bits));
+    all
+}
+
+/// Encode `seed` (normally the 32-byte long-term seed) as a BIP39 mnemonic phrase
+pub fn seed_to_mnemonic(seed: &[u8]) -> Result<String, Error> {
+    check_seed_length(seed.len())?;
+
+    let words = wordlist();
+    let phrase: Vec<&str> = seed_bits(seed)
+        .chunks(BITS_PER_WORD)
+        .map(|chunk| chunk.iter().fold(0, |acc, &b| (acc << 1) | b as usize))
+        .map(|idx| words[idx])
+        .collect();
+
+    Ok(phrase.join(" "))
+}
+
+///
+/// Decode a BIP39 mnemonic `phrase` back into the seed, verifying its checksum. Words are
+/// separated by any whitespace and case is ignored. As BIP39 words are unique in their
+/// first four letters, those are enough for longer words.
+///
+pub fn seed_from_mnemonic(phrase: &str) -> Result<Vec<u8>, Error> {
+    let words = wordlist();
+    let mut bits = Vec::new();
+    let mut count = 0;
+
+    for word in phrase.split_whitespace() {
+        let word = word.to_lowercase();
+        let idx = words
+            .iter()
+            .position(|w| *w == word || (word.len() == 4 && w.starts_with(&word)))
+            .ok_or_else(|| Error::InvalidMnemonic(format!("'{}' is not a BIP39 word", word)))?;
+
+        bits.extend((0..BITS_PER_WORD).rev().map(|i| ((idx >> i) & 1) as u8));
+        count += 1;
+    }
+
+    // 3 words per 4 bytes of seed
+    if count % 3 != 0 || count < 12 || count > 24 {
+        return Err(Error::InvalidMnemonic(format!(
+            "a mnemonic has 12, 15, 18, 21 or 24 words, not {}",
+            count
+        )));
+    }
+
+    let seed_len = count * 4 / 3;
+    let seed: Vec<u8> = bits[..seed_len * 8]
+        .chunks(8)
+        .map(|byte| byte.iter().fold(0u8, |acc, &b| (acc << 1) | b))
+        .collect();
+
+    if seed_bits(&seed) != bits {
+        return Err(Error::InvalidMnemonic(
+            "checksum mismatch; a word is wrong or out of order".to_string(),
+        ));
+    }
+
+    Ok(seed)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::key::mnemonic::{seed_from_mnemonic, seed_to_mnemonic, wordlist};
+    use crate::Error;
+
+    // From the BIP39 reference test vectors
+    const VECTORS: &[(&str, &str)] = &[
+        (
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon \
+             abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon \
+             abandon abandon abandon art",
+        ),
+        (
+            "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+            "legal winner thank year wave sausage worth useful legal winner thank year wave \
+             sausage worth useful legal winner thank year wave sausage worth title",
+        ),
+        (
+            "68a79eaca2324873eacc50cb9c6eca8cc68ea5d936f98787c60c7ebc74e6ce7c",
+            "hamster diagram private dutch cause delay private meat slide toddler razor book \
+             happy fancy gospel tennis maple dilemma loan word shrug inflict delay length",
+        ),
+        (
+            "f585c11aec520db57dd353c69554b21a89b20fb0650966fa0a9d6f74fd989d8f",
+            "void come effort suffer camp survey warrior heavy shoot primary clutch crush open \
+             amazing screen patrol group space point ten exist slush involve unfold",
+        ),
+        (
+            "9e885d952ad362caeb4efe34a8e91bd2",
+            "ozone drill grab fiber curtain grace pudding thank cruise elder eight picnic",
+        ),
+    ];
+
+    #[test]
+    fn wordlist_is_complete() {
+        let words = wordlist();
+        assert_eq!(words.len(), 2048);
+        assert_eq!(words[0], "abandon");
+        assert_eq!(words[2047], "zoo");
+    }
+
+    #[test]
+    fn reference_vectors_round_trip() {
+        for &(seed_hex, phrase) in VECTORS {
+            let seed = hex::decode(seed_hex).unwrap();
+            assert_eq!(seed_to_mnemonic(&seed).unwrap(), phrase);
+            assert_eq!(seed_from_mnemonic(phrase).unwrap(), seed);
+        }
+    }
+
+    #[test]
+    fn abbreviated_and_mixed_case_words_are_accepted() {
+        let phrase = "OZONE dril grab fibe curt grac pudd than crui elde eigh picn";
+        let seed = seed_from_mnemonic(phrase).unwrap();
+        assert_eq!(hex::encode(seed), "9e885d952ad362caeb4efe34a8e91bd2");
+    }
+
+    #[test]
+    fn bad_mnemonics_are_rejected() {
+        let swapped =
+            "drill ozone grab fiber curtain grace pudding thank cruise elder eight picnic";
+        let unknown =
+            "ozone drill grab fiber curtain grace pudding thank cruise elder eight roughtime";
+        let short = "ozone drill grab fiber curtain grace pudding thank cruise elder eight";
+
+        for phrase in &[swapped, unknown, short] {
+            match seed_from_mnemonic(phrase) {
+                Err(Error::InvalidMnemonic(_)) => (),
+                other => panic!("unexpected result {:?} for '{}'", other, phrase),
+            }
+        }
+
+        assert!(seed_to_mnemonic(&[0u8; 31]).is_err());
+    }
+}
@@ -16,15 +16,25 @@
 //! Representations and management of Roughtime's online and long-term Ed25519 keys
 //!
 
+mod certificate;
 mod longterm;
+mod mnemonic;
 mod online;
+mod shamir;
+mod shared;
 
 use std::fmt::Display;
 use std::fmt::Formatter;
 use std::str::FromStr;
 
+use ring::digest;
+
+pub use self::certificate::{assemble_cert, signing_request, verify_cert};
 pub use self::longterm::LongTermKey;
+pub use self::mnemonic::{seed_from_mnemonic, seed_to_mnemonic};
 pub use self::online::OnlineKey;
+pub use self::shamir::{combine_shares, split_seed, SeedShare};
+pub use self::shared::SharedDelegation;
 
 /// Methods for protecting the server's long-term identity
 #[derive(Debug, PartialEq, Eq, PartialOrd, Hash, Clone)]
@@ -55,16 +65,45 @@ impl FromStr for KmsProtection {
     fn from_str(s: &str) -> Result<KmsProtection, String> {
         match s {
             "plaintext" => Ok(KmsProtection::Plaintext),
-            s if s.starts_with("arn:") => Ok(KmsProtection::AwsKmsEnvelope(s.to_string())),
+            s if s.starts_with("arn:") || s.starts_with("alias/") || s.starts_with("mrk-") => {
+                Ok(KmsProtection::AwsKmsEnvelope(s.to_string()))
+            }
             s if s.starts_with("projects/") => Ok(KmsProtection::GoogleKmsEnvelope(s.to_string())),
             s => Err(format!("unknown KmsProtection '{}'", s)),
         }
     }
 }
 
+///
+/// Short, human-comparable form of a long-term `public_key`: the first 8 bytes of its
+/// SHA-256 digest as colon-separated hex, e.g. `3a:1f:...:c2`.
+///
+pub fn fingerprint(public_key: &[u8]) -> String {
+    let digest = digest::digest(&digest::SHA256, public_key);
+    let bytes: Vec<String> = digest.as_ref()[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    bytes.join(":")
+}
+
+///
+/// The long-term `public_key` in hex, Base64 and [fingerprint](fn.fingerprint.html) forms,
+/// one `form: value` line each.
+///
+pub fn describe_public_key(public_key: &[u8]) -> String {
+    format!(
+        "hex: {}\nbase64: {}\nfingerprint: {}\n",
+        hex::encode(public_key),
+        base64::encode(public_key),
+        fingerprint(public_key)
+    )
+}
+
 #[cfg(test)]
 mod test {
-    use crate::key::KmsProtection;
+    use crate::key::{describe_public_key, fingerprint, KmsProtection};
     use std::str::FromStr;
 
     #[test]
@@ -86,9 +125,34 @@ mod test {
             Ok(KmsProtection::GoogleKmsEnvelope(msg)) => assert_eq!(msg, resource_id),
             e => panic!("unexpected result {:?}", e),
         }
+        for aws_ref in &["alias/roughenough", "mrk-1234abcd12ab34cd56ef1234567890ab"] {
+            match KmsProtection::from_str(aws_ref) {
+                Ok(KmsProtection::AwsKmsEnvelope(msg)) => assert_eq!(&msg, aws_ref),
+                e => panic!("unexpected result {:?}", e),
+            }
+        }
         match KmsProtection::from_str("frobble") {
             Err(msg) => assert!(msg.contains("unknown KmsProtection")),
             e => panic!("unexpected result {:?}", e),
         }
     }
+
+    #[test]
+    fn public_key_forms() {
+        let pubkey =
+            hex::decode("016e6e0284d24c37c6e4d7d8d5b4e1d3c1949ceaa545bf875616c9dce0c9bec1")
+                .unwrap();
+
+        let fp = fingerprint(&pubkey);
+        assert_eq!(fp, "b4:2e:41:8f:22:fd:94:0f");
+
+        let described = describe_public_key(&pubkey);
+        let lines: Vec<&str> = described.lines().collect();
+        assert_eq!(
+            lines[0],
+            "hex: 016e6e0284d24c37c6e4d7d8d5b4e1d3c1949ceaa545bf875616c9dce0c9bec1"
+        );
+        assert_eq!(lines[1], "base64: AW5uAoTSTDfG5NfY1bTh08GUnOqlRb+HVhbJ3ODJvsE=");
+        assert_eq!(lines[2], format!("fingerprint: {}", fp));
+    }
 }
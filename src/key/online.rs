@@ -13,41 +13,84 @@
 // limitations under the License.
 
 use crate::message::RtMessage;
-use crate::sign::Signer;
+use crate::sign::{RingSigner, Signer};
 use crate::tag::Tag;
 use time::Timespec;
 
-use byteorder::{LittleEndian, WriteBytesExt};
-
 use std::fmt;
 use std::fmt::Formatter;
 
-use crate::SIGNED_RESPONSE_CONTEXT;
+use crate::clock;
+#[cfg(feature = "pq")]
+use crate::pq::PqKey;
+use crate::{DEFAULT_RADIUS, SIGNED_RESPONSE_CONTEXT};
 
 ///
 /// Represents the delegated Roughtime ephemeral online key.
 ///
 pub struct OnlineKey {
-    signer: Signer,
+    signer: RingSigner,
+    #[cfg(feature = "pq")]
+    pq_key: Option<PqKey>,
 }
 
 impl OnlineKey {
     pub fn new() -> Self {
         OnlineKey {
-            signer: Signer::new(),
+            signer: RingSigner::new(),
+            #[cfg(feature = "pq")]
+            pq_key: None,
+        }
+    }
+
+    /// Re-create a previously generated online key from its `seed`. Used when the
+    /// delegation (CERT) for this key was produced ahead of time, e.g. by a key ceremony.
+    pub fn from_seed(seed: &[u8]) -> Self {
+        OnlineKey {
+            signer: RingSigner::from_seed(seed),
+            #[cfg(feature = "pq")]
+            pq_key: None,
         }
     }
 
+    ///
+    /// Also sign every SREP with a fresh experimental post-quantum key, whose public key is
+    /// added to the DELE. Must be called before the DELE is made and certified.
+    ///
+    #[cfg(feature = "pq")]
+    pub fn with_pq_key(mut self) -> Self {
+        self.pq_key = Some(PqKey::new());
+        self
+    }
+
+    /// Return the public key of this online key
+    pub fn public_key(&self) -> &[u8] {
+        self.signer.public_key_bytes()
+    }
+
     /// Create a DELE message containing the public key of this online key
     pub fn make_dele(&self) -> RtMessage {
-        let zeros = [0u8; 8];
-        let max = [0xff; 8];
+        self.make_dele_with_validity(0, u64::max_value())
+    }
+
+    /// Create a DELE message containing the public key of this online key, valid
+    /// from `mint` to `maxt` (microseconds since the epoch)
+    pub fn make_dele_with_validity(&self, mint: u64, maxt: u64) -> RtMessage {
         let pub_key_bytes = self.signer.public_key_bytes();
 
         let mut dele_msg = RtMessage::new(3);
-        dele_msg.add_field(Tag::PUBK, pub_key_bytes).unwrap();
-        dele_msg.add_field(Tag::MINT, &zeros).unwrap();
-        dele_msg.add_field(Tag::MAXT, &max).unwrap();
+        dele_msg.insert_field(Tag::PUBK, pub_key_bytes).unwrap();
+        dele_msg.add_u64(Tag::MINT, mint).unwrap();
+        dele_msg.add_u64(Tag::MAXT, maxt).unwrap();
+
+        #[cfg(feature = "pq")]
+        {
+            if let Some(ref pq_key) = self.pq_key {
+                dele_msg
+                    .insert_field(Tag::pq_public_key(), pq_key.public_key_bytes())
+                    .unwrap();
+            }
+        }
 
         dele_msg
     }
@@ -55,31 +98,41 @@ impl OnlineKey {
     /// Create an SREP response containing the provided time and Merkle root,
     /// signed by this online key.
     pub fn make_srep(&mut self, now: Timespec, merkle_root: &[u8], secondsoffset: u64) -> RtMessage {
-        let mut radi = [0; 4];
-        let mut midp = [0; 8];
-
-        // one second (in microseconds)
-        (&mut radi as &mut [u8])
-            .write_u32::<LittleEndian>(1_000_000)
-            .unwrap(); 
-
-        // current epoch time in microseconds
-        let midp_time = {
-            let secs = (now.sec as u64 + secondsoffset) * 1_000_000;
-            let nsecs = (now.nsec as u64) / 1_000;
+        self.make_srep_with_dtai(now, merkle_root, secondsoffset, None)
+    }
 
-            secs + nsecs
-        };
-        (&mut midp as &mut [u8])
-            .write_u64::<LittleEndian>(midp_time)
-            .unwrap();
+    /// Create an SREP response as [`make_srep`](#method.make_srep) does, additionally
+    /// including the TAI-UTC offset `dtai` (in seconds) as a `DTAI` tag when present.
+    pub fn make_srep_with_dtai(
+        &mut self,
+        now: Timespec,
+        merkle_root: &[u8],
+        secondsoffset: u64,
+        dtai: Option<u32>,
+    ) -> RtMessage {
+        let midp_time = clock::to_micros(now, secondsoffset);
+
+        self.sign_srep(midp_time, DEFAULT_RADIUS, merkle_root, dtai)
+    }
 
+    /// Create an SREP response with the given `midpoint` and `radius` (both in
+    /// microseconds), Merkle root and optional `DTAI`, signed by this online key.
+    pub fn sign_srep(
+        &mut self,
+        midpoint: u64,
+        radius: u32,
+        merkle_root: &[u8],
+        dtai: Option<u32>,
+    ) -> RtMessage {
         // Signed response SREP
         let srep_bytes = {
-            let mut srep_msg = RtMessage::new(3);
-            srep_msg.add_field(Tag::RADI, &radi).unwrap();
-            srep_msg.add_field(Tag::MIDP, &midp).unwrap();
-            srep_msg.add_field(Tag::ROOT, merkle_root).unwrap();
+            let mut srep_msg = RtMessage::new(4);
+            if let Some(dtai) = dtai {
+                srep_msg.add_u32(Tag::DTAI, dtai).unwrap();
+            }
+            srep_msg.add_u32(Tag::RADI, radius).unwrap();
+            srep_msg.add_u64(Tag::MIDP, midpoint).unwrap();
+            srep_msg.insert_field(Tag::ROOT, merkle_root).unwrap();
 
             srep_msg.encode().unwrap()
         };
@@ -95,12 +148,32 @@ impl OnlineKey {
         result.add_field(Tag::SIG, &srep_signature).unwrap();
         result.add_field(Tag::SREP, &srep_bytes).unwrap();
 
+        #[cfg(feature = "pq")]
+        {
+            if let Some(ref pq_key) = self.pq_key {
+                let mut signed = SIGNED_RESPONSE_CONTEXT.as_bytes().to_vec();
+                signed.extend_from_slice(&srep_bytes);
+                result
+                    .add_field(Tag::pq_signature(), &pq_key.sign(&signed))
+                    .unwrap();
+            }
+        }
+
         result
     }
 }
 
 impl fmt::Display for OnlineKey {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "{}", self.signer)
+        write!(f, "{}", self.signer)?;
+
+        #[cfg(feature = "pq")]
+        {
+            if let Some(ref pq_key) = self.pq_key {
+                write!(f, " + {}", pq_key)?;
+            }
+        }
+
+        Ok(())
     }
 }
@@ -0,0 +1,299 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Shamir secret sharing of the long-term seed over GF(2^8).
+//!
+//! The seed is split into N shares such that any K of them recover it and fewer than K
+//! reveal nothing about it, so no single custodian holds the server's identity.
+//!
+
+use std::fmt;
+use std::str::FromStr;
+
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::error::Error;
+
+/// Bytes of SHA-256 appended to each share to catch transcription errors
+const SHARE_CHECKSUM_LENGTH: usize = 2;
+
+/// One share of a seed split by [`split_seed`](fn.split_seed.html)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeedShare {
+    /// Number of shares needed to recover the seed
+    pub threshold: u8,
+    /// The x coordinate of this share, 1 to 255
+    pub index: u8,
+    /// The polynomials evaluated at `index`, one byte per seed byte
+    pub value: Vec<u8>,
+}
+
+impl SeedShare {
+    fn checksum(&self) -> Vec<u8> {
+        let mut bytes = vec![self.threshold, self.index];
+        bytes.extend_from_slice(&self.value);
+
+        digest::digest(&digest::SHA256, &bytes).as_ref()[..SHARE_CHECKSUM_LENGTH].to_vec()
+    }
+}
+
+/// Shares are written as `threshold-index-value` with a short checksum on the value hex
+impl fmt::Display for SeedShare {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}-{}{}",
+            self.threshold,
+            self.index,
+            hex::encode(&self.value),
+            hex::encode(self.checksum())
+        )
+    }
+}
+
+impl FromStr for SeedShare {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SeedShare, Error> {
+        let invalid = |why: &str| Error::InvalidShare(format!("'{}': {}", s, why));
+
+        let parts: Vec<&str> = s.trim().split('-').collect();
+        if parts.len() != 3 {
+            return Err(invalid("expected threshold-index-value"));
+        }
+
+        let threshold = parts[0].parse().map_err(|_| invalid("bad threshold"))?;
+        let index = parts[1].parse().map_err(|_| invalid("bad index"))?;
+        let mut value = hex::decode(parts[2]).map_err(|_| invalid("value is not hex"))?;
+
+        if index == 0 || value.len() <= SHARE_CHECKSUM_LENGTH {
+            return Err(invalid("share is truncated"));
+        }
+
+        let checksum = value.split_off(value.len() - SHARE_CHECKSUM_LENGTH);
+        let share = SeedShare {
+            threshold,
+            index,
+            value,
+        };
+
+        if share.checksum() != checksum {
+            return Err(invalid(
+                "checksum mismatch, the share was copied incorrectly",
+            ));
+        }
+
+        Ok(share)
+    }
+}
+
+// Multiplication in GF(2^8) modulo the AES polynomial x^8 + x^4 + x^3 + x + 1
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+
+    product
+}
+
+// Multiplicative inverse as a^254, since a^255 == 1 for every non-zero a
+fn gf_inv(a: u8) -> u8 {
+    let mut result = 1;
+    for _ in 0..254 {
+        result = gf_mul(result, a);
+    }
+    result
+}
+
+// Evaluate the polynomial with `coefficients` (constant term first) at `x`
+fn evaluate(coefficients: &[u8], x: u8) -> u8 {
+    coefficients
+        .iter()
+        .rev()
+        .fold(0, |acc, &c| gf_mul(acc, x) ^ c)
+}
+
+///
+/// Split `seed` into `shares` shares, any `threshold` of which recover it. Each seed byte
+/// is the constant term of its own random polynomial of degree `threshold - 1`.
+///
+pub fn split_seed(seed: &[u8], threshold: u8, shares: u8) -> Result<Vec<SeedShare>, Error> {
+    if threshold < 2 || threshold > shares {
+        return Err(Error::InvalidShare(format!(
+            "threshold must be from 2 to the number of shares, not {} of {}",
+            threshold, shares
+        )));
+    }
+    if seed.is_empty() {
+        return Err(Error::InvalidShare(
+            "cannot split an empty seed".to_string(),
+        ));
+    }
+
+    let rng = SystemRandom::new();
+    let mut coefficients = vec![0u8; threshold as usize];
+    let mut result: Vec<SeedShare> = (1..=shares)
+        .map(|index| SeedShare {
+            threshold,
+            index,
+            value: Vec::with_capacity(seed.len()),
+        })
+        .collect();
+
+    for &byte in seed {
+        coefficients[0] = byte;
+        rng.fill(&mut coefficients[1..]).expect("rng failure");
+
+        for share in &mut result {
+            share.value.push(evaluate(&coefficients, share.index));
+        }
+    }
+
+    Ok(result)
+}
+
+///
+/// Recover the seed from `shares`. At least the threshold number of shares from the same
+/// split must be given; extra shares are ignored.
+///
+pub fn combine_shares(shares: &[SeedShare]) -> Result<Vec<u8>, Error> {
+    let first = match shares.first() {
+        Some(share) => share,
+        None => return Err(Error::InvalidShare("no shares were given".to_string())),
+    };
+
+    for share in shares {
+        if share.threshold != first.threshold || share.value.len() != first.value.len() {
+            return Err(Error::InvalidShare(format!(
+                "share {} is not from the same split as share {}",
+                share.index, first.index
+            )));
+        }
+    }
+
+    let mut quorum: Vec<&SeedShare> = Vec::new();
+    for share in shares {
+        if !quorum.iter().any(|s| s.index == share.index) {
+            quorum.push(share);
+        }
+    }
+
+    if quorum.len() < first.threshold as usize {
+        return Err(Error::InvalidShare(format!(
+            "{} distinct shares given, {} are needed",
+            quorum.len(),
+            first.threshold
+        )));
+    }
+    quorum.truncate(first.threshold as usize);
+
+    // Lagrange basis polynomials evaluated at x = 0
+    let weights: Vec<u8> = quorum
+        .iter()
+        .map(|si| {
+            quorum
+                .iter()
+                .filter(|sj| sj.index != si.index)
+                .fold(1, |acc, sj| {
+                    gf_mul(acc, gf_mul(sj.index, gf_inv(sj.index ^ si.index)))
+                })
+        })
+        .collect();
+
+    let seed = (0..first.value.len())
+        .map(|i| {
+            quorum
+                .iter()
+                .zip(&weights)
+                .fold(0, |acc, (share, &w)| acc ^ gf_mul(share.value[i], w))
+        })
+        .collect();
+
+    Ok(seed)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::key::shamir::{combine_shares, gf_inv, gf_mul, split_seed, SeedShare};
+    use crate::Error;
+    use std::str::FromStr;
+
+    #[test]
+    fn field_inverses() {
+        assert_eq!(gf_mul(0x53, 0xca), 0x01);
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1, "inverse of {}", a);
+        }
+    }
+
+    #[test]
+    fn any_quorum_recovers_the_seed() {
+        let seed: Vec<u8> = (0..32).collect();
+        let shares = split_seed(&seed, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        for a in 0..5 {
+            for b in (a + 1)..5 {
+                for c in (b + 1)..5 {
+                    let quorum = [shares[a].clone(), shares[b].clone(), shares[c].clone()];
+                    assert_eq!(combine_shares(&quorum).unwrap(), seed);
+                }
+            }
+        }
+
+        match combine_shares(&shares[..2]) {
+            Err(Error::InvalidShare(msg)) => assert!(msg.contains("3 are needed"), "{}", msg),
+            other => panic!("unexpected result {:?}", other),
+        }
+        let repeated = [shares[0].clone(), shares[0].clone(), shares[1].clone()];
+        assert!(combine_shares(&repeated).is_err());
+    }
+
+    #[test]
+    fn shares_round_trip_as_text() {
+        let shares = split_seed(&[0x42; 32], 2, 3).unwrap();
+
+        for share in &shares {
+            let text = share.to_string();
+            assert!(text.starts_with(&format!("2-{}-", share.index)));
+            assert_eq!(&SeedShare::from_str(&text).unwrap(), share);
+        }
+
+        let mut typo = shares[0].to_string();
+        let last = if typo.ends_with('0') { "1" } else { "0" };
+        typo.pop();
+        typo.push_str(last);
+        assert!(SeedShare::from_str(&typo).is_err());
+        assert!(SeedShare::from_str("2-1").is_err());
+    }
+
+    #[test]
+    fn bad_thresholds_are_rejected() {
+        assert!(split_seed(&[0x42; 32], 1, 3).is_err());
+        assert!(split_seed(&[0x42; 32], 4, 3).is_err());
+        assert!(split_seed(&[0x42; 32], 255, 255).is_ok());
+    }
+}
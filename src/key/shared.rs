@@ -0,0 +1,226 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! A delegation (online key and CERT) shared by every instance of an anycast fleet.
+//!
+
+use ring::aead::{open_in_place, seal_in_place, OpeningKey, SealingKey, CHACHA20_POLY1305};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, hkdf, hmac};
+
+use crate::error::Error;
+use crate::key::{verify_cert, LongTermKey, OnlineKey};
+use crate::message::RtMessage;
+use crate::tag::Tag;
+use crate::MIN_SEED_LENGTH;
+
+// Identifies (and is authenticated as associated data of) a sealed shared delegation
+const MAGIC: &[u8] = b"RSD1";
+
+// HKDF salt used to derive the sealing key from the long-term seed
+const KDF_SALT: &[u8] = b"roughenough shared delegation sealing key";
+
+const KEY_SIZE_BYTES: usize = 32;
+const NONCE_SIZE_BYTES: usize = 12;
+const TAG_SIZE_BYTES: usize = 16;
+
+///
+/// The online key seed and the CERT delegating to it, shared by all instances behind an
+/// anycast address so every instance answers with the same delegation.
+///
+/// The delegation is distributed _sealed_: encrypted and authenticated with ChaCha20-Poly1305
+/// under a key derived (HKDF-SHA256) from the long-term seed. Only holders of the long-term
+/// seed, i.e. the fleet's instances, can open it.
+///
+/// Sealed layout:
+///
+///   * 4 bytes - magic `RSD1`
+///   * 12 bytes - random nonce
+///   * remainder - encrypted (32 byte online key seed + CERT) followed by 16 byte tag
+///
+pub struct SharedDelegation {
+    online_seed: Vec<u8>,
+    cert_bytes: Vec<u8>,
+}
+
+impl SharedDelegation {
+    /// A shared delegation of the online key derived from `online_seed`, certified by `cert_bytes`
+    pub fn new(online_seed: &[u8], cert_bytes: &[u8]) -> Self {
+        SharedDelegation {
+            online_seed: online_seed.to_vec(),
+            cert_bytes: cert_bytes.to_vec(),
+        }
+    }
+
+    /// Generate a new random online key and have `long_term_key` sign its delegation
+    pub fn generate(long_term_key: &mut LongTermKey) -> Self {
+        let mut online_seed = vec![0u8; MIN_SEED_LENGTH as usize];
+        SystemRandom::new().fill(&mut online_seed).unwrap();
+
+        let online_key = OnlineKey::from_seed(&online_seed);
+        let cert_bytes = long_term_key.make_cert(&online_key).encode().unwrap();
+
+        SharedDelegation {
+            online_seed,
+            cert_bytes,
+        }
+    }
+
+    /// The shared online key
+    pub fn online_key(&self) -> OnlineKey {
+        OnlineKey::from_seed(&self.online_seed)
+    }
+
+    /// The encoded CERT delegating to the shared online key
+    pub fn cert_bytes(&self) -> &[u8] {
+        &self.cert_bytes
+    }
+
+    ///
+    /// Check that the CERT is signed by `long_term_public_key` and delegates to the
+    /// shared online key.
+    ///
+    pub fn verify(&self, long_term_public_key: &[u8]) -> Result<(), Error> {
+        let cert = RtMessage::from_bytes(&self.cert_bytes)?;
+        verify_cert(&cert, long_term_public_key)?;
+
+        let dele = cert.get_nested(Tag::DELE)?;
+        let pubk = dele.get_field(Tag::PUBK).ok_or(Error::TagNotFound(Tag::PUBK))?;
+
+        if pubk != self.online_key().public_key() {
+            return Err(Error::InvalidConfiguration(
+                "shared delegation CERT is for a different online key".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Encrypt this delegation with a key derived from `long_term_seed`
+    pub fn seal(&self, long_term_seed: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut nonce = [0u8; NONCE_SIZE_BYTES];
+        SystemRandom::new().fill(&mut nonce).unwrap();
+
+        let mut in_out = Vec::with_capacity(self.online_seed.len() + self.cert_bytes.len());
+        in_out.extend_from_slice(&self.online_seed);
+        in_out.extend_from_slice(&self.cert_bytes);
+        in_out.extend_from_slice(&[0u8; TAG_SIZE_BYTES]);
+
+        let key = SealingKey::new(&CHACHA20_POLY1305, &sealing_key(long_term_seed))
+            .map_err(|_| sealing_error("invalid sealing key"))?;
+
+        let sealed_len = seal_in_place(&key, &nonce, MAGIC, &mut in_out, TAG_SIZE_BYTES)
+            .map_err(|_| sealing_error("encryption failed"))?;
+
+        let mut output = Vec::with_capacity(MAGIC.len() + NONCE_SIZE_BYTES + sealed_len);
+        output.extend_from_slice(MAGIC);
+        output.extend_from_slice(&nonce);
+        output.extend_from_slice(&in_out[..sealed_len]);
+
+        Ok(output)
+    }
+
+    /// Decrypt a delegation previously sealed with the same `long_term_seed`
+    pub fn open(sealed: &[u8], long_term_seed: &[u8]) -> Result<Self, Error> {
+        let header_len = MAGIC.len() + NONCE_SIZE_BYTES;
+        let seed_len = MIN_SEED_LENGTH as usize;
+
+        if sealed.len() < header_len + seed_len + TAG_SIZE_BYTES || !sealed.starts_with(MAGIC) {
+            return Err(sealing_error("not a sealed shared delegation"));
+        }
+
+        let nonce = &sealed[MAGIC.len()..header_len];
+        let mut in_out = sealed[header_len..].to_vec();
+
+        let key = OpeningKey::new(&CHACHA20_POLY1305, &sealing_key(long_term_seed))
+            .map_err(|_| sealing_error("invalid sealing key"))?;
+
+        let plaintext = open_in_place(&key, nonce, MAGIC, 0, &mut in_out)
+            .map_err(|_| sealing_error("decryption failed; wrong long-term seed?"))?;
+
+        Ok(SharedDelegation::new(
+            &plaintext[..seed_len],
+            &plaintext[seed_len..],
+        ))
+    }
+}
+
+// Derive the ChaCha20-Poly1305 key sealing shared delegations from the long-term seed
+fn sealing_key(long_term_seed: &[u8]) -> [u8; KEY_SIZE_BYTES] {
+    let salt = hmac::SigningKey::new(&digest::SHA256, KDF_SALT);
+    let mut key = [0u8; KEY_SIZE_BYTES];
+    hkdf::extract_and_expand(&salt, long_term_seed, MAGIC, &mut key);
+
+    key
+}
+
+fn sealing_error(msg: &str) -> Error {
+    Error::InvalidConfiguration(format!("shared delegation: {}", msg))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::key::{LongTermKey, SharedDelegation};
+
+    const SEED: [u8; 32] = [0x11; 32];
+
+    #[test]
+    fn seal_then_open_roundtrip() {
+        let mut long_term_key = LongTermKey::new(&SEED);
+        let delegation = SharedDelegation::generate(&mut long_term_key);
+
+        let sealed = delegation.seal(&SEED).unwrap();
+        let opened = SharedDelegation::open(&sealed, &SEED).unwrap();
+
+        assert_eq!(opened.cert_bytes(), delegation.cert_bytes());
+        assert_eq!(
+            opened.online_key().public_key(),
+            delegation.online_key().public_key()
+        );
+        assert!(opened.verify(long_term_key.public_key()).is_ok());
+    }
+
+    #[test]
+    fn wrong_seed_cannot_open() {
+        let mut long_term_key = LongTermKey::new(&SEED);
+        let sealed = SharedDelegation::generate(&mut long_term_key)
+            .seal(&SEED)
+            .unwrap();
+
+        assert!(SharedDelegation::open(&sealed, &[0x22; 32]).is_err());
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let mut long_term_key = LongTermKey::new(&SEED);
+        let mut sealed = SharedDelegation::generate(&mut long_term_key)
+            .seal(&SEED)
+            .unwrap();
+
+        let last = sealed.len() - 1;
+        sealed[last] ^= 0x01;
+
+        assert!(SharedDelegation::open(&sealed, &SEED).is_err());
+    }
+
+    #[test]
+    fn cert_for_other_key_is_rejected() {
+        let mut long_term_key = LongTermKey::new(&SEED);
+        let other = SharedDelegation::generate(&mut long_term_key);
+        let mismatched = SharedDelegation::new(&[0x33; 32], other.cert_bytes());
+
+        assert!(mismatched.verify(long_term_key.public_key()).is_err());
+    }
+}
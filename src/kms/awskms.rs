@@ -22,7 +22,7 @@ pub mod inner {
     use std::str::FromStr;
 
     use rusoto_core::Region;
-    use rusoto_kms::{DecryptRequest, EncryptRequest, Kms, KmsClient};
+    use rusoto_kms::{DecryptRequest, DescribeKeyRequest, EncryptRequest, Kms, KmsClient};
     use crate::kms::{EncryptedDEK, KmsError, KmsProvider, PlaintextDEK, AD, DEK_SIZE_BYTES};
 
     /// Amazon Web Services Key Management Service
@@ -33,28 +33,74 @@ pub mod inner {
     }
 
     impl AwsKms {
-        /// Create a new instance from the full ARN of a AWS KMS key. The ARN is expected
-        /// to be of the form `arn:aws:kms:some-aws-region:111122223333:key/1234abcd-12ab-34cd-56ef-1234567890ab`
+        /// Create a new instance from a reference to an AWS KMS key, one of:
+        ///
+        ///  * a key ARN `arn:aws:kms:some-aws-region:111122223333:key/1234abcd-12ab-34cd-56ef-1234567890ab`,
+        ///    including multi-region keys (`key/mrk-...`)
+        ///  * an alias ARN `arn:aws:kms:some-aws-region:111122223333:alias/roughenough`
+        ///  * an alias name `alias/roughenough` or multi-region key id `mrk-...`, used in
+        ///    the default region (`AWS_DEFAULT_REGION` or `AWS_REGION`)
+        ///
+        /// Aliases and key ids are resolved to the ARN of the underlying key at startup,
+        /// so the key an alias points to can be changed without editing configurations.
         pub fn from_arn(arn: &str) -> Result<Self, KmsError> {
-            let parts: Vec<&str> = arn.split(':').collect();
+            let kms_client = KmsClient::new(key_region(arn)?);
+            let key_id = resolve_key(&kms_client, arn)?;
 
-            if parts.len() != 6 {
-                return Err(KmsError::InvalidConfiguration(format!(
-                    "invalid KMS arn: too few parts {}",
-                    parts.len()
-                )));
+            if key_id != arn {
+                info!("AWS KMS key '{}' resolved to '{}'", arn, key_id);
             }
 
-            let region_part = parts.get(3).expect("region is missing");
-            let region = match Region::from_str(region_part) {
-                Ok(r) => r,
-                Err(e) => return Err(KmsError::InvalidConfiguration(e.description().to_string())),
-            };
+            Ok(AwsKms { kms_client, key_id })
+        }
+    }
+
+    // Region of the key referred to by `key_ref`
+    fn key_region(key_ref: &str) -> Result<Region, KmsError> {
+        if key_ref.starts_with("alias/") || key_ref.starts_with("mrk-") {
+            return Ok(Region::default());
+        }
+
+        let parts: Vec<&str> = key_ref.split(':').collect();
 
-            Ok(AwsKms {
-                kms_client: KmsClient::new(region),
-                key_id: arn.to_string(),
-            })
+        if parts.len() != 6 {
+            return Err(KmsError::InvalidConfiguration(format!(
+                "invalid KMS arn: too few parts {}",
+                parts.len()
+            )));
+        }
+
+        if !parts[5].starts_with("key/") && !parts[5].starts_with("alias/") {
+            return Err(KmsError::InvalidConfiguration(format!(
+                "invalid KMS arn: '{}' is neither a key nor an alias",
+                parts[5]
+            )));
+        }
+
+        match Region::from_str(parts[3]) {
+            Ok(r) => Ok(r),
+            Err(e) => Err(KmsError::InvalidConfiguration(e.description().to_string())),
+        }
+    }
+
+    // ARN of the key `key_ref` refers to, looked up with DescribeKey
+    fn resolve_key(kms_client: &KmsClient, key_ref: &str) -> Result<String, KmsError> {
+        let mut describe_req: DescribeKeyRequest = Default::default();
+        describe_req.key_id = key_ref.to_string();
+
+        match kms_client.describe_key(describe_req).sync() {
+            Ok(result) => match result.key_metadata.and_then(|metadata| metadata.arn) {
+                Some(arn) => Ok(arn),
+                None => Err(KmsError::OperationFailed(format!(
+                    "no ARN for key '{}' despite successful response",
+                    key_ref
+                ))),
+            },
+            Err(e) => Err(KmsError::OperationFailed(format!(
+                "unable to resolve key '{}': {}",
+                key_ref,
+                e.description()
+            ))),
         }
     }
 
@@ -125,4 +171,26 @@ pub mod inner {
             write!(f, "{}", self.key_id)
         }
     }
+
+    #[cfg(test)]
+    mod test {
+        use super::key_region;
+        use rusoto_core::Region;
+
+        #[test]
+        fn region_from_key_and_alias_arns() {
+            let key = "arn:aws:kms:eu-west-1:111122223333:key/mrk-1234abcd12ab34cd56ef1234567890ab";
+            let alias = "arn:aws:kms:us-east-2:111122223333:alias/roughenough";
+
+            assert_eq!(key_region(key).unwrap(), Region::EuWest1);
+            assert_eq!(key_region(alias).unwrap(), Region::UsEast2);
+        }
+
+        #[test]
+        fn invalid_references_are_rejected() {
+            assert!(key_region("arn:aws:kms:us-east-2:111122223333").is_err());
+            assert!(key_region("arn:aws:kms:us-east-2:111122223333:grant/abc").is_err());
+            assert!(key_region("arn:aws:kms:nowhere-9:111122223333:key/abc").is_err());
+        }
+    }
 }
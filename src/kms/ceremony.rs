@@ -0,0 +1,310 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Printable "sheets" carrying the output of an offline key ceremony to the online server.
+//!
+//! A new seed is generated on a machine that is never connected to anything. What leaves
+//! that machine is only its long-term public key and the seed in protected form: KMS
+//! encrypted, or split into Shamir shares. Each sheet holds one of those as a single line
+//! of text, printed both as text and as a QR code, so it crosses the air gap on paper
+//! (typed in or scanned) rather than on removable media.
+//!
+//! A sheet line looks like one of
+//!
+//! ```text
+//! roughenough:1:share:<public key hex>:<share>
+//! roughenough:1:kms:<public key hex>:<encrypted seed hex>:<kms key id>
+//! ```
+//!
+//! The KMS key id comes last as AWS ARNs contain colons.
+//!
+//! Printing sheets with QR codes requires the `ceremony` feature; reading them does not.
+//!
+
+#[cfg(feature = "ceremony")]
+use qrcode::render::unicode::Dense1x2;
+#[cfg(feature = "ceremony")]
+use qrcode::QrCode;
+
+use crate::error::Error;
+use crate::key::{combine_shares, fingerprint, LongTermKey, SeedShare};
+
+/// Every sheet line starts with this, followed by the kind of sheet
+pub const SHEET_PREFIX: &str = "roughenough:1:";
+
+/// How a sheet carries the seed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SealedSeed {
+    /// One Shamir share of the plaintext seed
+    Share(SeedShare),
+
+    /// The whole seed, envelope encrypted with the KMS key `kms_key`
+    Encrypted { kms_key: String, blob: Vec<u8> },
+}
+
+/// One ceremony sheet: the long-term public key and the seed in a protected form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sheet {
+    pub public_key: Vec<u8>,
+    pub seed: SealedSeed,
+}
+
+impl Sheet {
+    /// The single line form of this sheet, as put in its QR code
+    pub fn encode(&self) -> String {
+        let public_key = hex::encode(&self.public_key);
+
+        match self.seed {
+            SealedSeed::Share(ref share) => {
+                format!("{}share:{}:{}", SHEET_PREFIX, public_key, share)
+            }
+            SealedSeed::Encrypted {
+                ref kms_key,
+                ref blob,
+            } => format!(
+                "{}kms:{}:{}:{}",
+                SHEET_PREFIX,
+                public_key,
+                hex::encode(blob),
+                kms_key
+            ),
+        }
+    }
+
+    /// Parse the single line form of a sheet, as produced by [`encode`](#method.encode)
+    pub fn decode(line: &str) -> Result<Sheet, Error> {
+        let invalid = |why: &str| Error::InvalidCeremonySheet(why.to_string());
+
+        let line = line.trim();
+        if !line.starts_with(SHEET_PREFIX) {
+            return Err(invalid("not a roughenough ceremony sheet"));
+        }
+
+        let fields: Vec<&str> = line[SHEET_PREFIX.len()..].splitn(4, ':').collect();
+        if fields.len() < 3 {
+            return Err(invalid("sheet is truncated"));
+        }
+
+        let public_key = hex::decode(fields[1])
+            .ok()
+            .filter(|pk| pk.len() == 32)
+            .ok_or_else(|| invalid("public key is not 32 bytes of hex"))?;
+
+        let seed = match (fields[0], fields.len()) {
+            ("share", 3) => SealedSeed::Share(fields[2].parse()?),
+            ("kms", 4) if !fields[3].is_empty() => SealedSeed::Encrypted {
+                kms_key: fields[3].to_string(),
+                blob: hex::decode(fields[2]).map_err(|_| invalid("encrypted seed is not hex"))?,
+            },
+            _ => return Err(invalid("unknown kind of sheet")),
+        };
+
+        Ok(Sheet { public_key, seed })
+    }
+
+    ///
+    /// The sheet as printable text: what it is, the public key's fingerprint to check it
+    /// against, the single line form and that line as a QR code. `label` names the sheet,
+    /// e.g. "share 2 of 5".
+    ///
+    #[cfg(feature = "ceremony")]
+    pub fn printable(&self, label: &str) -> Result<String, Error> {
+        let what = match self.seed {
+            SealedSeed::Share(ref share) => format!(
+                "Shamir share {} of the seed; any {} shares recover it",
+                share.index, share.threshold
+            ),
+            SealedSeed::Encrypted { ref kms_key, .. } => {
+                format!("seed encrypted with KMS key {}", kms_key)
+            }
+        };
+
+        Ok(format!(
+            "=== Roughenough key ceremony: {} ===\n\
+             Contents    : {}\n\
+             Fingerprint : {}\n\
+             \n\
+             {}\n\
+             \n\
+             {}\n",
+            label,
+            what,
+            fingerprint(&self.public_key),
+            self.encode(),
+            qr_code(&self.encode())?
+        ))
+    }
+}
+
+/// `data` as a QR code drawn with Unicode block characters, two modules per character
+#[cfg(feature = "ceremony")]
+pub fn qr_code(data: &str) -> Result<String, Error> {
+    let code = QrCode::new(data.as_bytes())
+        .map_err(|e| Error::InvalidCeremonySheet(format!("cannot make QR code: {}", e)))?;
+
+    Ok(code.render::<Dense1x2>().build())
+}
+
+///
+/// Find and parse every sheet line in `text`, e.g. the output of a QR scanner or lines
+/// typed from paper. Lines that are not sheets are ignored.
+///
+pub fn read_sheets(text: &str) -> Result<Vec<Sheet>, Error> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with(SHEET_PREFIX))
+        .map(Sheet::decode)
+        .collect()
+}
+
+///
+/// Reassemble the ceremony's output from `sheets`, checking they all belong to the same
+/// public key. Shares are combined and the recovered seed checked against that public key.
+/// Returns the public key and the seed, in plaintext if it came from shares.
+///
+pub fn import_sheets(sheets: &[Sheet]) -> Result<(Vec<u8>, ImportedSeed), Error> {
+    let invalid = |why: String| Error::InvalidCeremonySheet(why);
+
+    let public_key = match sheets.first() {
+        Some(sheet) => sheet.public_key.clone(),
+        None => return Err(invalid("no ceremony sheets were found".to_string())),
+    };
+
+    if let Some(other) = sheets.iter().find(|s| s.public_key != public_key) {
+        return Err(invalid(format!(
+            "sheets are for different public keys ({} and {})",
+            fingerprint(&public_key),
+            fingerprint(&other.public_key)
+        )));
+    }
+
+    let shares: Vec<SeedShare> = sheets
+        .iter()
+        .filter_map(|s| match s.seed {
+            SealedSeed::Share(ref share) => Some(share.clone()),
+            _ => None,
+        })
+        .collect();
+
+    if shares.is_empty() {
+        // every sheet is an encrypted seed; any one of them will do
+        return match sheets[0].seed {
+            SealedSeed::Encrypted {
+                ref kms_key,
+                ref blob,
+            } => Ok((
+                public_key,
+                ImportedSeed::Encrypted(kms_key.clone(), blob.clone()),
+            )),
+            SealedSeed::Share(_) => unreachable!(),
+        };
+    }
+
+    let seed = combine_shares(&shares)?;
+    if LongTermKey::new(&seed).public_key() != &public_key[..] {
+        return Err(invalid(format!(
+            "the shares do not recover the seed of {}; a share is from another ceremony",
+            fingerprint(&public_key)
+        )));
+    }
+
+    Ok((public_key, ImportedSeed::Plaintext(seed)))
+}
+
+/// The seed as recovered by [`import_sheets`](fn.import_sheets.html)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportedSeed {
+    /// Recovered from shares, to be protected (e.g. KMS encrypted) on the online side
+    Plaintext(Vec<u8>),
+
+    /// Still encrypted by the KMS key (first), ready for the server's configuration
+    Encrypted(String, Vec<u8>),
+}
+
+#[cfg(test)]
+mod test {
+    use crate::key::{split_seed, LongTermKey};
+    use crate::kms::ceremony::{import_sheets, read_sheets, ImportedSeed, SealedSeed, Sheet};
+
+    fn share_sheets(seed: &[u8]) -> Vec<Sheet> {
+        let public_key = LongTermKey::new(seed).public_key().to_vec();
+
+        split_seed(seed, 2, 3)
+            .unwrap()
+            .into_iter()
+            .map(|share| Sheet {
+                public_key: public_key.clone(),
+                seed: SealedSeed::Share(share),
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "ceremony")]
+    fn printed(sheet: &Sheet) -> String {
+        sheet.printable("test").unwrap()
+    }
+
+    #[cfg(not(feature = "ceremony"))]
+    fn printed(sheet: &Sheet) -> String {
+        sheet.encode()
+    }
+
+    #[test]
+    fn sheets_round_trip_through_printed_text() {
+        let arn = "arn:aws:kms:us-east-1:111122223333:key/1234abcd-12ab-34cd-56ef-1234567890ab";
+        let mut sheets = share_sheets(&[0x42; 32]);
+        sheets.push(Sheet {
+            public_key: sheets[0].public_key.clone(),
+            seed: SealedSeed::Encrypted {
+                kms_key: arn.to_string(),
+                blob: vec![0xde, 0xad, 0xbe, 0xef],
+            },
+        });
+
+        let text: Vec<String> = sheets.iter().map(printed).collect();
+
+        assert_eq!(read_sheets(&text.join("\n")).unwrap(), sheets);
+    }
+
+    #[test]
+    fn a_quorum_of_share_sheets_recovers_the_seed() {
+        let seed = [0x42; 32];
+        let sheets = share_sheets(&seed);
+
+        match import_sheets(&sheets[1..]).unwrap() {
+            (_, ImportedSeed::Plaintext(recovered)) => assert_eq!(recovered, seed),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        assert!(import_sheets(&sheets[..1]).is_err());
+        assert!(import_sheets(&[]).is_err());
+    }
+
+    #[test]
+    fn sheets_from_different_ceremonies_are_rejected() {
+        let mut mixed = share_sheets(&[0x42; 32]);
+        mixed.truncate(1);
+        mixed.extend(share_sheets(&[0x43; 32]).into_iter().skip(1).take(1));
+        assert!(import_sheets(&mixed).is_err());
+
+        // same public key on the sheet, but the share is from another seed
+        let mut forged = share_sheets(&[0x43; 32]);
+        for sheet in &mut forged {
+            sheet.public_key = LongTermKey::new(&[0x42; 32]).public_key().to_vec();
+        }
+        let err = format!("{:?}", import_sheets(&forged).unwrap_err());
+        assert!(err.contains("another ceremony"), "{}", err);
+    }
+}
@@ -18,12 +18,17 @@ use ring::aead::{open_in_place, seal_in_place, OpeningKey, SealingKey, AES_256_G
 use ring::rand::{SecureRandom, SystemRandom};
 
 use crate::MIN_SEED_LENGTH;
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
 use crate::kms::{KmsError, KmsProvider, AD, DEK_SIZE_BYTES, NONCE_SIZE_BYTES, TAG_SIZE_BYTES};
 
 const DEK_LEN_FIELD: usize = 2;
 const NONCE_LEN_FIELD: usize = 2;
 
+// Optional key version prefix, present when the KMS reported the wrapping key version:
+// 2 bytes - KEY_VERSION_MARKER
+// 2 bytes - key version name length
+// n bytes - key version name (UTF-8)
+//
 // 2 bytes - encrypted DEK length
 // 2 bytes - nonce length
 // n bytes - encrypted DEK
@@ -36,6 +41,9 @@ const MIN_PAYLOAD_SIZE: usize = DEK_LEN_FIELD
     + MIN_SEED_LENGTH as usize
     + TAG_SIZE_BYTES;
 
+// Marks an envelope that starts with a key version; never a plausible encrypted DEK length
+const KEY_VERSION_MARKER: u16 = 0xffff;
+
 // No input prefix to skip, consume entire buffer
 const IN_PREFIX_LEN: usize = 0;
 
@@ -56,13 +64,27 @@ fn vec_zero_filled(len: usize) -> Vec<u8> {
 /// [`ring::rand::SecureRandom`](https://briansmith.org/rustdoc/ring/rand/trait.SecureRandom.html).
 ///
 /// The key used to encrypt the seed is wrapped (encrypted) using a
-/// [`KmsProvider`](trait.KmsProvider.html) implementation.
+/// [`KmsProvider`](trait.KmsProvider.html) implementation. If the KMS reports which key
+/// version wrapped it, that version is recorded in the envelope and checked with
+/// [`KmsProvider::check_key_version`](trait.KmsProvider.html#method.check_key_version)
+/// before unwrapping.
 ///
 pub struct EnvelopeEncryption;
 
 impl EnvelopeEncryption {
+    /// The key version recorded in an envelope produced by `encrypt_seed()`, if any
+    pub fn key_version(ciphertext_blob: &[u8]) -> Result<Option<String>, KmsError> {
+        split_key_version(ciphertext_blob).map(|(version, _)| version)
+    }
+
     /// Decrypt a seed previously encrypted with `encrypt_seed()`
     pub fn decrypt_seed(kms: &KmsProvider, ciphertext_blob: &[u8]) -> Result<Vec<u8>, KmsError> {
+        let (key_version, ciphertext_blob) = split_key_version(ciphertext_blob)?;
+
+        if let Some(ref version) = key_version {
+            kms.check_key_version(version)?;
+        }
+
         if ciphertext_blob.len() < MIN_PAYLOAD_SIZE {
             return Err(KmsError::InvalidData(format!(
                 "ciphertext too short: min {}, found {}",
@@ -156,10 +178,15 @@ impl EnvelopeEncryption {
         };
 
         // Use the KMS to wrap the DEK
-        let wrapped_dek = kms.encrypt_dek(&dek.to_vec())?;
+        let (wrapped_dek, key_version) = kms.encrypt_dek_versioned(&dek.to_vec())?;
 
         // And coalesce everything together
         let mut output = Vec::new();
+        if let Some(version) = key_version {
+            output.write_u16::<LittleEndian>(KEY_VERSION_MARKER)?;
+            output.write_u16::<LittleEndian>(version.len() as u16)?;
+            output.write_all(version.as_bytes())?;
+        }
         output.write_u16::<LittleEndian>(wrapped_dek.len() as u16)?;
         output.write_u16::<LittleEndian>(nonce.len() as u16)?;
         output.write_all(&wrapped_dek)?;
@@ -170,6 +197,26 @@ impl EnvelopeEncryption {
     }
 }
 
+// Separate the optional key version prefix from the rest of the envelope
+fn split_key_version(blob: &[u8]) -> Result<(Option<String>, &[u8]), KmsError> {
+    if blob.len() < 4 || LittleEndian::read_u16(&blob[0..2]) != KEY_VERSION_MARKER {
+        return Ok((None, blob));
+    }
+
+    let version_len = LittleEndian::read_u16(&blob[2..4]) as usize;
+    if blob.len() < 4 + version_len {
+        return Err(KmsError::InvalidData(format!(
+            "key version length {} exceeds ciphertext",
+            version_len
+        )));
+    }
+
+    match String::from_utf8(blob[4..4 + version_len].to_vec()) {
+        Ok(version) => Ok((Some(version), &blob[4 + version_len..])),
+        Err(_) => Err(KmsError::InvalidData("key version is not UTF-8".to_string())),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::kms::envelope::{DEK_LEN_FIELD, MIN_PAYLOAD_SIZE, NONCE_LEN_FIELD};
@@ -284,4 +331,69 @@ mod test {
             }
         }
     }
+
+    // Mock provider that reports a key version and only accepts `expected`
+    struct VersionedKmsProvider {
+        version: String,
+        expected: String,
+    }
+
+    impl KmsProvider for VersionedKmsProvider {
+        fn encrypt_dek(&self, plaintext_dek: &Vec<u8>) -> Result<Vec<u8>, KmsError> {
+            Ok(plaintext_dek.to_vec())
+        }
+
+        fn decrypt_dek(&self, encrypted_dek: &Vec<u8>) -> Result<Vec<u8>, KmsError> {
+            Ok(encrypted_dek.to_vec())
+        }
+
+        fn encrypt_dek_versioned(
+            &self,
+            plaintext_dek: &Vec<u8>,
+        ) -> Result<(Vec<u8>, Option<String>), KmsError> {
+            Ok((plaintext_dek.to_vec(), Some(self.version.clone())))
+        }
+
+        fn check_key_version(&self, version: &str) -> Result<(), KmsError> {
+            if version == self.expected {
+                Ok(())
+            } else {
+                Err(KmsError::InvalidKey(format!("wrapped by {}", version)))
+            }
+        }
+    }
+
+    #[test]
+    fn key_version_is_recorded_and_checked() {
+        let plaintext = Vec::from("This is the plaintext used for this test 5");
+        let kms = VersionedKmsProvider {
+            version: "cryptoKeyVersions/3".to_string(),
+            expected: "cryptoKeyVersions/3".to_string(),
+        };
+
+        let ciphertext = EnvelopeEncryption::encrypt_seed(&kms, &plaintext).unwrap();
+        assert_eq!(
+            EnvelopeEncryption::key_version(&ciphertext).unwrap(),
+            Some("cryptoKeyVersions/3".to_string())
+        );
+        assert_eq!(EnvelopeEncryption::decrypt_seed(&kms, &ciphertext).unwrap(), plaintext);
+
+        let rotated = VersionedKmsProvider {
+            version: "cryptoKeyVersions/4".to_string(),
+            expected: "cryptoKeyVersions/4".to_string(),
+        };
+        match EnvelopeEncryption::decrypt_seed(&rotated, &ciphertext) {
+            Err(KmsError::InvalidKey(msg)) => assert!(msg.contains("cryptoKeyVersions/3")),
+            e => panic!("unexpected result {:?}", e),
+        }
+    }
+
+    #[test]
+    fn unversioned_envelopes_have_no_key_version() {
+        let kms = MockKmsProvider {};
+        let plaintext = Vec::from("This is the plaintext used for this test 6");
+
+        let ciphertext = EnvelopeEncryption::encrypt_seed(&kms, &plaintext).unwrap();
+        assert_eq!(EnvelopeEncryption::key_version(&ciphertext).unwrap(), None);
+    }
 }
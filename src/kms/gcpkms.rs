@@ -36,10 +36,13 @@ pub mod inner {
 
     const GOOGLE_APP_CREDS: &str = &"GOOGLE_APPLICATION_CREDENTIALS";
 
+    const KEY_VERSIONS: &str = "/cryptoKeyVersions/";
+
     /// Google Cloud Key Management Service
     /// https://cloud.google.com/kms/
     pub struct GcpKms {
         key_resource_id: String,
+        pinned_version: Option<String>,
         service_account: ServiceAccountKey,
     }
 
@@ -48,15 +51,27 @@ pub mod inner {
         /// Create a new GcpKms from a Google Cloud KMS key resource ID of the form
         /// `projects/*/locations/*/keyRings/*/cryptoKeys/*`
         ///
+        /// A key version resource ID (`projects/*/.../cryptoKeys/*/cryptoKeyVersions/*`)
+        /// pins that version: it is used for wrapping instead of the key's primary
+        /// version, and unwrapping fails with an explicit error if the seed was wrapped
+        /// by any other version.
+        ///
         pub fn from_resource_id(resource_id: &str) -> Result<Self, KmsError> {
             let svc_acct = load_gcp_credential()?;
+            let (key_resource_id, pinned_version) = split_version(resource_id);
 
             Ok(GcpKms {
-                key_resource_id: resource_id.to_string(),
+                key_resource_id,
+                pinned_version,
                 service_account: svc_acct,
             })
         }
 
+        /// The pinned key version, if any
+        pub fn pinned_version(&self) -> Option<&str> {
+            self.pinned_version.as_deref()
+        }
+
         fn new_hub(&self) -> CloudKMS<hyper::Client, ServiceAccountAccess<hyper::Client>> {
             let client1 = hyper::Client::with_connector(HttpsConnector::new(TlsClient::new()));
             let access = oauth2::ServiceAccountAccess::new(self.service_account.clone(), client1);
@@ -75,14 +90,27 @@ pub mod inner {
 
     impl KmsProvider for GcpKms {
         fn encrypt_dek(&self, plaintext_dek: &PlaintextDEK) -> Result<EncryptedDEK, KmsError> {
+            self.encrypt_dek_versioned(plaintext_dek).map(|(ct, _)| ct)
+        }
+
+        fn encrypt_dek_versioned(
+            &self,
+            plaintext_dek: &PlaintextDEK,
+        ) -> Result<(EncryptedDEK, Option<String>), KmsError> {
             let mut request = EncryptRequest::default();
             request.plaintext = Some(base64::encode(plaintext_dek));
             request.additional_authenticated_data = Some(base64::encode(AD));
 
+            // encrypting with a key version name uses that version instead of the primary
+            let name = self
+                .pinned_version
+                .as_ref()
+                .unwrap_or(&self.key_resource_id);
+
             let hub = self.new_hub();
             let result = hub
                 .projects()
-                .locations_key_rings_crypto_keys_encrypt(request, &self.key_resource_id)
+                .locations_key_rings_crypto_keys_encrypt(request, name)
                 .doit();
 
             match result {
@@ -90,7 +118,8 @@ pub mod inner {
                     if http_resp.status == StatusCode::Ok {
                         let ciphertext = enc_resp.ciphertext.unwrap();
                         let ct = base64::decode(&ciphertext)?;
-                        Ok(ct)
+                        // the name of the key version that performed the encryption
+                        Ok((ct, enc_resp.name))
                     } else {
                         Err(self.pretty_http_error(&http_resp))
                     }
@@ -123,6 +152,30 @@ pub mod inner {
                 Err(e) => Err(KmsError::OperationFailed(format!("decrypt_dek() {:?}", e))),
             }
         }
+
+        fn check_key_version(&self, version: &str) -> Result<(), KmsError> {
+            match self.pinned_version {
+                Some(ref pinned) if version != pinned => Err(KmsError::InvalidKey(format!(
+                    "seed was wrapped by key version '{}' but '{}' is pinned",
+                    version, pinned
+                ))),
+                _ => {
+                    info!("Seed was wrapped by Google KMS key version '{}'", version);
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    // Split a resource ID into the crypto key's resource ID and the key version, if one is named
+    fn split_version(resource_id: &str) -> (String, Option<String>) {
+        match resource_id.find(KEY_VERSIONS) {
+            Some(idx) => (
+                resource_id[..idx].to_string(),
+                Some(resource_id.to_string()),
+            ),
+            None => (resource_id.to_string(), None),
+        }
     }
 
     /// Minimal implementation of Application Default Credentials.
@@ -163,4 +216,21 @@ pub mod inner {
             GOOGLE_APP_CREDS
         );
     }
+
+    #[cfg(test)]
+    mod test {
+        use super::split_version;
+
+        #[test]
+        fn version_is_split_from_key() {
+            let key = "projects/p/locations/global/keyRings/r/cryptoKeys/k";
+            let version = format!("{}/cryptoKeyVersions/3", key);
+
+            assert_eq!(split_version(key), (key.to_string(), None));
+            assert_eq!(
+                split_version(&version),
+                (key.to_string(), Some(version.clone()))
+            );
+        }
+    }
 }
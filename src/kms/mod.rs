@@ -53,7 +53,9 @@
 //!     for more in-depth explanations of envelope encryption.
 //!
 
+mod ceremony;
 mod envelope;
+mod snippet;
 
 use base64;
 use ring;
@@ -63,7 +65,13 @@ use crate::config::ServerConfig;
 use crate::error;
 use crate::key::KmsProtection;
 
+pub use self::ceremony::{
+    import_sheets, read_sheets, ImportedSeed, SealedSeed, Sheet, SHEET_PREFIX,
+};
+#[cfg(feature = "ceremony")]
+pub use self::ceremony::qr_code;
 pub use self::envelope::EnvelopeEncryption;
+pub use self::snippet::{config_snippet, patch_config};
 
 /// Errors generated by KMS operations
 #[derive(Debug, PartialEq, Eq, PartialOrd, Hash, Clone)]
@@ -123,6 +131,21 @@ pub trait KmsProvider {
 
     /// Make a blocking request to decrypt (unwrap) a previously encrypted data encryption key.
     fn decrypt_dek(&self, encrypted_dek: &EncryptedDEK) -> Result<PlaintextDEK, KmsError>;
+
+    /// As `encrypt_dek()`, additionally returning the name of the key version that wrapped
+    /// the DEK for KMSs that report one. The name is recorded in the envelope.
+    fn encrypt_dek_versioned(
+        &self,
+        plaintext_dek: &PlaintextDEK,
+    ) -> Result<(EncryptedDEK, Option<String>), KmsError> {
+        Ok((self.encrypt_dek(plaintext_dek)?, None))
+    }
+
+    /// Called before `decrypt_dek()` with the key version recorded in the envelope, to
+    /// report a version that is not the expected one (or is unusable) explicitly.
+    fn check_key_version(&self, _version: &str) -> Result<(), KmsError> {
+        Ok(())
+    }
 }
 
 #[cfg(feature = "awskms")]
@@ -0,0 +1,168 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! YAML configuration for a KMS-protected seed, as a fragment or patched into a config file.
+//!
+
+use yaml_rust::YamlLoader;
+
+use crate::error::Error;
+
+const KMS_PROTECTION_KEY: &str = "kms_protection";
+const SEED_KEY: &str = "seed";
+
+// Both values are quoted: an all-digit hex seed would otherwise be read as an integer
+fn kms_protection_line(kms_key: &str) -> String {
+    format!("{}: \"{}\"", KMS_PROTECTION_KEY, kms_key)
+}
+
+fn seed_line(encrypted_seed: &[u8]) -> String {
+    format!("{}: \"{}\"", SEED_KEY, hex::encode(encrypted_seed))
+}
+
+///
+/// A YAML fragment setting `kms_protection` to `kms_key` and `seed` to the
+/// `encrypted_seed` blob, ready to paste into a server configuration file.
+///
+pub fn config_snippet(kms_key: &str, encrypted_seed: &[u8]) -> String {
+    format!(
+        "{}\n{}\n",
+        kms_protection_line(kms_key),
+        seed_line(encrypted_seed)
+    )
+}
+
+///
+/// Replace the `kms_protection` and `seed` settings of the YAML configuration `contents`
+/// (adding whichever is missing) and return the result. Other lines, including comments,
+/// are kept as they are. Fails if the patched configuration does not read back with the
+/// new values.
+///
+pub fn patch_config(
+    contents: &str,
+    kms_key: &str,
+    encrypted_seed: &[u8],
+) -> Result<String, Error> {
+    let mut patched = Vec::new();
+    let mut have_kms_protection = false;
+    let mut have_seed = false;
+
+    for line in contents.lines() {
+        if is_setting(line, KMS_PROTECTION_KEY) {
+            if !have_kms_protection {
+                patched.push(kms_protection_line(kms_key));
+            }
+            have_kms_protection = true;
+        } else if is_setting(line, SEED_KEY) {
+            if !have_seed {
+                patched.push(seed_line(encrypted_seed));
+            }
+            have_seed = true;
+        } else {
+            patched.push(line.to_string());
+        }
+    }
+
+    if !have_kms_protection {
+        patched.push(kms_protection_line(kms_key));
+    }
+    if !have_seed {
+        patched.push(seed_line(encrypted_seed));
+    }
+
+    let mut patched = patched.join("\n");
+    patched.push('\n');
+
+    check_patched(&patched, kms_key, encrypted_seed)?;
+    Ok(patched)
+}
+
+// Is `line` the top-level (unindented) setting of `key`?
+fn is_setting(line: &str, key: &str) -> bool {
+    line.starts_with(key) && line[key.len()..].trim_start().starts_with(':')
+}
+
+fn check_patched(patched: &str, kms_key: &str, encrypted_seed: &[u8]) -> Result<(), Error> {
+    let invalid = |msg: &str| Error::InvalidConfiguration(format!("patched config {}", msg));
+
+    let docs = YamlLoader::load_from_str(patched).map_err(|e| invalid(&e.to_string()))?;
+    if docs.len() != 1 {
+        return Err(invalid("is not a single YAML document"));
+    }
+
+    let seed = hex::encode(encrypted_seed);
+    if docs[0][KMS_PROTECTION_KEY].as_str() != Some(kms_key)
+        || docs[0][SEED_KEY].as_str() != Some(seed.as_str())
+    {
+        return Err(invalid("does not contain the new kms_protection and seed"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kms::snippet::{config_snippet, patch_config};
+
+    const KEY: &str = "arn:aws:kms:us-east-2:111122223333:key/1234abcd";
+
+    #[test]
+    fn snippet_quotes_both_values() {
+        assert_eq!(
+            config_snippet(KEY, &[0x12, 0x34]),
+            format!("kms_protection: \"{}\"\nseed: \"1234\"\n", KEY)
+        );
+    }
+
+    #[test]
+    fn existing_settings_are_replaced() {
+        let config = "interface: 127.0.0.1\n# the seed\nseed: 0011\nport: 8686\nkms_protection: plaintext\n";
+        let patched = patch_config(config, KEY, &[0xab, 0xcd]).unwrap();
+
+        assert_eq!(
+            patched,
+            format!(
+                "interface: 127.0.0.1\n# the seed\nseed: \"abcd\"\nport: 8686\nkms_protection: \"{}\"\n",
+                KEY
+            )
+        );
+    }
+
+    #[test]
+    fn missing_settings_are_appended() {
+        let patched = patch_config("interface: 127.0.0.1\nport: 8686", KEY, &[0xab]).unwrap();
+
+        assert_eq!(
+            patched,
+            format!(
+                "interface: 127.0.0.1\nport: 8686\nkms_protection: \"{}\"\nseed: \"ab\"\n",
+                KEY
+            )
+        );
+    }
+
+    #[test]
+    fn similar_and_nested_keys_are_untouched() {
+        let config = "seed_file: /tmp/x\nnested:\n  seed: 00\n";
+        let patched = patch_config(config, KEY, &[0xab]).unwrap();
+
+        assert!(patched.starts_with(config));
+    }
+
+    #[test]
+    fn unparseable_result_is_rejected() {
+        assert!(patch_config("- a list\n- not a map\n", KEY, &[0xab]).is_err());
+    }
+}
@@ -49,12 +49,20 @@
 //!
 //! The core Roughtime server implementation is in `src/server.rs` and the server's CLI can
 //! be found in `src/bin/roughenough-server.rs`.
+//! Embedders and tests needing protocol-correct responses without a server can use
+//! [`ResponseBuilder`](responder/struct.ResponseBuilder.html). Embedders running the server
+//! can observe requests, batches and responses through [`hooks`](hooks/index.html).
+//! The server's request handling is split into [`batch`](batch/index.html) assembly,
+//! response generation by the `ResponseBuilder`, and the counters in
+//! [`stats`](stats/index.html), each usable without a socket.
 //!
 //! The server has multiple ways it can be configured,
 //! see [`ServerConfig`](config/trait.ServerConfig.html) for the configuration trait and
 //!
 //!
 
+#[macro_use]
+extern crate lazy_static;
 #[macro_use]
 extern crate log;
 
@@ -62,16 +70,45 @@ mod error;
 mod message;
 mod tag;
 
+pub mod audit;
+pub mod batch;
+pub mod chain;
+pub mod chaos;
+pub mod clock;
 pub mod config;
+pub mod ecosystem;
+pub mod filter;
+pub mod hooks;
 pub mod key;
 pub mod kms;
 pub mod merkle;
+pub mod ntp;
+pub mod privileges;
+pub mod rate;
+pub mod responder;
+pub mod selfcheck;
+pub mod selftest;
 pub mod server;
 pub mod sign;
+pub mod stats;
+pub mod test_support;
+pub mod verify;
+
+#[cfg(unix)]
+pub mod supervisor;
+
+#[cfg(feature = "otlp")]
+pub mod telemetry;
+
+#[cfg(feature = "grpc-health")]
+pub mod grpc_health;
+
+#[cfg(feature = "pq")]
+pub mod pq;
 
 pub use crate::error::Error;
-pub use crate::message::RtMessage;
-pub use crate::tag::Tag;
+pub use crate::message::{FieldIter, RtMessage};
+pub use crate::tag::{CustomTag, Tag};
 
 /// Version of Roughenough
 pub const VERSION: &str = "1.1.1";
@@ -86,7 +123,9 @@ pub fn roughenough_version() -> String {
         ""
     };
 
-    format!("{}{}", VERSION, kms_str)
+    let pq_str = if cfg!(feature = "pq") { " (+PQ)" } else { "" };
+
+    format!("{}{}{}", VERSION, kms_str, pq_str)
 }
 
 //  Constants and magic numbers of the Roughtime protocol
@@ -109,12 +148,18 @@ pub const SIGNATURE_LENGTH: u32 = 64;
 /// Size (in bytes) of a SHA-512 hash
 pub const HASH_LENGTH: u32 = 64;
 
+/// Maximum depth of a Merkle tree PATH (number of hashes) accepted in a response
+pub const MAX_PATH_DEPTH: u32 = 32;
+
 /// Size (in bytes) of server's timestamp value
 pub const TIMESTAMP_LENGTH: u32 = 8;
 
 /// Size (in bytes) of server's time uncertainty value
 pub const RADIUS_LENGTH: u32 = 4;
 
+/// Uncertainty (in microseconds) of the server's time, before any coarsening of the midpoint
+pub const DEFAULT_RADIUS: u32 = 1_000_000;
+
 /// Prefixed to the server's certificate before generating or verifying certificate's signature
 pub const CERTIFICATE_CONTEXT: &str = "RoughTime v1 delegation signature--\x00";
 
@@ -17,11 +17,20 @@
 //!
 
 use ring::digest;
-use super::{HASH_LENGTH, TREE_LEAF_TWEAK, TREE_NODE_TWEAK};
+use super::{Error, HASH_LENGTH, MAX_PATH_DEPTH, TREE_LEAF_TWEAK, TREE_NODE_TWEAK};
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
 type Data = Vec<u8>;
 type Hash = Data;
 
+/// When the `parallel` feature is enabled, leaf batches and tree levels with at least
+/// this many entries are hashed using rayon's thread pool. Smaller inputs are hashed
+/// sequentially as dispatch overhead would dominate.
+#[cfg(feature = "parallel")]
+pub const PARALLEL_THRESHOLD: usize = 32;
+
 ///
 /// Merkle Tree implementation using SHA-512 and the Roughtime leaf and node tweak values.
 ///
@@ -40,10 +49,18 @@ impl MerkleTree {
     }
 
     pub fn push_leaf(&mut self, data: &[u8]) {
-        let hash = self.hash_leaf(data);
+        let hash = hash_leaf(data);
         self.levels[0].push(hash);
     }
 
+    ///
+    /// Add all of `leaves` to the tree, in order. Equivalent to calling `push_leaf()` on each
+    /// leaf, but large batches are hashed in parallel if the `parallel` feature is enabled.
+    ///
+    pub fn push_leaves<T: AsRef<[u8]> + Sync>(&mut self, leaves: &[T]) {
+        hash_leaves(leaves, &mut self.levels[0]);
+    }
+
     pub fn get_paths(&self, mut index: usize) -> Vec<u8> {
         let mut paths = Vec::with_capacity(self.levels.len() * 64);
         let mut level = 0;
@@ -81,13 +98,8 @@ impl MerkleTree {
 
             node_count /= 2;
 
-            for i in 0..node_count {
-                let hash = self.hash_nodes(
-                    &self.levels[level - 1][i * 2],
-                    &self.levels[level - 1][(i * 2) + 1],
-                );
-                self.levels[level].push(hash);
-            }
+            let (lower, upper) = self.levels.split_at_mut(level);
+            hash_level(&lower[level - 1], &mut upper[0]);
         }
 
         assert_eq!(self.levels[level].len(), 1);
@@ -100,23 +112,84 @@ impl MerkleTree {
         }
     }
 
-    fn hash_leaf(&self, leaf: &[u8]) -> Data {
-        self.hash(&[TREE_LEAF_TWEAK, leaf])
+}
+
+fn hash_leaf(leaf: &[u8]) -> Data {
+    hash(&[TREE_LEAF_TWEAK, leaf])
+}
+
+fn hash_nodes(first: &[u8], second: &[u8]) -> Data {
+    hash(&[TREE_NODE_TWEAK, first, second])
+}
+
+fn hash(to_hash: &[&[u8]]) -> Data {
+    let mut ctx = digest::Context::new(&digest::SHA512);
+    for data in to_hash {
+        ctx.update(data);
     }
+    Data::from(ctx.finish().as_ref())
+}
 
-    fn hash_nodes(&self, first: &[u8], second: &[u8]) -> Data {
-        self.hash(&[TREE_NODE_TWEAK, first, second])
+// Hash each leaf and append the result to `out`
+#[cfg(not(feature = "parallel"))]
+fn hash_leaves<T: AsRef<[u8]> + Sync>(leaves: &[T], out: &mut Vec<Data>) {
+    out.extend(leaves.iter().map(|leaf| hash_leaf(leaf.as_ref())));
+}
+
+#[cfg(feature = "parallel")]
+fn hash_leaves<T: AsRef<[u8]> + Sync>(leaves: &[T], out: &mut Vec<Data>) {
+    if leaves.len() < PARALLEL_THRESHOLD {
+        out.extend(leaves.iter().map(|leaf| hash_leaf(leaf.as_ref())));
+    } else {
+        let hashes: Vec<Data> = leaves
+            .par_iter()
+            .map(|leaf| hash_leaf(leaf.as_ref()))
+            .collect();
+        out.extend(hashes);
     }
+}
 
-    fn hash(&self, to_hash: &[&[u8]]) -> Data {
-        let mut ctx = digest::Context::new(&digest::SHA512);
-        for data in to_hash {
-            ctx.update(data);
-        }
-        Data::from(ctx.finish().as_ref())
+// Hash each pair of nodes in `nodes` and append the result to `out`
+#[cfg(not(feature = "parallel"))]
+fn hash_level(nodes: &[Data], out: &mut Vec<Data>) {
+    out.extend(nodes.chunks(2).map(|pair| hash_nodes(&pair[0], &pair[1])));
+}
+
+#[cfg(feature = "parallel")]
+fn hash_level(nodes: &[Data], out: &mut Vec<Data>) {
+    if nodes.len() < PARALLEL_THRESHOLD {
+        out.extend(nodes.chunks(2).map(|pair| hash_nodes(&pair[0], &pair[1])));
+    } else {
+        let hashes: Vec<Data> = nodes
+            .par_chunks(2)
+            .map(|pair| hash_nodes(&pair[0], &pair[1]))
+            .collect();
+        out.extend(hashes);
     }
 }
 
+///
+/// Check that a response's PATH and INDX are well formed before computing a root from them:
+/// `paths` must be a whole number of hashes, no deeper than `MAX_PATH_DEPTH`, and `index`
+/// must identify a leaf of a tree of that depth.
+///
+pub fn validate_path(index: u32, paths: &[u8]) -> Result<(), Error> {
+    if paths.len() % HASH_LENGTH as usize != 0 {
+        return Err(Error::InvalidPathLength(paths.len() as u32));
+    }
+
+    let depth = (paths.len() / HASH_LENGTH as usize) as u32;
+    if depth > MAX_PATH_DEPTH {
+        return Err(Error::PathTooDeep(depth));
+    }
+
+    if u64::from(index) >> depth != 0 {
+        return Err(Error::InvalidIndex(index, depth));
+    }
+
+    Ok(())
+}
+
 pub fn root_from_paths(mut index: usize, data: &[u8], paths: &[u8]) -> Hash {
     let mut hash = {
         let mut ctx = digest::Context::new(&digest::SHA512);
@@ -170,6 +243,42 @@ mod test {
         }
     }
 
+    #[test]
+    fn validate_path_accepts_well_formed_paths() {
+        assert!(validate_path(0, &[]).is_ok());
+        assert!(validate_path(1, &[0; 64]).is_ok());
+        assert!(validate_path(7, &[0; 3 * 64]).is_ok());
+        assert!(validate_path(u32::max_value(), &[0; 32 * 64]).is_ok());
+    }
+
+    #[test]
+    fn validate_path_rejects_partial_hashes() {
+        match validate_path(0, &[0; 65]) {
+            Err(Error::InvalidPathLength(65)) => (),
+            e => panic!("unexpected result {:?}", e),
+        }
+    }
+
+    #[test]
+    fn validate_path_rejects_deep_paths() {
+        match validate_path(0, &[0; 33 * 64]) {
+            Err(Error::PathTooDeep(33)) => (),
+            e => panic!("unexpected result {:?}", e),
+        }
+    }
+
+    #[test]
+    fn validate_path_rejects_index_outside_tree() {
+        match validate_path(1, &[]) {
+            Err(Error::InvalidIndex(1, 0)) => (),
+            e => panic!("unexpected result {:?}", e),
+        }
+        match validate_path(8, &[0; 3 * 64]) {
+            Err(Error::InvalidIndex(8, 3)) => (),
+            e => panic!("unexpected result {:?}", e),
+        }
+    }
+
     #[test]
     fn power_of_two() {
         test_paths_with_num(2);
@@ -183,4 +292,32 @@ mod test {
         test_paths_with_num(1);
         test_paths_with_num(20);
     }
+
+    #[test]
+    fn large_trees() {
+        test_paths_with_num(255);
+        test_paths_with_num(256);
+    }
+
+    #[test]
+    fn push_leaves_matches_push_leaf() {
+        for &num in &[1usize, 7, 64, 300] {
+            let leaves: Vec<Vec<u8>> = (0..num).map(|i| vec![i as u8; 64]).collect();
+
+            let mut one_by_one = MerkleTree::new();
+            for leaf in &leaves {
+                one_by_one.push_leaf(leaf);
+            }
+
+            let mut batched = MerkleTree::new();
+            batched.push_leaves(&leaves);
+
+            let root = batched.compute_root();
+            assert_eq!(one_by_one.compute_root(), root);
+
+            for (i, leaf) in leaves.iter().enumerate() {
+                assert_eq!(root_from_paths(i, leaf, &batched.get_paths(i)), root);
+            }
+        }
+    }
 }
@@ -180,6 +180,55 @@ impl RtMessage {
         Ok(())
     }
 
+    /// Insert a field into this `RtMessage`. Unlike
+    /// [`add_field`](struct.RtMessage.html#method.add_field) fields may be inserted in
+    /// any order; the message is kept sorted by tag so it always encodes in the order
+    /// required by the protocol.
+    ///
+    /// ## Arguments
+    ///
+    /// * `tag` - The [`Tag`](enum.Tag.html) to add. Inserting a tag that is already present
+    ///   results in an [`Error::DuplicateTag`](enum.Error.html).
+    ///
+    /// * `value` - Value for the tag.
+    ///
+    pub fn insert_field(&mut self, tag: Tag, value: &[u8]) -> Result<(), Error> {
+        match self.tags.binary_search(&tag) {
+            Ok(_) => Err(Error::DuplicateTag(tag)),
+            Err(idx) => {
+                self.tags.insert(idx, tag);
+                self.values.insert(idx, value.to_vec());
+                Ok(())
+            }
+        }
+    }
+
+    /// Insert `value` encoded as a little-endian `u32`. Fields may be inserted in any order,
+    /// see [`insert_field`](struct.RtMessage.html#method.insert_field).
+    pub fn add_u32(&mut self, tag: Tag, value: u32) -> Result<(), Error> {
+        let mut bytes = [0u8; 4];
+        (&mut bytes as &mut [u8]).write_u32::<LittleEndian>(value)?;
+
+        self.insert_field(tag, &bytes)
+    }
+
+    /// Insert `value` encoded as a little-endian `u64`. Fields may be inserted in any order,
+    /// see [`insert_field`](struct.RtMessage.html#method.insert_field).
+    pub fn add_u64(&mut self, tag: Tag, value: u64) -> Result<(), Error> {
+        let mut bytes = [0u8; 8];
+        (&mut bytes as &mut [u8]).write_u64::<LittleEndian>(value)?;
+
+        self.insert_field(tag, &bytes)
+    }
+
+    /// Insert the encoded form of the nested message `msg`. Fields may be inserted in any
+    /// order, see [`insert_field`](struct.RtMessage.html#method.insert_field).
+    pub fn add_nested(&mut self, tag: Tag, msg: &RtMessage) -> Result<(), Error> {
+        let bytes = msg.encode()?;
+
+        self.insert_field(tag, &bytes)
+    }
+
     /// Retrieve the value associated with `tag`, if present.
     ///
     /// ## Arguments
@@ -196,6 +245,41 @@ impl RtMessage {
         None
     }
 
+    /// Retrieve the value of `tag` as a little-endian `u32`.
+    ///
+    /// Returns `Error::TagNotFound` if `tag` is absent or `Error::InvalidValueLength` if
+    /// its value is not exactly 4 bytes long.
+    pub fn get_u32(&self, tag: Tag) -> Result<u32, Error> {
+        match self.get_field(tag) {
+            Some(mut value) if value.len() == 4 => Ok(value.read_u32::<LittleEndian>()?),
+            Some(value) => Err(Error::InvalidValueLength(tag, value.len() as u32)),
+            None => Err(Error::TagNotFound(tag)),
+        }
+    }
+
+    /// Retrieve the value of `tag` as a little-endian `u64`.
+    ///
+    /// Returns `Error::TagNotFound` if `tag` is absent or `Error::InvalidValueLength` if
+    /// its value is not exactly 8 bytes long.
+    pub fn get_u64(&self, tag: Tag) -> Result<u64, Error> {
+        match self.get_field(tag) {
+            Some(mut value) if value.len() == 8 => Ok(value.read_u64::<LittleEndian>()?),
+            Some(value) => Err(Error::InvalidValueLength(tag, value.len() as u32)),
+            None => Err(Error::TagNotFound(tag)),
+        }
+    }
+
+    /// Decode the value of `tag` as a nested `RtMessage` (e.g. `SREP`, `CERT`, or `DELE`).
+    ///
+    /// Returns `Error::TagNotFound` if `tag` is absent or any error produced while decoding
+    /// the value.
+    pub fn get_nested(&self, tag: Tag) -> Result<RtMessage, Error> {
+        match self.get_field(tag) {
+            Some(value) => RtMessage::from_bytes(value),
+            None => Err(Error::TagNotFound(tag)),
+        }
+    }
+
     /// Returns the number of tag/value pairs in the message
     pub fn num_fields(&self) -> u32 {
         self.tags.len() as u32
@@ -211,6 +295,15 @@ impl RtMessage {
         &self.values
     }
 
+    /// Returns an iterator over the `(Tag, value)` pairs of this message in tag order.
+    /// No allocation is performed.
+    pub fn iter(&self) -> FieldIter {
+        FieldIter {
+            tags: self.tags.iter(),
+            values: self.values.iter(),
+        }
+    }
+
     /// Converts the message into a `HashMap` mapping each tag to its value
     pub fn into_hash_map(self) -> HashMap<Tag, Vec<u8>> {
         self.tags.into_iter().zip(self.values.into_iter()).collect()
@@ -218,8 +311,26 @@ impl RtMessage {
 
     /// Encode this message into its on-the-wire representation.
     pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut out = vec![0u8; self.encoded_size()];
+        self.encode_into(&mut out)?;
+
+        Ok(out)
+    }
+
+    ///
+    /// Encode this message into the start of `buf`, returning the number of bytes
+    /// written (always `encoded_size()`). Nothing is allocated, so a buffer can be reused
+    /// across messages. Fails with `BufferTooSmall` if `buf` cannot hold the message.
+    ///
+    pub fn encode_into(&self, buf: &mut [u8]) -> Result<usize, Error> {
         let num_tags = self.tags.len();
-        let mut out = Vec::with_capacity(self.encoded_size());
+        let size = self.encoded_size();
+
+        if buf.len() < size {
+            return Err(Error::BufferTooSmall(size, buf.len()));
+        }
+
+        let mut out = Cursor::new(&mut buf[..size]);
 
         // number of tags
         out.write_u32::<LittleEndian>(num_tags as u32)?;
@@ -245,9 +356,9 @@ impl RtMessage {
         }
 
         // check we wrote exactly what we expected
-        assert_eq!(out.len(), self.encoded_size(), "unexpected length");
+        assert_eq!(out.position() as usize, size, "unexpected length");
 
-        Ok(out)
+        Ok(size)
     }
 
     /// Returns the length in bytes of this message's on-the-wire representation.
@@ -266,29 +377,70 @@ impl RtMessage {
     /// If the encoded size of this message is already >= 1KB,
     /// this method does nothing
     pub fn pad_to_kilobyte(&mut self) {
-        let size = self.encoded_size();
-        if size >= 1024 {
+        self.pad_to(1024);
+    }
+
+    /// Adds a PAD tag to the end of this message, with a length set such that the
+    /// final encoded size of this message is `size` bytes (a multiple of 4)
+    ///
+    /// If the encoded size of this message is already >= `size`, or too close to
+    /// `size` to fit a PAD tag, this method does nothing
+    pub fn pad_to(&mut self, size: usize) {
+        assert_eq!(size % 4, 0, "padded size must be a multiple of 4");
+
+        let current = self.encoded_size();
+        // the PAD tag itself, plus a 32-bit offset unless it will be the only tag
+        let overhead = if self.tags.is_empty() { 4 } else { 8 };
+
+        if current + overhead > size {
             return;
         }
 
-        let mut padding_needed = 1024 - size;
-        if self.tags.len() == 1 {
-            // If we currently only have one tag, adding a padding tag will cause
-            // a 32-bit offset values to be written
-            padding_needed -= 4;
+        let padding = vec![0; size - current - overhead];
+        self.add_field(Tag::PAD, &padding).unwrap();
+
+        assert_eq!(self.encoded_size(), size);
+    }
+}
+
+/// Iterator over the `(Tag, value)` fields of an [`RtMessage`](struct.RtMessage.html),
+/// created by [`RtMessage::iter`](struct.RtMessage.html#method.iter).
+#[derive(Debug, Clone)]
+pub struct FieldIter<'a> {
+    tags: std::slice::Iter<'a, Tag>,
+    values: std::slice::Iter<'a, Vec<u8>>,
+}
+
+impl<'a> Iterator for FieldIter<'a> {
+    type Item = (Tag, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match (self.tags.next(), self.values.next()) {
+            (Some(tag), Some(value)) => Some((*tag, value.as_slice())),
+            _ => None,
         }
-        padding_needed -= Tag::PAD.wire_value().len();
-        let padding = vec![0; padding_needed];
+    }
 
-        self.add_field(Tag::PAD, &padding).unwrap();
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.tags.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for FieldIter<'a> {}
+
+impl<'a> IntoIterator for &'a RtMessage {
+    type Item = (Tag, &'a [u8]);
+    type IntoIter = FieldIter<'a>;
 
-        assert_eq!(self.encoded_size(), 1024);
+    fn into_iter(self) -> FieldIter<'a> {
+        self.iter()
     }
 }
 
 #[cfg(test)]
 mod test {
     use byteorder::{LittleEndian, ReadBytesExt};
+    use crate::error::Error;
     use crate::message::*;
     use std::io::{Cursor, Read};
     use crate::tag::Tag;
@@ -439,6 +591,114 @@ mod test {
         assert_eq!(msg.get_field(Tag::CERT), None);
     }
 
+    #[test]
+    fn insert_fields_in_any_order() {
+        let mut msg = RtMessage::new(4);
+        msg.add_u32(Tag::INDX, 7).unwrap();
+        msg.insert_field(Tag::PATH, b"path").unwrap();
+        msg.add_u64(Tag::MIDP, 0x0102_0304_0506_0708).unwrap();
+        msg.insert_field(Tag::SIG, b"sig!").unwrap();
+
+        assert_eq!(msg.tags(), &[Tag::SIG, Tag::PATH, Tag::MIDP, Tag::INDX]);
+        assert_eq!(msg.get_field(Tag::INDX), Some([7u8, 0, 0, 0].as_ref()));
+        assert_eq!(
+            msg.get_field(Tag::MIDP),
+            Some([8u8, 7, 6, 5, 4, 3, 2, 1].as_ref())
+        );
+
+        // Encoded form is accepted by the strictly-ordered decoder
+        let decoded = RtMessage::from_bytes(&msg.encode().unwrap()).unwrap();
+        assert_eq!(decoded.tags(), msg.tags());
+    }
+
+    #[test]
+    fn typed_getters() {
+        let mut inner = RtMessage::new(1);
+        inner.add_u64(Tag::MIDP, 1_234_567).unwrap();
+
+        let mut msg = RtMessage::new(3);
+        msg.add_u32(Tag::RADI, 1_000_000).unwrap();
+        msg.add_nested(Tag::SREP, &inner).unwrap();
+        msg.add_u32(Tag::INDX, 3).unwrap();
+
+        assert_eq!(msg.get_u32(Tag::RADI).unwrap(), 1_000_000);
+        assert_eq!(msg.get_u32(Tag::INDX).unwrap(), 3);
+        assert_eq!(
+            msg.get_nested(Tag::SREP).unwrap().get_u64(Tag::MIDP).unwrap(),
+            1_234_567
+        );
+    }
+
+    #[test]
+    fn typed_getters_report_errors() {
+        let mut msg = RtMessage::new(1);
+        msg.add_u32(Tag::RADI, 1).unwrap();
+
+        match msg.get_u64(Tag::RADI) {
+            Err(Error::InvalidValueLength(Tag::RADI, 4)) => (),
+            e => panic!("unexpected result {:?}", e),
+        }
+        match msg.get_u32(Tag::MIDP) {
+            Err(Error::TagNotFound(Tag::MIDP)) => (),
+            e => panic!("unexpected result {:?}", e),
+        }
+        match msg.get_nested(Tag::CERT) {
+            Err(Error::TagNotFound(Tag::CERT)) => (),
+            e => panic!("unexpected result {:?}", e),
+        }
+    }
+
+    #[test]
+    fn iterate_fields_in_order() {
+        let mut msg = RtMessage::new(3);
+        msg.add_field(Tag::SIG, b"1111").unwrap();
+        msg.add_field(Tag::NONC, b"22").unwrap();
+        msg.add_field(Tag::PAD, b"").unwrap();
+
+        let fields: Vec<(Tag, &[u8])> = msg.iter().collect();
+        assert_eq!(
+            fields,
+            vec![
+                (Tag::SIG, b"1111".as_ref()),
+                (Tag::NONC, b"22".as_ref()),
+                (Tag::PAD, b"".as_ref())
+            ]
+        );
+        assert_eq!(msg.iter().len(), 3);
+
+        let mut count = 0;
+        for (tag, value) in &msg {
+            assert_eq!(msg.get_field(tag), Some(value));
+            count += 1;
+        }
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn insert_duplicate_tag_is_an_error() {
+        let mut msg = RtMessage::new(2);
+        msg.add_u32(Tag::RADI, 1).unwrap();
+
+        match msg.add_u32(Tag::RADI, 2) {
+            Err(Error::DuplicateTag(Tag::RADI)) => (),
+            e => panic!("unexpected result {:?}", e),
+        }
+    }
+
+    #[test]
+    fn add_nested_message() {
+        let mut inner = RtMessage::new(1);
+        inner.add_u32(Tag::RADI, 1_000_000).unwrap();
+
+        let mut outer = RtMessage::new(1);
+        outer.add_nested(Tag::SREP, &inner).unwrap();
+
+        assert_eq!(
+            outer.get_field(Tag::SREP).unwrap(),
+            inner.encode().unwrap().as_slice()
+        );
+    }
+
     #[test]
     #[should_panic(expected = "InvalidAlignment")]
     fn from_bytes_offset_past_end_of_message() {
@@ -461,4 +721,52 @@ mod test {
         RtMessage::from_bytes(bytes).unwrap();
     }
 
+    #[test]
+    fn pad_to_exact_sizes() {
+        for &size in &[80, 1024, 1280] {
+            let mut msg = RtMessage::new(1);
+            msg.add_field(Tag::NONC, &[0x22; 64]).unwrap();
+            msg.pad_to(size);
+
+            assert_eq!(msg.encoded_size(), size);
+            assert_eq!(msg.encode().unwrap().len(), size);
+        }
+
+        // no room for a PAD tag
+        let mut msg = RtMessage::new(1);
+        msg.add_field(Tag::NONC, &[0x22; 64]).unwrap();
+        msg.pad_to(76);
+        assert_eq!(msg.num_fields(), 1);
+    }
+
+    #[test]
+    fn encode_into_matches_encode() {
+        let mut msg = RtMessage::new(3);
+        msg.add_field(Tag::SIG, &[0x11; 64]).unwrap();
+        msg.add_field(Tag::NONC, &[0x22; 64]).unwrap();
+        msg.add_u32(Tag::INDX, 7).unwrap();
+
+        let mut buf = [0xffu8; 512];
+        let len = msg.encode_into(&mut buf).unwrap();
+
+        assert_eq!(len, msg.encoded_size());
+        assert_eq!(&buf[..len], msg.encode().unwrap().as_slice());
+        // bytes past the message are untouched
+        assert!(buf[len..].iter().all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn encode_into_rejects_short_buffer() {
+        let mut msg = RtMessage::new(1);
+        msg.add_field(Tag::NONC, &[0x22; 64]).unwrap();
+
+        let mut buf = [0u8; 16];
+        match msg.encode_into(&mut buf) {
+            Err(Error::BufferTooSmall(needed, available)) => {
+                assert_eq!(needed, msg.encoded_size());
+                assert_eq!(available, 16);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
 }
@@ -0,0 +1,217 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! A minimal SNTP ([RFC 4330](https://tools.ietf.org/html/rfc4330)) client, used to
+//! cross-check an NTP server against an authenticated Roughtime response.
+//!
+//! NTP is unauthenticated, so its answer is only trusted as far as it agrees with
+//! Roughtime: an NTP time outside the Roughtime interval suggests a spoofed or broken
+//! NTP server.
+//!
+
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::Error;
+
+/// The default NTP port
+pub const NTP_PORT: u16 = 123;
+
+/// Seconds from the NTP epoch (1900-01-01) to the Unix epoch
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Length of an NTP packet without extensions or authenticator
+const PACKET_LENGTH: usize = 48;
+
+// Leap indicator 0, version 4, mode 3 (client)
+const CLIENT_REQUEST: u8 = 0x23;
+const MODE_SERVER: u8 = 4;
+const LEAP_UNSYNCHRONIZED: u8 = 3;
+
+///
+/// The result of one NTP exchange, relative to the local clock.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NtpSample {
+    /// Seconds the server's clock is ahead of the local clock
+    pub offset: f64,
+    /// Round-trip delay of the exchange, in seconds, excluding the server's processing time
+    pub delay: f64,
+    /// The server's stratum (1 for a primary server)
+    pub stratum: u8,
+}
+
+/// The 64-bit NTP timestamp of `time`
+fn to_ntp(time: SystemTime) -> u64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap();
+    let secs = since_epoch.as_secs() + NTP_UNIX_OFFSET;
+    let frac = (u64::from(since_epoch.subsec_nanos()) << 32) / 1_000_000_000;
+
+    (secs << 32) | frac
+}
+
+/// Seconds since the Unix epoch of the NTP timestamp `ts`
+fn from_ntp(ts: u64) -> f64 {
+    let secs = (ts >> 32) as f64 - NTP_UNIX_OFFSET as f64;
+    let frac = (ts & 0xffff_ffff) as f64 / 4_294_967_296.0;
+
+    secs + frac
+}
+
+fn unix_secs(time: SystemTime) -> f64 {
+    from_ntp(to_ntp(time))
+}
+
+/// An SNTP client request sent at `now`. The server echoes the transmit timestamp back.
+pub fn make_request(now: SystemTime) -> [u8; PACKET_LENGTH] {
+    let mut request = [0u8; PACKET_LENGTH];
+    request[0] = CLIENT_REQUEST;
+    BigEndian::write_u64(&mut request[40..48], to_ntp(now));
+
+    request
+}
+
+///
+/// Check the server's `response` to `request` (sent at `sent`, received at `received`)
+/// and compute the clock offset and round-trip delay.
+///
+pub fn parse_response(
+    request: &[u8],
+    response: &[u8],
+    sent: SystemTime,
+    received: SystemTime,
+) -> Result<NtpSample, Error> {
+    let invalid = |msg: String| Err(Error::InvalidResponse(msg));
+
+    if response.len() < PACKET_LENGTH {
+        return invalid(format!("NTP response is only {} bytes", response.len()));
+    }
+
+    let leap = response[0] >> 6;
+    let mode = response[0] & 0x07;
+    let stratum = response[1];
+
+    if mode != MODE_SERVER {
+        return invalid(format!(
+            "NTP response has mode {}, expected {}",
+            mode, MODE_SERVER
+        ));
+    }
+    if stratum == 0 {
+        let code = String::from_utf8_lossy(&response[12..16]);
+        return invalid(format!("NTP server sent kiss-o'-death '{}'", code));
+    }
+    if leap == LEAP_UNSYNCHRONIZED {
+        return invalid("NTP server clock is unsynchronized".to_string());
+    }
+    // an off-path attacker can't know the transmit timestamp we sent
+    if response[24..32] != request[40..48] {
+        return invalid("NTP response does not answer our request".to_string());
+    }
+
+    let t1 = unix_secs(sent);
+    let t2 = from_ntp(BigEndian::read_u64(&response[32..40]));
+    let t3 = from_ntp(BigEndian::read_u64(&response[40..48]));
+    let t4 = unix_secs(received);
+
+    Ok(NtpSample {
+        offset: ((t2 - t1) + (t3 - t4)) / 2.0,
+        delay: (t4 - t1) - (t3 - t2),
+        stratum,
+    })
+}
+
+/// Query the NTP server at `addr` once, waiting up to `timeout` for its response
+pub fn query(addr: &SocketAddr, timeout: Duration) -> Result<NtpSample, Error> {
+    let bind_addr = if addr.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    };
+    let socket = UdpSocket::bind(bind_addr)?;
+    socket.set_read_timeout(Some(timeout))?;
+
+    let sent = SystemTime::now();
+    let request = make_request(sent);
+    socket.send_to(&request, addr)?;
+
+    let mut buf = [0u8; 1024];
+    let (len, _) = socket.recv_from(&mut buf)?;
+    let received = SystemTime::now();
+
+    parse_response(&request, &buf[..len], sent, received)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use byteorder::{BigEndian, ByteOrder};
+
+    use crate::ntp::{make_request, parse_response, to_ntp};
+    use crate::Error;
+
+    // A server response to `request` that was received and answered at `server_time`
+    fn respond(request: &[u8], server_time: SystemTime) -> Vec<u8> {
+        let mut response = vec![0u8; 48];
+        response[0] = 0x24;
+        response[1] = 2;
+        response[24..32].copy_from_slice(&request[40..48]);
+        BigEndian::write_u64(&mut response[32..40], to_ntp(server_time));
+        BigEndian::write_u64(&mut response[40..48], to_ntp(server_time));
+        response
+    }
+
+    #[test]
+    fn offset_and_delay_are_computed() {
+        let sent = SystemTime::now();
+        let received = sent + Duration::from_millis(100);
+        let server_time = sent + Duration::from_millis(2_050);
+
+        let request = make_request(sent);
+        let sample =
+            parse_response(&request, &respond(&request, server_time), sent, received).unwrap();
+
+        assert!((sample.offset - 2.0).abs() < 1e-6, "{}", sample.offset);
+        assert!((sample.delay - 0.1).abs() < 1e-6, "{}", sample.delay);
+        assert_eq!(sample.stratum, 2);
+    }
+
+    #[test]
+    fn bad_responses_are_rejected() {
+        let sent = SystemTime::now();
+        let request = make_request(sent);
+        let good = respond(&request, sent);
+
+        let mut kiss = good.clone();
+        kiss[1] = 0;
+        kiss[12..16].copy_from_slice(b"RATE");
+
+        let mut spoofed = good.clone();
+        spoofed[24] ^= 0xff;
+
+        let mut client_mode = good.clone();
+        client_mode[0] = 0x23;
+
+        for response in &[kiss, spoofed, client_mode, good[..40].to_vec()] {
+            match parse_response(&request, response, sent, sent) {
+                Err(Error::InvalidResponse(_)) => (),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Experimental hybrid post-quantum signatures, enabled with the `pq` feature.
+//!
+//! When configured with `pq_signatures`, the online key has an ML-DSA-44 key pair alongside
+//! its Ed25519 one. The ML-DSA public key travels in the DELE, so it is certified by the
+//! long-term key, and every response carries an ML-DSA signature of the SREP in addition
+//! to the usual Ed25519 `SIG`. Both are private-use, non-critical tags that other clients
+//! ignore.
+//!
+//! This exists to measure the cost in response size and signing time. The long-term key
+//! is still Ed25519, so it does not make responses secure against a quantum adversary.
+//!
+
+use std::fmt;
+use std::fmt::Formatter;
+
+use pqcrypto_mldsa::mldsa44;
+use pqcrypto_traits::sign::{DetachedSignature, PublicKey};
+
+use crate::error::Error;
+
+/// An ML-DSA-44 key pair signing SREPs next to the online key's Ed25519 signature
+pub struct PqKey {
+    public_key: mldsa44::PublicKey,
+    secret_key: mldsa44::SecretKey,
+}
+
+impl PqKey {
+    /// A fresh random key pair
+    pub fn new() -> Self {
+        let (public_key, secret_key) = mldsa44::keypair();
+
+        PqKey {
+            public_key,
+            secret_key,
+        }
+    }
+
+    /// The encoded public key, as carried in the DELE
+    pub fn public_key_bytes(&self) -> &[u8] {
+        self.public_key.as_bytes()
+    }
+
+    /// A detached signature of `message`
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        mldsa44::detached_sign(message, &self.secret_key)
+            .as_bytes()
+            .to_vec()
+    }
+}
+
+impl Default for PqKey {
+    fn default() -> Self {
+        PqKey::new()
+    }
+}
+
+impl fmt::Display for PqKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ML-DSA-44 {}",
+            hex::encode(&self.public_key_bytes()[..8])
+        )
+    }
+}
+
+/// Check the detached ML-DSA-44 `signature` of `message` by `public_key`
+pub fn verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<(), Error> {
+    let invalid = |why: &str| Error::InvalidSignature(why.to_string());
+
+    let public_key = mldsa44::PublicKey::from_bytes(public_key)
+        .map_err(|_| invalid("malformed ML-DSA public key"))?;
+    let signature = mldsa44::DetachedSignature::from_bytes(signature)
+        .map_err(|_| invalid("malformed ML-DSA signature"))?;
+
+    mldsa44::verify_detached_signature(&signature, message, &public_key)
+        .map_err(|_| invalid("SREP is not signed by the delegated ML-DSA key"))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::pq::{verify, PqKey};
+
+    #[test]
+    fn signatures_verify_only_for_their_message_and_key() {
+        let key = PqKey::new();
+        let signature = key.sign(b"SREP bytes");
+
+        assert!(verify(key.public_key_bytes(), b"SREP bytes", &signature).is_ok());
+        assert!(verify(key.public_key_bytes(), b"other bytes", &signature).is_err());
+        assert!(verify(PqKey::new().public_key_bytes(), b"SREP bytes", &signature).is_err());
+        assert!(verify(key.public_key_bytes(), b"SREP bytes", &signature[1..]).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Dropping root privileges once the server's sockets are bound.
+//!
+//! The server is typically started as root so it can bind privileged ports. The `user`
+//! and `group` config settings name the unprivileged identity the server switches to
+//! before it starts answering requests.
+//!
+
+#[cfg(unix)]
+use std::ffi::CString;
+#[cfg(unix)]
+use std::ptr;
+
+use crate::config::ServerConfig;
+use crate::Error;
+
+/// Returns true if the process is running with an effective user id of root.
+#[cfg(unix)]
+pub fn running_as_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+pub fn running_as_root() -> bool {
+    false
+}
+
+///
+/// Refuse to run as root unless `config` names a user to switch to, or root was
+/// explicitly permitted with `allow_root`. A `group` alone is not enough, as the server
+/// would still run as root.
+///
+/// Intended to be called before the server is created so a misconfiguration is reported
+/// before any sockets are bound.
+///
+pub fn check_root(config: &ServerConfig) -> Result<(), Error> {
+    if running_as_root() && config.user().is_none() && !config.allow_root() {
+        return Err(Error::InvalidConfiguration(
+            "refusing to run as root; set 'user' to drop privileges or 'allow_root' to override"
+                .to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+///
+/// Switch to the `group` and `user` provided in `config`. Does nothing if neither is set.
+///
+/// The group is changed first (while still privileged), supplementary groups are cleared,
+/// and finally the user is changed. Fails if root privileges can be regained afterwards.
+///
+#[cfg(unix)]
+pub fn drop_privileges(config: &ServerConfig) -> Result<(), Error> {
+    if config.user().is_none() && config.group().is_none() {
+        return Ok(());
+    }
+
+    let user = match config.user() {
+        Some(name) => Some(lookup_user(name)?),
+        None => None,
+    };
+
+    let gid = match (config.group(), user) {
+        (Some(name), _) => Some(lookup_group(name)?),
+        (None, Some((_, primary_gid))) => Some(primary_gid),
+        (None, None) => None,
+    };
+
+    unsafe {
+        if let Some(gid) = gid {
+            if libc::setgroups(0, ptr::null()) != 0 {
+                return Err(os_error("setgroups"));
+            }
+            if libc::setgid(gid) != 0 {
+                return Err(os_error("setgid"));
+            }
+        }
+
+        if let Some((uid, _)) = user {
+            if libc::setuid(uid) != 0 {
+                return Err(os_error("setuid"));
+            }
+
+            if uid != 0 && libc::setuid(0) == 0 {
+                return Err(Error::InvalidConfiguration(
+                    "root privileges could be regained after dropping them".to_string(),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges(config: &ServerConfig) -> Result<(), Error> {
+    if config.user().is_some() || config.group().is_some() {
+        return Err(Error::InvalidConfiguration(
+            "'user' and 'group' are only supported on Unix platforms".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+// Returns the (uid, primary gid) of the user `name`
+#[cfg(unix)]
+fn lookup_user(name: &str) -> Result<(libc::uid_t, libc::gid_t), Error> {
+    let c_name = c_string(name)?;
+    let passwd = unsafe { libc::getpwnam(c_name.as_ptr()) };
+
+    if passwd.is_null() {
+        return Err(Error::InvalidConfiguration(format!("unknown user '{}'", name)));
+    }
+
+    unsafe { Ok(((*passwd).pw_uid, (*passwd).pw_gid)) }
+}
+
+#[cfg(unix)]
+fn lookup_group(name: &str) -> Result<libc::gid_t, Error> {
+    let c_name = c_string(name)?;
+    let group = unsafe { libc::getgrnam(c_name.as_ptr()) };
+
+    if group.is_null() {
+        return Err(Error::InvalidConfiguration(format!("unknown group '{}'", name)));
+    }
+
+    unsafe { Ok((*group).gr_gid) }
+}
+
+#[cfg(unix)]
+fn c_string(name: &str) -> Result<CString, Error> {
+    CString::new(name).map_err(|_| Error::InvalidConfiguration(format!("invalid name '{}'", name)))
+}
+
+#[cfg(unix)]
+fn os_error(call: &str) -> Error {
+    Error::InvalidConfiguration(format!(
+        "{} failed: {}",
+        call,
+        std::io::Error::last_os_error()
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use crate::config::MemoryConfig;
+    use crate::privileges::{check_root, drop_privileges, running_as_root};
+
+    #[test]
+    fn no_targets_is_a_noop() {
+        let config = MemoryConfig::builder().allow_root(true).build();
+        assert!(drop_privileges(&config).is_ok());
+        assert!(check_root(&config).is_ok());
+    }
+
+    #[test]
+    fn root_requires_user_or_override() {
+        let config = MemoryConfig::new(0);
+        assert_eq!(check_root(&config).is_err(), running_as_root());
+
+        let config = MemoryConfig::builder().group(Some("nogroup")).build();
+        assert_eq!(check_root(&config).is_err(), running_as_root());
+
+        let config = MemoryConfig::builder().user(Some("nobody")).build();
+        assert!(check_root(&config).is_ok());
+    }
+
+    #[test]
+    fn unknown_user_is_an_error() {
+        let config = MemoryConfig::builder()
+            .user(Some("no-such-roughenough-user"))
+            .build();
+        assert!(drop_privileges(&config).is_err());
+    }
+}
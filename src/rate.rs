@@ -0,0 +1,155 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Exponentially weighted moving averages of request and response rates.
+//!
+//! Like Unix load averages, rates are averaged over 1, 5 and 15 minute windows so a
+//! status line shows whether load is rising or falling, not just lifetime totals.
+//!
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Windows (in seconds) of the 1, 5 and 15 minute averages
+const WINDOWS: [f64; 3] = [60.0, 300.0, 900.0];
+
+/// Counters are sampled at most this often
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+///
+/// Moving averages, in events per second, of a monotonically increasing counter.
+///
+#[derive(Debug, Clone)]
+pub struct EwmaRate {
+    averages: [f64; 3],
+    last_total: u64,
+    last_sample: Instant,
+}
+
+impl EwmaRate {
+    /// Averages of a counter whose value at `now` is `total`
+    pub fn new(total: u64, now: Instant) -> EwmaRate {
+        EwmaRate {
+            averages: [0.0; 3],
+            last_total: total,
+            last_sample: now,
+        }
+    }
+
+    ///
+    /// Fold in the counter's value `total` at `now`. Samples closer together than
+    /// [`SAMPLE_INTERVAL`](constant.SAMPLE_INTERVAL.html) are ignored.
+    ///
+    pub fn update(&mut self, total: u64, now: Instant) {
+        let elapsed = now.duration_since(self.last_sample);
+        if elapsed < SAMPLE_INTERVAL {
+            return;
+        }
+
+        let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_micros()) / 1e6;
+        // a counter that was reset (e.g. a fuzzing run) restarts from zero
+        let events = total.saturating_sub(self.last_total) as f64;
+        let current = events / secs;
+
+        for (average, window) in self.averages.iter_mut().zip(WINDOWS.iter()) {
+            let alpha = 1.0 - (-secs / window).exp();
+            *average += alpha * (current - *average);
+        }
+
+        self.last_total = total;
+        self.last_sample = now;
+    }
+
+    /// Average over the last minute
+    pub fn one_minute(&self) -> f64 {
+        self.averages[0]
+    }
+
+    /// Average over the last five minutes
+    pub fn five_minutes(&self) -> f64 {
+        self.averages[1]
+    }
+
+    /// Average over the last fifteen minutes
+    pub fn fifteen_minutes(&self) -> f64 {
+        self.averages[2]
+    }
+}
+
+impl fmt::Display for EwmaRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.2}/{:.2}/{:.2}",
+            self.one_minute(),
+            self.five_minutes(),
+            self.fifteen_minutes()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rate::EwmaRate;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn steady_rate_converges() {
+        let start = Instant::now();
+        let mut rate = EwmaRate::new(0, start);
+
+        // 100 events per second for an hour
+        for secs in 1..=3600 {
+            rate.update(secs * 100, start + Duration::from_secs(secs));
+        }
+
+        assert!((rate.one_minute() - 100.0).abs() < 0.01);
+        assert!((rate.five_minutes() - 100.0).abs() < 0.01);
+        assert!((rate.fifteen_minutes() - 100.0).abs() < 2.0);
+    }
+
+    #[test]
+    fn short_window_reacts_faster() {
+        let start = Instant::now();
+        let mut rate = EwmaRate::new(0, start);
+
+        for secs in 1..=60 {
+            rate.update(secs * 100, start + Duration::from_secs(secs));
+        }
+
+        assert!(rate.one_minute() > rate.five_minutes());
+        assert!(rate.five_minutes() > rate.fifteen_minutes());
+        assert!(rate.one_minute() < 100.0);
+    }
+
+    #[test]
+    fn frequent_samples_are_ignored() {
+        let start = Instant::now();
+        let mut rate = EwmaRate::new(0, start);
+
+        rate.update(1000, start + Duration::from_millis(500));
+        assert_eq!(rate.one_minute(), 0.0);
+
+        // the ignored sample's events are counted by the next one
+        rate.update(1000, start + Duration::from_secs(1));
+        assert!(rate.one_minute() > 0.0);
+    }
+
+    #[test]
+    fn display_shows_all_windows() {
+        let rate = EwmaRate::new(0, Instant::now());
+        assert_eq!(rate.to_string(), "0.00/0.00/0.00");
+    }
+}
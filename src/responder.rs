@@ -0,0 +1,268 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Construction of protocol-correct Roughtime responses, independent of any socket.
+//!
+//! ```rust
+//! use roughenough::key::LongTermKey;
+//! use roughenough::responder::ResponseBuilder;
+//!
+//! let mut long_term_key = LongTermKey::new(&[0x42; 32]);
+//! let mut builder = ResponseBuilder::from_long_term_key(&mut long_term_key);
+//!
+//! let nonce = [0x01; 64];
+//! let responses = builder.respond(1_563_849_123_000_000, 1_000_000, None, &[&nonce[..]]);
+//! let bytes = responses[0].encode().unwrap();
+//! ```
+//!
+
+use crate::key::{LongTermKey, OnlineKey};
+use crate::merkle::MerkleTree;
+use crate::{RtMessage, Tag};
+
+///
+/// Owns an online key and the CERT delegating to it, and builds the signed responses
+/// for a batch of requests.
+///
+pub struct ResponseBuilder {
+    online_key: OnlineKey,
+    cert_bytes: Vec<u8>,
+    // the `instance_id` tag's value, NUL padded to a multiple of 4 bytes
+    instance_id: Option<Vec<u8>>,
+}
+
+impl ResponseBuilder {
+    /// A builder answering with `online_key` and the encoded CERT (`cert_bytes`) delegating to it
+    pub fn new(online_key: OnlineKey, cert_bytes: Vec<u8>) -> ResponseBuilder {
+        ResponseBuilder {
+            online_key,
+            cert_bytes,
+            instance_id: None,
+        }
+    }
+
+    ///
+    /// Add `instance_id` (if any) to every response in the non-critical
+    /// [`Tag::instance_id`](../enum.Tag.html#method.instance_id) tag. It is not signed.
+    ///
+    pub fn with_instance_id(mut self, instance_id: Option<&str>) -> ResponseBuilder {
+        self.instance_id = instance_id.map(|id| {
+            let mut value = id.as_bytes().to_vec();
+            value.resize((value.len() + 3) / 4 * 4, 0);
+            value
+        });
+        self
+    }
+
+    /// A builder with a fresh online key, delegated to by `long_term_key`
+    pub fn from_long_term_key(long_term_key: &mut LongTermKey) -> ResponseBuilder {
+        let online_key = OnlineKey::new();
+        let cert_bytes = long_term_key.make_cert(&online_key).encode().unwrap();
+
+        ResponseBuilder::new(online_key, cert_bytes)
+    }
+
+    /// The online key signing responses
+    pub fn online_key(&self) -> &OnlineKey {
+        &self.online_key
+    }
+
+    /// The encoded CERT included in every response
+    pub fn cert_bytes(&self) -> &[u8] {
+        &self.cert_bytes
+    }
+
+    ///
+    /// Sign an SREP for a batch whose Merkle tree has `merkle_root`. `midpoint` and
+    /// `radius` are in microseconds; `dtai` is the TAI-UTC offset for TAI clocks.
+    /// Returns a message holding the `SIG` and `SREP` fields.
+    ///
+    pub fn sign_srep(
+        &mut self,
+        midpoint: u64,
+        radius: u32,
+        merkle_root: &[u8],
+        dtai: Option<u32>,
+    ) -> RtMessage {
+        self.online_key.sign_srep(midpoint, radius, merkle_root, dtai)
+    }
+
+    /// The response to the request at Merkle tree leaf `idx`, whose inclusion proof is `path`
+    pub fn make_response(&self, srep: &RtMessage, path: &[u8], idx: u32) -> RtMessage {
+        self.make_response_with_cert(srep, &self.cert_bytes, path, idx)
+    }
+
+    ///
+    /// As [`make_response`](#method.make_response), but carrying the encoded CERT
+    /// `cert_bytes` instead of this builder's, e.g. to inject an expired delegation.
+    ///
+    pub fn make_response_with_cert(
+        &self,
+        srep: &RtMessage,
+        cert_bytes: &[u8],
+        path: &[u8],
+        idx: u32,
+    ) -> RtMessage {
+        let mut response = make_response(srep, cert_bytes, path, idx);
+
+        if let Some(ref instance_id) = self.instance_id {
+            response.insert_field(Tag::instance_id(), instance_id).unwrap();
+        }
+
+        response
+    }
+
+    ///
+    /// Build the responses to a batch of requests containing `nonces`, in the same order,
+    /// all carrying `midpoint` and `radius` (microseconds). Duplicate nonces are allowed.
+    ///
+    pub fn respond(
+        &mut self,
+        midpoint: u64,
+        radius: u32,
+        dtai: Option<u32>,
+        nonces: &[&[u8]],
+    ) -> Vec<RtMessage> {
+        let mut merkle = MerkleTree::new();
+        merkle.push_leaves(nonces);
+
+        let root = merkle.compute_root();
+        let srep = self.sign_srep(midpoint, radius, &root, dtai);
+
+        (0..nonces.len())
+            .map(|idx| self.make_response(&srep, &merkle.get_paths(idx), idx as u32))
+            .collect()
+    }
+}
+
+///
+/// Assemble a complete response from a signed `srep` (as returned by
+/// [`ResponseBuilder::sign_srep`](struct.ResponseBuilder.html#method.sign_srep)), the
+/// encoded CERT, and the Merkle inclusion `path` of the request at leaf `idx`.
+///
+pub fn make_response(srep: &RtMessage, cert_bytes: &[u8], path: &[u8], idx: u32) -> RtMessage {
+    let sig_bytes = srep.get_field(Tag::SIG).unwrap();
+    let srep_bytes = srep.get_field(Tag::SREP).unwrap();
+
+    let mut response = RtMessage::new(5);
+    response.insert_field(Tag::SIG, sig_bytes).unwrap();
+    response.insert_field(Tag::PATH, path).unwrap();
+    response.insert_field(Tag::SREP, srep_bytes).unwrap();
+    response.insert_field(Tag::CERT, cert_bytes).unwrap();
+    response.add_u32(Tag::INDX, idx).unwrap();
+
+    // only present when the online key has an experimental post-quantum key
+    if let Some(pq_signature) = srep.get_field(Tag::pq_signature()) {
+        response.insert_field(Tag::pq_signature(), pq_signature).unwrap();
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use crate::key::LongTermKey;
+    use crate::responder::ResponseBuilder;
+    use crate::verify::Response;
+    use crate::{Tag, DEFAULT_RADIUS};
+
+    #[test]
+    fn batch_responses_verify() {
+        let mut long_term_key = LongTermKey::new(&[0x42; 32]);
+        let mut builder = ResponseBuilder::from_long_term_key(&mut long_term_key);
+
+        let nonces = [[0x01u8; 64], [0x02u8; 64], [0x03u8; 64]];
+        let refs: Vec<&[u8]> = nonces.iter().map(|n| &n[..]).collect();
+        let midpoint = 1_563_849_123_000_000;
+
+        let responses = builder.respond(midpoint, DEFAULT_RADIUS, None, &refs);
+        assert_eq!(responses.len(), 3);
+
+        for (nonce, msg) in nonces.iter().zip(responses) {
+            let response = Response::new(msg, nonce).unwrap();
+            response.verify(long_term_key.public_key()).unwrap();
+            assert_eq!(response.midpoint().unwrap(), midpoint);
+            assert_eq!(response.radius().unwrap(), DEFAULT_RADIUS);
+        }
+    }
+
+    #[test]
+    fn instance_id_does_not_affect_verification() {
+        let mut long_term_key = LongTermKey::new(&[0x42; 32]);
+        let mut builder = ResponseBuilder::from_long_term_key(&mut long_term_key)
+            .with_instance_id(Some("fra-2"));
+
+        let nonce = [0x01u8; 64];
+        let msg = builder.respond(0, DEFAULT_RADIUS, None, &[&nonce[..]]).remove(0);
+        let bytes = msg.encode().unwrap();
+
+        let response = Response::from_bytes(&bytes, &nonce).unwrap();
+        response.verify_merkle().unwrap();
+        response.verify_cert(long_term_key.public_key()).unwrap();
+        response.verify_srep().unwrap();
+        assert_eq!(response.instance_id(), Some("fra-2".to_string()));
+    }
+
+    #[test]
+    fn responses_with_another_cert_keep_the_instance_id() {
+        let mut long_term_key = LongTermKey::new(&[0x42; 32]);
+        let mut builder = ResponseBuilder::from_long_term_key(&mut long_term_key)
+            .with_instance_id(Some("fra-2"));
+        let other = ResponseBuilder::from_long_term_key(&mut long_term_key);
+
+        let srep = builder.sign_srep(0, DEFAULT_RADIUS, &[0u8; 64], None);
+        let msg = builder.make_response_with_cert(&srep, other.cert_bytes(), &[], 0);
+
+        assert_eq!(msg.get_field(Tag::CERT), Some(other.cert_bytes()));
+        assert_eq!(msg.get_field(Tag::instance_id()), Some(&b"fra-2\0\0\0"[..]));
+    }
+
+    #[cfg(feature = "pq")]
+    #[test]
+    fn pq_signatures_verify_alongside_ed25519() {
+        use crate::key::OnlineKey;
+
+        let mut long_term_key = LongTermKey::new(&[0x42; 32]);
+        let online_key = OnlineKey::new().with_pq_key();
+        let cert_bytes = long_term_key.make_cert(&online_key).encode().unwrap();
+        let mut builder = ResponseBuilder::new(online_key, cert_bytes);
+
+        let nonce = [0x01u8; 64];
+        let msg = builder.respond(0, DEFAULT_RADIUS, None, &[&nonce[..]]).remove(0);
+        let response = Response::from_bytes(&msg.encode().unwrap(), &nonce).unwrap();
+
+        response.verify_cert(long_term_key.public_key()).unwrap();
+        response.verify_srep().unwrap();
+        assert!(response.has_pq_signature());
+        response.verify_pq().unwrap();
+
+        let plain = ResponseBuilder::from_long_term_key(&mut long_term_key)
+            .respond(0, DEFAULT_RADIUS, None, &[&nonce[..]])
+            .remove(0);
+        assert!(!Response::new(plain, &nonce).unwrap().has_pq_signature());
+    }
+
+    #[test]
+    fn response_for_wrong_nonce_fails() {
+        let mut long_term_key = LongTermKey::new(&[0x42; 32]);
+        let mut builder = ResponseBuilder::from_long_term_key(&mut long_term_key);
+
+        let nonce = [0x01u8; 64];
+        let msg = builder.respond(0, DEFAULT_RADIUS, None, &[&nonce[..]]).remove(0);
+
+        let response = Response::new(msg, &[0x02u8; 64]).unwrap();
+        assert!(response.verify_merkle().is_err());
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Checks the server runs at startup, before binding any socket, so that a server that
+//! would answer with wrong or unverifiable time refuses to start instead.
+//!
+//! Each check returns `Ok(())` or a description of exactly what is wrong.
+//!
+
+use crate::responder::ResponseBuilder;
+use crate::verify::Response;
+use crate::{DEFAULT_RADIUS, MIN_SEED_LENGTH, NONCE_LENGTH};
+
+/// Any system time before this (2019-01-01T00:00:00Z) is assumed to be a broken clock
+pub const MIN_SANE_TIME: i64 = 1_546_300_800;
+
+/// Any system time after this (2100-01-01T00:00:00Z) is assumed to be a broken clock
+pub const MAX_SANE_TIME: i64 = 4_102_444_800;
+
+/// Check that `now_secs`, seconds since the Unix epoch, is a plausible current time
+pub fn check_clock(now_secs: i64) -> Result<(), String> {
+    if now_secs < MIN_SANE_TIME || now_secs > MAX_SANE_TIME {
+        return Err(format!(
+            "system clock is implausible ({} seconds since the epoch, expected {} to {})",
+            now_secs, MIN_SANE_TIME, MAX_SANE_TIME
+        ));
+    }
+
+    Ok(())
+}
+
+///
+/// Check that the (possibly KMS-decrypted) `seed` is usable as a long-term key seed. A
+/// KMS that decrypts to the wrong length means the wrong key or a corrupted blob.
+///
+pub fn check_seed(seed: &[u8]) -> Result<(), String> {
+    if seed.len() != MIN_SEED_LENGTH as usize {
+        return Err(format!(
+            "seed is {} bytes after decryption, expected {}",
+            seed.len(),
+            MIN_SEED_LENGTH
+        ));
+    }
+
+    Ok(())
+}
+
+///
+/// Sign a test response at `midpoint` with `responder` and fully verify it against the
+/// long-term key `long_term_public_key`: the CERT's signature of the DELE, the SREP's
+/// signature by the online key, the Merkle path, and the midpoint lying within the
+/// delegation's validity. An experimental post-quantum signature is checked too.
+///
+pub fn check_signing(
+    responder: &mut ResponseBuilder,
+    long_term_public_key: &[u8],
+    midpoint: u64,
+) -> Result<(), String> {
+    let nonce = [0x5a; NONCE_LENGTH as usize];
+    let response = responder.respond(midpoint, DEFAULT_RADIUS, None, &[&nonce[..]]);
+
+    let bytes = response[0]
+        .encode()
+        .map_err(|e| format!("cannot encode test response: {:?}", e))?;
+
+    let response = Response::from_bytes(&bytes, &nonce)
+        .map_err(|e| format!("cannot parse test response: {:?}", e))?;
+
+    response
+        .verify(long_term_public_key)
+        .map_err(|e| format!("test response does not verify: {:?}", e))?;
+
+    #[cfg(feature = "pq")]
+    {
+        if response.has_pq_signature() {
+            response
+                .verify_pq()
+                .map_err(|e| format!("test response's post-quantum signature: {:?}", e))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::key::{LongTermKey, OnlineKey};
+    use crate::responder::ResponseBuilder;
+    use crate::selfcheck::{check_clock, check_seed, check_signing, MIN_SANE_TIME};
+
+    #[test]
+    fn implausible_clocks_are_rejected() {
+        assert!(check_clock(MIN_SANE_TIME + 1).is_ok());
+        assert!(check_clock(0).is_err());
+        assert!(check_clock(i64::max_value()).is_err());
+    }
+
+    #[test]
+    fn seeds_must_be_full_length() {
+        assert!(check_seed(&[0x42; 32]).is_ok());
+        assert!(check_seed(&[0x42; 16]).is_err());
+    }
+
+    #[test]
+    fn signing_is_checked_against_the_long_term_key() {
+        let midpoint = (MIN_SANE_TIME as u64 + 1_000) * 1_000_000;
+        let mut long_term_key = LongTermKey::new(&[0x42; 32]);
+        let mut responder = ResponseBuilder::from_long_term_key(&mut long_term_key);
+
+        assert!(check_signing(&mut responder, long_term_key.public_key(), midpoint).is_ok());
+
+        let other_key = LongTermKey::new(&[0x43; 32]);
+        let err = check_signing(&mut responder, other_key.public_key(), midpoint).unwrap_err();
+        assert!(err.contains("DELE"), "{}", err);
+
+        let online_key = OnlineKey::new();
+        let expired = long_term_key.sign_dele(&online_key.make_dele_with_validity(0, 1));
+        let mut responder = ResponseBuilder::new(online_key, expired.encode().unwrap());
+
+        let err = check_signing(&mut responder, long_term_key.public_key(), midpoint).unwrap_err();
+        assert!(err.contains("after* delegation span"), "{}", err);
+    }
+}
@@ -0,0 +1,161 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! An in-process benchmark of the server's response path, for capacity planning.
+//!
+//! Synthetic requests are parsed, batched, Merkle-hashed, signed and encoded exactly as
+//! the server does, but without any sockets, so the result is the CPU-bound ceiling on
+//! responses per second. Run it with `roughenough-server --selftest-bench`.
+//!
+
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::batch::{nonce_from_request, Batch, ProtocolVersion};
+use crate::key::LongTermKey;
+use crate::responder::ResponseBuilder;
+use crate::verify::make_request;
+use crate::{DEFAULT_RADIUS, NONCE_LENGTH};
+
+/// Throughput measured by [`run`](fn.run.html)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchResult {
+    /// Number of threads, each with its own responder, that ran concurrently
+    pub threads: usize,
+    /// Responses built and encoded, over all threads
+    pub responses: u64,
+    /// Batches signed, over all threads
+    pub batches: u64,
+    /// Wall-clock time the threads ran for
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    /// Responses per second over all threads
+    pub fn responses_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs() as f64 + f64::from(self.elapsed.subsec_nanos()) / 1e9;
+        self.responses as f64 / secs
+    }
+
+    /// Responses per second per thread
+    pub fn per_core(&self) -> f64 {
+        self.responses_per_sec() / self.threads as f64
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} thread(s): {:.0} responses/sec ({:.0} per core, {} batches)",
+            self.threads,
+            self.responses_per_sec(),
+            self.per_core(),
+            self.batches
+        )
+    }
+}
+
+///
+/// Answer synthetic batches of `batch_size` requests on `threads` threads for `duration`
+/// and report the combined throughput.
+///
+pub fn run(batch_size: u8, threads: usize, duration: Duration) -> BenchResult {
+    let started = Instant::now();
+
+    let workers: Vec<_> = (0..threads)
+        .map(|_| thread::spawn(move || respond_until(batch_size, started + duration)))
+        .collect();
+
+    let (responses, batches) = workers
+        .into_iter()
+        .map(|w| w.join().expect("benchmark thread panicked"))
+        .fold((0, 0), |(r, b), (wr, wb)| (r + wr, b + wb));
+
+    BenchResult {
+        threads,
+        responses,
+        batches,
+        elapsed: started.elapsed(),
+    }
+}
+
+// Answer batches until `deadline`, returning the number of (responses, batches)
+fn respond_until(batch_size: u8, deadline: Instant) -> (u64, u64) {
+    let mut long_term_key = LongTermKey::new(&[0x42; 32]);
+    let mut builder = ResponseBuilder::from_long_term_key(&mut long_term_key);
+    let source = "127.0.0.1:2002".parse().unwrap();
+
+    // a fresh nonce per request so every batch builds a full Merkle tree
+    let mut nonce = [0u8; NONCE_LENGTH as usize];
+    let mut counter = 0u64;
+    let mut requests: Vec<Vec<u8>> = (0..batch_size).map(|_| make_request(&nonce)).collect();
+
+    let mut batch = Batch::with_capacity(batch_size as usize);
+    let mut buf = vec![0u8; 1024];
+    let (mut responses, mut batches) = (0, 0);
+
+    while Instant::now() < deadline {
+        for request in &mut requests {
+            counter += 1;
+            nonce[..8].copy_from_slice(&counter.to_le_bytes());
+            request[0x10..0x50].copy_from_slice(&nonce);
+
+            batch.push(
+                nonce_from_request(request).unwrap(),
+                source,
+                ProtocolVersion::Classic,
+            );
+        }
+
+        let merkle_root = batch.merkle_root();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let midpoint = now.as_secs() * 1_000_000 + u64::from(now.subsec_micros());
+        let srep = builder.sign_srep(midpoint, DEFAULT_RADIUS, &merkle_root, None);
+
+        for entry in batch.entries() {
+            let resp = builder.make_response(&srep, &batch.paths(entry.leaf), entry.leaf as u32);
+            let resp_len = resp.encoded_size();
+            if buf.len() < resp_len {
+                buf.resize(resp_len, 0);
+            }
+            resp.encode_into(&mut buf).unwrap();
+            responses += 1;
+        }
+
+        batch.clear();
+        batches += 1;
+    }
+
+    (responses, batches)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::selftest::run;
+
+    #[test]
+    fn every_request_in_a_batch_is_answered() {
+        let result = run(4, 2, Duration::from_millis(200));
+
+        assert_eq!(result.threads, 2);
+        assert!(result.batches > 0);
+        assert_eq!(result.responses, result.batches * 4);
+        assert!(result.per_core() > 0.0);
+    }
+}
@@ -17,44 +17,88 @@
 //!
 
 use hex;
-use std::io::ErrorKind;
-use std::net::SocketAddr;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::process;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use time;
 
-use byteorder::{LittleEndian, WriteBytesExt};
-
-use mio::net::{TcpListener, UdpSocket};
+use mio::net::{TcpListener, TcpStream, UdpSocket};
 use mio::{Events, Poll, PollOpt, Ready, Token};
 use mio_extras::timer::Timer;
-
-use crate::config::ServerConfig;
-use crate::key::{LongTermKey, OnlineKey};
+use net2::{TcpBuilder, UdpBuilder, UdpSocketExt};
+
+use crate::audit::{AuditLog, AuditRecord};
+use crate::batch::{nonce_from_request, request_version, Batch, ProtocolVersion};
+#[cfg(feature = "chaos")]
+use crate::chaos::{self, Fault, FaultInjector};
+use crate::clock::{self, Clock, ClockSource};
+use crate::config::{HealthCheckMode, ServerConfig};
+use crate::filter::{FilterChain, RequestFilter, Verdict};
+#[cfg(feature = "grpc-health")]
+use crate::grpc_health::GrpcHealth;
+use crate::hooks::{BatchEvent, Hooks, RequestEvent, ResponseEvent};
+use crate::key::{self, LongTermKey, OnlineKey, SharedDelegation};
 use crate::kms;
-use crate::merkle::MerkleTree;
+use crate::rate::EwmaRate;
+use crate::responder::ResponseBuilder;
+use crate::selfcheck;
+use crate::stats::ServerStats;
+#[cfg(unix)]
+use crate::supervisor::StatsReporter;
+#[cfg(feature = "otlp")]
+use crate::telemetry::Telemetry;
+use crate::verify::{create_nonce, make_request, Response};
 use mio::tcp::Shutdown;
 use std::io::Write;
-use crate::{Error, RtMessage, Tag, MIN_REQUEST_LENGTH};
-
-macro_rules! check_ctrlc {
-    ($keep_running:expr) => {
-        if !$keep_running.load(Ordering::Acquire) {
-            warn!("Ctrl-C caught, exiting...");
-            return true;
-        }
-    };
-}
+use crate::{Error, RtMessage, Tag, DEFAULT_RADIUS};
 
 // mio event registrations
 const MESSAGE: Token = Token(0);
 const STATUS: Token = Token(1);
 const HEALTH_CHECK: Token = Token(2);
+const READINESS_CHECK: Token = Token(3);
+const HEALTH_PROBE: Token = Token(4);
 
-// Canned response to health check request
+// Canned responses to health and readiness check requests
 const HTTP_RESPONSE: &str = "HTTP/1.1 200 OK\nContent-Length: 0\nConnection: close\n\n";
+const HTTP_UNAVAILABLE: &str =
+    "HTTP/1.1 503 Service Unavailable\nContent-Length: 0\nConnection: close\n\n";
+
+
+// Warn in status updates when the delegation expires sooner than this
+const EXPIRY_WARNING: Duration = Duration::from_secs(24 * 60 * 60);
+
+// How long a self-query health check waits for the server to answer itself
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// A batch's signed SREP and the Merkle root it was signed over
+struct SignedBatch {
+    srep: RtMessage,
+    merkle_root: Vec<u8>,
+}
+
+// A health check connection waiting on the response to its query of our own UDP port
+struct PendingProbe {
+    stream: TcpStream,
+    nonce: [u8; 64],
+    started: Instant,
+}
+
+/// Age and remaining lifetime of the server's delegation to its online key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DelegationStats {
+    /// Time since the current online key was put into service
+    pub key_age: Duration,
+
+    /// Time until the delegation's MAXT; zero if it has already expired
+    pub remaining: Duration,
+
+    /// Number of times the online key and its delegation were replaced since startup
+    pub rotations: u64,
+}
 
 /// The main Roughenough server instance.
 ///
@@ -68,24 +112,51 @@ const HTTP_RESPONSE: &str = "HTTP/1.1 200 OK\nContent-Length: 0\nConnection: clo
 ///
 pub struct Server {
     config: Box<ServerConfig>,
-    online_key: OnlineKey,
-    cert_bytes: Vec<u8>,
+    responder: ResponseBuilder,
+    clock: Clock,
+    online_key_created: Instant,
+    rotations: u64,
 
-    response_counter: u64,
-    num_bad_requests: u64,
+    stats: ServerStats,
 
     socket: UdpSocket,
     health_listener: Option<TcpListener>,
+    readiness_listener: Option<TcpListener>,
+    probe_socket: Option<UdpSocket>,
+    pending_probes: Vec<PendingProbe>,
     keep_running: Arc<AtomicBool>,
+    // when `keep_running` was cleared, while requests are still answered for `shutdown_delay`
+    draining_since: Option<Instant>,
     poll_duration: Option<Duration>,
     timer: Timer<()>,
     poll: Poll,
     events: Events,
-    merkle: MerkleTree,
-    requests: Vec<(Vec<u8>, SocketAddr)>,
+    batch: Batch,
     buf: [u8; 65_536],
+    // responses are encoded here, grown to the largest response sent so far
+    resp_buf: Vec<u8>,
 
     public_key: String,
+    audit_log: Option<AuditLog>,
+    hooks: Hooks,
+    filters: FilterChain,
+
+    // Sends counters to the supervisor when running as one of several workers
+    #[cfg(unix)]
+    stats_reporter: Option<StatsReporter>,
+
+    #[cfg(feature = "otlp")]
+    telemetry: Option<Telemetry>,
+
+    // Answers gRPC health checks in place of `health_listener`
+    #[cfg(feature = "grpc-health")]
+    grpc_health: Option<GrpcHealth>,
+
+    #[cfg(feature = "chaos")]
+    fault_injector: Option<FaultInjector>,
+    // CERT of an already expired delegation, sent by the `ExpiredDelegation` fault
+    #[cfg(feature = "chaos")]
+    expired_cert: Option<Vec<u8>>,
 
     // Used to send requests to ourselves in fuzzing mode
     #[cfg(fuzzing)]
@@ -98,27 +169,161 @@ impl Server {
     /// [`ServerConfig`](../config/trait.ServerConfig.html) trait object instance.
     ///
     pub fn new(config: Box<ServerConfig>) -> Server {
-        let online_key = OnlineKey::new();
-        let public_key: String;
+        let seed = match kms::load_seed(&config) {
+            Ok(seed) => seed,
+            Err(e) => {
+                error!("Failed to load seed: {:#?}", e);
+                process::exit(1);
+            }
+        };
 
-        let cert_bytes = {
-            let seed = match kms::load_seed(&config) {
-                Ok(seed) => seed,
-                Err(e) => {
-                    error!("Failed to load seed: {:#?}", e);
-                    process::exit(1);
+        if let Err(e) = selfcheck::check_seed(&seed) {
+            error!("Startup self-check failed: {}", e);
+            process::exit(1);
+        }
+
+        let mut long_term_key = LongTermKey::new(&seed);
+
+        match config.shared_delegation().map(str::to_string) {
+            Some(path) => {
+                let delegation = std::fs::read(&path)
+                    .map_err(Error::from)
+                    .and_then(|sealed| SharedDelegation::open(&sealed, &seed))
+                    .and_then(|d| d.verify(long_term_key.public_key()).map(|_| d));
+
+                match delegation {
+                    Ok(delegation) => {
+                        let public_key = long_term_key.public_key().to_vec();
+                        let mut server = Server::with_delegation(
+                            config,
+                            delegation.online_key(),
+                            delegation.cert_bytes().to_vec(),
+                            &public_key,
+                        );
+                        server.sign_expired_cert(&mut long_term_key);
+                        server
+                    }
+                    Err(e) => {
+                        error!("Failed to load shared delegation '{}': {:?}", path, e);
+                        process::exit(1);
+                    }
                 }
-            };
-            let mut long_term_key = LongTermKey::new(&seed);
-            public_key = hex::encode(long_term_key.public_key());
+            }
+            None => Server::with_long_term_key(config, long_term_key),
+        }
+    }
+
+    ///
+    /// Create a new server instance using the provided long-term key instead of the one
+    /// derived from `config.seed()`. The long-term key is used once to sign the delegation
+    /// of the on-line key and then dropped; it may be backed by an external
+    /// [`Signer`](../sign/trait.Signer.html) such as an HSM.
+    ///
+    pub fn with_long_term_key(config: Box<ServerConfig>, mut long_term_key: LongTermKey) -> Server {
+        let online_key = Server::new_online_key(&*config);
+        let public_key = long_term_key.public_key().to_vec();
+        let cert_bytes = long_term_key.make_cert(&online_key).encode().unwrap();
+
+        let mut server = Server::with_delegation(config, online_key, cert_bytes, &public_key);
+        server.sign_expired_cert(&mut long_term_key);
+        server
+    }
 
-            long_term_key.make_cert(&online_key).encode().unwrap()
+    // When the `ExpiredDelegation` fault is configured, have the long-term key sign a
+    // delegation of the online key that expired long ago
+    #[cfg(feature = "chaos")]
+    fn sign_expired_cert(&mut self, long_term_key: &mut LongTermKey) {
+        let wanted = match self.fault_injector {
+            Some(ref injector) => injector.injects(Fault::ExpiredDelegation),
+            None => false,
         };
 
+        if wanted {
+            let dele = self.responder.online_key().make_dele_with_validity(0, 1);
+            let cert = long_term_key.sign_dele(&dele);
+            self.expired_cert = Some(cert.encode().unwrap());
+        }
+    }
+
+    #[cfg(not(feature = "chaos"))]
+    fn sign_expired_cert(&mut self, _long_term_key: &mut LongTermKey) {}
+
+    // A fresh online key, with an experimental post-quantum key too if `pq_signatures` is set
+    #[cfg(feature = "pq")]
+    fn new_online_key(config: &ServerConfig) -> OnlineKey {
+        if config.pq_signatures() {
+            info!("Experimental post-quantum signatures enabled; responses grow by ~3.7 KB");
+            OnlineKey::new().with_pq_key()
+        } else {
+            OnlineKey::new()
+        }
+    }
+
+    #[cfg(not(feature = "pq"))]
+    fn new_online_key(_config: &ServerConfig) -> OnlineKey {
+        OnlineKey::new()
+    }
+
+    // Before binding: the clock is plausible, and a response signed now verifies against
+    // the long-term key
+    fn self_check(
+        config: &ServerConfig,
+        responder: &mut ResponseBuilder,
+        long_term_public_key: &[u8],
+    ) -> Result<(), String> {
+        let now = time::get_time();
+        selfcheck::check_clock(now.sec + config.secondsoffset() as i64)?;
+
+        let midpoint = clock::to_micros(now, config.secondsoffset());
+        selfcheck::check_signing(responder, long_term_public_key, midpoint)?;
+
+        info!("Startup self-check passed");
+        Ok(())
+    }
+
+    ///
+    /// Create a new server instance answering with an existing delegation: `online_key` and
+    /// the encoded CERT (`cert_bytes`) signed by the long-term key `long_term_public_key`.
+    /// Used by anycast fleets where every instance shares the same
+    /// [`SharedDelegation`](../key/struct.SharedDelegation.html).
+    ///
+    pub fn with_delegation(
+        config: Box<ServerConfig>,
+        online_key: OnlineKey,
+        cert_bytes: Vec<u8>,
+        long_term_public_key: &[u8],
+    ) -> Server {
+        let public_key = hex::encode(long_term_public_key);
+        let mut responder =
+            ResponseBuilder::new(online_key, cert_bytes).with_instance_id(config.instance_id());
+
+        if let Err(e) = Server::self_check(&*config, &mut responder, long_term_public_key) {
+            error!("Startup self-check failed: {}", e);
+            process::exit(1);
+        }
+
         let keep_running = Arc::new(AtomicBool::new(true));
 
-        let sock_addr = config.udp_socket_addr().expect("udp sock addr");
-        let socket = UdpSocket::bind(&sock_addr).expect("failed to bind to socket");
+        // the only place the server resolves a hostname `interface`
+        let sock_addr = config.udp_socket_addr().unwrap_or_else(|e| {
+            match e {
+                Error::InvalidConfiguration(msg) => error!("Invalid interface: {}", msg),
+                e => error!("Invalid interface '{}': {:?}", config.interface(), e),
+            }
+            process::exit(1);
+        });
+        let ip = sock_addr.ip();
+        let reuse_port = config.workers() > 0;
+        let socket = bind_udp(
+            &sock_addr,
+            reuse_port,
+            config.unicast_hops(),
+            config.bind_device(),
+        )
+        .unwrap_or_else(|e| {
+            error!("Failed to bind UDP socket to {}: {}", sock_addr, e);
+            process::exit(1);
+        });
 
         let poll_duration = Some(Duration::from_millis(100));
 
@@ -131,100 +336,325 @@ impl Server {
         poll.register(&timer, STATUS, Ready::readable(), PollOpt::edge())
             .unwrap();
 
-        let health_listener = if let Some(hc_port) = config.health_check_port() {
-            let hc_sock_addr: SocketAddr = format!("{}:{}", config.interface(), hc_port)
-                .parse()
-                .unwrap();
+        let health_listener = config
+            .health_check_port()
+            .filter(|_| config.health_check_mode() != HealthCheckMode::Grpc)
+            .map(|port| Server::bind_http_check(&*config, &poll, ip, port, HEALTH_CHECK));
+
+        #[cfg(feature = "grpc-health")]
+        let grpc_health = match config.health_check_port() {
+            Some(port) if config.health_check_mode() == HealthCheckMode::Grpc => {
+                Some(Server::start_grpc_health(&*config, ip, port))
+            }
+            _ => None,
+        };
+
+        let readiness_listener = config
+            .readiness_check_port()
+            .map(|port| Server::bind_http_check(&*config, &poll, ip, port, READINESS_CHECK));
 
-            let tcp_listener = TcpListener::bind(&hc_sock_addr)
-                .expect("failed to bind TCP listener for health check");
+        let probe_socket = if config.health_check_port().is_some()
+            && config.health_check_mode() == HealthCheckMode::SelfQuery
+        {
+            let self_addr = self_query_addr(socket.local_addr().unwrap());
+            let probe = UdpSocket::bind(&SocketAddr::new(self_addr.ip(), 0))
+                .expect("failed to bind health check probe socket");
 
-            poll.register(&tcp_listener, HEALTH_CHECK, Ready::readable(), PollOpt::edge())
+            poll.register(&probe, HEALTH_PROBE, Ready::readable(), PollOpt::edge())
                 .unwrap();
 
-            Some(tcp_listener)
+            Some(probe)
         } else {
             None
         };
 
-        let merkle = MerkleTree::new();
-        let requests = Vec::with_capacity(config.batch_size() as usize);
+        #[cfg(feature = "otlp")]
+        let telemetry = config.otlp_endpoint().map(|endpoint| {
+            Telemetry::new(endpoint, config.status_interval()).unwrap_or_else(|e| {
+                error!("Failed to start OpenTelemetry export: {:?}", e);
+                process::exit(1);
+            })
+        });
+
+        #[cfg(feature = "chaos")]
+        let fault_injector = FaultInjector::from_config(&*config);
+
+        // workers of a supervisor each keep their own audit log
+        let audit_path = config.audit_log().map(|path| match worker_id() {
+            Some(id) => format!("{}.worker{}", path, id),
+            None => path.to_string(),
+        });
+
+        let audit_log = audit_path.as_ref().map(|path| {
+            AuditLog::open(path, config.audit_log_max_size()).unwrap_or_else(|e| {
+                error!("Failed to open audit log '{}': {:?}", path, e);
+                process::exit(1);
+            })
+        });
+
+        let clock = Clock::new(config.clock_source()).unwrap_or_else(|e| {
+            error!("Failed to set up {} clock: {:?}", config.clock_source(), e);
+            process::exit(1);
+        });
+
+        let filters = FilterChain::from_config(&*config);
+        let batch = Batch::with_capacity(config.batch_size() as usize);
 
         Server {
             config,
-            online_key,
-            cert_bytes,
+            responder,
+            clock,
+            online_key_created: Instant::now(),
+            rotations: 0,
 
-            response_counter: 0,
-            num_bad_requests: 0,
+            stats: ServerStats::new(Instant::now()),
             socket,
             health_listener,
+            readiness_listener,
+            probe_socket,
+            pending_probes: Vec::new(),
 
             keep_running,
+            draining_since: None,
             poll_duration,
             timer,
             poll,
             events: Events::with_capacity(32),
-            merkle,
-            requests,
+            batch,
             buf: [0u8; 65_536],
+            resp_buf: Vec::new(),
 
             public_key,
+            audit_log,
+            hooks: Hooks::new(),
+            filters,
+
+            #[cfg(unix)]
+            stats_reporter: StatsReporter::from_env(),
+
+            #[cfg(feature = "otlp")]
+            telemetry,
+
+            #[cfg(feature = "grpc-health")]
+            grpc_health,
+
+            #[cfg(feature = "chaos")]
+            fault_injector,
+            #[cfg(feature = "chaos")]
+            expired_cert: None,
 
             #[cfg(fuzzing)]
             fake_client_socket: UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap(),
         }
     }
 
-    /// Returns a reference counted pointer the this server's `keep_running` value.
-    pub fn get_keep_running(&self) -> Arc<AtomicBool> {
-        self.keep_running.clone()
+    // `ip` is the interface's address, already resolved for the UDP socket
+    fn bind_http_check(
+        config: &ServerConfig,
+        poll: &Poll,
+        ip: IpAddr,
+        port: u16,
+        token: Token,
+    ) -> TcpListener {
+        let sock_addr = SocketAddr::new(ip, port);
+
+        // with multiple workers every worker answers checks on the shared port
+        let tcp_listener = bind_tcp(&sock_addr, config.workers() > 0)
+            .expect("failed to bind TCP listener for health check");
+
+        poll.register(&tcp_listener, token, Ready::readable(), PollOpt::edge())
+            .unwrap();
+
+        tcp_listener
     }
 
-    // extract the client's nonce from its request
-    fn nonce_from_request<'a>(&self, buf: &'a [u8], num_bytes: usize) -> Result<&'a [u8], Error> {
-        if num_bytes < MIN_REQUEST_LENGTH as usize {
-            return Err(Error::RequestTooShort);
+    #[cfg(feature = "grpc-health")]
+    fn start_grpc_health(config: &ServerConfig, ip: IpAddr, port: u16) -> GrpcHealth {
+        let sock_addr = SocketAddr::new(ip, port);
+
+        bind_std_tcp(&sock_addr, config.workers() > 0)
+            .map_err(Error::from)
+            .and_then(GrpcHealth::start)
+            .unwrap_or_else(|e| {
+                error!("Failed to start gRPC health service: {:?}", e);
+                process::exit(1);
+            })
+    }
+
+    fn accept_http(listener: &TcpListener) -> Option<TcpStream> {
+        match listener.accept() {
+            Ok((stream, src_addr)) => {
+                info!("health check from {}", src_addr);
+                Some(stream)
+            }
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                debug!("blocking in TCP health check");
+                None
+            }
+            Err(e) => {
+                warn!("unexpected health check error {}", e);
+                None
+            }
         }
+    }
 
-        let tag_count = &buf[..4];
-        let expected_nonc = &buf[8..12];
-        let expected_pad = &buf[12..16];
+    // Write `response` to a health check connection and close it
+    fn write_http(stream: &mut TcpStream, response: &str) {
+        match stream.write(response.as_bytes()) {
+            Ok(_) => (),
+            Err(e) => warn!("error writing health check {}", e),
+        }
 
-        let tag_count_is_2 = tag_count == [0x02, 0x00, 0x00, 0x00];
-        let tag1_is_nonc = expected_nonc == Tag::NONC.wire_value();
-        let tag2_is_pad = expected_pad == Tag::PAD.wire_value();
+        match stream.shutdown(Shutdown::Both) {
+            Ok(_) => (),
+            Err(e) => warn!("error in health check socket shutdown {}", e),
+        }
+    }
 
-        if tag_count_is_2 && tag1_is_nonc && tag2_is_pad {
-            Ok(&buf[0x10..0x50])
-        } else {
-            Err(Error::InvalidRequest)
+    // Accept one connection on `listener`, write `response` and close the connection
+    fn respond_http(listener: &TcpListener, response: &str) {
+        if let Some(mut stream) = Server::accept_http(listener) {
+            Server::write_http(&mut stream, response);
         }
     }
 
-    fn make_response(
-        &self,
-        srep: &RtMessage,
-        cert_bytes: &[u8],
-        path: &[u8],
-        idx: u32,
-    ) -> RtMessage {
-        let mut index = [0; 4];
-        (&mut index as &mut [u8])
-            .write_u32::<LittleEndian>(idx)
-            .unwrap();
+    // Send a request with a fresh nonce to our own UDP port; `stream` is answered once
+    // the response arrives and verifies, or the probe times out
+    fn start_probe(&mut self, mut stream: TcpStream) {
+        let nonce = create_nonce();
+        let target = self_query_addr(self.local_addr());
+        let probe_socket = self.probe_socket.as_ref().unwrap();
+
+        match probe_socket.send_to(&make_request(&nonce), &target) {
+            Ok(_) => self.pending_probes.push(PendingProbe {
+                stream,
+                nonce,
+                started: Instant::now(),
+            }),
+            Err(e) => {
+                warn!("health check self-query failed: {}", e);
+                Server::write_http(&mut stream, HTTP_UNAVAILABLE);
+            }
+        }
+    }
 
-        let sig_bytes = srep.get_field(Tag::SIG).unwrap();
-        let srep_bytes = srep.get_field(Tag::SREP).unwrap();
+    fn receive_probe_responses(&mut self) {
+        let public_key = hex::decode(&self.public_key).unwrap();
 
-        let mut response = RtMessage::new(5);
-        response.add_field(Tag::SIG, sig_bytes).unwrap();
-        response.add_field(Tag::PATH, path).unwrap();
-        response.add_field(Tag::SREP, srep_bytes).unwrap();
-        response.add_field(Tag::CERT, cert_bytes).unwrap();
-        response.add_field(Tag::INDX, &index).unwrap();
+        loop {
+            let num_bytes = match self.probe_socket.as_ref().unwrap().recv_from(&mut self.buf) {
+                Ok((num_bytes, _)) => num_bytes,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("error receiving health check self-query response: {}", e);
+                    break;
+                }
+            };
 
-        response
+            let resp_bytes = &self.buf[..num_bytes];
+            let verified = self.pending_probes.iter().position(|probe| {
+                Response::from_bytes(resp_bytes, &probe.nonce)
+                    .and_then(|resp| resp.verify(&public_key))
+                    .is_ok()
+            });
+
+            match verified {
+                Some(idx) => {
+                    let mut probe = self.pending_probes.remove(idx);
+                    Server::write_http(&mut probe.stream, HTTP_RESPONSE);
+                }
+                None => warn!("health check self-query response did not verify"),
+            }
+        }
+    }
+
+    // Answer health checks whose self-query has not verified in time as unhealthy
+    fn expire_probes(&mut self) {
+        let mut idx = 0;
+
+        while idx < self.pending_probes.len() {
+            if self.pending_probes[idx].started.elapsed() >= PROBE_TIMEOUT {
+                let mut probe = self.pending_probes.remove(idx);
+                warn!("health check self-query timed out");
+                Server::write_http(&mut probe.stream, HTTP_UNAVAILABLE);
+            } else {
+                idx += 1;
+            }
+        }
+    }
+
+    ///
+    /// Returns `Ok(())` if the server is ready to serve correct time, otherwise a
+    /// description of why it is not. The server is ready when its long-term key has
+    /// signed a delegation, the system clock is plausible, and the delegation is valid
+    /// at the current time. A server that is shutting down is never ready.
+    ///
+    pub fn check_readiness(&self) -> Result<(), String> {
+        if self.draining_since.is_some() {
+            return Err("shutting down".to_string());
+        }
+
+        if self.responder.cert_bytes().is_empty() {
+            return Err("no delegation from the long-term key".to_string());
+        }
+
+        let now_secs = time::get_time().sec + self.config.secondsoffset() as i64;
+        selfcheck::check_clock(now_secs)?;
+
+        let now_micros = self.now_micros();
+        match self.delegation_validity() {
+            Ok((mint, maxt)) if mint <= now_micros && now_micros <= maxt => Ok(()),
+            Ok((mint, maxt)) => Err(format!(
+                "delegation is not valid now ({}); valid from {} to {}",
+                now_micros, mint, maxt
+            )),
+            Err(e) => Err(format!("delegation is malformed: {:?}", e)),
+        }
+    }
+
+    // current server time (including `secondsoffset`) in microseconds since the epoch
+    fn now_micros(&self) -> u64 {
+        clock::to_micros(time::get_time(), self.config.secondsoffset())
+    }
+
+    // (MINT, MAXT) of the current delegation
+    fn delegation_validity(&self) -> Result<(u64, u64), Error> {
+        let dele = RtMessage::from_bytes(self.responder.cert_bytes())?.get_nested(Tag::DELE)?;
+
+        Ok((dele.get_u64(Tag::MINT)?, dele.get_u64(Tag::MAXT)?))
+    }
+
+    /// Returns the age of the online key, the time remaining until its delegation expires,
+    /// and the number of rotations performed.
+    pub fn delegation_stats(&self) -> DelegationStats {
+        let remaining = match self.delegation_validity() {
+            Ok((_, maxt)) => Duration::from_micros(maxt.saturating_sub(self.now_micros())),
+            Err(_) => Duration::from_secs(0),
+        };
+
+        DelegationStats {
+            key_age: self.online_key_created.elapsed(),
+            remaining,
+            rotations: self.rotations,
+        }
+    }
+
+    ///
+    /// Replace the online key and the encoded CERT delegating to it. Responses to the
+    /// next batch onwards use the new delegation.
+    ///
+    pub fn replace_delegation(&mut self, online_key: OnlineKey, cert_bytes: Vec<u8>) {
+        self.responder = ResponseBuilder::new(online_key, cert_bytes)
+            .with_instance_id(self.config.instance_id());
+        self.online_key_created = Instant::now();
+        self.rotations += 1;
+
+        info!("Online key rotated to {}", self.responder.online_key());
+    }
+
+    /// Returns a reference counted pointer the this server's `keep_running` value.
+    pub fn get_keep_running(&self) -> Arc<AtomicBool> {
+        self.keep_running.clone()
     }
 
     /// The main processing function for incoming connections. This method should be
@@ -232,134 +662,516 @@ impl Server {
     /// server has shutdown (due to keep_running being set to 'false').
     ///
     pub fn process_events(&mut self) -> bool {
+        if self.should_stop() {
+            return true;
+        }
+
         self.poll
             .poll(&mut self.events, self.poll_duration)
             .expect("poll failed");
 
-        for event in self.events.iter() {
-            match event.token() {
+        let tokens: Vec<Token> = self.events.iter().map(|event| event.token()).collect();
+
+        for token in tokens {
+            match token {
                 MESSAGE => {
-                    let mut done = false;
-
-                    'process_batch: loop {
-                        check_ctrlc!(self.keep_running);
-
-                        let resp_start = self.response_counter;
-
-                        for i in 0..self.config.batch_size() {
-                            match self.socket.recv_from(&mut self.buf) {
-                                Ok((num_bytes, src_addr)) => {
-                                    match self.nonce_from_request(&self.buf, num_bytes) {
-                                        Ok(nonce) => {
-                                            self.requests.push((Vec::from(nonce), src_addr));
-                                            self.merkle.push_leaf(nonce);
-                                        }
-                                        Err(e) => {
-                                            self.num_bad_requests += 1;
-
-                                            info!(
-                                                "Invalid request: '{:?}' ({} bytes) from {} (#{} in batch, resp #{})",
-                                                e, num_bytes, src_addr, i, resp_start + i as u64
-                                            );
-                                        }
-                                    }
-                                }
-                                Err(e) => match e.kind() {
-                                    ErrorKind::WouldBlock => {
-                                        done = true;
-                                        break;
-                                    }
-                                    _ => {
-                                        error!(
-                                            "Error receiving from socket: {:?}: {:?}",
-                                            e.kind(),
-                                            e
-                                        );
-                                        break;
-                                    }
-                                },
-                            };
-                        }
+                    if self.process_requests() {
+                        return true;
+                    }
+                }
 
-                        if self.requests.is_empty() {
-                            break 'process_batch;
+                HEALTH_CHECK => {
+                    let listener = self.health_listener.as_ref().unwrap();
+
+                    if self.probe_socket.is_none() {
+                        Server::respond_http(listener, HTTP_RESPONSE);
+                    } else if let Some(stream) = Server::accept_http(listener) {
+                        self.start_probe(stream);
+                    }
+                }
+
+                HEALTH_PROBE => self.receive_probe_responses(),
+
+                READINESS_CHECK => {
+                    let response = match self.check_readiness() {
+                        Ok(_) => HTTP_RESPONSE,
+                        Err(reason) => {
+                            warn!("not ready: {}", reason);
+                            HTTP_UNAVAILABLE
                         }
+                    };
+
+                    let listener = self.readiness_listener.as_ref().unwrap();
+                    Server::respond_http(listener, response);
+                }
+
+                STATUS => {
+                    self.report_status();
+                    self.timer.set_timeout(self.config.status_interval(), ());
+                }
 
-                        let merkle_root = self.merkle.compute_root();
-                        let srep = self.online_key.make_srep(time::get_time(), &merkle_root, self.config.secondsoffset());
+                _ => unreachable!(),
+            }
+        }
 
-                        for (i, &(ref nonce, ref src_addr)) in self.requests.iter().enumerate() {
-                            let paths = self.merkle.get_paths(i);
+        if !self.pending_probes.is_empty() {
+            self.expire_probes();
+        }
 
-                            let resp =
-                                self.make_response(&srep, &self.cert_bytes, &paths, i as u32);
-                            let resp_bytes = resp.encode().unwrap();
+        #[cfg(feature = "grpc-health")]
+        {
+            if self.grpc_health.is_some() {
+                let ready = self.check_readiness().is_ok();
+                self.grpc_health.as_mut().unwrap().set_serving(ready);
+            }
+        }
 
-                            let bytes_sent = self
-                                .socket
-                                .send_to(&resp_bytes, &src_addr)
-                                .expect("send_to failed");
+        self.stats.update_rates(Instant::now());
 
-                            self.response_counter += 1;
+        false
+    }
 
-                            info!(
-                                "Responded {} bytes to {} for '{}..' (#{} in batch, resp #{})",
-                                bytes_sent,
-                                src_addr,
-                                hex::encode(&nonce[0..4]),
-                                i,
-                                self.response_counter
-                            );
-                        }
+    /// Process events until `keep_running` is set to `false` and any `shutdown_delay` passes
+    pub fn run(&mut self) {
+        while !self.process_events() {}
+    }
 
-                        self.merkle.reset();
-                        self.requests.clear();
+    // Whether the server should stop now. Once `keep_running` is cleared the server keeps
+    // answering requests, but fails readiness checks, until `shutdown_delay` has passed.
+    fn should_stop(&mut self) -> bool {
+        if self.keep_running.load(Ordering::Acquire) {
+            return false;
+        }
 
-                        if done {
-                            break 'process_batch;
-                        }
-                    }
+        let delay = self.config.shutdown_delay();
+
+        match self.draining_since {
+            None if delay > Duration::from_secs(0) => {
+                warn!(
+                    "Shutdown requested, answering requests for {}s before exiting...",
+                    delay.as_secs()
+                );
+                self.draining_since = Some(Instant::now());
+                false
+            }
+            Some(started) if started.elapsed() < delay => false,
+            _ => {
+                warn!("Ctrl-C caught, exiting...");
+                true
+            }
+        }
+    }
+
+    // Answer batches of requests until the socket is drained. Returns true if the server
+    // was shut down.
+    fn process_requests(&mut self) -> bool {
+        loop {
+            if self.should_stop() {
+                return true;
+            }
+
+            #[cfg(feature = "otlp")]
+            let batch_started = std::time::SystemTime::now();
+
+            let drained = self.receive_batch();
+
+            // the socket was drained, so there is capacity to answer deprioritized
+            // requests; otherwise they are shed in favor of the requests waiting
+            if drained {
+                self.batch.admit_deferred();
+            } else {
+                let shed = self.batch.shed_deferred();
+                if shed > 0 {
+                    debug!("Shedding {} deprioritized requests", shed);
+                    self.stats.filtered_requests += shed as u64;
                 }
+            }
 
-                HEALTH_CHECK => {
-                    let listener = self.health_listener.as_ref().unwrap();
+            if self.batch.is_empty() {
+                return false;
+            }
+
+            self.respond_to_batch();
 
-                    match listener.accept() {
-                        Ok((ref mut stream, src_addr)) => {
-                            info!("health check from {}", src_addr);
+            #[cfg(feature = "otlp")]
+            {
+                if let Some(ref telemetry) = self.telemetry {
+                    telemetry.record_batch(self.batch.len(), batch_started);
+                }
+            }
 
-                            match stream.write(HTTP_RESPONSE.as_bytes()) {
-                                Ok(_) => (),
-                                Err(e) => warn!("error writing health check {}", e),
-                            }
+            self.batch.clear();
 
-                            match stream.shutdown(Shutdown::Both) {
-                                Ok(_) => (),
-                                Err(e) => warn!("error in health check socket shutdown {}", e),
-                            }
-                        }
-                        Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
-                            debug!("blocking in TCP health check");
-                        }
-                        Err(e) => {
-                            warn!("unexpected health check error {}", e);
-                        }
+            if drained {
+                return false;
+            }
+        }
+    }
+
+    // Read up to `batch_size` requests into the batch. Returns true if the socket was drained.
+    fn receive_batch(&mut self) -> bool {
+        let resp_start = self.stats.responses;
+
+        for i in 0..self.config.batch_size() {
+            match self.socket.recv_from(&mut self.buf) {
+                Ok((num_bytes, src_addr)) => {
+                    let resp_num = resp_start + u64::from(i);
+                    self.admit_request(num_bytes, src_addr, i, resp_num);
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return true,
+                Err(e) => {
+                    error!("Error receiving from socket: {:?}: {:?}", e.kind(), e);
+                    return false;
+                }
+            }
+        }
+
+        false
+    }
+
+    // Filter and parse the request of `num_bytes` in `buf` from `src_addr`, adding its
+    // nonce to the batch if it is valid. `i` is its position in the batch.
+    fn admit_request(&mut self, num_bytes: usize, src_addr: SocketAddr, i: u8, resp_num: u64) {
+        let packet = &self.buf[..num_bytes];
+
+        let verdict = self.filters.check(&src_addr, packet, Instant::now());
+        if verdict == Verdict::Drop {
+            self.stats.filtered_requests += 1;
+            return;
+        }
+
+        let version = request_version(packet);
+        if let Some(version) = version {
+            self.stats.count_request(version);
+        }
+
+        match nonce_from_request(packet) {
+            Ok(nonce) => {
+                let version = version.unwrap_or(ProtocolVersion::Classic);
+                if verdict == Verdict::Deprioritize {
+                    self.batch.defer(nonce, src_addr, version);
+                } else {
+                    self.batch.push(nonce, src_addr, version);
+                }
+
+                self.hooks.request(&RequestEvent {
+                    source: src_addr,
+                    packet,
+                    nonce: Some(nonce),
+                });
+            }
+            Err(e) => {
+                self.stats.bad_requests += 1;
+
+                #[cfg(feature = "otlp")]
+                {
+                    if let Some(ref telemetry) = self.telemetry {
+                        telemetry.record_invalid_request();
                     }
                 }
 
-                STATUS => {
-                    info!(
-                        "responses {}, invalid requests {}",
-                        self.response_counter, self.num_bad_requests
-                    );
+                info!(
+                    "Invalid request: '{:?}' ({} bytes) from {} (#{} in batch, resp #{})",
+                    e, num_bytes, src_addr, i, resp_num
+                );
 
-                    self.timer.set_timeout(self.config.status_interval(), ());
+                self.hooks.request(&RequestEvent {
+                    source: src_addr,
+                    packet,
+                    nonce: None,
+                });
+            }
+        }
+    }
+
+    // Sign the batch's Merkle root and send a response to every request in the batch
+    fn respond_to_batch(&mut self) {
+        let merkle_root = self.batch.merkle_root();
+        let (now, dtai) = self.clock.now();
+        let (midpoint, radius) = clock::coarsen(
+            clock::to_micros(now, self.config.secondsoffset()),
+            DEFAULT_RADIUS,
+            self.config.midpoint_resolution(),
+        );
+
+        let signed = SignedBatch {
+            srep: self.responder.sign_srep(midpoint, radius, &merkle_root, dtai),
+            merkle_root,
+        };
+
+        self.hooks.batch_signed(&BatchEvent {
+            batch: self.stats.batches,
+            size: self.batch.len(),
+            midpoint,
+            radius,
+            merkle_root: &signed.merkle_root,
+        });
+
+        for i in 0..self.batch.len() {
+            let (leaf, src_addr, version) = {
+                let entry = &self.batch.entries()[i];
+                (entry.leaf, entry.source, entry.version)
+            };
+
+            let resp = self.make_response(&signed, leaf, &src_addr);
+            let bytes_sent = self.send_response(&resp, &src_addr);
+            self.stats.responses += 1;
+            self.stats.count_response(version);
+
+            let nonce = &self.batch.entries()[i].nonce;
+
+            self.hooks.response_sent(&ResponseEvent {
+                destination: src_addr,
+                nonce,
+                batch: self.stats.batches,
+                bytes_sent,
+            });
+
+            if let Some(ref mut audit_log) = self.audit_log {
+                let record = AuditRecord::new(midpoint, self.stats.batches, nonce, src_addr);
+                if let Err(e) = audit_log.append(&record) {
+                    error!("Failed to write audit record: {:?}", e);
                 }
+            }
 
-                _ => unreachable!(),
+            info!(
+                "Responded {} bytes to {} for '{}..' (#{} in batch, resp #{})",
+                bytes_sent,
+                src_addr,
+                hex::encode(&nonce[0..4]),
+                i,
+                self.stats.responses
+            );
+        }
+
+        if let Some(ref mut audit_log) = self.audit_log {
+            if let Err(e) = audit_log.flush() {
+                error!("Failed to flush audit log: {:?}", e);
             }
         }
-        false
+
+        self.stats.batches += 1;
+    }
+
+    // The response to the request at `leaf` of the signed batch
+    #[cfg(not(feature = "chaos"))]
+    fn make_response(
+        &mut self,
+        signed: &SignedBatch,
+        leaf: usize,
+        _src_addr: &SocketAddr,
+    ) -> RtMessage {
+        let paths = self.batch.paths(leaf);
+        self.responder.make_response(&signed.srep, &paths, leaf as u32)
+    }
+
+    // The response to the request at `leaf` of the signed batch, with a fault injected if
+    // one is chosen
+    #[cfg(feature = "chaos")]
+    fn make_response(
+        &mut self,
+        signed: &SignedBatch,
+        leaf: usize,
+        src_addr: &SocketAddr,
+    ) -> RtMessage {
+        let fault = self.fault_injector.as_ref().and_then(|f| f.choose());
+        let mut srep = signed.srep.clone();
+        let mut paths = self.batch.paths(leaf);
+        let mut expired_cert: Option<&[u8]> = None;
+
+        match fault {
+            Some(Fault::SkewedMidpoint) => {
+                // re-sign the batch's own SREP values, with the midpoint moved
+                let values = signed.srep.get_nested(Tag::SREP).unwrap();
+                srep = self.responder.sign_srep(
+                    values.get_u64(Tag::MIDP).unwrap() + chaos::SKEW_SECONDS * 1_000_000,
+                    values.get_u32(Tag::RADI).unwrap(),
+                    &signed.merkle_root,
+                    values.get_u32(Tag::DTAI).ok(),
+                )
+            }
+            Some(Fault::InvalidSignature) => srep = chaos::corrupt_signature(&srep),
+            Some(Fault::TruncatedPath) => paths = chaos::truncate_path(&paths),
+            Some(Fault::ExpiredDelegation) => {
+                expired_cert = self.expired_cert.as_ref().map(Vec::as_slice);
+            }
+            None => (),
+        }
+
+        if let Some(fault) = fault {
+            warn!("Injecting fault '{}' into response to {}", fault, src_addr);
+        }
+
+        match expired_cert {
+            Some(cert) => self.responder.make_response_with_cert(&srep, cert, &paths, leaf as u32),
+            None => self.responder.make_response(&srep, &paths, leaf as u32),
+        }
+    }
+
+    // Encode `resp` and send it to `dest`, returning the number of bytes sent
+    fn send_response(&mut self, resp: &RtMessage, dest: &SocketAddr) -> usize {
+        let resp_len = resp.encoded_size();
+        if self.resp_buf.len() < resp_len {
+            self.resp_buf.resize(resp_len, 0);
+        }
+        resp.encode_into(&mut self.resp_buf).unwrap();
+
+        self.socket
+            .send_to(&self.resp_buf[..resp_len], dest)
+            .expect("send_to failed")
+    }
+
+    // Log (and export) the periodic status update
+    fn report_status(&self) {
+        info!("{}", self.stats);
+        info!(
+            "requests/sec {}, responses/sec {} (1m/5m/15m)",
+            self.stats.request_rate(),
+            self.stats.response_rate()
+        );
+        info!("by protocol version: {}", self.stats.versions_summary());
+
+        let stats = self.delegation_stats();
+        info!(
+            "online key age {}s, delegation expires in {}s, rotations {}",
+            stats.key_age.as_secs(),
+            stats.remaining.as_secs(),
+            stats.rotations
+        );
+        if stats.remaining < EXPIRY_WARNING {
+            warn!(
+                "delegation expires in {} seconds",
+                stats.remaining.as_secs()
+            );
+        }
+
+        #[cfg(feature = "otlp")]
+        {
+            if let Some(ref telemetry) = self.telemetry {
+                telemetry.record_delegation(&stats);
+                telemetry.record_rates(self.stats.request_rate(), self.stats.response_rate());
+            }
+        }
+
+        #[cfg(unix)]
+        {
+            if let Some(ref reporter) = self.stats_reporter {
+                reporter.report(self.stats.responses, self.stats.bad_requests);
+            }
+        }
+    }
+
+    /// Call `f` for every request received, valid or not, that the request filters (see
+    /// [`add_filter`](#method.add_filter)) do not drop; dropped requests are only counted.
+    /// Replaces any previous callback.
+    pub fn on_request<F>(&mut self, f: F)
+    where
+        F: FnMut(&RequestEvent) + 'static,
+    {
+        self.hooks.set_on_request(f);
+    }
+
+    /// Call `f` each time a batch's SREP is signed, before its responses are sent.
+    /// Replaces any previous callback.
+    pub fn on_batch_signed<F>(&mut self, f: F)
+    where
+        F: FnMut(&BatchEvent) + 'static,
+    {
+        self.hooks.set_on_batch_signed(f);
+    }
+
+    /// Call `f` after each response is sent. Replaces any previous callback.
+    pub fn on_response_sent<F>(&mut self, f: F)
+    where
+        F: FnMut(&ResponseEvent) + 'static,
+    {
+        self.hooks.set_on_response_sent(f);
+    }
+
+    ///
+    /// Append `filter` to the chain every request passes through before it is parsed.
+    /// Filters from the configuration (`deny_cidrs`, `allow_cidrs`, `rate_limit`) run first.
+    ///
+    pub fn add_filter(&mut self, filter: Box<RequestFilter>) {
+        self.filters.push(filter);
+    }
+
+    /// Moving averages of requests received per second over 1, 5 and 15 minutes
+    pub fn request_rate(&self) -> &EwmaRate {
+        self.stats.request_rate()
+    }
+
+    /// Moving averages of responses sent per second over 1, 5 and 15 minutes
+    pub fn response_rate(&self) -> &EwmaRate {
+        self.stats.response_rate()
+    }
+
+    /// Request, response and batch counters since startup
+    pub fn stats(&self) -> &ServerStats {
+        &self.stats
+    }
+
+    /// Log the server's keys and the settings it is running with
+    pub fn log_startup(&self) {
+        let public_key = hex::decode(&self.public_key).unwrap();
+
+        info!("Long-term public key    : {}", self.get_public_key());
+        info!("Public key (Base64)     : {}", base64::encode(&public_key));
+        info!("Public key fingerprint  : {}", key::fingerprint(&public_key));
+        if let Some(path) = self.config.public_key_file() {
+            info!("Public key written to   : {}", path);
+        }
+        info!("Online public key       : {}", self.get_online_key());
+        info!("Max response batch size : {}", self.config.batch_size());
+        info!(
+            "Status updates every    : {} seconds",
+            self.config.status_interval().as_secs()
+        );
+        info!(
+            "Server listening on     : {}:{}",
+            self.config.interface(),
+            self.config.port()
+        );
+        info!("Second offset is : {}", self.config.secondsoffset());
+        if let Some(hc_port) = self.config.health_check_port() {
+            info!(
+                "TCP health check        : {}:{} ({})",
+                self.config.interface(),
+                hc_port,
+                self.config.health_check_mode()
+            );
+        }
+
+        if let Some(rc_port) = self.config.readiness_check_port() {
+            info!("TCP readiness check     : {}:{}", self.config.interface(), rc_port);
+        }
+        if self.config.clock_source() != ClockSource::Realtime {
+            info!("Midpoint clock source   : {}", self.config.clock_source());
+        }
+        if let Some(endpoint) = self.config.otlp_endpoint() {
+            info!("OpenTelemetry export to : {}", endpoint);
+        }
+        if let Some(audit_log) = self.config.audit_log() {
+            info!("Audit log               : {}", audit_log);
+        }
+        if self.config.shutdown_delay() > Duration::from_secs(0) {
+            info!("Shutdown delay          : {} seconds", self.config.shutdown_delay().as_secs());
+        }
+        if let Some(user) = self.config.user() {
+            info!("Running as user         : {}", user);
+        }
+        if let Some(group) = self.config.group() {
+            info!("Running as group        : {}", group);
+        }
+        if !self.config.chaos_faults().is_empty() {
+            let faults: Vec<String> =
+                self.config.chaos_faults().iter().map(|f| f.to_string()).collect();
+            warn!(
+                "CHAOS: injecting faults : {} (probability {})",
+                faults.join(", "),
+                self.config.chaos_probability()
+            );
+        }
     }
 
     /// Returns a reference to the server's long-term public key
@@ -369,7 +1181,7 @@ impl Server {
 
     /// Returns a reference to the server's on-line (delegated) key
     pub fn get_online_key(&self) -> &OnlineKey {
-        &self.online_key
+        self.responder.online_key()
     }
 
     /// Returns a reference to the `ServerConfig` this server was configured with
@@ -377,13 +1189,404 @@ impl Server {
         &self.config
     }
 
+    /// Returns the address the server's UDP socket is bound to. Useful when the server
+    /// was configured with port `0` and the OS chose an ephemeral port.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.socket.local_addr().expect("local_addr")
+    }
+
     #[cfg(fuzzing)]
     pub fn send_to_self(&mut self, data: &[u8]) {
-        self.response_counter = 0;
-        self.num_bad_requests = 0;
+        self.stats.reset_counters();
         let res = self
             .fake_client_socket
             .send_to(data, &self.socket.local_addr().unwrap());
         info!("Sent to self: {:?}", res);
     }
 }
+
+// Id of this process when it is one of a supervisor's workers
+#[cfg(unix)]
+fn worker_id() -> Option<String> {
+    std::env::var(crate::supervisor::WORKER_ID_ENV).ok()
+}
+
+#[cfg(not(unix))]
+fn worker_id() -> Option<String> {
+    None
+}
+
+// Bind a UDP socket, with SO_REUSEPORT if `reuse_port` so several processes can share it,
+// sending with a TTL/hop limit of `unicast_hops` if given and restricted to the network
+// device `device` if given
+fn bind_udp(
+    addr: &SocketAddr,
+    reuse_port: bool,
+    unicast_hops: Option<u32>,
+    device: Option<&str>,
+) -> io::Result<UdpSocket> {
+    let builder = match *addr {
+        SocketAddr::V4(_) => UdpBuilder::new_v4()?,
+        SocketAddr::V6(_) => UdpBuilder::new_v6()?,
+    };
+
+    #[cfg(unix)]
+    {
+        use net2::unix::UnixUdpBuilderExt;
+        builder.reuse_port(reuse_port)?;
+    }
+    #[cfg(not(unix))]
+    let _ = reuse_port;
+
+    if let Some(device) = device {
+        bind_to_device(&builder, device)?;
+    }
+
+    let socket = builder.bind(addr)?;
+
+    if let Some(hops) = unicast_hops {
+        match *addr {
+            SocketAddr::V4(_) => socket.set_ttl(hops)?,
+            SocketAddr::V6(_) => socket.set_unicast_hops_v6(hops)?,
+        }
+    }
+
+    UdpSocket::from_socket(socket)
+}
+
+// Restrict the socket to sending and receiving through the network device `device`
+#[cfg(target_os = "linux")]
+fn bind_to_device(builder: &UdpBuilder, device: &str) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let ret = unsafe {
+        libc::setsockopt(
+            builder.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            device.as_ptr() as *const libc::c_void,
+            device.len() as libc::socklen_t,
+        )
+    };
+
+    if ret != 0 {
+        let e = io::Error::last_os_error();
+        return Err(io::Error::new(
+            e.kind(),
+            format!("SO_BINDTODEVICE '{}': {}", device, e),
+        ));
+    }
+
+    Ok(())
+}
+
+// Validation rejects `bind_device` elsewhere
+#[cfg(not(target_os = "linux"))]
+fn bind_to_device(_builder: &UdpBuilder, device: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        format!("cannot bind to device '{}': SO_BINDTODEVICE is Linux only", device),
+    ))
+}
+
+// Bind a TCP listener, with SO_REUSEPORT if `reuse_port` so several processes can share it
+fn bind_tcp(addr: &SocketAddr, reuse_port: bool) -> io::Result<TcpListener> {
+    TcpListener::from_std(bind_std_tcp(addr, reuse_port)?)
+}
+
+// The listening std socket behind `bind_tcp`, for services not driven by mio
+fn bind_std_tcp(addr: &SocketAddr, reuse_port: bool) -> io::Result<std::net::TcpListener> {
+    let builder = match *addr {
+        SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+        SocketAddr::V6(_) => TcpBuilder::new_v6()?,
+    };
+
+    #[cfg(unix)]
+    {
+        use net2::unix::UnixTcpBuilderExt;
+        builder.reuse_address(true)?;
+        builder.reuse_port(reuse_port)?;
+    }
+    #[cfg(not(unix))]
+    let _ = reuse_port;
+
+    builder.bind(addr)?;
+    builder.listen(1024)
+}
+
+// Address to query our own UDP socket at: the loopback address when listening on all interfaces
+fn self_query_addr(local_addr: SocketAddr) -> SocketAddr {
+    let ip = match local_addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+
+    SocketAddr::new(ip, local_addr.port())
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::io::Read;
+    use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+    use std::rc::Rc;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+
+    use std::time::{Duration, Instant};
+
+    use crate::batch::ProtocolVersion;
+    use crate::config::{HealthCheckMode, MemoryConfig};
+    use crate::filter::{RequestFilter, Verdict};
+    use crate::key::{LongTermKey, OnlineKey};
+    use crate::server::Server;
+    use crate::test_support::TestServer;
+    use crate::verify::{create_nonce, make_request, Response};
+    use crate::{RtMessage, Tag};
+
+    #[test]
+    fn ready_after_startup() {
+        let server = Server::new(Box::new(MemoryConfig::new(0)));
+        assert_eq!(server.check_readiness(), Ok(()));
+    }
+
+    #[test]
+    fn delegation_stats_track_rotations() {
+        let mut server = Server::new(Box::new(MemoryConfig::new(0)));
+
+        let stats = server.delegation_stats();
+        assert_eq!(stats.rotations, 0);
+        assert!(stats.remaining > Duration::from_secs(365 * 24 * 60 * 60));
+
+        let online_key = OnlineKey::new();
+        let cert_bytes = LongTermKey::new(&[0x11; 32])
+            .make_cert(&online_key)
+            .encode()
+            .unwrap();
+        server.replace_delegation(online_key, cert_bytes);
+
+        assert_eq!(server.delegation_stats().rotations, 1);
+    }
+
+    #[test]
+    fn hooks_see_requests_batches_and_responses() {
+        let mut server = Server::new(Box::new(MemoryConfig::new(0)));
+        let events = Rc::new(RefCell::new(Vec::new()));
+
+        let log = events.clone();
+        server.on_request(move |e| {
+            let valid = e.nonce.is_some();
+            log.borrow_mut().push(format!("request {}", valid))
+        });
+        let log = events.clone();
+        server.on_batch_signed(move |e| log.borrow_mut().push(format!("batch {}", e.size)));
+        let log = events.clone();
+        server.on_response_sent(move |e| {
+            log.borrow_mut().push(format!("response {}", e.batch))
+        });
+
+        let nonce = create_nonce();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.send_to(&[0u8; 8], server.local_addr()).unwrap();
+        client
+            .send_to(&make_request(&nonce), server.local_addr())
+            .unwrap();
+
+        while events.borrow().len() < 4 {
+            server.process_events();
+        }
+
+        let mut buf = [0u8; 1024];
+        let (len, _) = client.recv_from(&mut buf).unwrap();
+        assert!(Response::from_bytes(&buf[..len], &nonce).is_ok());
+
+        assert_eq!(
+            *events.borrow(),
+            vec!["request false", "request true", "batch 1", "response 0"]
+        );
+        assert_eq!(server.stats().bad_requests, 1);
+        assert_eq!(server.stats().batches, 1);
+    }
+
+    #[test]
+    fn requests_and_responses_are_counted_by_protocol_version() {
+        let mut server = Server::new(Box::new(MemoryConfig::new(0)));
+        let requests = Rc::new(RefCell::new(0));
+
+        let seen = requests.clone();
+        server.on_request(move |_| *seen.borrow_mut() += 1);
+
+        // an IETF draft-12 request: framed, with VER, NONC and padding
+        let mut msg = RtMessage::new(3);
+        msg.add_field(Tag::VER, &0x8000_000cu32.to_le_bytes()).unwrap();
+        msg.add_field(Tag::NONC, &[0x42; 32]).unwrap();
+        msg.add_field(Tag::ZZZZ, &[0u8; 952]).unwrap();
+        let body = msg.encode().unwrap();
+        let mut ietf = b"ROUGHTIM".to_vec();
+        ietf.extend(&(body.len() as u32).to_le_bytes());
+        ietf.extend(body);
+
+        let nonce = create_nonce();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.send_to(&ietf, server.local_addr()).unwrap();
+        client
+            .send_to(&make_request(&nonce), server.local_addr())
+            .unwrap();
+
+        while *requests.borrow() < 2 || server.stats().responses < 1 {
+            server.process_events();
+        }
+
+        let mut buf = [0u8; 1024];
+        client.recv_from(&mut buf).unwrap();
+
+        let versions = server.stats().versions();
+        let draft = &versions[&ProtocolVersion::Ietf(Some(0x8000_000c))];
+        assert_eq!((draft.requests, draft.responses), (1, 0));
+        let classic = &versions[&ProtocolVersion::Classic];
+        assert_eq!((classic.requests, classic.responses), (1, 1));
+    }
+
+    struct DropShortPackets;
+
+    impl RequestFilter for DropShortPackets {
+        fn name(&self) -> &str {
+            "drop-short-packets"
+        }
+
+        fn check(&mut self, _source: &SocketAddr, packet: &[u8], _now: Instant) -> Verdict {
+            if packet.len() < 16 {
+                Verdict::Drop
+            } else {
+                Verdict::Deprioritize
+            }
+        }
+    }
+
+    #[test]
+    fn filters_drop_and_deprioritize_requests() {
+        let mut server = Server::new(Box::new(MemoryConfig::new(0)));
+        server.add_filter(Box::new(DropShortPackets));
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let log = events.clone();
+        server.on_request(move |e| {
+            let valid = e.nonce.is_some();
+            log.borrow_mut().push(format!("request {}", valid))
+        });
+        let log = events.clone();
+        server.on_batch_signed(move |e| log.borrow_mut().push(format!("batch {}", e.size)));
+
+        let nonce = create_nonce();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.send_to(&[0u8; 8], server.local_addr()).unwrap();
+        client
+            .send_to(&make_request(&nonce), server.local_addr())
+            .unwrap();
+
+        while events.borrow().len() < 2 {
+            server.process_events();
+        }
+
+        // the deprioritized request is answered because the server is keeping up
+        let mut buf = [0u8; 1024];
+        let (len, _) = client.recv_from(&mut buf).unwrap();
+        assert!(Response::from_bytes(&buf[..len], &nonce).is_ok());
+
+        assert_eq!(*events.borrow(), vec!["request true", "batch 1"]);
+        assert_eq!(server.stats().filtered_requests, 1);
+        assert_eq!(server.stats().responses, 1);
+    }
+
+    #[test]
+    fn requests_are_answered_during_shutdown_delay() {
+        let config = MemoryConfig::builder()
+            .shutdown_delay(Duration::from_millis(500))
+            .build();
+        let mut server = Server::new(Box::new(config));
+        server.get_keep_running().store(false, Ordering::Release);
+
+        assert!(!server.process_events());
+        assert_eq!(server.check_readiness(), Err("shutting down".to_string()));
+
+        let nonce = create_nonce();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+            .send_to(&make_request(&nonce), server.local_addr())
+            .unwrap();
+
+        while server.stats().responses == 0 {
+            assert!(!server.process_events());
+        }
+
+        let mut buf = [0u8; 1024];
+        let (len, _) = client.recv_from(&mut buf).unwrap();
+        assert!(Response::from_bytes(&buf[..len], &nonce).is_ok());
+
+        thread::sleep(Duration::from_millis(500));
+        assert!(server.process_events());
+    }
+
+    #[test]
+    fn duplicate_nonces_are_answered() {
+        let server = TestServer::start(MemoryConfig::new(0));
+        let nonce = create_nonce();
+        let request = make_request(&nonce);
+
+        let sockets: Vec<UdpSocket> = (0..3)
+            .map(|_| UdpSocket::bind("127.0.0.1:0").unwrap())
+            .collect();
+
+        for socket in &sockets {
+            socket
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            socket.send_to(&request, server.addr()).unwrap();
+        }
+
+        for socket in &sockets {
+            let mut buf = [0u8; 1024];
+            let (len, _) = socket.recv_from(&mut buf).unwrap();
+            let response = Response::from_bytes(&buf[..len], &nonce).unwrap();
+
+            assert!(response.verify(server.public_key()).is_ok());
+        }
+    }
+
+    #[test]
+    fn self_query_health_check_verifies_response() {
+        let hc_port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let config = MemoryConfig::builder()
+            .health_check_port(Some(hc_port))
+            .health_check_mode(HealthCheckMode::SelfQuery)
+            .build();
+        let server = TestServer::start(config);
+
+        let mut response = String::new();
+        TcpStream::connect(("127.0.0.1", hc_port))
+            .unwrap()
+            .read_to_string(&mut response)
+            .unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        server.shutdown();
+    }
+}
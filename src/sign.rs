@@ -59,57 +59,185 @@ impl<'a> Verifier<'a> {
     }
 }
 
-/// A multi-step (init-update-finish) interface for creating an Ed25519 signature
-pub struct Signer {
+/// Verifies many Ed25519 signatures at once.
+///
+/// With the `batchverify` feature, all signatures are first checked together by a
+/// single ed25519-dalek batch verification; if it passes, every signature is accepted.
+/// Otherwise (and always without the feature) each signature is checked individually
+/// with [`Verifier`](struct.Verifier.html), which also identifies the invalid one.
+///
+/// The batch equation is cofactored while ring's single check is cofactorless, so a
+/// batch can accept a signature ring would reject. Such signatures differ only by a
+/// small-order component, which only the holder of the private key can add; they do not
+/// allow forgeries, but a build with `batchverify` may accept them where one without
+/// it does not.
+#[derive(Debug, Default)]
+pub struct BatchVerifier {
+    entries: Vec<BatchEntry>,
+}
+
+#[derive(Debug)]
+struct BatchEntry {
+    pubkey: Vec<u8>,
+    message: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl BatchVerifier {
+    pub fn new() -> Self {
+        BatchVerifier {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Queue verification of `signature` over `message` by `pubkey`. Returns the index
+    /// of this entry, as used by the result of `verify()`.
+    pub fn add(&mut self, pubkey: &[u8], message: &[u8], signature: &[u8]) -> usize {
+        self.entries.push(BatchEntry {
+            pubkey: pubkey.to_vec(),
+            message: message.to_vec(),
+            signature: signature.to_vec(),
+        });
+
+        self.entries.len() - 1
+    }
+
+    /// Number of signatures queued for verification
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no signatures are queued
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Verify all queued signatures. Returns `Ok(())` if every signature is valid,
+    /// otherwise `Err(index)` of the first invalid entry.
+    pub fn verify(&self) -> Result<(), usize> {
+        if self.verify_batch() {
+            return Ok(());
+        }
+
+        for (idx, entry) in self.entries.iter().enumerate() {
+            let mut verifier = Verifier::new(&entry.pubkey);
+            verifier.update(&entry.message);
+
+            if !verifier.verify(&entry.signature) {
+                return Err(idx);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "batchverify")]
+    fn verify_batch(&self) -> bool {
+        use ed25519_dalek::{PublicKey, Signature};
+        use std::convert::TryFrom;
+
+        let mut messages = Vec::with_capacity(self.entries.len());
+        let mut signatures = Vec::with_capacity(self.entries.len());
+        let mut pubkeys = Vec::with_capacity(self.entries.len());
+
+        for entry in &self.entries {
+            match (
+                PublicKey::from_bytes(&entry.pubkey),
+                Signature::try_from(entry.signature.as_slice()),
+            ) {
+                (Ok(pubkey), Ok(signature)) => {
+                    messages.push(entry.message.as_slice());
+                    signatures.push(signature);
+                    pubkeys.push(pubkey);
+                }
+                _ => return false,
+            }
+        }
+
+        if ed25519_dalek::verify_batch(&messages, &signatures, &pubkeys).is_ok() {
+            true
+        } else {
+            debug!("batch of {} signatures failed, finding the invalid one", self.len());
+            false
+        }
+    }
+
+    // Without batch support every signature is checked on its own
+    #[cfg(not(feature = "batchverify"))]
+    fn verify_batch(&self) -> bool {
+        false
+    }
+}
+
+/// A multi-step (init-update-finish) interface for creating an Ed25519 signature.
+///
+/// [`RingSigner`](struct.RingSigner.html) holds the private key in memory. Other
+/// implementations can delegate signing to an external system such as an HSM or a KMS
+/// asymmetric key so the private key never resides in the server's memory.
+pub trait Signer {
+    /// Append `data` to the message to be signed
+    fn update(&mut self, data: &[u8]);
+
+    /// Sign the accumulated message and reset for the next message
+    fn sign(&mut self) -> Vec<u8>;
+
+    /// The Ed25519 public key corresponding to this signer's private key
+    fn public_key_bytes(&self) -> &[u8];
+}
+
+/// A [`Signer`](trait.Signer.html) using an in-memory Ed25519 key pair and *ring*.
+pub struct RingSigner {
     key_pair: Ed25519KeyPair,
     buf: Vec<u8>,
 }
 
-impl Signer {
+impl RingSigner {
     pub fn new() -> Self {
         let rng = rand::SystemRandom::new();
         let mut seed = [0u8; 32];
         rng.fill(&mut seed).unwrap();
 
-        Signer::from_seed(&seed)
+        RingSigner::from_seed(&seed)
     }
 
     pub fn from_seed(seed: &[u8]) -> Self {
         let seed_input = Input::from(seed);
-        Signer {
+        RingSigner {
             key_pair: Ed25519KeyPair::from_seed_unchecked(seed_input).unwrap(),
             buf: Vec::with_capacity(INITIAL_BUF_SIZE),
         }
     }
+}
 
-    pub fn update(&mut self, data: &[u8]) {
+impl Signer for RingSigner {
+    fn update(&mut self, data: &[u8]) {
         self.buf.reserve(data.len());
         self.buf.extend_from_slice(data);
     }
 
-    pub fn sign(&mut self) -> Vec<u8> {
+    fn sign(&mut self) -> Vec<u8> {
         let signature = self.key_pair.sign(&self.buf).as_ref().to_vec();
         self.buf.clear();
 
         signature
     }
 
-    pub fn public_key_bytes(&self) -> &[u8] {
+    fn public_key_bytes(&self) -> &[u8] {
         self.key_pair.public_key_bytes()
     }
 }
 
-impl fmt::Display for Signer {
+impl fmt::Display for RingSigner {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(f, "{}", hex::encode(self.public_key_bytes()))
     }
 }
 
-impl fmt::Debug for Signer {
+impl fmt::Debug for RingSigner {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(
             f,
-            "Signer({}, {:?})",
+            "RingSigner({}, {:?})",
             hex::encode(self.public_key_bytes()),
             self.buf
         )
@@ -163,7 +291,7 @@ mod test {
             "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
         ).unwrap();
 
-        let mut s = Signer::from_seed(&seed);
+        let mut s = RingSigner::from_seed(&seed);
         let sig = s.sign();
         assert_eq!(sig, expected_sig);
     }
@@ -179,12 +307,36 @@ mod test {
             "d9868d52c2bebce5f3fa5a79891970f309cb6591e3e1702a70276fa97c24b3a8e58606c38c9758529da50ee31b8219cba45271c689afa60b0ea26c99db19b00c"
         ).unwrap();
 
-        let mut s = Signer::from_seed(&seed);
+        let mut s = RingSigner::from_seed(&seed);
         s.update(&message);
         let sig = s.sign();
         assert_eq!(sig, expected_sig);
     }
 
+    #[test]
+    fn batch_verify_identifies_bad_signature() {
+        let mut batch = BatchVerifier::new();
+        let mut signers: Vec<RingSigner> =
+            (0..4u8).map(|i| RingSigner::from_seed(&[i; 32])).collect();
+
+        for (i, signer) in signers.iter_mut().enumerate() {
+            let message = vec![i as u8; 10];
+            signer.update(&message);
+            let signature = signer.sign();
+            batch.add(signer.public_key_bytes(), &message, &signature);
+        }
+
+        assert_eq!(batch.len(), 4);
+        assert_eq!(batch.verify(), Ok(()));
+
+        // signature by signer #0 over a message it did not sign
+        let mut bad_sig = signers[0].sign();
+        bad_sig[0] ^= 0x01;
+        let idx = batch.add(signers[0].public_key_bytes(), b"oops", &bad_sig);
+
+        assert_eq!(batch.verify(), Err(idx));
+    }
+
     #[test]
     fn sign_verify_round_trip() {
         let seed = hex::decode("334a05b07352a5436e180356da0ae6efa0345ff7fb1572575772e8005ed978e9")
@@ -192,7 +344,7 @@ mod test {
 
         let message = "Hello world".as_bytes();
 
-        let mut signer = Signer::from_seed(&seed);
+        let mut signer = RingSigner::from_seed(&seed);
         signer.update(&message);
         let signature = signer.sign();
 
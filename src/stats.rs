@@ -0,0 +1,196 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Request and response counters reported in the server's status updates.
+//!
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Instant;
+
+use crate::batch::ProtocolVersion;
+use crate::rate::EwmaRate;
+
+/// Requests received and responses sent in one protocol version
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VersionCounts {
+    pub requests: u64,
+    pub responses: u64,
+}
+
+///
+/// Running totals of a server's requests, responses and batches, with moving averages
+/// of the request and response rates.
+///
+#[derive(Debug, Clone)]
+pub struct ServerStats {
+    /// Responses sent
+    pub responses: u64,
+    /// Batches signed
+    pub batches: u64,
+    /// Requests that could not be parsed
+    pub bad_requests: u64,
+    /// Requests dropped or shed by the request filters
+    pub filtered_requests: u64,
+
+    // parsed requests and their responses by protocol version; filtered requests are not
+    // parsed so are not counted
+    versions: BTreeMap<ProtocolVersion, VersionCounts>,
+    request_rate: EwmaRate,
+    response_rate: EwmaRate,
+}
+
+impl ServerStats {
+    /// All counters zero, with rates measured from `now`
+    pub fn new(now: Instant) -> ServerStats {
+        ServerStats {
+            responses: 0,
+            batches: 0,
+            bad_requests: 0,
+            filtered_requests: 0,
+            versions: BTreeMap::new(),
+            request_rate: EwmaRate::new(0, now),
+            response_rate: EwmaRate::new(0, now),
+        }
+    }
+
+    /// Every request received: answered, invalid or filtered
+    pub fn requests(&self) -> u64 {
+        self.responses + self.bad_requests + self.filtered_requests
+    }
+
+    /// Count a request received in `version`
+    pub fn count_request(&mut self, version: ProtocolVersion) {
+        self.versions.entry(version).or_default().requests += 1;
+    }
+
+    /// Count a response sent in `version`
+    pub fn count_response(&mut self, version: ProtocolVersion) {
+        self.versions.entry(version).or_default().responses += 1;
+    }
+
+    /// Requests and responses by protocol version, oldest version first
+    pub fn versions(&self) -> &BTreeMap<ProtocolVersion, VersionCounts> {
+        &self.versions
+    }
+
+    ///
+    /// The per-version counts as one line, e.g.
+    /// `classic 120/120, ietf draft-12 8/0 (requests/responses)`
+    ///
+    pub fn versions_summary(&self) -> String {
+        if self.versions.is_empty() {
+            return "no requests".to_string();
+        }
+
+        let counts: Vec<String> = self
+            .versions
+            .iter()
+            .map(|(version, c)| format!("{} {}/{}", version, c.requests, c.responses))
+            .collect();
+
+        format!("{} (requests/responses)", counts.join(", "))
+    }
+
+    /// Fold the current totals into the moving averages
+    pub fn update_rates(&mut self, now: Instant) {
+        let requests = self.requests();
+        self.request_rate.update(requests, now);
+        self.response_rate.update(self.responses, now);
+    }
+
+    /// Moving averages of requests received per second over 1, 5 and 15 minutes
+    pub fn request_rate(&self) -> &EwmaRate {
+        &self.request_rate
+    }
+
+    /// Moving averages of responses sent per second over 1, 5 and 15 minutes
+    pub fn response_rate(&self) -> &EwmaRate {
+        &self.response_rate
+    }
+
+    /// Zero the counters, keeping the rates' history
+    pub fn reset_counters(&mut self) {
+        self.responses = 0;
+        self.batches = 0;
+        self.bad_requests = 0;
+        self.filtered_requests = 0;
+        self.versions.clear();
+    }
+}
+
+impl fmt::Display for ServerStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "responses {}, invalid requests {}, filtered requests {}",
+            self.responses, self.bad_requests, self.filtered_requests
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::batch::ProtocolVersion;
+    use crate::stats::{ServerStats, VersionCounts};
+
+    #[test]
+    fn requests_include_invalid_and_filtered() {
+        let start = Instant::now();
+        let mut stats = ServerStats::new(start);
+        stats.responses = 6;
+        stats.bad_requests = 3;
+        stats.filtered_requests = 1;
+
+        assert_eq!(stats.requests(), 10);
+        assert_eq!(
+            stats.to_string(),
+            "responses 6, invalid requests 3, filtered requests 1"
+        );
+
+        stats.update_rates(start + Duration::from_secs(60));
+        assert!(stats.request_rate().one_minute() > stats.response_rate().one_minute());
+
+        stats.reset_counters();
+        assert_eq!(stats.requests(), 0);
+    }
+
+    #[test]
+    fn counts_by_protocol_version() {
+        let mut stats = ServerStats::new(Instant::now());
+        assert_eq!(stats.versions_summary(), "no requests");
+
+        let draft = ProtocolVersion::Ietf(Some(0x8000_000c));
+        stats.count_request(draft);
+        stats.count_request(ProtocolVersion::Classic);
+        stats.count_response(ProtocolVersion::Classic);
+        stats.count_request(draft);
+
+        let expected = VersionCounts {
+            requests: 2,
+            responses: 0,
+        };
+        assert_eq!(stats.versions()[&draft], expected);
+        assert_eq!(
+            stats.versions_summary(),
+            "classic 1/1, ietf draft-12 2/0 (requests/responses)"
+        );
+
+        stats.reset_counters();
+        assert!(stats.versions().is_empty());
+    }
+}
@@ -0,0 +1,394 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Multi-process mode: a supervisor runs `workers` copies of the server, all bound to the
+//! same port with `SO_REUSEPORT`, and restarts any that exit.
+//!
+//! Workers are the server binary re-executed with the supervisor's arguments and
+//! `ROUGHENOUGH_WORKER_ID` set in their environment. Each worker periodically sends its
+//! counters to the supervisor over a Unix datagram socket; the supervisor logs the totals.
+//!
+
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::Error;
+
+/// Environment variable holding a worker's id; its presence marks a process as a worker
+pub const WORKER_ID_ENV: &str = "ROUGHENOUGH_WORKER_ID";
+
+/// Environment variable holding the path of the supervisor's stats socket
+pub const SUPERVISOR_SOCKET_ENV: &str = "ROUGHENOUGH_SUPERVISOR_SOCKET";
+
+/// Delay before restarting a worker that exited
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest delay before restarting a repeatedly failing worker
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A worker that ran at least this long before exiting is restarted after `MIN_BACKOFF`
+const STABLE_RUN: Duration = Duration::from_secs(60);
+
+/// How often the supervisor checks on its workers
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const STATS_LENGTH: usize = 20;
+
+/// Returns true if this process was started by a supervisor as one of its workers
+pub fn is_worker() -> bool {
+    env::var(WORKER_ID_ENV).is_ok()
+}
+
+/// Counters a worker reports to its supervisor
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct WorkerStats {
+    /// Id (0 to `workers - 1`) of the reporting worker
+    pub worker: u32,
+    /// Responses sent since the worker started
+    pub responses: u64,
+    /// Invalid requests received since the worker started
+    pub bad_requests: u64,
+}
+
+impl WorkerStats {
+    fn encode(&self) -> [u8; STATS_LENGTH] {
+        let mut buf = [0u8; STATS_LENGTH];
+        LittleEndian::write_u32(&mut buf[0..4], self.worker);
+        LittleEndian::write_u64(&mut buf[4..12], self.responses);
+        LittleEndian::write_u64(&mut buf[12..20], self.bad_requests);
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Option<WorkerStats> {
+        if buf.len() != STATS_LENGTH {
+            return None;
+        }
+
+        Some(WorkerStats {
+            worker: LittleEndian::read_u32(&buf[0..4]),
+            responses: LittleEndian::read_u64(&buf[4..12]),
+            bad_requests: LittleEndian::read_u64(&buf[12..20]),
+        })
+    }
+}
+
+///
+/// Sends a worker's counters to its supervisor.
+///
+pub struct StatsReporter {
+    socket: UnixDatagram,
+    target: PathBuf,
+    worker: u32,
+}
+
+impl StatsReporter {
+    /// A reporter for this process if it is a supervised worker, otherwise `None`
+    pub fn from_env() -> Option<StatsReporter> {
+        let worker = env::var(WORKER_ID_ENV).ok()?.parse().ok()?;
+        let target = env::var(SUPERVISOR_SOCKET_ENV).ok()?;
+
+        StatsReporter::new(worker, target).ok()
+    }
+
+    fn new<P: AsRef<Path>>(worker: u32, target: P) -> Result<StatsReporter, Error> {
+        Ok(StatsReporter {
+            socket: UnixDatagram::unbound()?,
+            target: target.as_ref().to_path_buf(),
+            worker,
+        })
+    }
+
+    /// Send the worker's cumulative counters. Failures are logged and otherwise ignored.
+    pub fn report(&self, responses: u64, bad_requests: u64) {
+        let stats = WorkerStats {
+            worker: self.worker,
+            responses,
+            bad_requests,
+        };
+
+        if let Err(e) = self.socket.send_to(&stats.encode(), &self.target) {
+            warn!("Failed to report stats to supervisor: {}", e);
+        }
+    }
+}
+
+// Delay before restarting a worker that exited after running for `ran_for`
+fn next_backoff(previous: Duration, ran_for: Duration) -> Duration {
+    if ran_for >= STABLE_RUN {
+        MIN_BACKOFF
+    } else {
+        (previous * 2).max(MIN_BACKOFF).min(MAX_BACKOFF)
+    }
+}
+
+struct Worker {
+    id: u32,
+    child: Option<Child>,
+    started: Instant,
+    backoff: Duration,
+    restart_at: Option<Instant>,
+    stats: WorkerStats,
+}
+
+///
+/// Starts, monitors and restarts worker processes.
+///
+pub struct Supervisor {
+    program: PathBuf,
+    args: Vec<String>,
+    workers: Vec<Worker>,
+    socket: UnixDatagram,
+    socket_path: PathBuf,
+    // counters of workers that have since exited
+    retired: WorkerStats,
+    restarts: u64,
+}
+
+impl Supervisor {
+    ///
+    /// A supervisor for `num_workers` copies of the current executable, each run with `args`.
+    ///
+    pub fn new(num_workers: u32, args: &[String]) -> Result<Supervisor, Error> {
+        let program = env::current_exe()?;
+
+        let socket_path =
+            env::temp_dir().join(format!("roughenough-supervisor-{}.sock", process::id()));
+        let _ = fs::remove_file(&socket_path);
+        let socket = UnixDatagram::bind(&socket_path)?;
+        socket.set_nonblocking(true)?;
+
+        let workers = (0..num_workers)
+            .map(|id| Worker {
+                id,
+                child: None,
+                started: Instant::now(),
+                backoff: Duration::from_secs(0),
+                restart_at: Some(Instant::now()),
+                stats: WorkerStats {
+                    worker: id,
+                    ..WorkerStats::default()
+                },
+            })
+            .collect();
+
+        Ok(Supervisor {
+            program,
+            args: args.to_vec(),
+            workers,
+            socket,
+            socket_path,
+            retired: WorkerStats::default(),
+            restarts: 0,
+        })
+    }
+
+    ///
+    /// Run the workers until `keep_running` becomes false, then stop them and return.
+    /// Aggregated counters are logged every `status_interval`.
+    ///
+    pub fn run(&mut self, keep_running: &Arc<AtomicBool>, status_interval: Duration) {
+        let mut next_status = Instant::now() + status_interval;
+
+        while keep_running.load(Ordering::Acquire) {
+            self.receive_stats();
+            self.reap_workers();
+            self.start_workers();
+
+            if Instant::now() >= next_status {
+                self.log_status();
+                next_status = Instant::now() + status_interval;
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        self.stop_workers();
+    }
+
+    fn receive_stats(&mut self) {
+        let mut buf = [0u8; STATS_LENGTH];
+
+        loop {
+            match self.socket.recv(&mut buf) {
+                Ok(len) => {
+                    if let Some(stats) = WorkerStats::decode(&buf[..len]) {
+                        if let Some(worker) = self.workers.get_mut(stats.worker as usize) {
+                            worker.stats = stats;
+                        }
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    warn!("Failed to receive worker stats: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn reap_workers(&mut self) {
+        for worker in &mut self.workers {
+            let status = match worker.child.as_mut().map(Child::try_wait) {
+                Some(Ok(Some(status))) => status,
+                _ => continue,
+            };
+
+            let ran_for = worker.started.elapsed();
+            worker.backoff = next_backoff(worker.backoff, ran_for);
+            worker.restart_at = Some(Instant::now() + worker.backoff);
+            worker.child = None;
+            self.restarts += 1;
+
+            self.retired.responses += worker.stats.responses;
+            self.retired.bad_requests += worker.stats.bad_requests;
+            worker.stats.responses = 0;
+            worker.stats.bad_requests = 0;
+
+            error!(
+                "Worker {} exited ({}) after {}s; restarting in {}s",
+                worker.id,
+                status,
+                ran_for.as_secs(),
+                worker.backoff.as_secs()
+            );
+        }
+    }
+
+    fn start_workers(&mut self) {
+        let now = Instant::now();
+
+        for worker in &mut self.workers {
+            match worker.restart_at {
+                Some(at) if at <= now => (),
+                _ => continue,
+            }
+
+            let result = Command::new(&self.program)
+                .args(&self.args)
+                .env(WORKER_ID_ENV, worker.id.to_string())
+                .env(SUPERVISOR_SOCKET_ENV, &self.socket_path)
+                .spawn();
+
+            match result {
+                Ok(child) => {
+                    info!("Started worker {} (pid {})", worker.id, child.id());
+                    worker.child = Some(child);
+                    worker.started = now;
+                    worker.restart_at = None;
+                }
+                Err(e) => {
+                    worker.backoff = next_backoff(worker.backoff, Duration::from_secs(0));
+                    worker.restart_at = Some(now + worker.backoff);
+                    error!("Failed to start worker {}: {}", worker.id, e);
+                }
+            }
+        }
+    }
+
+    fn log_status(&self) {
+        let running = self.workers.iter().filter(|w| w.child.is_some()).count();
+        let (responses, bad_requests) = self
+            .workers
+            .iter()
+            .fold((self.retired.responses, self.retired.bad_requests), |acc, w| {
+                (acc.0 + w.stats.responses, acc.1 + w.stats.bad_requests)
+            });
+
+        info!(
+            "workers {}/{} running, restarts {}, responses {}, invalid requests {}",
+            running,
+            self.workers.len(),
+            self.restarts,
+            responses,
+            bad_requests
+        );
+    }
+
+    fn stop_workers(&mut self) {
+        for worker in &mut self.workers {
+            if let Some(ref mut child) = worker.child {
+                unsafe {
+                    libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+                }
+                if let Err(e) = child.wait() {
+                    warn!("Failed waiting for worker {}: {}", worker.id, e);
+                }
+            }
+            worker.child = None;
+        }
+
+        let _ = fs::remove_file(&self.socket_path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::supervisor::{
+        next_backoff, StatsReporter, WorkerStats, MAX_BACKOFF, MIN_BACKOFF, STABLE_RUN,
+    };
+    use std::os::unix::net::UnixDatagram;
+    use std::time::Duration;
+
+    #[test]
+    fn stats_roundtrip() {
+        let stats = WorkerStats {
+            worker: 3,
+            responses: 123_456,
+            bad_requests: 7,
+        };
+
+        assert_eq!(WorkerStats::decode(&stats.encode()), Some(stats));
+        assert_eq!(WorkerStats::decode(&[0u8; 3]), None);
+    }
+
+    #[test]
+    fn backoff_doubles_then_caps_and_resets() {
+        let quick = Duration::from_secs(1);
+
+        assert_eq!(next_backoff(Duration::from_secs(0), quick), MIN_BACKOFF);
+        assert_eq!(next_backoff(MIN_BACKOFF, quick), MIN_BACKOFF * 2);
+        assert_eq!(next_backoff(MAX_BACKOFF, quick), MAX_BACKOFF);
+        assert_eq!(next_backoff(MAX_BACKOFF, STABLE_RUN), MIN_BACKOFF);
+    }
+
+    #[test]
+    fn reporter_reaches_supervisor_socket() {
+        let path = std::env::temp_dir().join(format!("roughenough-stats-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        StatsReporter::new(2, &path).unwrap().report(10, 1);
+
+        let mut buf = [0u8; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        let stats = WorkerStats::decode(&buf[..len]).unwrap();
+
+        assert_eq!(stats.worker, 2);
+        assert_eq!(stats.responses, 10);
+        assert_eq!(stats.bad_requests, 1);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
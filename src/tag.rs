@@ -12,39 +12,104 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::RwLock;
+
 use crate::error::Error;
 
 /// An unsigned 32-bit value (key) that maps to a byte-string (value).
-#[derive(Debug, PartialEq, Eq, PartialOrd, Hash, Clone, Copy)]
+///
+/// Includes the tags of both the original Google Roughtime protocol and the IETF
+/// Roughtime drafts. Experimental tags can be added at runtime with
+/// [`Tag::register`](#method.register).
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Tag {
     // Enforcement of the "tags in strictly increasing order" rule is done using the
     // little-endian encoding of the ASCII tag value; e.g. 'SIG\x00' is 0x00474953 and
-    // 'NONC' is 0x434e4f4e.
+    // 'NONC' is 0x434e4f4e. See `wire_u32()` and the `Ord` implementation below.
     //
     // Tags are written here in ascending order
     SIG,
+    VER,
+    SRV,
+    DUT1,
     NONC,
     DELE,
     PATH,
+    DTAI,
     RADI,
     PUBK,
+    LEAP,
     MIDP,
     SREP,
+    VERS,
     MINT,
     ROOT,
     CERT,
     MAXT,
     INDX,
+    ZZZZ,
     PAD,
+
+    /// A private-use tag added with [`Tag::register`](#method.register). Its position
+    /// among the tags above is determined by its wire value, like any other tag.
+    Custom(&'static CustomTag),
+}
+
+///
+/// A private-use tag registered by a deployment to carry extension fields, for example
+/// in research or experimental deployments.
+///
+#[derive(PartialEq, Eq, Hash)]
+pub struct CustomTag {
+    wire: [u8; 4],
+    critical: bool,
+}
+
+impl fmt::Debug for CustomTag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.wire))
+    }
 }
 
+// Tags added with `Tag::register()`. Entries are leaked so `Tag` can stay `Copy` and
+// `wire_value()` can keep returning `'static` bytes; registration is rare and bounded.
+lazy_static! {
+    static ref CUSTOM_TAGS: RwLock<Vec<&'static CustomTag>> = RwLock::new(Vec::new());
+}
+
+// The server's configured `instance_id`. Private-use (trailing 0xff) and non-critical, so
+// other clients skip it; it is known here so our own clients parse it without registering.
+static INSTANCE_ID: CustomTag = CustomTag {
+    wire: *b"IID\xff",
+    critical: false,
+};
+
+// The experimental post-quantum public key (in the DELE) and signature of the SREP (in the
+// response). Like `IID\xff` they are known statically; see the `pq` module.
+static PQ_PUBLIC_KEY: CustomTag = CustomTag {
+    wire: *b"PQK\xff",
+    critical: false,
+};
+static PQ_SIGNATURE: CustomTag = CustomTag {
+    wire: *b"PQS\xff",
+    critical: false,
+};
+
+// Private-use tags every build of Roughenough knows without registering them
+static PREDEFINED_TAGS: [&CustomTag; 3] = [&INSTANCE_ID, &PQ_PUBLIC_KEY, &PQ_SIGNATURE];
+
 impl Tag {
     /// Translates a tag into its on-the-wire representation
     pub fn wire_value(self) -> &'static [u8] {
         match self {
             Tag::CERT => b"CERT",
             Tag::DELE => b"DELE",
+            Tag::DTAI => b"DTAI",
+            Tag::DUT1 => b"DUT1",
             Tag::INDX => b"INDX",
+            Tag::LEAP => b"LEAP",
             Tag::MAXT => b"MAXT",
             Tag::MIDP => b"MIDP",
             Tag::MINT => b"MINT",
@@ -56,16 +121,35 @@ impl Tag {
             Tag::ROOT => b"ROOT",
             Tag::SIG => b"SIG\x00",
             Tag::SREP => b"SREP",
+            Tag::SRV => b"SRV\x00",
+            Tag::VER => b"VER\x00",
+            Tag::VERS => b"VERS",
+            Tag::ZZZZ => b"ZZZZ",
+            Tag::Custom(custom) => &custom.wire,
         }
     }
 
+    /// The on-the-wire representation of this tag interpreted as a little-endian `u32`.
+    /// Tags in a message must appear in strictly increasing order of this value.
+    pub fn wire_u32(self) -> u32 {
+        let bytes = self.wire_value();
+
+        u32::from(bytes[0])
+            | u32::from(bytes[1]) << 8
+            | u32::from(bytes[2]) << 16
+            | u32::from(bytes[3]) << 24
+    }
+
     /// Return the `Tag` corresponding to the on-the-wire representation in `bytes` or an
     /// `Error::InvalidTag` if `bytes` do not correspond to a valid tag.
     pub fn from_wire(bytes: &[u8]) -> Result<Self, Error> {
         match bytes {
             b"CERT" => Ok(Tag::CERT),
             b"DELE" => Ok(Tag::DELE),
+            b"DTAI" => Ok(Tag::DTAI),
+            b"DUT1" => Ok(Tag::DUT1),
             b"INDX" => Ok(Tag::INDX),
+            b"LEAP" => Ok(Tag::LEAP),
             b"MAXT" => Ok(Tag::MAXT),
             b"MIDP" => Ok(Tag::MIDP),
             b"MINT" => Ok(Tag::MINT),
@@ -77,7 +161,256 @@ impl Tag {
             b"ROOT" => Ok(Tag::ROOT),
             b"SIG\x00" => Ok(Tag::SIG),
             b"SREP" => Ok(Tag::SREP),
-            _ => Err(Error::InvalidTag(Box::from(bytes))),
+            b"SRV\x00" => Ok(Tag::SRV),
+            b"VER\x00" => Ok(Tag::VER),
+            b"VERS" => Ok(Tag::VERS),
+            b"ZZZZ" => Ok(Tag::ZZZZ),
+            b"IID\xff" => Ok(Tag::instance_id()),
+            b"PQK\xff" => Ok(Tag::pq_public_key()),
+            b"PQS\xff" => Ok(Tag::pq_signature()),
+            _ => Tag::find_custom(bytes).ok_or_else(|| Error::InvalidTag(Box::from(bytes))),
+        }
+    }
+
+    ///
+    /// Register the private-use tag `wire` (exactly 4 bytes) so it can be added to, and
+    /// parsed from, an `RtMessage` like a built-in tag. `critical` is the tag's
+    /// [`is_critical`](#method.is_critical) value.
+    ///
+    /// Tags in a message are ordered by their wire value read as a little-endian `u32`
+    /// (see [`wire_u32`](#method.wire_u32)): by the last byte first, then the third, the
+    /// second and the first. Choose `wire` for where the field should sort; e.g. a
+    /// trailing `0xff` byte sorts after every built-in tag other than `PAD\xff`, and is
+    /// ordered against `PAD\xff` by its remaining bytes, so `IID\xff` (`0xff444949`)
+    /// sorts after it (`0xff444150`).
+    ///
+    /// Registering the same tag again returns it. Fails if `wire` is not 4 bytes, is a
+    /// built-in tag, or was already registered with a different `critical` value. The
+    /// [`instance_id`](#method.instance_id), [`pq_public_key`](#method.pq_public_key) and
+    /// [`pq_signature`](#method.pq_signature) tags are always registered, as non-critical.
+    ///
+    pub fn register(wire: &[u8], critical: bool) -> Result<Tag, Error> {
+        if wire.len() != 4 {
+            return Err(Error::InvalidTag(Box::from(wire)));
+        }
+
+        match Tag::from_wire(wire) {
+            Ok(Tag::Custom(custom)) if PREDEFINED_TAGS.contains(&custom) => {
+                return if critical {
+                    Err(Error::InvalidTag(Box::from(wire)))
+                } else {
+                    Ok(Tag::Custom(custom))
+                };
+            }
+            Ok(Tag::Custom(_)) | Err(_) => (),
+            Ok(_) => return Err(Error::InvalidTag(Box::from(wire))),
+        }
+
+        let mut registry = CUSTOM_TAGS.write().unwrap();
+
+        if let Some(&existing) = registry.iter().find(|custom| custom.wire == wire) {
+            return if existing.critical == critical {
+                Ok(Tag::Custom(existing))
+            } else {
+                Err(Error::InvalidTag(Box::from(wire)))
+            };
+        }
+
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(wire);
+        let custom: &'static CustomTag = Box::leak(Box::new(CustomTag {
+            wire: bytes,
+            critical,
+        }));
+
+        registry.push(custom);
+        Ok(Tag::Custom(custom))
+    }
+
+    ///
+    /// The private-use tag `IID\xff` in which a server sends its configured `instance_id`,
+    /// outside the signed SREP, to identify the node of an anycast fleet that answered.
+    ///
+    pub fn instance_id() -> Tag {
+        Tag::Custom(&INSTANCE_ID)
+    }
+
+    ///
+    /// The private-use tag `PQK\xff` holding the online key's experimental post-quantum
+    /// public key inside the DELE, where it is certified by the long-term key.
+    ///
+    pub fn pq_public_key() -> Tag {
+        Tag::Custom(&PQ_PUBLIC_KEY)
+    }
+
+    ///
+    /// The private-use tag `PQS\xff` holding a post-quantum signature of the SREP, sent
+    /// next to the Ed25519 `SIG` when the server has `pq_signatures` enabled.
+    ///
+    pub fn pq_signature() -> Tag {
+        Tag::Custom(&PQ_SIGNATURE)
+    }
+
+    fn find_custom(bytes: &[u8]) -> Option<Tag> {
+        CUSTOM_TAGS
+            .read()
+            .unwrap()
+            .iter()
+            .find(|custom| custom.wire == bytes)
+            .map(|&custom| Tag::Custom(custom))
+    }
+
+    /// Returns `true` if a receiver must understand this tag to correctly process a
+    /// message containing it.
+    ///
+    /// Padding (`PAD`, `ZZZZ`) and the informational leap second and UT1 tags
+    /// (`DTAI`, `DUT1`, `LEAP`) are not critical and may be safely ignored.
+    pub fn is_critical(self) -> bool {
+        match self {
+            Tag::PAD | Tag::ZZZZ | Tag::DTAI | Tag::DUT1 | Tag::LEAP => false,
+            Tag::Custom(custom) => custom.critical,
+            _ => true,
         }
     }
 }
+
+impl Ord for Tag {
+    fn cmp(&self, other: &Tag) -> Ordering {
+        self.wire_u32().cmp(&other.wire_u32())
+    }
+}
+
+impl PartialOrd for Tag {
+    fn partial_cmp(&self, other: &Tag) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::message::RtMessage;
+    use crate::tag::Tag;
+
+    const ALL_TAGS: &[Tag] = &[
+        Tag::SIG,
+        Tag::VER,
+        Tag::SRV,
+        Tag::DUT1,
+        Tag::NONC,
+        Tag::DELE,
+        Tag::PATH,
+        Tag::DTAI,
+        Tag::RADI,
+        Tag::PUBK,
+        Tag::LEAP,
+        Tag::MIDP,
+        Tag::SREP,
+        Tag::VERS,
+        Tag::MINT,
+        Tag::ROOT,
+        Tag::CERT,
+        Tag::MAXT,
+        Tag::INDX,
+        Tag::ZZZZ,
+        Tag::PAD,
+    ];
+
+    #[test]
+    fn wire_values_round_trip() {
+        for tag in ALL_TAGS {
+            assert_eq!(Tag::from_wire(tag.wire_value()).unwrap(), *tag);
+        }
+    }
+
+    #[test]
+    fn tags_are_in_strictly_increasing_wire_order() {
+        for pair in ALL_TAGS.windows(2) {
+            assert!(
+                pair[0].wire_u32() < pair[1].wire_u32(),
+                "{:?} should sort before {:?}",
+                pair[0],
+                pair[1]
+            );
+            assert!(pair[0] < pair[1]);
+        }
+    }
+
+    #[test]
+    fn known_wire_u32_values() {
+        assert_eq!(Tag::SIG.wire_u32(), 0x0047_4953);
+        assert_eq!(Tag::NONC.wire_u32(), 0x434e_4f4e);
+        assert_eq!(Tag::PAD.wire_u32(), 0xff44_4150);
+    }
+
+    #[test]
+    fn custom_tags_round_trip() {
+        let ext = Tag::register(b"XEXT", false).unwrap();
+        let late = Tag::register(b"LAT\xfe", true).unwrap();
+
+        assert_eq!(ext.wire_value(), b"XEXT");
+        assert_eq!(Tag::from_wire(b"XEXT").unwrap(), ext);
+        assert!(!ext.is_critical());
+        assert!(late.is_critical());
+
+        // ordered by wire value among the built-in tags
+        assert!(Tag::MAXT < ext && ext < Tag::INDX);
+        assert!(Tag::ZZZZ < late && late < Tag::PAD);
+
+        let mut msg = RtMessage::new(4);
+        msg.add_field(Tag::NONC, &[0x01; 4]).unwrap();
+        msg.add_field(ext, &[0x02; 4]).unwrap();
+        msg.add_field(late, &[0x03; 4]).unwrap();
+        msg.add_field(Tag::PAD, &[0x00; 4]).unwrap();
+
+        let decoded = RtMessage::from_bytes(&msg.encode().unwrap()).unwrap();
+        assert_eq!(decoded.tags(), &[Tag::NONC, ext, late, Tag::PAD]);
+        assert_eq!(decoded.get_field(ext).unwrap(), &[0x02; 4]);
+    }
+
+    #[test]
+    fn custom_tag_registration_is_checked() {
+        assert!(Tag::register(b"NONC", false).is_err());
+        assert!(Tag::register(b"TOOLONG", false).is_err());
+
+        let tag = Tag::register(b"XDUP", false).unwrap();
+        assert_eq!(Tag::register(b"XDUP", false).unwrap(), tag);
+        assert!(Tag::register(b"XDUP", true).is_err());
+
+        assert!(Tag::from_wire(b"XNON").is_err());
+    }
+
+    #[test]
+    fn instance_id_tag_is_known_and_not_critical() {
+        let tag = Tag::instance_id();
+
+        assert_eq!(Tag::from_wire(b"IID\xff").unwrap(), tag);
+        assert_eq!(Tag::register(b"IID\xff", false).unwrap(), tag);
+        assert!(Tag::register(b"IID\xff", true).is_err());
+        assert!(!tag.is_critical());
+        assert!(Tag::INDX < tag);
+    }
+
+    #[test]
+    fn pq_tags_are_known_and_not_critical() {
+        for &(wire, tag) in &[
+            (b"PQK\xff", Tag::pq_public_key()),
+            (b"PQS\xff", Tag::pq_signature()),
+        ] {
+            assert_eq!(Tag::from_wire(wire).unwrap(), tag);
+            assert_eq!(Tag::register(wire, false).unwrap(), tag);
+            assert!(Tag::register(wire, true).is_err());
+            assert!(!tag.is_critical());
+        }
+
+        assert!(Tag::PUBK < Tag::pq_public_key());
+        assert!(Tag::SREP < Tag::pq_signature());
+    }
+
+    #[test]
+    fn padding_is_not_critical() {
+        assert!(!Tag::PAD.is_critical());
+        assert!(!Tag::ZZZZ.is_critical());
+        assert!(Tag::SREP.is_critical());
+        assert!(Tag::VER.is_critical());
+    }
+}
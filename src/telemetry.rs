@@ -0,0 +1,200 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Export of server metrics and traces to an OpenTelemetry collector (OTLP over HTTP).
+//!
+//! Enabled with the `otlp` feature and the `otlp_endpoint` config setting. Metrics are
+//! pushed every `status_interval`; one span is emitted per batch of responses.
+//!
+
+use std::time::{Duration, SystemTime};
+
+use opentelemetry::metrics::{Counter, Gauge, MeterProvider as _};
+use opentelemetry::trace::{Span, Tracer, TracerProvider as _};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+
+use crate::rate::EwmaRate;
+use crate::server::DelegationStats;
+use crate::Error;
+
+/// Name reported as the OpenTelemetry `service.name` and instrumentation scope
+const SERVICE_NAME: &str = "roughenough";
+
+///
+/// Pushes the server's counters and per-batch spans to an OTLP collector.
+///
+/// Export happens on background threads owned by the OpenTelemetry SDK; recording is
+/// cheap and never blocks the server's event loop on the network.
+///
+pub struct Telemetry {
+    tracer_provider: SdkTracerProvider,
+    meter_provider: SdkMeterProvider,
+    responses: Counter<u64>,
+    invalid_requests: Counter<u64>,
+    batches: Counter<u64>,
+    key_age: Gauge<u64>,
+    delegation_remaining: Gauge<u64>,
+    rotations: Gauge<u64>,
+    request_rate: Gauge<f64>,
+    response_rate: Gauge<f64>,
+}
+
+impl Telemetry {
+    ///
+    /// Create an exporter sending to the collector at `endpoint` (e.g.
+    /// `http://localhost:4318`). Metrics are exported every `interval`.
+    ///
+    pub fn new(endpoint: &str, interval: Duration) -> Result<Self, Error> {
+        let endpoint = endpoint.trim_end_matches('/');
+        let resource = Resource::builder().with_service_name(SERVICE_NAME).build();
+
+        let span_exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", endpoint))
+            .build()
+            .map_err(|e| Error::InvalidConfiguration(format!("OTLP span exporter: {}", e)))?;
+
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_batch_exporter(span_exporter)
+            .with_resource(resource.clone())
+            .build();
+
+        let metric_exporter = MetricExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/metrics", endpoint))
+            .build()
+            .map_err(|e| Error::InvalidConfiguration(format!("OTLP metric exporter: {}", e)))?;
+
+        let reader = PeriodicReader::builder(metric_exporter)
+            .with_interval(interval)
+            .build();
+
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(reader)
+            .with_resource(resource)
+            .build();
+
+        let meter = meter_provider.meter(SERVICE_NAME);
+
+        let responses = meter
+            .u64_counter("roughenough.responses")
+            .with_description("Responses sent to clients")
+            .build();
+        let invalid_requests = meter
+            .u64_counter("roughenough.invalid_requests")
+            .with_description("Requests that were rejected as invalid")
+            .build();
+        let batches = meter
+            .u64_counter("roughenough.batches")
+            .with_description("Batches of requests signed")
+            .build();
+        let key_age = meter
+            .u64_gauge("roughenough.online_key.age")
+            .with_description("Time since the online key was put into service")
+            .with_unit("s")
+            .build();
+        let delegation_remaining = meter
+            .u64_gauge("roughenough.delegation.remaining")
+            .with_description("Time until the delegation to the online key expires")
+            .with_unit("s")
+            .build();
+        let rotations = meter
+            .u64_gauge("roughenough.delegation.rotations")
+            .with_description("Online key rotations since startup")
+            .build();
+        let request_rate = meter
+            .f64_gauge("roughenough.requests.rate")
+            .with_description("Moving average of requests received per second")
+            .with_unit("1/s")
+            .build();
+        let response_rate = meter
+            .f64_gauge("roughenough.responses.rate")
+            .with_description("Moving average of responses sent per second")
+            .with_unit("1/s")
+            .build();
+
+        Ok(Telemetry {
+            tracer_provider,
+            meter_provider,
+            responses,
+            invalid_requests,
+            batches,
+            key_age,
+            delegation_remaining,
+            rotations,
+            request_rate,
+            response_rate,
+        })
+    }
+
+    /// Count one rejected request
+    pub fn record_invalid_request(&self) {
+        self.invalid_requests.add(1, &[]);
+    }
+
+    ///
+    /// Record a batch of `size` responses that began processing at `started`. Emits a
+    /// `batch` span covering the batch and updates the response counters.
+    ///
+    pub fn record_batch(&self, size: usize, started: SystemTime) {
+        self.batches.add(1, &[]);
+        self.responses.add(size as u64, &[]);
+
+        let tracer = self.tracer_provider.tracer(SERVICE_NAME);
+        let mut span = tracer
+            .span_builder("batch")
+            .with_start_time(started)
+            .with_attributes(vec![KeyValue::new("batch.size", size as i64)])
+            .start(&tracer);
+
+        span.end();
+    }
+
+    /// Record the current age and remaining lifetime of the delegation
+    pub fn record_delegation(&self, stats: &DelegationStats) {
+        self.key_age.record(stats.key_age.as_secs(), &[]);
+        self.delegation_remaining
+            .record(stats.remaining.as_secs(), &[]);
+        self.rotations.record(stats.rotations, &[]);
+    }
+
+    /// Record the 1, 5 and 15 minute request and response rates, labelled by `window`
+    pub fn record_rates(&self, requests: &EwmaRate, responses: &EwmaRate) {
+        for &(gauge, rate) in &[
+            (&self.request_rate, requests),
+            (&self.response_rate, responses),
+        ] {
+            gauge.record(rate.one_minute(), &[KeyValue::new("window", "1m")]);
+            gauge.record(rate.five_minutes(), &[KeyValue::new("window", "5m")]);
+            gauge.record(rate.fifteen_minutes(), &[KeyValue::new("window", "15m")]);
+        }
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        // flush anything still buffered
+        if let Err(e) = self.tracer_provider.shutdown() {
+            warn!("failed to shut down OTLP trace export: {}", e);
+        }
+        if let Err(e) = self.meter_provider.shutdown() {
+            warn!("failed to shut down OTLP metric export: {}", e);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Helpers for running an in-process Roughenough server in tests.
+//!
+//! ```no_run
+//! use roughenough::config::MemoryConfig;
+//! use roughenough::test_support::TestServer;
+//!
+//! // port 0 asks the OS for an ephemeral port
+//! let server = TestServer::start(MemoryConfig::new(0));
+//! println!("server {} has public key {}", server.addr(), hex::encode(server.public_key()));
+//! server.shutdown();
+//! ```
+//!
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+
+use crate::config::MemoryConfig;
+use crate::server::Server;
+
+///
+/// A Roughenough server running on a background thread.
+///
+/// The server is stopped when `shutdown()` is called or the `TestServer` is dropped.
+///
+pub struct TestServer {
+    addr: SocketAddr,
+    public_key: Vec<u8>,
+    keep_running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    ///
+    /// Start a server configured by `config` on a background thread. Use port `0`
+    /// in `config` to have the server bind to an ephemeral port.
+    ///
+    /// Blocks until the server's socket is bound and it is ready to accept requests.
+    ///
+    pub fn start(config: MemoryConfig) -> TestServer {
+        let (tx, rx) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            let mut server = Server::new(Box::new(config));
+            let keep_running = server.get_keep_running();
+            let public_key = hex::decode(server.get_public_key()).unwrap();
+
+            tx.send((server.local_addr(), public_key, keep_running.clone()))
+                .expect("test server startup");
+
+            while keep_running.load(Ordering::Acquire) {
+                if server.process_events() {
+                    break;
+                }
+            }
+        });
+
+        let (addr, public_key, keep_running) = rx.recv().expect("test server failed to start");
+
+        TestServer {
+            addr,
+            public_key,
+            keep_running,
+            handle: Some(handle),
+        }
+    }
+
+    /// Returns the address of the server's UDP socket
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the server's long-term public key
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    /// Stop the server and wait for its thread to exit
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.keep_running.store(false, Ordering::Release);
+
+        if let Some(handle) = self.handle.take() {
+            handle.join().expect("test server thread panicked");
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    use crate::config::MemoryConfig;
+    use crate::test_support::TestServer;
+    use crate::{RtMessage, Tag};
+
+    #[test]
+    fn server_answers_request() {
+        let server = TestServer::start(MemoryConfig::new(0));
+        assert_ne!(server.addr().port(), 0);
+        assert_eq!(server.public_key().len(), 32);
+
+        let mut request = RtMessage::new(2);
+        request.add_field(Tag::NONC, &[0x42; 64]).unwrap();
+        request.pad_to_kilobyte();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        socket
+            .send_to(&request.encode().unwrap(), server.addr())
+            .unwrap();
+
+        let mut buf = [0u8; 1024];
+        let (len, _) = socket.recv_from(&mut buf).unwrap();
+        let response = RtMessage::from_bytes(&buf[..len]).unwrap();
+
+        assert!(response.get_field(Tag::SREP).is_some());
+        assert!(response.get_field(Tag::CERT).is_some());
+
+        server.shutdown();
+    }
+}
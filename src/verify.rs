@@ -0,0 +1,513 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Client-side validation of Roughtime responses.
+//!
+
+use byteorder::{ByteOrder, LittleEndian};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::merkle::{root_from_paths, validate_path};
+use crate::sign::{BatchVerifier, Verifier};
+use crate::{
+    Error, RtMessage, Tag, CERTIFICATE_CONTEXT, MIN_REQUEST_LENGTH, NONCE_LENGTH,
+    SIGNED_RESPONSE_CONTEXT,
+};
+
+/// Generate a fresh random nonce for a request
+pub fn create_nonce() -> [u8; NONCE_LENGTH as usize] {
+    let mut nonce = [0u8; NONCE_LENGTH as usize];
+    SystemRandom::new().fill(&mut nonce).unwrap();
+
+    nonce
+}
+
+/// Create an encoded, padded request for `nonce`
+pub fn make_request(nonce: &[u8]) -> Vec<u8> {
+    make_request_with_size(nonce, MIN_REQUEST_LENGTH as usize)
+}
+
+///
+/// Create an encoded request for `nonce`, padded to `size` bytes (a multiple of 4).
+/// Servers do not answer requests under `MIN_REQUEST_LENGTH` bytes, which limits their
+/// use for traffic amplification.
+///
+pub fn make_request_with_size(nonce: &[u8], size: usize) -> Vec<u8> {
+    let mut msg = RtMessage::new(1);
+    msg.add_field(Tag::NONC, nonce).unwrap();
+    msg.pad_to(size);
+
+    msg.encode().unwrap()
+}
+
+///
+/// The cause given by a server that answered with an error response rather than the time,
+/// or `None` if `bytes` is not an error response.
+///
+/// Servers implementing the newer drafts may reply to a request they refuse with a
+/// well-formed message that carries no signed response (`SREP`) but one of the
+/// [`ERROR_TAGS`](constant.ERROR_TAGS.html). Such messages can use other tags this crate
+/// does not know, so the fields are split without decoding the tags. The cause is the
+/// first error tag holding printable text, prefixed by the tag.
+///
+pub fn error_response(bytes: &[u8]) -> Option<String> {
+    let fields = raw_fields(bytes)?;
+
+    if fields.iter().any(|(tag, _)| tag == b"SREP") {
+        return None;
+    }
+
+    let errors: Vec<_> = fields
+        .iter()
+        .filter(|(tag, _)| ERROR_TAGS.contains(&tag))
+        .collect();
+    if errors.is_empty() {
+        return None;
+    }
+
+    let cause = errors.iter().find_map(|(tag, value)| {
+        let text = std::str::from_utf8(value).ok()?.trim_end_matches('\0');
+        if text.is_empty() || text.chars().any(char::is_control) {
+            return None;
+        }
+        Some(format!("{}: {}", escape_tag(tag), text))
+    });
+
+    let tags: Vec<_> = fields.iter().map(|(tag, _)| escape_tag(tag)).collect();
+
+    Some(cause.unwrap_or_else(|| format!("no cause given (tags: {})", tags.join(", "))))
+}
+
+/// Tags that mark a message as an error response and hold the server's cause for it
+pub const ERROR_TAGS: [&[u8; 4]; 3] = [b"ERRO", b"ERR\x00", b"CAUS"];
+
+// A tag as text, with any non-printable bytes escaped
+fn escape_tag(tag: &[u8]) -> String {
+    tag.iter()
+        .flat_map(|&b| std::ascii::escape_default(b))
+        .map(char::from)
+        .collect()
+}
+
+// Split a message into its (tag, value) pairs without interpreting the tags
+fn raw_fields(bytes: &[u8]) -> Option<Vec<([u8; 4], &[u8])>> {
+    if bytes.len() < 4 || bytes.len() % 4 != 0 {
+        return None;
+    }
+
+    let num_tags = LittleEndian::read_u32(bytes) as usize;
+    if num_tags == 0 || num_tags > bytes.len() / 8 {
+        return None;
+    }
+
+    let header_len = 8 * num_tags;
+    let offsets = &bytes[4..4 * num_tags];
+    let tags = &bytes[4 * num_tags..header_len];
+    let values = &bytes[header_len..];
+
+    let mut bounds = vec![0];
+    for chunk in offsets.chunks(4) {
+        bounds.push(LittleEndian::read_u32(chunk) as usize);
+    }
+    bounds.push(values.len());
+
+    let mut fields = Vec::with_capacity(num_tags);
+    for (i, tag) in tags.chunks(4).enumerate() {
+        let (start, end) = (bounds[i], bounds[i + 1]);
+        if start > end || end > values.len() || start % 4 != 0 {
+            return None;
+        }
+        fields.push(([tag[0], tag[1], tag[2], tag[3]], &values[start..end]));
+    }
+
+    Some(fields)
+}
+
+///
+/// The outcome of one step of [`Response::verify_strict`](struct.Response.html#method.verify_strict)
+///
+#[derive(Debug)]
+pub struct VerificationStep {
+    /// What was checked
+    pub name: &'static str,
+    pub result: Result<(), Error>,
+}
+
+///
+/// A server response along with the nonce of the request it answers.
+///
+/// Parsing only checks that the nested messages are present; use `verify()` (or
+/// `add_signatures()` followed by `verify_merkle()` and `verify_midpoint()`) to
+/// authenticate the response.
+///
+pub struct Response {
+    msg: RtMessage,
+    srep: RtMessage,
+    cert: RtMessage,
+    dele: RtMessage,
+    nonce: Vec<u8>,
+}
+
+impl Response {
+    /// Parse `bytes` received in reply to a request containing `nonce`
+    pub fn from_bytes(bytes: &[u8], nonce: &[u8]) -> Result<Response, Error> {
+        Response::new(RtMessage::from_bytes(bytes)?, nonce)
+    }
+
+    /// Wrap an already decoded response to a request containing `nonce`
+    pub fn new(msg: RtMessage, nonce: &[u8]) -> Result<Response, Error> {
+        let srep = msg.get_nested(Tag::SREP)?;
+        let cert = msg.get_nested(Tag::CERT)?;
+        let dele = cert.get_nested(Tag::DELE)?;
+
+        Ok(Response {
+            msg,
+            srep,
+            cert,
+            dele,
+            nonce: nonce.to_vec(),
+        })
+    }
+
+    /// The top-level response message
+    pub fn message(&self) -> &RtMessage {
+        &self.msg
+    }
+
+    /// Midpoint of the server's time, in microseconds since the epoch
+    pub fn midpoint(&self) -> Result<u64, Error> {
+        self.srep.get_u64(Tag::MIDP)
+    }
+
+    /// Radius of the server's uncertainty, in microseconds
+    pub fn radius(&self) -> Result<u32, Error> {
+        self.srep.get_u32(Tag::RADI)
+    }
+
+    /// TAI-UTC offset in seconds, present when the midpoint was read from a TAI clock
+    pub fn dtai(&self) -> Option<u32> {
+        self.srep.get_u32(Tag::DTAI).ok()
+    }
+
+    /// The answering server's `instance_id`, if it sends one. It is not authenticated.
+    pub fn instance_id(&self) -> Option<String> {
+        self.msg.get_field(Tag::instance_id()).map(|value| {
+            let id = value.split(|&b| b == 0).next().unwrap_or(value);
+            String::from_utf8_lossy(id).into_owned()
+        })
+    }
+
+    /// Index of the request's nonce in the server's Merkle tree
+    pub fn index(&self) -> Result<u32, Error> {
+        self.msg.get_u32(Tag::INDX)
+    }
+
+    ///
+    /// Queue the DELE signature (by the long-term key `pub_key`) and the SREP signature
+    /// (by the delegated online key) of this response. The DELE signature is added first.
+    ///
+    pub fn add_signatures(&self, pub_key: &[u8], batch: &mut BatchVerifier) -> Result<(), Error> {
+        let mut full_cert = Vec::from(CERTIFICATE_CONTEXT.as_bytes());
+        full_cert.extend(field(&self.cert, Tag::DELE)?);
+
+        batch.add(pub_key, &full_cert, field(&self.cert, Tag::SIG)?);
+
+        let mut full_srep = Vec::from(SIGNED_RESPONSE_CONTEXT.as_bytes());
+        full_srep.extend(field(&self.msg, Tag::SREP)?);
+
+        batch.add(
+            field(&self.dele, Tag::PUBK)?,
+            &full_srep,
+            field(&self.msg, Tag::SIG)?,
+        );
+
+        Ok(())
+    }
+
+    /// Check that the request's nonce is included in the signed Merkle root. Malformed
+    /// PATH or INDX values are reported as errors before any hashing is done.
+    pub fn verify_merkle(&self) -> Result<(), Error> {
+        let index = self.index()?;
+        let paths = field(&self.msg, Tag::PATH)?;
+
+        validate_path(index, paths)?;
+        let hash = root_from_paths(index as usize, &self.nonce, paths);
+
+        if hash.as_slice() == field(&self.srep, Tag::ROOT)? {
+            Ok(())
+        } else {
+            Err(Error::InvalidResponse(
+                "nonce is not present in the response's merkle tree".to_string(),
+            ))
+        }
+    }
+
+    /// Check that the midpoint lies within the delegation's validity span
+    pub fn verify_midpoint(&self) -> Result<(), Error> {
+        let midpoint = self.midpoint()?;
+        let mint = self.dele.get_u64(Tag::MINT)?;
+        let maxt = self.dele.get_u64(Tag::MAXT)?;
+
+        if midpoint < mint {
+            return Err(Error::InvalidResponse(format!(
+                "response midpoint {} lies *before* delegation span ({}, {})",
+                midpoint, mint, maxt
+            )));
+        }
+        if midpoint > maxt {
+            return Err(Error::InvalidResponse(format!(
+                "response midpoint {} lies *after* delegation span ({}, {})",
+                midpoint, mint, maxt
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Check the CERT's signature of the DELE by the server's long-term key `pub_key`
+    pub fn verify_cert(&self, pub_key: &[u8]) -> Result<(), Error> {
+        let mut verifier = Verifier::new(pub_key);
+        verifier.update(CERTIFICATE_CONTEXT.as_bytes());
+        verifier.update(field(&self.cert, Tag::DELE)?);
+
+        if verifier.verify(field(&self.cert, Tag::SIG)?) {
+            Ok(())
+        } else {
+            Err(Error::InvalidSignature(
+                "DELE is not signed by the long-term key".to_string(),
+            ))
+        }
+    }
+
+    /// Check the SREP's signature by the online key delegated to in the DELE
+    pub fn verify_srep(&self) -> Result<(), Error> {
+        let mut verifier = Verifier::new(field(&self.dele, Tag::PUBK)?);
+        verifier.update(SIGNED_RESPONSE_CONTEXT.as_bytes());
+        verifier.update(field(&self.msg, Tag::SREP)?);
+
+        if verifier.verify(field(&self.msg, Tag::SIG)?) {
+            Ok(())
+        } else {
+            Err(Error::InvalidSignature(
+                "SREP is not signed by the delegated online key".to_string(),
+            ))
+        }
+    }
+
+    /// Whether the response carries an experimental post-quantum signature of the SREP
+    pub fn has_pq_signature(&self) -> bool {
+        self.msg.get_field(Tag::pq_signature()).is_some()
+    }
+
+    ///
+    /// Check the experimental post-quantum signature of the SREP by the key in the DELE.
+    /// This is in addition to, not instead of, [`verify`](#method.verify): the DELE is
+    /// only certified by the Ed25519 long-term key.
+    ///
+    #[cfg(feature = "pq")]
+    pub fn verify_pq(&self) -> Result<(), Error> {
+        let mut signed = SIGNED_RESPONSE_CONTEXT.as_bytes().to_vec();
+        signed.extend_from_slice(field(&self.msg, Tag::SREP)?);
+
+        crate::pq::verify(
+            field(&self.dele, Tag::pq_public_key())?,
+            &signed,
+            field(&self.msg, Tag::pq_signature())?,
+        )
+    }
+
+    /// Check that the PATH is well formed for the response's INDX, without hashing
+    pub fn verify_path(&self) -> Result<(), Error> {
+        validate_path(self.index()?, field(&self.msg, Tag::PATH)?)
+    }
+
+    /// Check that the radius is non-zero and, if given, no more than `max_radius` microseconds
+    pub fn verify_radius(&self, max_radius: Option<u32>) -> Result<(), Error> {
+        let radius = self.radius()?;
+
+        if radius == 0 {
+            return Err(Error::InvalidResponse(
+                "radius is zero; no clock is that certain".to_string(),
+            ));
+        }
+
+        match max_radius {
+            Some(max) if radius > max => Err(Error::InvalidResponse(format!(
+                "radius {}us exceeds the maximum of {}us",
+                radius, max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    ///
+    /// Run every verification step independently, in protocol order, and report each
+    /// outcome rather than stopping at the first failure. The response is valid only if
+    /// every step passes.
+    ///
+    pub fn verify_strict(&self, pub_key: &[u8], max_radius: Option<u32>) -> Vec<VerificationStep> {
+        let step = |name, result| VerificationStep { name, result };
+
+        vec![
+            step("CERT signature", self.verify_cert(pub_key)),
+            step("DELE validity window", self.verify_midpoint()),
+            step("SREP signature", self.verify_srep()),
+            step("Merkle path", self.verify_path()),
+            step("nonce match", self.verify_merkle()),
+            step("radius bounds", self.verify_radius(max_radius)),
+        ]
+    }
+
+    /// Fully authenticate this response against the server's long-term public key
+    pub fn verify(&self, pub_key: &[u8]) -> Result<(), Error> {
+        self.verify_cert(pub_key)?;
+        self.verify_srep()?;
+        self.verify_merkle()?;
+        self.verify_midpoint()
+    }
+}
+
+fn field(msg: &RtMessage, tag: Tag) -> Result<&[u8], Error> {
+    msg.get_field(tag).ok_or(Error::TagNotFound(tag))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::MemoryConfig;
+    use crate::test_support::TestServer;
+    use crate::verify::{
+        create_nonce, error_response, make_request, Response, VerificationStep,
+    };
+    use crate::{Error, RtMessage, Tag};
+
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    fn query(server: &TestServer, nonce: &[u8]) -> Vec<u8> {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        socket
+            .send_to(&make_request(nonce), server.addr())
+            .unwrap();
+
+        let mut buf = [0u8; 1024];
+        let (len, _) = socket.recv_from(&mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn valid_response_verifies() {
+        let server = TestServer::start(MemoryConfig::new(0));
+        let nonce = create_nonce();
+
+        let response = Response::from_bytes(&query(&server, &nonce), &nonce).unwrap();
+
+        assert!(response.verify(server.public_key()).is_ok());
+        assert_eq!(response.index().unwrap(), 0);
+        assert_eq!(response.radius().unwrap(), 1_000_000);
+    }
+
+    #[test]
+    fn wrong_nonce_fails_merkle_check() {
+        let server = TestServer::start(MemoryConfig::new(0));
+        let nonce = create_nonce();
+        let bytes = query(&server, &nonce);
+
+        let response = Response::from_bytes(&bytes, &create_nonce()).unwrap();
+
+        match response.verify(server.public_key()) {
+            Err(Error::InvalidResponse(_)) => (),
+            e => panic!("unexpected result {:?}", e),
+        }
+    }
+
+    fn failed(steps: &[VerificationStep]) -> Vec<&'static str> {
+        steps
+            .iter()
+            .filter(|s| s.result.is_err())
+            .map(|s| s.name)
+            .collect()
+    }
+
+    #[test]
+    fn strict_steps_pinpoint_failures() {
+        let server = TestServer::start(MemoryConfig::new(0));
+        let nonce = create_nonce();
+        let bytes = query(&server, &nonce);
+
+        let response = Response::from_bytes(&bytes, &nonce).unwrap();
+        let steps = response.verify_strict(server.public_key(), None);
+        assert_eq!(steps.len(), 6);
+        assert!(steps.iter().all(|s| s.result.is_ok()));
+
+        // only the long-term key's signature is affected by the wrong key
+        let steps = response.verify_strict(&[0u8; 32], None);
+        assert_eq!(failed(&steps), vec!["CERT signature"]);
+
+        // and only the nonce check by the wrong nonce
+        let response = Response::from_bytes(&bytes, &create_nonce()).unwrap();
+        let steps = response.verify_strict(server.public_key(), Some(1));
+        assert_eq!(failed(&steps), vec!["nonce match", "radius bounds"]);
+    }
+
+    #[test]
+    fn wrong_key_fails_signature_check() {
+        let server = TestServer::start(MemoryConfig::new(0));
+        let nonce = create_nonce();
+        let bytes = query(&server, &nonce);
+
+        let response = Response::from_bytes(&bytes, &nonce).unwrap();
+
+        match response.verify(&[0u8; 32]) {
+            Err(Error::InvalidSignature(_)) => (),
+            e => panic!("unexpected result {:?}", e),
+        }
+    }
+
+    #[test]
+    fn error_responses_are_recognized() {
+        let server = TestServer::start(MemoryConfig::new(0));
+        let nonce = create_nonce();
+
+        assert_eq!(error_response(&query(&server, &nonce)), None);
+        assert_eq!(error_response(&[]), None);
+        assert_eq!(error_response(&[1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]), None);
+
+        // tags unknown to this crate are fine
+        let mut bytes = vec![2, 0, 0, 0, 8, 0, 0, 0];
+        bytes.extend(b"NONCERRO");
+        bytes.extend(&[0u8; 8]);
+        bytes.extend(b"server busy\0");
+        assert_eq!(
+            error_response(&bytes),
+            Some("ERRO: server busy".to_string())
+        );
+
+        // a message without SREP is only an error response if it has an error tag
+        let mut msg = RtMessage::new(1);
+        msg.add_field(Tag::NONC, &nonce).unwrap();
+        assert_eq!(error_response(&msg.encode().unwrap()), None);
+
+        let mut bytes = vec![2, 0, 0, 0, 4, 0, 0, 0];
+        bytes.extend(b"ERR\x00\xff\x01AB");
+        bytes.extend(&[0x01, 0x02, 0x03, 0x04, 0, 0, 0, 0]);
+        assert_eq!(
+            error_response(&bytes),
+            Some("no cause given (tags: ERR\\x00, \\xff\\x01AB)".to_string())
+        );
+    }
+}
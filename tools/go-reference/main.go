@@ -0,0 +1,191 @@
+// Copyright 2017-2019 int08h LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Command go-reference exposes the Roughtime Go reference implementation's wire format
+// and verification code to roughenough-difftest. It reads one command per line on stdin
+// and writes one result line to stdout: "ok ..." or "err <message>".
+//
+//	encode <tag>=<hex>,...                   -> ok <hex>
+//	decode <hex>                             -> ok <tag>=<hex>,...  (ascending tags)
+//	verify <reply hex> <pubkey hex> <nonce hex>
+//	                                         -> ok <midpoint> <radius>
+//	replies <seed hex> <midpoint> <radius> <nonce hex>...
+//	                                         -> ok <reply hex>...
+//
+// Tags are decimal uint32 values (the little-endian interpretation of the tag's bytes).
+package main
+
+import (
+	"bufio"
+	"crypto/ed25519"
+	"encoding/hex"
+	"errors"
+	"fmt"
+	"os"
+	"sort"
+	"strconv"
+	"strings"
+
+	"roughtime.googlesource.com/roughtime.git/go/protocol"
+)
+
+func main() {
+	in := bufio.NewScanner(os.Stdin)
+	in.Buffer(make([]byte, 1<<20), 1<<20)
+	out := bufio.NewWriter(os.Stdout)
+
+	for in.Scan() {
+		result, err := run(strings.Fields(in.Text()))
+		if err != nil {
+			fmt.Fprintf(out, "err %s\n", strings.Replace(err.Error(), "\n", " ", -1))
+		} else {
+			fmt.Fprintf(out, "ok %s\n", result)
+		}
+		out.Flush()
+	}
+}
+
+func run(args []string) (string, error) {
+	if len(args) == 0 {
+		return "", errors.New("empty command")
+	}
+
+	switch args[0] {
+	case "encode":
+		msg := make(map[uint32][]byte)
+		if len(args) > 1 {
+			for _, field := range strings.Split(args[1], ",") {
+				parts := strings.SplitN(field, "=", 2)
+				if len(parts) != 2 {
+					return "", fmt.Errorf("malformed field %q", field)
+				}
+				tag, err := strconv.ParseUint(parts[0], 10, 32)
+				if err != nil {
+					return "", err
+				}
+				value, err := hex.DecodeString(parts[1])
+				if err != nil {
+					return "", err
+				}
+				msg[uint32(tag)] = value
+			}
+		}
+		encoded, err := protocol.Encode(msg)
+		if err != nil {
+			return "", err
+		}
+		return hex.EncodeToString(encoded), nil
+
+	case "decode":
+		bytes, err := hexArg(args, 1)
+		if err != nil {
+			return "", err
+		}
+		msg, err := protocol.Decode(bytes)
+		if err != nil {
+			return "", err
+		}
+		tags := make([]int, 0, len(msg))
+		for tag := range msg {
+			tags = append(tags, int(tag))
+		}
+		sort.Ints(tags)
+		fields := make([]string, 0, len(tags))
+		for _, tag := range tags {
+			fields = append(fields, fmt.Sprintf("%d=%s", tag, hex.EncodeToString(msg[uint32(tag)])))
+		}
+		return strings.Join(fields, ","), nil
+
+	case "verify":
+		reply, err := hexArg(args, 1)
+		if err != nil {
+			return "", err
+		}
+		pubKey, err := hexArg(args, 2)
+		if err != nil {
+			return "", err
+		}
+		nonceBytes, err := hexArg(args, 3)
+		if err != nil {
+			return "", err
+		}
+		var nonce [protocol.NonceSize]byte
+		if len(nonceBytes) != len(nonce) {
+			return "", fmt.Errorf("nonce must be %d bytes", len(nonce))
+		}
+		copy(nonce[:], nonceBytes)
+
+		midpoint, radius, err := protocol.VerifyReply(reply, pubKey, nonce)
+		if err != nil {
+			return "", err
+		}
+		return fmt.Sprintf("%d %d", midpoint, radius), nil
+
+	case "replies":
+		seed, err := hexArg(args, 1)
+		if err != nil {
+			return "", err
+		}
+		if len(args) < 5 {
+			return "", errors.New("usage: replies <seed> <midpoint> <radius> <nonce>...")
+		}
+		midpoint, err := strconv.ParseUint(args[2], 10, 64)
+		if err != nil {
+			return "", err
+		}
+		radius, err := strconv.ParseUint(args[3], 10, 32)
+		if err != nil {
+			return "", err
+		}
+
+		var nonces [][]byte
+		for i := 4; i < len(args); i++ {
+			nonce, err := hexArg(args, i)
+			if err != nil {
+				return "", err
+			}
+			nonces = append(nonces, nonce)
+		}
+
+		rootKey := ed25519.NewKeyFromSeed(seed)
+		onlinePub, onlinePriv, err := ed25519.GenerateKey(nil)
+		if err != nil {
+			return "", err
+		}
+		cert, err := protocol.CreateCertificate(0, ^uint64(0), onlinePub, rootKey)
+		if err != nil {
+			return "", err
+		}
+		replies, err := protocol.CreateReplies(nonces, midpoint, uint32(radius), cert, onlinePriv)
+		if err != nil {
+			return "", err
+		}
+
+		encoded := make([]string, len(replies))
+		for i, reply := range replies {
+			encoded[i] = hex.EncodeToString(reply)
+		}
+		return strings.Join(encoded, " "), nil
+
+	default:
+		return "", fmt.Errorf("unknown command %q", args[0])
+	}
+}
+
+func hexArg(args []string, i int) ([]byte, error) {
+	if i >= len(args) {
+		return nil, fmt.Errorf("missing argument %d", i)
+	}
+	return hex.DecodeString(args[i])
+}